@@ -0,0 +1,16 @@
+use std::io::{self, Write};
+
+use iso9660::*;
+
+/// The floppy `boot/Makefile` puts stage 1 on, the BIOS boots the CD as if it
+/// was that floppy
+const FLOPPY_IMG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/boot/floppy.img");
+
+fn main () {
+    let mut builder = IsoBuilder::new();
+    builder.add_host_file("boot/floppy.img", FLOPPY_IMG).unwrap();
+    builder.add_boot_image(BootImage::emulation("boot/floppy.img", BootMedia::Floppy1_44));
+
+    let image = builder.build().unwrap();
+    io::stdout().write_all(&image).unwrap();
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use iso9660::*;
+
+use crate::info::{media_name, platform_name};
+use crate::{ctx, open, Args, Error};
+
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut args = Args::parse(args, &[], &["--extract"])?;
+    let image = args.image()?;
+    if !args.operands.is_empty() {
+        return Err(Error::Usage("boot takes a single image".to_owned()))
+    }
+    let mut fs = open(&image)?;
+
+    let Some(catalog) = fs.read_boot_catalog().map_err(ctx(&image))? else {
+        return Err(Error::Failed(format!("{}: no boot catalog", image)))
+    };
+    let validation = BootCatalogRef::new(&catalog)
+        .and_then(|c| c.validation_entry())
+        .map_err(ctx(&image))?;
+    let entries = fs.boot_entries().map_err(ctx(&image))?;
+    let catalog_lba = fs.descriptors().boot_record.as_ref().and_then(|r| r.boot_catalog_addr).unwrap_or(0);
+
+    let mut out = io::stdout().lock();
+    write!(out, "Boot catalog at sector {}, {} bytes", catalog_lba, catalog.len())?;
+    if let Some(id) = &validation.manufacturer_id {
+        write!(out, ", manufacturer {:?}", id.as_str().trim_end())?;
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:>2}  {:<8} {:<13} {:<4} {:>6} {:>4} {:>7} {:>8} {:>10}  File",
+        "#", "Platform", "Media", "Boot", "Seg", "Type", "Sectors", "LBA", "Size",
+    )?;
+    for entry in &entries {
+        let file = entry.file.as_ref().map(|(path, _)| path.as_str()).unwrap_or("-");
+        // only BIOSes load the image at a segment
+        let segment = match entry.platform {
+            Platform::X86 => format!("{:#06x}", entry.load_segment.unwrap_or(DEFAULT_LOAD_SEGMENT)),
+            _ => "-".to_owned(),
+        };
+        write!(
+            out,
+            "{:>2}  {:<8} {:<13} {:<4} {:>6} {:>#4x} {:>7} {:>8} {:>10}  {}",
+            entry.index, platform_name(entry.platform), media_name(entry.media),
+            if entry.bootable { "yes" } else { "no" }, segment, entry.sys_type,
+            entry.sector_count, entry.lba, entry.size, file,
+        )?;
+
+        let mut notes = Vec::new();
+        if let (BootMedia::NoEmulation, Some((_, record))) = (entry.media, &entry.file) {
+            if fs.verify_boot_info_table(record).map_err(ctx(&image))? {
+                notes.push("boot info table");
+            }
+            if entry.size >= (GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE) as u64 {
+                let mut head = vec![0_u8; GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE];
+                fs.read_at(entry.lba as u64 * SECTOR_SIZE as u64, &mut head).map_err(ctx(&image))?;
+                if Grub2BootInfo::parse(&head) == Some(Grub2BootInfo::new(entry.lba)) {
+                    notes.push("GRUB2 boot info");
+                }
+            }
+        }
+        if !notes.is_empty() {
+            write!(out, " ({})", notes.join(", "))?;
+        }
+        writeln!(out)?;
+    }
+
+    if let Some(dir) = args.value("--extract") {
+        fs::create_dir_all(dir).map_err(ctx(dir))?;
+        for entry in &entries {
+            let path = Path::new(dir).join(entry.file_name());
+            fs.read_boot_image(entry)
+                .and_then(|data| fs::write(&path, data))
+                .map_err(ctx(path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let dir = std::env::temp_dir().join(format!("iso-boot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/loader.bin", vec![0xEB; 4096]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("boot/loader.bin"));
+        let path = dir.join("image.iso");
+        fs::write(&path, builder.build().unwrap()).unwrap();
+
+        let out = dir.join("out");
+        let args = ["--extract", out.to_str().unwrap(), path.to_str().unwrap()];
+        assert!(run(args.into_iter().map(str::to_owned)).is_ok());
+        assert_eq!(fs::read(out.join("boot-00-x86.img")).unwrap(), [0xEB; 4096]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,363 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use iso9660::*;
+
+use crate::glob;
+use crate::ls::MAX_DEPTH;
+use crate::{ctx, namespace, open, Args, Error, Image, NAMESPACE_FLAGS};
+
+/// Symbolic links followed before giving up, like the `ELOOP` of Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// `path` without `.`, `..` and repeated `/`
+fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            },
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+/// Looks `path` up, following the symbolic links of its last component
+fn resolve(fs: &mut Image, path: &str, namespace: Namespace) -> Result<DirEntry, VDErr> {
+    let mut path = normalize(path);
+    for _ in 0..MAX_SYMLINKS {
+        let entry = fs.lookup_entry(&path, namespace)?;
+        let Some(target) = entry.symlink() else {
+            return Ok(entry)
+        };
+        path = match (target.starts_with('/'), path.rfind('/')) {
+            (true, _) => normalize(target),
+            (false, Some(pos)) => normalize(&format!("{}/{}", &path[..pos], target)),
+            (false, None) => normalize(target),
+        };
+    }
+    Err(VDErr::Io(io::Error::other("too many levels of symbolic links")))
+}
+
+pub fn cat(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut args = Args::parse(args, &NAMESPACE_FLAGS, &[])?;
+    let image = args.image()?;
+    if args.operands.is_empty() {
+        return Err(Error::Usage("missing path".to_owned()))
+    }
+    let mut fs = open(&image)?;
+    let namespace = namespace(&args, &mut fs, &image)?;
+
+    let mut out = io::stdout().lock();
+    for path in &args.operands {
+        let what = format!("{}: {}", image, path);
+        let entry = resolve(&mut fs, path, namespace).map_err(ctx(&what))?;
+        if entry.is_dir() {
+            return Err(ctx(&what)(VDErr::IsADirectory))
+        }
+        let mut file = fs.open_file(entry.record);
+        // tell read errors from write ones, only the latter may be a closed
+        // pipe
+        let mut buf = vec![0_u8; 64 * 1024];
+        loop {
+            let len = match io::Read::read(&mut file, &mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ctx(&what)(e)),
+            };
+            out.write_all(&buf[..len])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// `true` if `name` can be created in a directory without escaping it
+fn is_safe_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains(['/', '\0'])
+}
+
+struct Extractor {
+    fs: Image,
+    /// The directory given to `-C`, nothing is written outside of it
+    dest: PathBuf,
+    namespace: Namespace,
+    image: String,
+    patterns: Vec<String>,
+    matched: Vec<bool>,
+    verbose: bool,
+    /// Directories whose mode is set once their content is written, it may
+    /// not allow writing
+    dir_modes: Vec<(PathBuf, u32)>,
+    errors: usize,
+}
+
+impl Extractor {
+    fn warn(&mut self, msg: impl std::fmt::Display) {
+        eprintln!("iso: {}", msg);
+        self.errors += 1;
+    }
+
+    /// `true` if `path` or one of its parents was asked for
+    fn is_selected(&mut self, path: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true
+        }
+        let mut selected = false;
+        for (pattern, matched) in self.patterns.iter().zip(&mut self.matched) {
+            if glob::matches(pattern, path) {
+                *matched = true;
+                selected = true;
+            }
+        }
+        selected
+    }
+
+    fn extract_dir(&mut self, dir: &DirEntry, path: &str, dest: &Path, selected: bool, depth: usize) -> Result<(), Error> {
+        let entries = self.fs.entries(&dir.record, self.namespace)
+            .map_err(ctx(format!("{}: /{}", self.image, path)))?;
+        for entry in entries {
+            if !is_safe_name(&entry.name) {
+                self.warn(format!("{}: /{}: skipping unsafe name {:?}", self.image, path, entry.name));
+                continue
+            }
+            let child = if path.is_empty() { entry.name.clone() } else { format!("{}/{}", path, entry.name) };
+            let child_dest = dest.join(&entry.name);
+            let selected = selected || self.is_selected(&child);
+            if entry.is_dir() {
+                if selected {
+                    self.create_dir(&entry, &child_dest)?;
+                }
+                if depth >= MAX_DEPTH {
+                    self.warn(format!("{}: /{}: too deep", self.image, child));
+                    continue
+                }
+                self.extract_dir(&entry, &child, &child_dest, selected, depth + 1)?;
+            } else if selected {
+                self.create_dirs(dest)?;
+                self.extract_file(&entry, &child, &child_dest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates `dir` and the directories missing above it, refusing to go
+    /// through anything that is not a directory: the image may have put a
+    /// link there to write outside of the destination
+    fn create_dirs(&self, dir: &Path) -> Result<(), Error> {
+        let relative = dir.strip_prefix(&self.dest).expect("entries are extracted under the destination");
+        let mut path = self.dest.clone();
+        for component in relative.components() {
+            path.push(component);
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => (),
+                Ok(_) => return Err(Error::Failed(format!("{}: not a directory, refusing to write through it", path.display()))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&path).map_err(ctx(path.display()))?,
+                Err(e) => return Err(ctx(path.display())(e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn create_dir(&mut self, entry: &DirEntry, dest: &Path) -> Result<(), Error> {
+        if self.verbose {
+            println!("{}/", dest.display());
+        }
+        self.create_dirs(dest)?;
+        if let Some(attrs) = entry.rock_ridge.as_ref().and_then(|rr| rr.attrs.as_ref()) {
+            self.dir_modes.push((dest.to_owned(), attrs.mode));
+        }
+        Ok(())
+    }
+
+    fn extract_file(&mut self, entry: &DirEntry, path: &str, dest: &Path) -> Result<(), Error> {
+        if self.verbose {
+            println!("{}", dest.display());
+        }
+        if let Some(target) = entry.symlink() {
+            return self.create_symlink(target, dest)
+        }
+
+        let what = format!("{}: /{}", self.image, path);
+        // a link in place of the file would be followed
+        remove_file(dest)?;
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)
+            .map_err(ctx(dest.display()))?;
+        let mut file = self.fs.open_file(entry.record.clone());
+        let mut buf = vec![0_u8; 64 * 1024];
+        loop {
+            let len = match io::Read::read(&mut file, &mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ctx(&what)(e)),
+            };
+            out.write_all(&buf[..len]).map_err(ctx(dest.display()))?;
+        }
+
+        let rr = entry.rock_ridge.as_ref();
+        let mtime = rr.and_then(|rr| rr.times.modify)
+            .unwrap_or_else(|| entry.record.create_date.to_unix_time());
+        let mtime = match u64::try_from(mtime) {
+            Ok(secs) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            Err(_) => SystemTime::UNIX_EPOCH - Duration::from_secs(mtime.unsigned_abs()),
+        };
+        if let Err(e) = out.set_modified(mtime) {
+            self.warn(format!("{}: {}", dest.display(), e));
+        }
+        if let Some(attrs) = rr.and_then(|rr| rr.attrs.as_ref()) {
+            if let Err(e) = set_file_mode(&out, attrs.mode) {
+                self.warn(format!("{}: {}", dest.display(), e));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn create_symlink(&mut self, target: &str, dest: &Path) -> Result<(), Error> {
+        remove_file(dest)?;
+        std::os::unix::fs::symlink(target, dest).map_err(ctx(dest.display()))
+    }
+
+    #[cfg(not(unix))]
+    fn create_symlink(&mut self, _target: &str, dest: &Path) -> Result<(), Error> {
+        self.warn(format!("{}: symbolic links are not supported here", dest.display()));
+        Ok(())
+    }
+}
+
+/// Removes what is at `dest` unless it is a directory, which is an error, so
+/// that it can be created anew without following a link
+fn remove_file(dest: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(dest) {
+        Ok(meta) if meta.is_dir() => Err(Error::Failed(format!("{}: is a directory", dest.display()))),
+        Ok(_) => fs::remove_file(dest).map_err(ctx(dest.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ctx(dest.display())(e)),
+    }
+}
+
+#[cfg(unix)]
+fn set_file_mode(file: &fs::File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(file: &fs::File, mode: u32) -> io::Result<()> {
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    file.set_permissions(permissions)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+pub fn extract(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut flags = vec!["-v"];
+    flags.extend(NAMESPACE_FLAGS);
+    let mut args = Args::parse(args, &flags, &["-C"])?;
+    let image = args.image()?;
+    let dest = PathBuf::from(args.value("-C").unwrap_or("."));
+    let mut fs = open(&image)?;
+    let namespace = namespace(&args, &mut fs, &image)?;
+    let root = fs.root_entry(namespace).map_err(ctx(&image))?;
+
+    let patterns = std::mem::take(&mut args.operands);
+    let mut extractor = Extractor {
+        fs,
+        dest: dest.clone(),
+        namespace,
+        image: image.clone(),
+        matched: vec![false; patterns.len()],
+        patterns,
+        verbose: args.flag("-v"),
+        dir_modes: Vec::new(),
+        errors: 0,
+    };
+    fs::create_dir_all(&dest).map_err(ctx(dest.display()))?;
+    extractor.extract_dir(&root, "", &dest, false, 0)?;
+
+    // children first, so that read only directories are filled
+    for (dir, mode) in extractor.dir_modes.iter().rev() {
+        if let Err(e) = set_mode(dir, *mode) {
+            eprintln!("iso: {}: {}", dir.display(), e);
+            extractor.errors += 1;
+        }
+    }
+    for (pattern, matched) in extractor.patterns.iter().zip(&extractor.matched) {
+        if !matched {
+            eprintln!("iso: {}: {}: no match", image, pattern);
+            extractor.errors += 1;
+        }
+    }
+    match extractor.errors {
+        0 => Ok(()),
+        _ => Err(Error::Failed(format!("{}: not everything could be extracted", image))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Extracts `builder` once the Rock Ridge name `from` is renamed `to`,
+    /// giving two entries of the same directory the same name
+    fn extract_renamed(builder: IsoBuilder, from: u8, to: u8, dir: &Path) -> Result<(), Error> {
+        let mut image = builder.build().unwrap();
+        let nm = [b'N', b'M', 6, 1, 0, from];
+        let pos = image.windows(nm.len()).position(|w| w == nm).unwrap();
+        image[pos + 5] = to;
+        let path = dir.join("image.iso");
+        fs::write(&path, image).unwrap();
+        let out = dir.join("out");
+        let args = ["-C", out.to_str().unwrap(), path.to_str().unwrap()];
+        extract(args.into_iter().map(str::to_owned))
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_extract_through_symlink() {
+        let dir = std::env::temp_dir().join(format!("iso-extract-{}", std::process::id()));
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("f"), b"kept").unwrap();
+
+        // a link to a file outside, then a file of the same name
+        let mut builder = IsoBuilder::new().with_rock_ridge();
+        builder.add_symlink("f", outside.join("f").to_str().unwrap()).unwrap();
+        builder.add_file("g", b"written".to_vec()).unwrap();
+        assert!(extract_renamed(builder, b'g', b'f', &dir).is_ok());
+        assert_eq!(fs::read(outside.join("f")).unwrap(), b"kept");
+        assert_eq!(fs::read(dir.join("out/f")).unwrap(), b"written");
+        assert!(!fs::symlink_metadata(dir.join("out/f")).unwrap().is_symlink());
+
+        // a link to a directory outside, then a directory of the same name
+        let mut builder = IsoBuilder::new().with_rock_ridge();
+        builder.add_symlink("d", outside.to_str().unwrap()).unwrap();
+        builder.add_file("e/x", b"written".to_vec()).unwrap();
+        assert!(extract_renamed(builder, b'e', b'd', &dir).is_err());
+        assert!(!outside.join("x").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
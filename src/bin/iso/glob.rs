@@ -0,0 +1,81 @@
+/// `true` if `path` matches `pattern`, both relative to the root
+///
+/// `*` and `?` match within a component, `**` matches any number of them and
+/// `[...]` a character of a set like `[a-z]` or `[!0-9]`.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.trim_start_matches('/').chars().collect();
+    let path: Vec<char> = path.trim_start_matches('/').chars().collect();
+    match_from(&pattern, &path)
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches `a/b`
+            if let ['/', after @ ..] = rest {
+                if match_from(after, path) {
+                    return true
+                }
+            }
+            (0..=path.len()).any(|i| match_from(rest, &path[i..]))
+        },
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| match_from(rest, &path[i..])),
+        ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && match_from(rest, &path[1..]),
+        ['[', class @ ..] => match (parse_class(class), path) {
+            (Some((matched, len)), [c, path @ ..]) => *c != '/' && matched(*c) && match_from(&class[len..], path),
+            (Some(_), []) => false,
+            // an unclosed `[` is literal
+            (None, [c, path @ ..]) => *c == '[' && match_from(class, path),
+            (None, []) => false,
+        },
+        [c, rest @ ..] => path.first() == Some(c) && match_from(rest, &path[1..]),
+    }
+}
+
+/// The set of the class that follows a `[` and its length with the `]`
+fn parse_class(class: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let (negated, start) = match class.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+    // a `]` right after the `[` is part of the set
+    let end = start + 1 + class.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    let set = &class[start..end];
+    let matched = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= (set[i]..=set[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matched, end + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(matches("boot/*.cfg", "boot/grub.cfg"));
+        assert!(!matches("boot/*.cfg", "boot/grub/grub.cfg"));
+        assert!(matches("boot/**/*.cfg", "boot/grub/x86/grub.cfg"));
+        assert!(matches("boot/**/*.cfg", "boot/grub.cfg"));
+        assert!(matches("/**", "a/b"));
+        assert!(matches("file?.[ch]", "file1.h"));
+        assert!(!matches("file?.[!ch]", "file1.h"));
+        assert!(matches("v[0-9].[]]", "v7.]"));
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("?", "/"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Write};
+
+use iso9660::*;
+
+use crate::{ctx, format_size, format_time, open_device, Args, Error};
+
+fn descriptor_name(ty: VDType) -> &'static str {
+    match ty {
+        VDType::BootRecord => "boot record",
+        VDType::PrimaryVD => "primary",
+        VDType::EVD => "supplementary",
+        VDType::PartDes => "partition",
+        VDType::VDEnd => "terminator",
+    }
+}
+
+pub fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::X86 => "x86",
+        Platform::PPC => "PowerPC",
+        Platform::Mac => "Mac",
+        Platform::UEFI => "UEFI",
+    }
+}
+
+pub fn media_name(media: BootMedia) -> &'static str {
+    match media {
+        BootMedia::NoEmulation => "no emulation",
+        BootMedia::Floppy1_2 => "1.2M floppy",
+        BootMedia::Floppy1_44 => "1.44M floppy",
+        BootMedia::Floppy2_88 => "2.88M floppy",
+        BootMedia::HardDrive => "hard disk",
+    }
+}
+
+fn scheme_name(scheme: PartitionScheme) -> &'static str {
+    match scheme {
+        PartitionScheme::Mbr => "MBR",
+        PartitionScheme::Gpt => "GPT",
+        PartitionScheme::Apm => "APM",
+        PartitionScheme::Sun => "SUN",
+    }
+}
+
+/// What `info --json` prints
+#[cfg(feature = "json")]
+#[derive(serde::Serialize)]
+struct Info<'a> {
+    descriptors: &'a VolumeDescriptorSet,
+    joliet: &'a Option<Joliet>,
+    rock_ridge: bool,
+    boot_entries: &'a [BootEntry],
+    /// Sector at which each session starts
+    sessions: Vec<u32>,
+    partitions: &'a [(Partition, PartitionAlias)],
+}
+
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut args = Args::parse(args, &["--json"], &[])?;
+    let path = args.image()?;
+    if !args.operands.is_empty() {
+        return Err(Error::Usage("info takes a single image".to_owned()))
+    }
+
+    let mut dev = open_device(&path)?;
+    let system_area = SystemArea::read(&mut dev).map_err(ctx(&path))?;
+    let sessions = find_sessions(&mut dev).map_err(ctx(&path))?;
+    let mut fs = IsoFs::open_last_session(dev).map_err(ctx(&path))?;
+    let joliet = fs.joliet().map_err(ctx(&path))?;
+    let rock_ridge = fs.has_rock_ridge().map_err(ctx(&path))?;
+    let boot_entries = fs.boot_entries().map_err(ctx(&path))?;
+    let aliases = system_area.aliases(&mut fs).map_err(ctx(&path))?;
+
+    #[cfg(feature = "json")]
+    if args.flag("--json") {
+        return crate::print_json(&Info {
+            descriptors: fs.descriptors(),
+            joliet: &joliet,
+            rock_ridge,
+            boot_entries: &boot_entries,
+            sessions: sessions.iter().map(|s| s.start).collect(),
+            partitions: &aliases,
+        })
+    }
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "Volume descriptors:")?;
+    for (lba, ty) in &fs.descriptors().descriptors {
+        match &joliet {
+            Some(joliet) if joliet.lba == *lba => {
+                writeln!(out, "  {:>8}  {} (Joliet level {})", lba, descriptor_name(*ty), joliet.level)?
+            },
+            _ => writeln!(out, "  {:>8}  {}", lba, descriptor_name(*ty))?,
+        }
+    }
+
+    let pvd = fs.pvd();
+    let field = |s: Option<&str>| s.map(|s| s.trim_end().to_owned()).unwrap_or_default();
+    let date = |d: &Option<DecDateTime>| d.as_ref()
+        .and_then(|d| d.to_unix_time())
+        .map(format_time)
+        .unwrap_or_else(|| "-".to_owned());
+    writeln!(out, "System identifier:      {}", field(pvd.sys_ident.as_ref().map(|s| s.as_str())))?;
+    writeln!(out, "Volume identifier:      {}", field(pvd.vol_ident.as_ref().map(|s| s.as_str())))?;
+    if let Some(joliet) = &joliet {
+        writeln!(out, "Joliet identifier:      {}", joliet.vol_ident)?;
+    }
+    writeln!(out, "Volume set identifier:  {}", field(pvd.vol_set_ident.as_ref().map(|s| s.as_str())))?;
+    let volume_ident = |ident: &Option<VolumeIdent>| match ident {
+        Some(VolumeIdent::File(name)) => format!("in the file {}", name.as_str()),
+        ident => field(ident.as_ref().map(VolumeIdent::as_str)),
+    };
+    writeln!(out, "Publisher:              {}", volume_ident(&pvd.publisher_ident))?;
+    writeln!(out, "Data preparer:          {}", volume_ident(&pvd.data_prep_ident))?;
+    writeln!(out, "Application:            {}", volume_ident(&pvd.app_ident))?;
+    let files = [
+        ("Copyright file:", &pvd.copyright_file_name),
+        ("Abstract file:", &pvd.abstract_file_name),
+        ("Bibliographic file:", &pvd.bibliographic_file_name),
+    ];
+    for (label, file) in files {
+        if let Some(file) = file {
+            writeln!(out, "{:<24}{}", label, file.as_str().trim_end())?;
+        }
+    }
+    writeln!(out, "Logical block size:     {}", pvd.logical_block_size)?;
+    let volume_len = pvd.vol_space_size as u64 * pvd.logical_block_size as u64;
+    writeln!(out, "Volume size:            {} blocks ({})", pvd.vol_space_size, format_size(volume_len))?;
+    writeln!(out, "Created:                {}", date(&pvd.vol_create_date_time))?;
+    writeln!(out, "Modified:               {}", date(&pvd.vol_mod_date_time))?;
+    writeln!(out, "Expires:                {}", date(&pvd.vol_expiration_date_time))?;
+    writeln!(out, "Effective:              {}", date(&pvd.vol_effective_date_time))?;
+
+    let mut extensions = Vec::new();
+    if rock_ridge {
+        extensions.push("Rock Ridge".to_owned());
+    }
+    if let Some(joliet) = &joliet {
+        extensions.push(format!("Joliet level {}", joliet.level));
+    }
+    if extensions.is_empty() {
+        extensions.push("none".to_owned());
+    }
+    writeln!(out, "Extensions:             {}", extensions.join(", "))?;
+
+    if boot_entries.is_empty() {
+        writeln!(out, "El Torito:              no")?;
+    } else {
+        let entries: Vec<String> = boot_entries.iter()
+            .map(|e| format!("{} {}", platform_name(e.platform), media_name(e.media)))
+            .collect();
+        writeln!(out, "El Torito:              {}", entries.join(", "))?;
+    }
+
+    write!(out, "Sessions:               {}", sessions.len())?;
+    if sessions.len() > 1 {
+        let starts: Vec<String> = sessions.iter().map(|s| s.start.to_string()).collect();
+        write!(out, ", starting at sectors {}", starts.join(", "))?;
+    }
+    writeln!(out)?;
+
+    if aliases.is_empty() {
+        writeln!(out, "Partitions:             none")?;
+    } else {
+        writeln!(out, "Partitions:")?;
+        for (partition, alias) in &aliases {
+            let alias = match alias {
+                PartitionAlias::Volume => "the volume".to_owned(),
+                PartitionAlias::File(file) => file.clone(),
+                PartitionAlias::InsideVolume => "inside the volume".to_owned(),
+                PartitionAlias::OutsideVolume => "past the volume".to_owned(),
+            };
+            writeln!(
+                out,
+                "  {} {:<2} {:<38} start {:>10}  size {:>10}  {}",
+                scheme_name(partition.scheme), partition.number, partition.ty,
+                partition.start, format_size(partition.size), alias,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use std::io::{self, Write};
+
+use iso9660::*;
+
+use crate::{ctx, format_time, namespace, open, Args, Error, Image, NAMESPACE_FLAGS};
+
+/// Deepest directory `-R` and `tree` go into, images can link directories
+/// in a loop
+pub const MAX_DEPTH: usize = 64;
+
+/// What `ls -l` prints of an entry, synthesized when the image has no Rock
+/// Ridge attributes
+pub struct Stat {
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: i64,
+}
+
+impl Stat {
+    pub fn new(entry: &DirEntry) -> Self {
+        let rr = entry.rock_ridge.as_ref();
+        let attrs = rr.and_then(|rr| rr.attrs.as_ref());
+        let mode = match attrs {
+            Some(attrs) => attrs.mode,
+            None if entry.is_dir() => PosixAttrs::S_IFDIR | 0o555,
+            None => PosixAttrs::S_IFREG | 0o444,
+        };
+        Self {
+            mode,
+            nlink: attrs.map(|a| a.nlink).unwrap_or(if entry.is_dir() { 2 } else { 1 }),
+            uid: attrs.map(|a| a.uid).unwrap_or(0),
+            gid: attrs.map(|a| a.gid).unwrap_or(0),
+            size: entry.symlink().map(|s| s.len() as u64).unwrap_or(entry.record.data_size as u64),
+            mtime: rr.and_then(|rr| rr.times.modify)
+                .unwrap_or_else(|| entry.record.create_date.to_unix_time()),
+        }
+    }
+
+    /// Like `drwxr-xr-x`
+    pub fn mode_string(&self) -> String {
+        let ty = match self.mode & PosixAttrs::S_IFMT {
+            PosixAttrs::S_IFDIR => 'd',
+            PosixAttrs::S_IFLNK => 'l',
+            0o020000 => 'c',
+            0o060000 => 'b',
+            0o010000 => 'p',
+            0o140000 => 's',
+            _ => '-',
+        };
+        let mut s = String::from(ty);
+        for shift in [6, 3, 0] {
+            let bits = self.mode >> shift;
+            s.push(if bits & 4 != 0 { 'r' } else { '-' });
+            s.push(if bits & 2 != 0 { 'w' } else { '-' });
+            s.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        s
+    }
+}
+
+/// The `-l` line of `entry` without its name
+fn long_prefix(entry: &DirEntry) -> String {
+    let stat = Stat::new(entry);
+    format!(
+        "{} {:>3} {:>5} {:>5} {:>10} {} [{:>8}]",
+        stat.mode_string(), stat.nlink, stat.uid, stat.gid, stat.size,
+        format_time(stat.mtime), entry.record.extent_location,
+    )
+}
+
+fn display_name(entry: &DirEntry) -> String {
+    match entry.symlink() {
+        Some(target) => format!("{} -> {}", entry.name, target),
+        None => entry.name.clone(),
+    }
+}
+
+fn sorted_entries(fs: &mut Image, dir: &DirEntry, namespace: Namespace, image: &str, path: &str) -> Result<Vec<DirEntry>, Error> {
+    let mut entries = fs.entries(&dir.record, namespace).map_err(ctx(format!("{}: {}", image, path)))?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn lookup(fs: &mut Image, namespace: Namespace, image: &str, path: &str) -> Result<DirEntry, Error> {
+    fs.lookup_entry(path, namespace).map_err(ctx(format!("{}: {}", image, path)))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+struct Lister<'a> {
+    fs: Image,
+    namespace: Namespace,
+    image: &'a str,
+    long: bool,
+    recursive: bool,
+}
+
+impl Lister<'_> {
+    fn print_entry(&self, out: &mut impl Write, entry: &DirEntry) -> io::Result<()> {
+        if self.long {
+            writeln!(out, "{} {}", long_prefix(entry), display_name(entry))
+        } else {
+            writeln!(out, "{}", entry.name)
+        }
+    }
+
+    fn list_dir(&mut self, out: &mut impl Write, dir: &DirEntry, path: &str, header: bool, depth: usize) -> Result<(), Error> {
+        if header {
+            writeln!(out, "{}:", path)?;
+        }
+        let entries = sorted_entries(&mut self.fs, dir, self.namespace, self.image, path)?;
+        for entry in &entries {
+            self.print_entry(out, entry)?;
+        }
+        if self.recursive && depth < MAX_DEPTH {
+            for entry in entries.iter().filter(|e| e.is_dir()) {
+                writeln!(out)?;
+                self.list_dir(out, entry, &join(path, &entry.name), true, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn ls(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut flags = vec!["-l", "-R", "-d"];
+    flags.extend(NAMESPACE_FLAGS);
+    let mut args = Args::parse(args, &flags, &[])?;
+    let image = args.image()?;
+    let mut fs = open(&image)?;
+    let namespace = namespace(&args, &mut fs, &image)?;
+    let mut paths = std::mem::take(&mut args.operands);
+    if paths.is_empty() {
+        paths.push("/".to_owned());
+    }
+
+    let mut lister = Lister {
+        fs,
+        namespace,
+        image: &image,
+        long: args.flag("-l"),
+        recursive: args.flag("-R"),
+    };
+    let mut out = io::stdout().lock();
+    let mut failed = None;
+    let header = paths.len() > 1 || lister.recursive;
+    for (i, path) in paths.iter().enumerate() {
+        let listed = lookup(&mut lister.fs, namespace, &image, path).and_then(|entry| {
+            if entry.is_dir() && !args.flag("-d") {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                lister.list_dir(&mut out, &entry, path, header, 0)
+            } else {
+                let entry = DirEntry { name: path.clone(), ..entry };
+                lister.print_entry(&mut out, &entry).map_err(Error::from)
+            }
+        });
+        // like ls, the other paths are still listed
+        match listed {
+            Err(Error::Failed(msg)) => {
+                eprintln!("iso: {}", msg);
+                failed = Some(Error::Failed(format!("{}: some paths could not be listed", image)));
+            },
+            other => other?,
+        }
+    }
+    failed.map_or(Ok(()), Err)
+}
+
+/// An entry of `tree --json` along with the entries under it
+#[cfg(feature = "json")]
+#[derive(serde::Serialize)]
+struct JsonEntry {
+    #[serde(flatten)]
+    entry: DirEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<JsonEntry>>,
+}
+
+#[cfg(feature = "json")]
+fn json_entry(fs: &mut Image, entry: DirEntry, namespace: Namespace, image: &str, path: &str, depth: usize) -> Result<JsonEntry, Error> {
+    let children = if entry.is_dir() && depth < MAX_DEPTH {
+        let entries = sorted_entries(fs, &entry, namespace, image, path)?;
+        let children = entries.into_iter()
+            .map(|child| {
+                let path = join(path, &child.name);
+                json_entry(fs, child, namespace, image, &path, depth + 1)
+            })
+            .collect::<Result<_, _>>()?;
+        Some(children)
+    } else {
+        None
+    };
+    Ok(JsonEntry {
+        entry,
+        children,
+    })
+}
+
+pub fn tree(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut flags = vec!["-l", "--json"];
+    flags.extend(NAMESPACE_FLAGS);
+    let mut args = Args::parse(args, &flags, &[])?;
+    let image = args.image()?;
+    let path = match args.operands.len() {
+        0 => "/".to_owned(),
+        1 => args.operands.remove(0),
+        _ => return Err(Error::Usage("tree takes a single path".to_owned())),
+    };
+    let mut fs = open(&image)?;
+    let namespace = namespace(&args, &mut fs, &image)?;
+    let root = lookup(&mut fs, namespace, &image, &path)?;
+    let long = args.flag("-l");
+
+    #[cfg(feature = "json")]
+    if args.flag("--json") {
+        let root = DirEntry { name: path.clone(), ..root };
+        return crate::print_json(&json_entry(&mut fs, root, namespace, &image, &path, 0)?)
+    }
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", path)?;
+    let mut counts = (0, 0);
+    tree_dir(&mut fs, &mut out, &root, namespace, &image, &path, "", long, &mut counts, 0)?;
+    writeln!(out, "\n{} directories, {} files", counts.0, counts.1)?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tree_dir(
+    fs: &mut Image,
+    out: &mut impl Write,
+    dir: &DirEntry,
+    namespace: Namespace,
+    image: &str,
+    path: &str,
+    prefix: &str,
+    long: bool,
+    counts: &mut (usize, usize),
+    depth: usize,
+) -> Result<(), Error> {
+    let entries = sorted_entries(fs, dir, namespace, image, path)?;
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        let branch = if last { "└── " } else { "├── " };
+        if long {
+            writeln!(out, "{}{}{} {}", prefix, branch, long_prefix(entry), display_name(entry))?;
+        } else {
+            writeln!(out, "{}{}{}", prefix, branch, display_name(entry))?;
+        }
+        if entry.is_dir() {
+            counts.0 += 1;
+            if depth < MAX_DEPTH {
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                tree_dir(fs, out, entry, namespace, image, &join(path, &entry.name), &prefix, long, counts, depth + 1)?;
+            }
+        } else {
+            counts.1 += 1;
+        }
+    }
+    Ok(())
+}
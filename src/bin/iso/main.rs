@@ -0,0 +1,262 @@
+//! `iso`, a tool to look into ISO 9660 images
+//!
+//! Exits with 0 on success, 1 when the image cannot be read and 2 on a usage
+//! error.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+
+use iso9660::*;
+
+mod boot;
+mod extract;
+mod glob;
+mod info;
+mod ls;
+mod verify;
+
+const USAGE: &str = "\
+Usage: iso <command> [options] <image> [args]
+
+Commands:
+  info [--json] <image>                print the volume descriptors
+  ls [-l] [-R] [-d] <image> [path...]  list directories
+  tree [-l] [--json] <image> [path]    print the tree under a directory
+  cat <image> <path...>                write files to the standard output
+  extract [-v] [-C dir] <image> [glob...]
+                                       extract the files matching the globs
+  boot [--extract dir] <image>         print the boot catalog, and write
+                                       the boot images to dir
+  verify [--strict] [--json] <image>   check the image against the standards,
+                                       fails on errors, or warnings too with
+                                       --strict
+  help                                 print this message
+
+Names are read from Rock Ridge, then Joliet, then the ISO 9660 identifiers.
+ls, tree, cat and extract take --rock-ridge, --joliet or --iso to pick them.
+--json prints every field of the descriptors or the records, or the findings
+of verify, it needs iso to be built with the json feature.
+Images can be ISO files or cue sheets.";
+
+/// Why a command failed
+pub enum Error {
+    /// Printed along with the usage, exits with 2
+    Usage(String),
+    Failed(String),
+    /// Writing to the standard output failed, a closed pipe is not an error
+    Output(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Output(value)
+    }
+}
+
+/// Turns an error about `what` into `Error::Failed`, for `map_err`
+pub fn ctx<E: Display>(what: impl Display) -> impl FnOnce(E) -> Error {
+    move |e| Error::Failed(format!("{}: {}", what, e))
+}
+
+pub type Image = IsoFs<Box<dyn BlockDevice>>;
+
+/// Opens the device of `path`, a cue sheet or an image of 2K sectors
+pub fn open_device(path: &str) -> Result<Box<dyn BlockDevice>, Error> {
+    let is_cue = Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+    if is_cue {
+        let dev = open_cue(path).map_err(ctx(path))?;
+        Ok(Box::new(dev))
+    } else {
+        let file = File::open(path).map_err(ctx(path))?;
+        Ok(Box::new(IoDevice::new(file)))
+    }
+}
+
+/// Opens the last session of the image at `path`, the one a mount sees
+pub fn open(path: &str) -> Result<Image, Error> {
+    IsoFs::open_last_session(open_device(path)?).map_err(ctx(path))
+}
+
+/// The options of a command and its operands
+pub struct Args {
+    flags: BTreeSet<&'static str>,
+    values: Vec<(&'static str, String)>,
+    pub operands: Vec<String>,
+}
+
+impl Args {
+    /// Splits `args` on the flags and the options that take a value that
+    /// the command knows of, short flags can be grouped like `-lR`
+    pub fn parse(
+        args: impl IntoIterator<Item = String>,
+        flags: &[&'static str],
+        options: &[&'static str],
+    ) -> Result<Self, Error> {
+        let mut parsed = Self {
+            flags: BTreeSet::new(),
+            values: Vec::new(),
+            operands: Vec::new(),
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.operands.extend(args);
+                break
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                parsed.operands.push(arg);
+                continue
+            }
+            if let Some(&option) = options.iter().find(|o| **o == arg) {
+                let value = args.next()
+                    .ok_or_else(|| Error::Usage(format!("option {} needs a value", arg)))?;
+                parsed.values.push((option, value));
+                continue
+            }
+            if let Some(&flag) = flags.iter().find(|f| **f == arg) {
+                parsed.flags.insert(flag);
+                continue
+            }
+            // grouped short flags
+            let grouped: Option<Vec<&'static str>> = arg.strip_prefix('-')
+                .filter(|s| !s.starts_with('-'))
+                .map(|s| s.chars().map(|c| flags.iter().copied().find(|f| *f == format!("-{}", c))).collect())
+                .unwrap_or(None);
+            match grouped {
+                Some(grouped) => parsed.flags.extend(grouped),
+                None => return Err(Error::Usage(format!("unknown option {}", arg))),
+            }
+        }
+        #[cfg(not(feature = "json"))]
+        if parsed.flag("--json") {
+            return Err(Error::Usage("--json needs iso to be built with the json feature".to_owned()))
+        }
+        Ok(parsed)
+    }
+
+    pub fn flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// The last value of `option`
+    pub fn value(&self, option: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(o, _)| *o == option).map(|(_, v)| v.as_str())
+    }
+
+    /// The first operand, the path of the image
+    pub fn image(&mut self) -> Result<String, Error> {
+        if self.operands.is_empty() {
+            return Err(Error::Usage("missing image".to_owned()))
+        }
+        Ok(self.operands.remove(0))
+    }
+}
+
+/// The flags that pick a namespace
+pub const NAMESPACE_FLAGS: [&str; 3] = ["--rock-ridge", "--joliet", "--iso"];
+
+/// The namespace asked for in `args`, the richest one of `fs` otherwise
+pub fn namespace(args: &Args, fs: &mut Image, path: &str) -> Result<Namespace, Error> {
+    let namespace = if args.flag("--iso") {
+        Namespace::Iso
+    } else if args.flag("--joliet") {
+        Namespace::Joliet
+    } else if args.flag("--rock-ridge") {
+        Namespace::RockRidge
+    } else {
+        return fs.default_namespace().map_err(ctx(path))
+    };
+    let available = match namespace {
+        Namespace::Iso => true,
+        Namespace::Joliet => fs.joliet().map_err(ctx(path))?.is_some(),
+        Namespace::RockRidge => fs.has_rock_ridge().map_err(ctx(path))?,
+    };
+    if !available {
+        let name = match namespace {
+            Namespace::Iso => "ISO 9660",
+            Namespace::Joliet => "Joliet",
+            Namespace::RockRidge => "Rock Ridge",
+        };
+        return Err(Error::Failed(format!("{}: no {} names", path, name)))
+    }
+    Ok(namespace)
+}
+
+/// Prints `value` as JSON to the standard output
+#[cfg(feature = "json")]
+pub fn print_json<T: serde::Serialize>(value: &T) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, value).map_err(io::Error::from)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// `secs` since the epoch as `2024-01-31 12:00:00`, in UTC
+pub fn format_time(secs: i64) -> String {
+    let date = DirectoryRecordDate::from_unix_time(secs);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        1900 + date.years_since_1900 as u32, date.month, date.day, date.hour, date.minute, date.second,
+    )
+}
+
+/// `len` in the largest unit that keeps it above 1
+pub fn format_size(len: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if len < 1024 {
+        return format!("{} B", len)
+    }
+    let mut size = len as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let Some(command) = args.next() else {
+        return Err(Error::Usage("missing command".to_owned()))
+    };
+    match command.as_str() {
+        "info" => info::run(args),
+        "ls" => ls::ls(args),
+        "tree" => ls::tree(args),
+        "cat" => extract::cat(args),
+        "extract" => extract::extract(args),
+        "boot" => boot::run(args),
+        "verify" => verify::run(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => Err(Error::Usage(format!("unknown command {}", command))),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(msg)) => {
+            eprintln!("iso: {}\n\n{}", msg, USAGE);
+            ExitCode::from(2)
+        },
+        Err(Error::Failed(msg)) => {
+            eprintln!("iso: {}", msg);
+            ExitCode::FAILURE
+        },
+        Err(Error::Output(e)) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(Error::Output(e)) => {
+            eprintln!("iso: write error: {}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,41 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use iso9660::*;
+
+use crate::{ctx, open_device, Args, Error};
+
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut args = Args::parse(args, &["--strict", "--json"], &[])?;
+    let image = args.image()?;
+    if !args.operands.is_empty() {
+        return Err(Error::Usage("verify takes a single image".to_owned()))
+    }
+    // the size of a cue sheet is not the one of its image
+    let is_cue = Path::new(&image).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+    let image_len = if is_cue {
+        None
+    } else {
+        Some(std::fs::metadata(&image).map_err(ctx(&image))?.len())
+    };
+    let verification = verify(open_device(&image)?, image_len).map_err(ctx(&image))?;
+    let errors = verification.count(Severity::Error);
+    let warnings = verification.count(Severity::Warning);
+
+    #[cfg(feature = "json")]
+    if args.flag("--json") {
+        crate::print_json(&verification)?;
+    }
+    if !args.flag("--json") {
+        let mut out = io::stdout().lock();
+        for finding in &verification.findings {
+            writeln!(out, "{}", finding)?;
+        }
+        writeln!(out, "{} errors, {} warnings", errors, warnings)?;
+    }
+
+    if errors > 0 || (args.flag("--strict") && warnings > 0) {
+        return Err(Error::Failed(format!("{}: the image does not verify", image)))
+    }
+    Ok(())
+}
@@ -0,0 +1,677 @@
+//! `mkiso`, builds an image from directories of the host with the options of
+//! mkisofs
+//!
+//! Exits with 0 on success, 1 when the image cannot be built and 2 on a usage
+//! error.
+
+use std::env;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::UNIX_EPOCH;
+
+use iso9660::*;
+
+#[path = "../iso/glob.rs"]
+mod glob;
+
+const USAGE: &str = "\
+Usage: mkiso [options] -o <file> <path...>
+       mkiso [options] -o <file> -manifest <manifest> [path...]
+
+Options:
+  -o FILE              write the image to FILE
+  -manifest FILE       start from the image described by the TOML manifest
+                       FILE, the other options and paths are added to it
+  -V ID                volume identifier
+  -A ID                application identifier
+  -p ID                data preparer identifier
+  -publisher ID        publisher identifier
+  -sysid ID            system identifier
+  -volset ID           volume set identifier
+  -J                   add Joliet names
+  -R                   add Rock Ridge attributes as they are on the host
+  -r                   add Rock Ridge attributes readable by everyone and
+                       owned by root
+  -f, -follow-links    follow symbolic links instead of recording them
+  -graft-points        take paths like /iso/path=host/path
+  -exclude GLOB, -x    leave out the files whose path or name matches
+  -quiet               do not print warnings
+
+El Torito:
+  -b FILE              BIOS boot image, a path in the image
+  -c FILE              record the boot catalog as FILE
+  -no-emul-boot        the image is not a floppy or hard disk image
+  -hard-disk-boot      the image is a hard disk image
+  -boot-load-size N    512 bytes sectors the BIOS loads
+  -boot-load-seg SEG   segment the BIOS loads the image at
+  -boot-info-table     patch a boot info table in the image
+  --grub2-boot-info    patch the address of the rest of GRUB2's core.img
+  -eltorito-alt-boot   start another boot entry
+  -e FILE              UEFI boot image, a FAT image in the image
+  -isohybrid-mbr FILE  boot code of an MBR to boot the image from a disk
+
+SOURCE_DATE_EPOCH sets the dates of the image.";
+
+/// Directories walked before giving up, symbolic links can make loops
+const MAX_DEPTH: usize = 64;
+
+enum Error {
+    /// Printed along with the usage, exits with 2
+    Usage(String),
+    Failed(String),
+}
+
+fn usage(msg: impl Into<String>) -> Error {
+    Error::Usage(msg.into())
+}
+
+/// Turns an error about `what` into `Error::Failed`, for `map_err`
+fn ctx<E: std::fmt::Display>(what: impl std::fmt::Display) -> impl FnOnce(E) -> Error {
+    move |e| Error::Failed(format!("{}: {}", what, e))
+}
+
+/// The errors of the builder name the path they are about
+fn build_err(e: BuildErr) -> Error {
+    Error::Failed(e.to_string())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RockRidgeMode {
+    /// `-R`, the attributes of the host
+    Host,
+    /// `-r`, readable by everyone and owned by root
+    Rationalized,
+}
+
+/// The options of an entry of the boot catalog, from `-b` or `-e` to the
+/// next `-eltorito-alt-boot`
+#[derive(Default)]
+struct BootOptions {
+    path: Option<String>,
+    uefi: bool,
+    no_emulation: bool,
+    hard_disk: bool,
+    load_size: Option<u16>,
+    load_segment: Option<u16>,
+    boot_info_table: bool,
+    grub2_boot_info: bool,
+}
+
+impl BootOptions {
+    fn is_empty(&self) -> bool {
+        self.path.is_none() && !self.no_emulation && !self.hard_disk && self.load_size.is_none()
+            && self.load_segment.is_none() && !self.boot_info_table && !self.grub2_boot_info
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    output: Option<String>,
+    vol_ident: Option<String>,
+    sys_ident: Option<String>,
+    vol_set_ident: Option<String>,
+    publisher_ident: Option<String>,
+    data_prep_ident: Option<String>,
+    app_ident: Option<String>,
+    joliet: bool,
+    rock_ridge: Option<RockRidgeMode>,
+    follow_links: bool,
+    graft_points: bool,
+    excludes: Vec<String>,
+    quiet: bool,
+    catalog: Option<String>,
+    boot: Vec<BootOptions>,
+    isohybrid_mbr: Option<String>,
+    manifest: Option<String>,
+    sources: Vec<String>,
+}
+
+/// `0x7c0` or `1984`
+fn parse_number<T: TryFrom<u32>>(option: &str, value: &str) -> Result<T, Error> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.ok()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| usage(format!("invalid value {:?} for {}", value, option)))
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut options = Self::default();
+        let mut boot = BootOptions::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| usage(format!("option {} needs a value", arg)));
+            match arg.as_str() {
+                "-o" => options.output = Some(value()?),
+                "-V" => options.vol_ident = Some(value()?),
+                "-sysid" => options.sys_ident = Some(value()?),
+                "-volset" => options.vol_set_ident = Some(value()?),
+                "-publisher" => options.publisher_ident = Some(value()?),
+                "-p" | "-preparer" => options.data_prep_ident = Some(value()?),
+                "-A" | "-appid" => options.app_ident = Some(value()?),
+                "-J" | "-joliet" => options.joliet = true,
+                "-R" | "-rock" => options.rock_ridge = Some(RockRidgeMode::Host),
+                "-r" | "-rational-rock" => options.rock_ridge = Some(RockRidgeMode::Rationalized),
+                "-f" | "-follow-links" => options.follow_links = true,
+                "-graft-points" => options.graft_points = true,
+                "-x" | "-exclude" | "-m" => options.excludes.push(value()?),
+                "-quiet" => options.quiet = true,
+                "-input-charset" => {
+                    let charset = value()?;
+                    if !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("utf8") {
+                        return Err(usage(format!("unsupported input charset {}, names are read as UTF-8", charset)))
+                    }
+                },
+                "-c" | "-eltorito-catalog" => options.catalog = Some(value()?),
+                "-b" | "-eltorito-boot" => {
+                    if boot.path.is_some() {
+                        return Err(usage("-b or -e given twice without -eltorito-alt-boot"))
+                    }
+                    boot.path = Some(value()?);
+                },
+                "-e" | "-efi-boot" => {
+                    if boot.path.is_some() {
+                        return Err(usage("-b or -e given twice without -eltorito-alt-boot"))
+                    }
+                    boot.path = Some(value()?);
+                    boot.uefi = true;
+                    boot.no_emulation = true;
+                },
+                "-no-emul-boot" => boot.no_emulation = true,
+                "-hard-disk-boot" => boot.hard_disk = true,
+                "-boot-load-size" => boot.load_size = Some(parse_number(&arg, &value()?)?),
+                "-boot-load-seg" => boot.load_segment = Some(parse_number(&arg, &value()?)?),
+                "-boot-info-table" => boot.boot_info_table = true,
+                "--grub2-boot-info" => boot.grub2_boot_info = true,
+                "-eltorito-alt-boot" => {
+                    if boot.path.is_none() {
+                        return Err(usage("-eltorito-alt-boot needs a -b or -e before it"))
+                    }
+                    options.boot.push(std::mem::take(&mut boot));
+                },
+                "-isohybrid-mbr" => options.isohybrid_mbr = Some(value()?),
+                "-manifest" => options.manifest = Some(value()?),
+                "-h" | "-help" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0)
+                },
+                "--" => options.sources.extend(args.by_ref()),
+                _ if arg.starts_with('-') && arg.len() > 1 => return Err(usage(format!("unknown option {}", arg))),
+                _ => options.sources.push(arg),
+            }
+        }
+        match boot.path {
+            Some(_) => options.boot.push(boot),
+            None if boot.is_empty() => (),
+            None => return Err(usage("boot options need a -b or -e")),
+        }
+        if options.output.is_none() {
+            return Err(usage("missing -o"))
+        }
+        if options.sources.is_empty() && options.manifest.is_none() {
+            return Err(usage("missing paths"))
+        }
+        // the manifest can have boot images of its own
+        if options.catalog.is_some() && options.boot.is_empty() && options.manifest.is_none() {
+            return Err(usage("-c needs a boot image"))
+        }
+        if options.isohybrid_mbr.is_some() && options.boot.is_empty() && options.manifest.is_none() {
+            return Err(usage("-isohybrid-mbr needs a boot image"))
+        }
+        Ok(options)
+    }
+}
+
+/// Splits a graft point on its first `=` that is not escaped as `\=`
+fn split_graft_point(arg: &str) -> Option<(String, String)> {
+    let mut iso_path = String::new();
+    let mut chars = arg.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) => iso_path.push(c),
+                None => iso_path.push('\\'),
+            },
+            '=' => return Some((iso_path, arg[i + 1..].to_owned())),
+            c => iso_path.push(c),
+        }
+    }
+    None
+}
+
+/// `name` in the directory `dir` of the image, `""` for the root
+fn join(dir: &str, name: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Seconds since the epoch, before it for files that old
+fn mtime(meta: &Metadata) -> Option<i64> {
+    let modified = meta.modified().ok()?;
+    match modified.duration_since(UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_secs()).ok(),
+        Err(e) => i64::try_from(e.duration().as_secs()).ok().map(|s| -s),
+    }
+}
+
+#[cfg(unix)]
+fn host_attrs(meta: &Metadata) -> FileAttrs {
+    use std::os::unix::fs::MetadataExt;
+    FileAttrs::new(meta.mode() & 0o7777).with_owner(meta.uid(), meta.gid())
+}
+
+#[cfg(not(unix))]
+fn host_attrs(meta: &Metadata) -> FileAttrs {
+    let mode = match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, true) => 0o555,
+        (true, false) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    };
+    FileAttrs::new(mode)
+}
+
+/// The canonical path of the output, which can be in a directory that is
+/// walked
+fn output_path(output: &str) -> Option<PathBuf> {
+    let output = Path::new(output);
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(fs::canonicalize(dir).ok()?.join(output.file_name()?))
+}
+
+struct Walker<'a> {
+    options: &'a Options,
+    builder: IsoBuilder,
+    /// See `output_path`
+    output: Option<PathBuf>,
+}
+
+impl Walker<'_> {
+    fn warn(&self, msg: impl std::fmt::Display) {
+        if !self.options.quiet {
+            eprintln!("mkiso: {}", msg);
+        }
+    }
+
+    fn is_excluded(&self, host: &Path) -> bool {
+        let path = host.to_string_lossy();
+        let path = path.trim_start_matches("./");
+        let name = host.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        self.options.excludes.iter().any(|pattern| {
+            let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+            glob::matches(pattern, path) || glob::matches(pattern, &name)
+        })
+    }
+
+    /// Whether `host` is the image being written, left over from an earlier
+    /// run or not
+    fn is_output(&self, host: &Path) -> bool {
+        let Some(output) = &self.output else {
+            return false
+        };
+        host.file_name() == output.file_name() && fs::canonicalize(host).is_ok_and(|host| host == *output)
+    }
+
+    fn set_attrs(&mut self, iso_path: &str, meta: &Metadata) -> Result<(), Error> {
+        let Some(mode) = self.options.rock_ridge else {
+            return Ok(())
+        };
+        let mut attrs = host_attrs(meta);
+        if mode == RockRidgeMode::Rationalized {
+            // like mkisofs -r, executable by everyone if by anyone
+            attrs = FileAttrs::new(match meta.file_type() {
+                t if t.is_symlink() => 0o777,
+                t if t.is_dir() => 0o555,
+                _ if attrs.mode & 0o111 != 0 => 0o555,
+                _ => 0o444,
+            });
+        }
+        if let Some(secs) = mtime(meta) {
+            attrs = attrs.with_mtime(secs);
+        }
+        self.builder.set_attrs(iso_path, attrs).map_err(build_err)
+    }
+
+    /// Adds `host` as `iso_path`, or in it when it ends with a `/`, the
+    /// content of directories goes into `iso_path`
+    fn add_source(&mut self, iso_path: &str, host: &Path) -> Result<(), Error> {
+        // operands are followed, as with mkisofs
+        let meta = fs::metadata(host).map_err(ctx(host.display()))?;
+        if meta.is_dir() {
+            let dir = iso_path.trim_matches('/');
+            if !dir.is_empty() {
+                self.builder.add_dir(dir).map_err(build_err)?;
+            }
+            self.set_attrs(dir, &meta)?;
+            return self.add_dir_content(dir, host, 0)
+        }
+        let iso_path = if iso_path.is_empty() || iso_path.ends_with('/') {
+            let name = host.file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| Error::Failed(format!("{}: not a UTF-8 file name", host.display())))?;
+            join(iso_path, name)
+        } else {
+            iso_path.trim_matches('/').to_owned()
+        };
+        if self.is_output(host) {
+            return Err(usage(format!("{}: the output can not be one of the paths", host.display())))
+        }
+        self.add_file(&iso_path, host, &meta)
+    }
+
+    fn add_file(&mut self, iso_path: &str, host: &Path, meta: &Metadata) -> Result<(), Error> {
+        self.builder.add_host_file(iso_path, host).map_err(build_err)?;
+        self.set_attrs(iso_path, meta)
+    }
+
+    fn add_dir_content(&mut self, iso_dir: &str, host: &Path, depth: usize) -> Result<(), Error> {
+        if depth >= MAX_DEPTH {
+            self.warn(format!("{}: too deep, skipped", host.display()));
+            return Ok(())
+        }
+        let mut entries: Vec<_> = fs::read_dir(host)
+            .and_then(|dir| dir.collect::<io::Result<Vec<_>>>())
+            .map_err(ctx(host.display()))?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let host = entry.path();
+            if self.is_excluded(&host) || self.is_output(&host) {
+                continue
+            }
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                self.warn(format!("{}: not a UTF-8 file name, skipped", host.display()));
+                continue
+            };
+            let iso_path = join(iso_dir, &name);
+            let mut meta = fs::symlink_metadata(&host).map_err(ctx(host.display()))?;
+
+            if meta.file_type().is_symlink() {
+                if self.options.follow_links {
+                    meta = match fs::metadata(&host) {
+                        Ok(meta) => meta,
+                        Err(e) => {
+                            self.warn(format!("{}: {}, skipped", host.display(), e));
+                            continue
+                        },
+                    };
+                } else if self.options.rock_ridge.is_some() {
+                    let target = fs::read_link(&host).map_err(ctx(host.display()))?;
+                    let Some(target) = target.to_str() else {
+                        self.warn(format!("{}: not a UTF-8 link target, skipped", host.display()));
+                        continue
+                    };
+                    self.builder.add_symlink(&iso_path, target).map_err(build_err)?;
+                    self.set_attrs(&iso_path, &meta)?;
+                    continue
+                } else {
+                    self.warn(format!("{}: symbolic link skipped, use -R or -f", host.display()));
+                    continue
+                }
+            }
+
+            if meta.is_dir() {
+                self.builder.add_dir(&iso_path).map_err(build_err)?;
+                self.set_attrs(&iso_path, &meta)?;
+                self.add_dir_content(&iso_path, &host, depth + 1)?;
+            } else if meta.is_file() {
+                self.add_file(&iso_path, &host, &meta)?;
+            } else {
+                self.warn(format!("{}: not a regular file, skipped", host.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The boot image of `boot`, a file of the tree wherever it comes from,
+    /// the host or the manifest
+    fn boot_image(&self, boot: &BootOptions) -> Result<BootImage, Error> {
+        let path = boot.path.as_deref().unwrap_or_default().trim_matches('/');
+        let size = self.builder.file_size(path).map_err(build_err)?;
+        let media = if boot.no_emulation {
+            BootMedia::NoEmulation
+        } else if boot.hard_disk {
+            BootMedia::HardDrive
+        } else {
+            [BootMedia::Floppy1_2, BootMedia::Floppy1_44, BootMedia::Floppy2_88].into_iter()
+                .find(|m| m.floppy_size() == Some(size))
+                .ok_or_else(|| Error::Failed(format!(
+                    "{}: {} bytes is not the size of a floppy, use -no-emul-boot or -hard-disk-boot", path, size,
+                )))?
+        };
+        let mut image = BootImage::emulation(path, media);
+        if boot.uefi {
+            image = image.with_platform(Platform::UEFI);
+        }
+        if let Some(count) = boot.load_size {
+            image = image.with_sector_count(count);
+        }
+        if let Some(segment) = boot.load_segment {
+            image = image.with_load_segment(segment);
+        }
+        if boot.boot_info_table {
+            image = image.with_boot_info_table();
+        }
+        if boot.grub2_boot_info {
+            image = image.with_grub2_boot_info();
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(feature = "manifest")]
+fn from_manifest(path: &str) -> Result<IsoBuilder, Error> {
+    let manifest = Manifest::open(path).map_err(|e| match e {
+        ManifestErr::Io(..) => Error::Failed(e.to_string()),
+        e => ctx(path)(e),
+    })?;
+    IsoBuilder::from_manifest(&manifest).map_err(ctx(path))
+}
+
+#[cfg(not(feature = "manifest"))]
+fn from_manifest(_path: &str) -> Result<IsoBuilder, Error> {
+    Err(usage("-manifest needs mkiso to be built with the manifest feature"))
+}
+
+fn builder(options: &Options) -> Result<IsoBuilder, Error> {
+    let mut builder = match &options.manifest {
+        Some(path) => from_manifest(path)?,
+        None => IsoBuilder::new(),
+    };
+    type With = fn(IsoBuilder, &str) -> IsoBuilder;
+    let fields: [(&Option<String>, With); 6] = [
+        (&options.vol_ident, IsoBuilder::with_vol_ident),
+        (&options.sys_ident, IsoBuilder::with_sys_ident),
+        (&options.vol_set_ident, IsoBuilder::with_vol_set_ident),
+        (&options.publisher_ident, IsoBuilder::with_publisher_ident),
+        (&options.data_prep_ident, IsoBuilder::with_data_prep_ident),
+        (&options.app_ident, IsoBuilder::with_app_ident),
+    ];
+    for (value, with) in fields {
+        if let Some(value) = value {
+            builder = with(builder, value);
+        }
+    }
+    if options.joliet {
+        builder = builder.with_joliet();
+    }
+    if options.rock_ridge.is_some() {
+        builder = builder.with_rock_ridge();
+    }
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        let secs = epoch.trim().parse()
+            .map_err(|_| Error::Failed(format!("SOURCE_DATE_EPOCH: invalid value {:?}", epoch)))?;
+        builder = builder.with_creation_time(secs);
+    }
+    if let Some(path) = &options.isohybrid_mbr {
+        let boot_code = fs::read(path).map_err(ctx(path))?;
+        builder = builder.with_hybrid_mbr(HybridMbr::new(boot_code));
+    }
+    Ok(builder)
+}
+
+fn run(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let options = Options::parse(args)?;
+    let mut walker = Walker {
+        options: &options,
+        builder: builder(&options)?,
+        output: output_path(options.output.as_deref().unwrap_or_default()),
+    };
+
+    for source in &options.sources {
+        let graft = options.graft_points.then(|| split_graft_point(source)).flatten();
+        let (iso_path, host) = match graft {
+            Some((iso_path, host)) => (iso_path, PathBuf::from(host)),
+            None => (String::new(), PathBuf::from(source)),
+        };
+        walker.add_source(&iso_path, &host)?;
+    }
+
+    if let Some(catalog) = &options.catalog {
+        walker.builder.add_boot_catalog(catalog).map_err(build_err)?;
+    }
+    for boot in &options.boot {
+        let image = walker.boot_image(boot)?;
+        walker.builder.add_boot_image(image);
+    }
+
+    let output = options.output.as_deref().unwrap_or_default();
+    let file = File::create(output).map_err(ctx(output))?;
+    let mut out = BufWriter::new(file);
+    let written = walker.builder.write(&mut out)
+        .map_err(|e| match e {
+            BuildErr::Io(e) => ctx(output)(e),
+            e => build_err(e),
+        })
+        .and_then(|written| out.flush().map(|()| written).map_err(ctx(output)));
+    match written {
+        Ok(written) => {
+            if !options.quiet {
+                eprintln!("mkiso: wrote {} sectors to {}", written / SECTOR_SIZE as u64, output);
+            }
+            Ok(())
+        },
+        Err(e) => {
+            // do not leave a truncated image behind
+            drop(out);
+            let _ = fs::remove_file(output);
+            Err(e)
+        },
+    }
+}
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(msg)) => {
+            eprintln!("mkiso: {}\n\n{}", msg, USAGE);
+            ExitCode::from(2)
+        },
+        Err(Error::Failed(msg)) => {
+            eprintln!("mkiso: {}", msg);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::parse(args(&[
+            "-o", "out.iso", "-V", "LIVE", "-J", "-r", "-c", "boot.cat",
+            "-b", "isolinux.bin", "-no-emul-boot", "-boot-load-size", "4", "-boot-info-table",
+            "-eltorito-alt-boot", "-e", "efi.img", "dir", "--", "-dash",
+        ])).ok().unwrap();
+        assert_eq!(options.output.as_deref(), Some("out.iso"));
+        assert!(options.joliet && options.rock_ridge == Some(RockRidgeMode::Rationalized));
+        assert_eq!(options.sources, ["dir", "-dash"]);
+        assert_eq!(options.boot.len(), 2);
+        assert_eq!(options.boot[0].load_size, Some(4));
+        assert!(options.boot[0].boot_info_table && !options.boot[0].uefi);
+        assert!(options.boot[1].uefi && options.boot[1].no_emulation);
+        assert_eq!(parse_number::<u16>("-boot-load-seg", "0x7c0").ok(), Some(0x7c0));
+
+        let invalid: [&[&str]; 8] = [
+            &["dir"],
+            &["-o", "out.iso"],
+            &["-o"],
+            &["-o", "out.iso", "-unknown", "dir"],
+            &["-o", "out.iso", "-b", "a.bin", "-b", "b.bin", "dir"],
+            &["-o", "out.iso", "-eltorito-alt-boot", "dir"],
+            &["-o", "out.iso", "-boot-load-size", "70000", "-b", "a.bin", "dir"],
+            &["-o", "out.iso", "-c", "boot.cat", "dir"],
+        ];
+        for invalid in invalid {
+            assert!(matches!(Options::parse(args(invalid)), Err(Error::Usage(_))), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_output_in_source() {
+        let dir = std::env::temp_dir().join(format!("mkiso-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), b"hello").unwrap();
+        // left over from an earlier run
+        fs::write(dir.join("out.iso"), vec![0; 4096]).unwrap();
+
+        let output = dir.join("out.iso");
+        let output = output.to_str().unwrap();
+        assert!(run(args(&["-quiet", "-o", output, dir.to_str().unwrap()])).is_ok());
+        let mut fs = IsoFs::open(MemDevice::new(fs::read(output).unwrap())).unwrap();
+        let record = fs.lookup("a.txt").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"hello");
+        assert!(matches!(fs.lookup("out.iso"), Err(VDErr::NotFound)));
+
+        assert!(matches!(run(args(&["-quiet", "-o", output, output])), Err(Error::Usage(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "manifest")]
+    fn test_boot_image_from_manifest() {
+        let dir = std::env::temp_dir().join(format!("mkiso-manifest-{}", std::process::id()));
+        fs::create_dir_all(dir.join("extra")).unwrap();
+        fs::write(dir.join("stage1.bin"), vec![0xFA; 2048]).unwrap();
+        fs::write(dir.join("extra/readme.txt"), b"hello").unwrap();
+        let manifest = "[[files]]\npath = \"boot/stage1.bin\"\nsource = \"stage1.bin\"\n";
+        fs::write(dir.join("iso.toml"), manifest).unwrap();
+
+        // the boot image is only in the manifest, not in the walked paths
+        let output = dir.join("out.iso");
+        assert!(run(args(&[
+            "-quiet", "-o", output.to_str().unwrap(), "-manifest", dir.join("iso.toml").to_str().unwrap(),
+            "-b", "boot/stage1.bin", "-no-emul-boot", "-boot-load-size", "4", dir.join("extra").to_str().unwrap(),
+        ])).is_ok());
+        let mut fs = IsoFs::open(MemDevice::new(fs::read(&output).unwrap())).unwrap();
+        assert!(fs.lookup("readme.txt").is_ok());
+        let entries = fs.boot_entries().unwrap();
+        assert_eq!(entries[0].file.as_ref().unwrap().0, "/BOOT/STAGE1.BIN");
+        assert_eq!(entries[0].sector_count, 4);
+
+        // a floppy image picks its media from its size
+        assert!(matches!(run(args(&[
+            "-quiet", "-o", output.to_str().unwrap(), "-manifest", dir.join("iso.toml").to_str().unwrap(),
+            "-b", "boot/stage1.bin",
+        ])), Err(Error::Failed(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
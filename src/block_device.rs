@@ -0,0 +1,290 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{FORM2_DATA_SIZE, SECTOR_SIZE};
+
+/// A random access device that is read in whole blocks
+///
+/// This is the only thing the filesystem reader needs from its backend, it can
+/// be implemented over a file, a network block device or a BIOS disk service.
+pub trait BlockDevice {
+    /// Size in bytes of a single block
+    fn block_size(&self) -> usize;
+
+    /// Reads `buf.len() / self.block_size()` blocks starting at block `lba`
+    ///
+    /// `buf.len()` must be a multiple of `self.block_size()`
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Reads the 2324 bytes that follow the subheader of the mode 2 sector
+    /// `lba`, counted in 2K sectors
+    ///
+    /// Only devices that see the raw sectors of a CD can do this, see
+    /// `RawSectorDevice`.
+    fn read_form2_sector(&mut self, lba: u64, buf: &mut [u8; FORM2_DATA_SIZE]) -> io::Result<()> {
+        let _ = (lba, buf);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the device does not give access to form 2 sectors",
+        ))
+    }
+}
+
+/// A `BlockDevice` that can also be written to, see `IsoFs::replace_in_place`
+pub trait BlockDeviceMut: BlockDevice {
+    /// Writes `buf.len() / self.block_size()` blocks starting at block `lba`
+    ///
+    /// `buf.len()` must be a multiple of `self.block_size()`
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()>;
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_blocks(lba, buf)
+    }
+
+    fn read_form2_sector(&mut self, lba: u64, buf: &mut [u8; FORM2_DATA_SIZE]) -> io::Result<()> {
+        (**self).read_form2_sector(lba, buf)
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_blocks(lba, buf)
+    }
+
+    fn read_form2_sector(&mut self, lba: u64, buf: &mut [u8; FORM2_DATA_SIZE]) -> io::Result<()> {
+        (**self).read_form2_sector(lba, buf)
+    }
+}
+
+impl<D: BlockDeviceMut + ?Sized> BlockDeviceMut for &mut D {
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        (**self).write_blocks(lba, buf)
+    }
+}
+
+impl<D: BlockDeviceMut + ?Sized> BlockDeviceMut for Box<D> {
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        (**self).write_blocks(lba, buf)
+    }
+}
+
+fn check_buf_len(buf: &[u8], block_size: usize) -> io::Result<()> {
+    check_buf_len_of(buf.len(), block_size)
+}
+
+fn check_buf_len_of(len: usize, block_size: usize) -> io::Result<()> {
+    if !len.is_multiple_of(block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "buffer length is not a multiple of the block size",
+        ))
+    }
+    Ok(())
+}
+
+/// Adapter for anything that is `Read + Seek`, typically a `File`
+#[derive(Debug)]
+pub struct IoDevice<R> {
+    inner: R,
+    block_size: usize,
+    offset: u64,
+}
+
+impl<R: Read + Seek> IoDevice<R> {
+    /// Uses 2K blocks, the size of a CD-ROM sector
+    pub fn new(inner: R) -> Self {
+        Self::with_block_size(inner, SECTOR_SIZE)
+    }
+
+    pub fn with_block_size(inner: R, block_size: usize) -> Self {
+        assert!(block_size > 0, "`block_size` must not be 0");
+        Self {
+            inner,
+            block_size,
+            offset: 0,
+        }
+    }
+
+    /// Skips the first `offset` bytes of `inner`, block 0 starts right after
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> BlockDevice for IoDevice<R> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        check_buf_len(buf, self.block_size)?;
+        self.inner.seek(SeekFrom::Start(self.offset + lba * self.block_size as u64))?;
+        self.inner.read_exact(buf)
+    }
+}
+
+impl<R: Read + Write + Seek> BlockDeviceMut for IoDevice<R> {
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        check_buf_len(buf, self.block_size)?;
+        self.inner.seek(SeekFrom::Start(self.offset + lba * self.block_size as u64))?;
+        self.inner.write_all(buf)
+    }
+}
+
+/// Adapter for an image that is already in memory
+///
+/// `T` can be a `Vec<u8>`, a `&[u8]` or a memory mapped file, anything that
+/// derefs to bytes.
+#[derive(Debug)]
+pub struct MemDevice<T> {
+    inner: T,
+    block_size: usize,
+}
+
+impl<T: AsRef<[u8]>> MemDevice<T> {
+    /// Uses 2K blocks, the size of a CD-ROM sector
+    pub fn new(inner: T) -> Self {
+        Self::with_block_size(inner, SECTOR_SIZE)
+    }
+
+    pub fn with_block_size(inner: T, block_size: usize) -> Self {
+        assert!(block_size > 0, "`block_size` must not be 0");
+        Self {
+            inner,
+            block_size,
+        }
+    }
+
+    /// The whole image
+    pub fn bytes(&self) -> &[u8] {
+        self.inner.as_ref()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+fn mem_blocks(bytes_len: usize, block_size: usize, lba: u64, len: usize) -> io::Result<core::ops::Range<usize>> {
+    check_buf_len_of(len, block_size)?;
+    lba.checked_mul(block_size as u64)
+        .and_then(|v| usize::try_from(v).ok())
+        .and_then(|start| Some(start..start.checked_add(len)?))
+        .filter(|range| range.end <= bytes_len)
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "access past the end of the device",
+        ))
+}
+
+fn read_mem_blocks(bytes: &[u8], block_size: usize, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+    let range = mem_blocks(bytes.len(), block_size, lba, buf.len())?;
+    buf.copy_from_slice(&bytes[range]);
+    Ok(())
+}
+
+impl<T: AsRef<[u8]>> BlockDevice for MemDevice<T> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        read_mem_blocks(self.inner.as_ref(), self.block_size, lba, buf)
+    }
+}
+
+/// The image can not grow, writing past its end fails
+impl<T: AsRef<[u8]> + AsMut<[u8]>> BlockDeviceMut for MemDevice<T> {
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        let bytes = self.inner.as_mut();
+        let range = mem_blocks(bytes.len(), self.block_size, lba, buf.len())?;
+        bytes[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl BlockDevice for &[u8] {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        read_mem_blocks(self, SECTOR_SIZE, lba, buf)
+    }
+}
+
+/// Reads the 2K sector `lba` of `dev` in `sector`
+///
+/// Devices with blocks smaller than a sector are read a few blocks at a time,
+/// which is how a BIOS disk service reporting 512 bytes blocks would be used.
+pub fn read_sector<D: BlockDevice + ?Sized>(
+    dev: &mut D,
+    lba: u64,
+    sector: &mut [u8; SECTOR_SIZE],
+) -> io::Result<()> {
+    let block_size = dev.block_size();
+    if block_size == 0 || !SECTOR_SIZE.is_multiple_of(block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the block size of the device does not divide the sector size",
+        ))
+    }
+    let blocks_per_sector = (SECTOR_SIZE / block_size) as u64;
+    dev.read_blocks(lba * blocks_per_sector, sector)
+}
+
+/// Writes `sector` as the 2K sector `lba` of `dev`, see `read_sector`
+pub fn write_sector<D: BlockDeviceMut + ?Sized>(
+    dev: &mut D,
+    lba: u64,
+    sector: &[u8; SECTOR_SIZE],
+) -> io::Result<()> {
+    let block_size = dev.block_size();
+    if block_size == 0 || !SECTOR_SIZE.is_multiple_of(block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the block size of the device does not divide the sector size",
+        ))
+    }
+    let blocks_per_sector = (SECTOR_SIZE / block_size) as u64;
+    dev.write_blocks(lba * blocks_per_sector, sector)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_sector_small_blocks() {
+        let image: Vec<u8> = (0..SECTOR_SIZE * 3).map(|i| (i / 7) as u8).collect();
+
+        let mut sector = [0_u8; SECTOR_SIZE];
+        let mut dev = MemDevice::with_block_size(&image, 512);
+        read_sector(&mut dev, 2, &mut sector).unwrap();
+        assert_eq!(&sector[..], &image[SECTOR_SIZE * 2..]);
+
+        let mut dev = IoDevice::new(io::Cursor::new(&image));
+        read_sector(&mut dev, 1, &mut sector).unwrap();
+        assert_eq!(&sector[..], &image[SECTOR_SIZE..SECTOR_SIZE * 2]);
+
+        assert!(read_sector(&mut image.as_slice(), 3, &mut sector).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::*;
+
+/// Offset of the boot info table in a no emulation boot image
+pub const BOOT_INFO_TABLE_OFFSET: usize = 8;
+
+/// The table and its 40 reserved bytes
+pub const BOOT_INFO_TABLE_SIZE: usize = 56;
+
+/// What mkisofs's `-boot-info-table` patches in a no emulation boot image,
+/// ISOLINUX and GRUB's `eltorito.img` read it to find themselves on the disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootInfoTable {
+    /// Sector of the primary volume descriptor
+    pub pvd_lba: u32,
+    /// Sector of the boot image
+    pub file_lba: u32,
+    pub file_len: u32,
+    /// See `BootInfoTable::checksum`
+    pub checksum: u32,
+}
+
+impl BootInfoTable {
+    /// The table of the boot image `data`, which must be longer than the
+    /// table
+    pub fn new(pvd_lba: u32, file_lba: u32, data: &[u8]) -> Self {
+        Self {
+            pvd_lba,
+            file_lba,
+            file_len: data.len() as u32,
+            checksum: Self::checksum(data),
+        }
+    }
+
+    /// Sum of the 32 bits little endian words of `data` that follow the
+    /// table, the last one padded with zeroes
+    pub fn checksum(data: &[u8]) -> u32 {
+        let start = BOOT_INFO_TABLE_OFFSET + BOOT_INFO_TABLE_SIZE;
+        data.get(start..)
+            .unwrap_or_default()
+            .chunks(4)
+            .map(|w| {
+                let mut word = [0_u8; 4];
+                word[..w.len()].copy_from_slice(w);
+                u32::from_le_bytes(word)
+            })
+            .fold(0_u32, u32::wrapping_add)
+    }
+
+    /// `None` if `data` is too short to hold a table
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let table = data.get(BOOT_INFO_TABLE_OFFSET..BOOT_INFO_TABLE_OFFSET + 16)?;
+        let u32_at = |off: usize| u32::from_le_bytes(table[off..off + 4].try_into().unwrap());
+        Some(Self {
+            pvd_lba: u32_at(0),
+            file_lba: u32_at(4),
+            file_len: u32_at(8),
+            checksum: u32_at(12),
+        })
+    }
+
+    /// Writes the table and zeroes its reserved bytes
+    pub fn dump(&self, data: &mut [u8]) {
+        let table = &mut data[BOOT_INFO_TABLE_OFFSET..BOOT_INFO_TABLE_OFFSET + BOOT_INFO_TABLE_SIZE];
+        table[0..4].copy_from_slice(&self.pvd_lba.to_le_bytes());
+        table[4..8].copy_from_slice(&self.file_lba.to_le_bytes());
+        table[8..12].copy_from_slice(&self.file_len.to_le_bytes());
+        table[12..16].copy_from_slice(&self.checksum.to_le_bytes());
+        table[16..].fill(0);
+    }
+}
+
+/// Offset of the address patched by `--grub2-boot-info` in GRUB2's
+/// `boot_hybrid.img`
+pub const GRUB2_BOOT_INFO_OFFSET: usize = 2548;
+
+pub const GRUB2_BOOT_INFO_SIZE: usize = 8;
+
+/// What xorriso's `--grub2-boot-info` patches in a no emulation boot image:
+/// the 512 bytes block that follows the first 2K of the image, where GRUB2's
+/// `core.img` goes on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grub2BootInfo {
+    /// In 512 bytes blocks
+    pub block: u64,
+}
+
+impl Grub2BootInfo {
+    /// The info of a boot image at the 2K sector `file_lba`
+    pub fn new(file_lba: u32) -> Self {
+        Self {
+            block: file_lba as u64 * (SECTOR_SIZE / MBR_SECTOR_SIZE) as u64 + 5,
+        }
+    }
+
+    /// `None` if `data` is too short to hold the info
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let bytes = data.get(GRUB2_BOOT_INFO_OFFSET..GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE)?;
+        Some(Self {
+            block: u64::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    pub fn dump(&self, data: &mut [u8]) {
+        data[GRUB2_BOOT_INFO_OFFSET..GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE]
+            .copy_from_slice(&self.block.to_le_bytes());
+    }
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// `true` if the file of `record` holds the boot info table it should,
+    /// for an image that was patched with `-boot-info-table`
+    pub fn verify_boot_info_table(&mut self, record: &DirectoryRecord) -> Result<bool, VDErr> {
+        let pvd_lba = self.descriptors().descriptors.iter()
+            .find(|(_, ty)| matches!(ty, VDType::PrimaryVD))
+            .map(|(lba, _)| *lba)
+            .ok_or(VDErr::MissingPrimaryVD)?;
+        let file_lba = (self.extent_offset(record) / SECTOR_SIZE as u64) as u32;
+        let data = self.read_file(record)?;
+        Ok(BootInfoTable::parse(&data) == Some(BootInfoTable::new(pvd_lba, file_lba, &data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_boot_info_table() {
+        let isolinux: Vec<u8> = (0..5000_u32).map(|i| (i * 7) as u8).collect();
+        let mut builder = IsoBuilder::new();
+        builder.add_file("isolinux/isolinux.bin", isolinux.clone()).unwrap();
+        builder.add_file("isolinux/ldlinux.c32", isolinux.clone()).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_boot_info_table());
+        let image = builder.build().unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let record = fs.lookup("isolinux/isolinux.bin").unwrap();
+        assert!(fs.verify_boot_info_table(&record).unwrap());
+        let data = fs.read_file(&record).unwrap();
+        let table = BootInfoTable::parse(&data).unwrap();
+        assert_eq!(table.pvd_lba, 16);
+        assert_eq!(table.file_lba, record.extent_location);
+        assert_eq!(table.file_len, 5000);
+        assert_eq!(data[..8], isolinux[..8]);
+        assert_eq!(data[64..], isolinux[64..]);
+
+        let record = fs.lookup("isolinux/ldlinux.c32").unwrap();
+        assert!(!fs.verify_boot_info_table(&record).unwrap());
+    }
+
+    #[test]
+    fn test_grub2_boot_info() {
+        let eltorito: Vec<u8> = (0..3000_u32).map(|i| (i * 3) as u8).collect();
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/grub/eltorito.img", eltorito.clone()).unwrap();
+        builder.add_file("boot/grub/small.img", vec![1; 2048]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("boot/grub/eltorito.img")
+            .with_boot_info_table()
+            .with_grub2_boot_info());
+        let image = builder.build().unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let record = fs.lookup("boot/grub/eltorito.img").unwrap();
+        let data = fs.read_file(&record).unwrap();
+        let info = Grub2BootInfo::parse(&data).unwrap();
+        assert_eq!(info.block, record.extent_location as u64 * 4 + 5);
+        assert_eq!(data[GRUB2_BOOT_INFO_OFFSET + 8..], eltorito[GRUB2_BOOT_INFO_OFFSET + 8..]);
+        // the catalog loads the whole image
+        let catalog = fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr.unwrap();
+        let start = catalog as usize * SECTOR_SIZE;
+        let initial = BootCatalogRef::new(&image[start..start + SECTOR_SIZE]).unwrap()
+            .initial_entry().unwrap();
+        assert_eq!(initial.sector_count, 6);
+        assert_eq!(initial.segment(), DEFAULT_LOAD_SEGMENT);
+
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/grub/small.img", vec![1; 2048]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("boot/grub/small.img").with_grub2_boot_info());
+        assert!(matches!(builder.build(), Err(BuildErr::BootImageTooSmall(_))));
+    }
+}
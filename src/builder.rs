@@ -0,0 +1,2637 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::*;
+
+#[derive(Debug)]
+pub enum BuildErr {
+    Io(io::Error),
+    /// A path given to the builder is empty or has an empty component
+    InvalidPath(String),
+    /// A component of the path is a file
+    NotADirectory(String),
+    AlreadyExists(String),
+    NotFound(String),
+    /// Larger than the 4G a single extent can describe
+    FileTooLarge(String),
+    InvalidLogicalBlockSize(u16),
+    InvalidAlphabet {
+        code_point: u8,
+        alphabet: &'static [u8],
+    },
+    /// Larger than the 32 bits of `vol_space_size`
+    VolumeTooLarge,
+    /// The image given to `IsoBuilder::append_to` could not be read
+    Image(VDErr),
+    /// A boot image is a directory
+    IsADirectory(String),
+    /// The boot code of a `HybridMbr` is larger than
+    /// `HybridMbr::MAX_BOOT_CODE_SIZE`
+    BootCodeTooLarge(usize),
+    InvalidGeometry(Geometry),
+    /// A hybrid MBR needs a boot image to point at
+    MissingBootImage,
+    /// The boot image is too small to hold the tables it is patched with
+    BootImageTooSmall(String),
+    /// A floppy emulation image is not the size of its media
+    InvalidFloppySize(String, u64),
+    /// A hard disk emulation image has no MBR, or not a single active
+    /// partition
+    NoActivePartition(String),
+    /// A file added with `IsoBuilder::add_host_file` could not be read
+    Host(PathBuf, io::Error),
+    /// Too many names of the directory map to the identifier of this path
+    /// once they are mangled
+    IdentifierClash(String),
+    /// The EFI system partition of the GPT would be empty or start at the
+    /// beginning of the disk
+    EmptyEfiSystemPartition,
+    /// An identifier of the volume descriptor is longer than its field
+    IdentifierTooLong(String, usize),
+}
+
+impl From<io::Error> for BuildErr {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<VDErr> for BuildErr {
+    fn from(value: VDErr) -> Self {
+        Self::Image(value)
+    }
+}
+
+impl From<InvalidChar> for BuildErr {
+    fn from(value: InvalidChar) -> Self {
+        Self::InvalidAlphabet {
+            code_point: value.code_point,
+            alphabet: value.alphabet,
+        }
+    }
+}
+
+impl fmt::Display for BuildErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::InvalidPath(path) => write!(f, "{:?}: invalid path", path),
+            Self::NotADirectory(path) => write!(f, "{}: not a directory", path),
+            Self::AlreadyExists(path) => write!(f, "{}: already exists", path),
+            Self::NotFound(path) => write!(f, "{}: no such file or directory", path),
+            Self::FileTooLarge(path) => write!(f, "{}: larger than 4G", path),
+            Self::InvalidLogicalBlockSize(size) => write!(f, "invalid logical block size {}", size),
+            Self::InvalidAlphabet { code_point, .. } => write!(f, "invalid character 0x{:02x} in an identifier", code_point),
+            Self::VolumeTooLarge => f.write_str("the volume is larger than 2^32 blocks"),
+            Self::Image(e) => write!(f, "{}", e),
+            Self::IsADirectory(path) => write!(f, "{}: the boot image is a directory", path),
+            Self::BootCodeTooLarge(len) => {
+                write!(f, "the MBR boot code is {} bytes, at most {} fit", len, HybridMbr::MAX_BOOT_CODE_SIZE)
+            },
+            Self::InvalidGeometry(geometry) => {
+                write!(f, "invalid geometry of {} heads and {} sectors per track", geometry.heads, geometry.sectors_per_track)
+            },
+            Self::MissingBootImage => f.write_str("no boot image"),
+            Self::BootImageTooSmall(path) => write!(f, "{}: too small to be patched", path),
+            Self::InvalidFloppySize(path, size) => write!(f, "{}: {} bytes is not the size of a floppy", path, size),
+            Self::NoActivePartition(path) => write!(f, "{}: no MBR with a single active partition", path),
+            Self::Host(path, e) => write!(f, "{}: {}", path.display(), e),
+            Self::IdentifierClash(path) => write!(f, "{}: too many names map to the same identifier", path),
+            Self::EmptyEfiSystemPartition => f.write_str("the EFI system partition is empty"),
+            Self::IdentifierTooLong(ident, len) => write!(f, "{:?}: longer than {} characters", ident, len),
+        }
+    }
+}
+
+impl std::error::Error for BuildErr {}
+
+#[derive(Debug, Clone, Default)]
+struct DirNode {
+    children: BTreeMap<String, Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File(Vec<u8>),
+    /// A file of the image that is appended to, its data stays where it is
+    Imported {
+        extent: u32,
+        size: u32,
+        system_use: Vec<u8>,
+    },
+    /// A file of the image given to `IsoBuilder::from_image`, its data is
+    /// copied when the new image is written
+    Copied {
+        offset: u64,
+        size: u32,
+    },
+    /// A file of the host, read when the image is written
+    Host {
+        path: PathBuf,
+        size: u32,
+    },
+    /// The record of the boot catalog, see `IsoBuilder::add_boot_catalog`
+    BootCatalog,
+    /// A symbolic link, only recorded with Rock Ridge
+    Symlink(String),
+    Dir(DirNode),
+}
+
+impl Node {
+    /// Number of bytes the file takes in the new image
+    fn len(&self) -> u64 {
+        match self {
+            Node::File(data) => data.len() as u64,
+            Node::Copied { size, .. } | Node::Host { size, .. } => *size as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// The image `IsoBuilder::from_image` imported the tree of, kept open to copy
+/// the files that were not replaced
+#[derive(Clone)]
+struct SourceImage(Rc<RefCell<IsoFs<Box<dyn BlockDevice>>>>);
+
+impl fmt::Debug for SourceImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SourceImage")
+    }
+}
+
+/// Permissions and owner of a file, recorded with Rock Ridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttrs {
+    /// Permission bits, the type of the file comes from the tree
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Seconds since the unix epoch, `None` for the creation time of the
+    /// image
+    pub mtime: Option<i64>,
+}
+
+impl FileAttrs {
+    /// `mode` for root
+    pub fn new(mode: u32) -> Self {
+        Self {
+            mode,
+            uid: 0,
+            gid: 0,
+            mtime: None,
+        }
+    }
+
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    pub fn with_mtime(mut self, secs: i64) -> Self {
+        self.mtime = Some(secs);
+        self
+    }
+}
+
+/// The components of `path` joined by `/`, which is how `IsoBuilder` keys
+/// the attributes of its files
+pub(crate) fn normalize(path: &str) -> String {
+    path.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+/// `name` in the directory `dir`, both normalized
+pub(crate) fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Directories `IsoBuilder::from_image` and `append_to` go down before
+/// they give up
+const MAX_IMPORT_DEPTH: usize = 64;
+
+/// Where `IsoBuilder::from_image` takes the names of the files from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Names {
+    RockRidge,
+    Joliet,
+    Iso,
+}
+
+/// An entry of the El Torito boot catalog written by `IsoBuilder`
+#[derive(Debug, Clone)]
+pub struct BootImage {
+    /// Path of the image in the tree, it is added like any other file
+    pub path: String,
+    pub platform: Platform,
+    pub media: BootMedia,
+    /// Segment the image is loaded at, `None` for `DEFAULT_LOAD_SEGMENT`
+    pub load_segment: Option<u16>,
+    /// Type of the partition of a hard disk emulation image, `None` to read
+    /// it from the active partition of its MBR
+    pub sys_type: Option<u8>,
+    /// Number of 512 bytes virtual sectors loaded by the BIOS, `None` to
+    /// compute it from the size of the image with `BootMedia::sector_count`
+    pub sector_count: Option<u16>,
+    /// Patch a `BootInfoTable` in the image, like `-boot-info-table`
+    pub boot_info_table: bool,
+    /// Patch the address GRUB2's `boot.img` loads the rest of the image from,
+    /// like `--grub2-boot-info`
+    pub grub2_boot_info: bool,
+}
+
+impl BootImage {
+    /// An image the BIOS loads the first 2K of, in the way of isolinux
+    pub fn no_emulation(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            platform: Platform::X86,
+            media: BootMedia::NoEmulation,
+            load_segment: None,
+            sys_type: None,
+            sector_count: None,
+            boot_info_table: false,
+            grub2_boot_info: false,
+        }
+    }
+
+    /// An image of a floppy or a hard disk that the BIOS emulates, floppy
+    /// images must be exactly the size of `media`
+    pub fn emulation(path: &str, media: BootMedia) -> Self {
+        Self {
+            media,
+            ..Self::no_emulation(path)
+        }
+    }
+
+    pub fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    pub fn with_sys_type(mut self, sys_type: u8) -> Self {
+        self.sys_type = Some(sys_type);
+        self
+    }
+
+    pub fn with_load_segment(mut self, segment: u16) -> Self {
+        self.load_segment = Some(segment);
+        self
+    }
+
+    pub fn with_sector_count(mut self, count: u16) -> Self {
+        self.sector_count = Some(count);
+        self
+    }
+
+    /// The image is patched when it is written, files imported by
+    /// `IsoBuilder::append_to` are left as they are
+    pub fn with_boot_info_table(mut self) -> Self {
+        self.boot_info_table = true;
+        self
+    }
+
+    /// Like `with_boot_info_table`, the image is patched when it is written
+    pub fn with_grub2_boot_info(mut self) -> Self {
+        self.grub2_boot_info = true;
+        self
+    }
+
+    /// The smallest image that can be patched as requested
+    fn min_len(&self) -> usize {
+        let mut len = 0;
+        if self.boot_info_table {
+            len = BOOT_INFO_TABLE_OFFSET + BOOT_INFO_TABLE_SIZE;
+        }
+        if self.grub2_boot_info {
+            len = len.max(GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE);
+        }
+        len
+    }
+
+    /// Validates the image laid out as `file`, which starts with `head`,
+    /// against its media and returns the system type of its catalog entry
+    ///
+    /// The MBR of imported hard disk images cannot be read, they need
+    /// `with_sys_type`.
+    fn check(&self, file: &LaidOutFile, head: &[u8]) -> Result<u8, BuildErr> {
+        let imported = matches!(file.node, Node::Imported { .. });
+        if !imported && (file.size as usize) < self.min_len() {
+            return Err(BuildErr::BootImageTooSmall(self.path.clone()))
+        }
+        if let Some(size) = self.media.floppy_size() {
+            if file.size as u64 != size {
+                return Err(BuildErr::InvalidFloppySize(self.path.clone(), file.size as u64))
+            }
+        }
+        match (self.sys_type, self.media) {
+            (Some(sys_type), _) => Ok(sys_type),
+            (None, BootMedia::HardDrive) => head.get(..MBR_SECTOR_SIZE)
+                .and_then(Mbr::parse)
+                .and_then(|mbr| mbr.active_partition().map(|p| p.ty))
+                .ok_or_else(|| BuildErr::NoActivePartition(self.path.clone())),
+            (None, _) => Ok(0),
+        }
+    }
+}
+
+/// The publisher, data preparer or application identifier given to the
+/// builder, see `VolumeIdent`
+#[derive(Debug, Clone)]
+enum IdentArg {
+    Text(String),
+    /// The name of a file of the root
+    File(String),
+}
+
+/// Builds an image from files held in memory
+///
+/// ```no_run
+/// # use iso9660::IsoBuilder;
+/// let mut builder = IsoBuilder::new().with_vol_ident("MY_DISC");
+/// builder.add_file("boot/grub/grub.cfg", b"set timeout=5\n".to_vec()).unwrap();
+/// let image = builder.build().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct IsoBuilder {
+    sys_ident: String,
+    vol_ident: String,
+    vol_set_ident: String,
+    publisher_ident: IdentArg,
+    data_prep_ident: IdentArg,
+    app_ident: IdentArg,
+    /// Names of files of the root recorded in the descriptor, `""` for none
+    copyright_file: String,
+    abstract_file: String,
+    bibliographic_file: String,
+    logical_block_size: u16,
+    creation_time: i64,
+    /// Sector at which the volume is written, not 0 when appending a session
+    session_start: u32,
+    root: DirNode,
+    /// Keyed by normalized path, the root is `""`
+    attrs: BTreeMap<String, FileAttrs>,
+    /// Files that are written but have no record, by normalized path
+    hidden: BTreeSet<String>,
+    rock_ridge: bool,
+    joliet: bool,
+    /// The first one is the default entry of the catalog
+    boot_images: Vec<BootImage>,
+    hybrid_mbr: Option<HybridMbr>,
+    gpt: Option<Gpt>,
+    source: Option<SourceImage>,
+}
+
+impl Default for IsoBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IsoBuilder {
+    /// An empty volume with 2K logical blocks, created now
+    pub fn new() -> Self {
+        let creation_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Self {
+            sys_ident: String::new(),
+            vol_ident: String::new(),
+            vol_set_ident: String::new(),
+            publisher_ident: IdentArg::Text(String::new()),
+            data_prep_ident: IdentArg::Text(String::new()),
+            app_ident: IdentArg::Text(String::new()),
+            copyright_file: String::new(),
+            abstract_file: String::new(),
+            bibliographic_file: String::new(),
+            logical_block_size: SECTOR_SIZE as u16,
+            creation_time,
+            session_start: 0,
+            root: DirNode::default(),
+            attrs: BTreeMap::new(),
+            hidden: BTreeSet::new(),
+            rock_ridge: false,
+            joliet: false,
+            boot_images: Vec::new(),
+            hybrid_mbr: None,
+            gpt: None,
+            source: None,
+        }
+    }
+
+    /// Starts a new session after the last one of `dev`
+    ///
+    /// The last session is imported like `from_image` does, with its names,
+    /// extensions and boot catalog. `write` then only produces the new
+    /// session, which goes at `session_offset` in the image: the files that
+    /// were not replaced, boot images included, keep their extents in the
+    /// previous sessions. The system area at the start of the image is not
+    /// rewritten, so an isohybrid MBR or a GPT goes on pointing at the boot
+    /// images that were not replaced. The logical block size of the image
+    /// must be kept.
+    ///
+    /// ```no_run
+    /// # use iso9660::{IsoBuilder, MemDevice};
+    /// # let mut image: Vec<u8> = Vec::new();
+    /// let mut builder = IsoBuilder::append_to(&mut MemDevice::new(&image)).unwrap();
+    /// builder.replace_file("BOOT/GRUB/GRUB.CFG", b"set timeout=0\n".to_vec()).unwrap();
+    /// image.truncate(builder.session_offset() as usize);
+    /// builder.write(&mut image).unwrap();
+    /// ```
+    pub fn append_to<D: BlockDevice + ?Sized>(dev: &mut D) -> Result<Self, BuildErr> {
+        let last = find_sessions(dev)?.pop().unwrap();
+        let mut fs = IsoFs::open_session(dev, last.start)?;
+        let mut builder = Self {
+            session_start: last.end(),
+            ..Self::new()
+        };
+        builder.import(&mut fs)?;
+        Ok(builder)
+    }
+
+    /// Imports everything of the last session of `dev` to write a new image
+    /// that boots the same way
+    ///
+    /// The tree is imported with its Rock Ridge names, attributes and links
+    /// if it has them, else with its Joliet names, else with the identifiers
+    /// of its records; the image gets the same extensions. The boot catalog,
+    /// the patched tables of its images, an isohybrid MBR, a GPT with an EFI
+    /// System Partition and the identifiers of the volume are kept. Boot
+    /// images that no file starts at become hidden files named after
+    /// `BootEntry::file_name`. Apple partition maps and SUN labels are not.
+    ///
+    /// The files that are not replaced are copied from `dev` when the image is
+    /// written.
+    ///
+    /// ```no_run
+    /// # use iso9660::{IsoBuilder, IoDevice};
+    /// # let file = std::fs::File::open("vendor.iso").unwrap();
+    /// let mut builder = IsoBuilder::from_image(IoDevice::new(file)).unwrap();
+    /// builder.add_file("ks.cfg", b"text\n".to_vec()).unwrap();
+    /// builder.write(&mut std::fs::File::create("custom.iso").unwrap()).unwrap();
+    /// ```
+    pub fn from_image<D: BlockDevice + 'static>(mut dev: D) -> Result<Self, BuildErr> {
+        let system_area = SystemArea::read(&mut dev)?;
+        let mut fs = IsoFs::open_last_session(Box::new(dev) as Box<dyn BlockDevice>)?;
+        let mut builder = Self::new();
+        if let Some(secs) = fs.pvd().vol_create_date_time.as_ref().and_then(DecDateTime::to_unix_time) {
+            builder.creation_time = secs;
+        }
+        builder.import(&mut fs)?;
+        let volume_size = fs.pvd().vol_space_size as u64 * fs.logical_block_size();
+        let has_uefi_image = builder.boot_images.iter().any(|b| matches!(b.platform, Platform::UEFI));
+
+        if let Some(mbr) = system_area.mbr.as_ref().filter(|m| m.has_boot_code) {
+            if !builder.boot_images.is_empty() {
+                let mut sector = [0_u8; MBR_SECTOR_SIZE];
+                fs.read_at(0, &mut sector)?;
+                let partition = mbr.partitions.iter()
+                    .find(|p| !p.is_empty() && p.ty != MBR_TYPE_GPT_PROTECTIVE);
+                // isohybrid's partition ends on the last sector of a cylinder
+                let geometry = partition
+                    .map(|p| Geometry {
+                        heads: p.end_chs[0].wrapping_add(1),
+                        sectors_per_track: p.end_chs[1] & 0x3F,
+                    })
+                    .filter(Geometry::is_valid)
+                    .unwrap_or_default();
+                builder.hybrid_mbr = Some(HybridMbr {
+                    boot_code: sector[..HybridMbr::MAX_BOOT_CODE_SIZE].to_vec(),
+                    geometry,
+                    partition_type: partition.map(|p| p.ty).unwrap_or(0x17),
+                    disk_signature: mbr.disk_signature,
+                });
+            }
+        }
+
+        if let Some(gpt) = &system_area.gpt {
+            let esp = gpt.partitions.iter()
+                .map(|(_, p)| p)
+                .find(|p| p.type_guid == Guid::EFI_SYSTEM_PARTITION);
+            if let Some(esp) = esp {
+                let start = esp.first_lba.checked_mul(MBR_SECTOR_SIZE as u64)
+                    .ok_or(BuildErr::Image(VDErr::Truncated))?;
+                let esp = if start < volume_size {
+                    has_uefi_image.then_some(EfiSystemPartition::BootImage)
+                } else {
+                    // the size comes from the table, reading past the end
+                    // of the device fails before it is all allocated
+                    let len = esp.nb_sectors().checked_mul(MBR_SECTOR_SIZE as u64)
+                        .filter(|len| start.checked_add(*len).is_some())
+                        .ok_or(BuildErr::Image(VDErr::Truncated))?;
+                    Some(EfiSystemPartition::Appended(fs.read_vec(start, len)?))
+                };
+                builder.gpt = esp.map(|esp| Gpt::new(esp).with_disk_guid(gpt.header.disk_guid));
+            }
+        }
+
+        builder.source = Some(SourceImage(Rc::new(RefCell::new(fs))));
+        Ok(builder)
+    }
+
+    /// Imports the identifiers, the tree, the extensions and the boot images
+    /// of the session `fs` is opened on
+    ///
+    /// Appended sessions refer to the files where they are, new images copy
+    /// them, see `imported_file`.
+    fn import<D: BlockDevice>(&mut self, fs: &mut IsoFs<D>) -> Result<(), BuildErr> {
+        let pvd = fs.pvd();
+        let ident = |s: Option<&str>| s.unwrap_or("").trim_end().to_owned();
+        self.sys_ident = ident(pvd.sys_ident.as_ref().map(|s| s.as_str()));
+        self.vol_ident = ident(pvd.vol_ident.as_ref().map(|s| s.as_str()));
+        self.vol_set_ident = ident(pvd.vol_set_ident.as_ref().map(|s| s.as_str()));
+        // file references are resolved once the tree is imported
+        let volume_idents = [&pvd.publisher_ident, &pvd.data_prep_ident, &pvd.app_ident].map(|i| match i {
+            Some(VolumeIdent::File(name)) => Err(name.as_str().to_owned()),
+            i => Ok(ident(i.as_ref().map(VolumeIdent::as_str))),
+        });
+        self.logical_block_size = pvd.logical_block_size;
+        let pvd_lba = fs.session_start() + (DATA_START / SECTOR_SIZE as u64) as u32;
+
+        self.rock_ridge = fs.has_rock_ridge()?;
+        let joliet = fs.joliet()?;
+        self.joliet = joliet.is_some();
+        let (names, root) = match joliet {
+            Some(joliet) if !self.rock_ridge => (Names::Joliet, joliet.root),
+            _ if self.rock_ridge => (Names::RockRidge, fs.root().clone()),
+            _ => (Names::Iso, fs.root().clone()),
+        };
+        // the files by the offset of their extent, for the boot images
+        let mut extents = HashMap::new();
+        self.import_dir(fs, &root, "", names, &mut extents, 0)?;
+
+        let [publisher, data_prep, app] = volume_idents.map(|i| match i {
+            Ok(text) => IdentArg::Text(text),
+            Err(name) => Self::imported_reference(fs, &name, &extents),
+        });
+        self.publisher_ident = publisher;
+        self.data_prep_ident = data_prep;
+        self.app_ident = app;
+
+        for entry in fs.boot_entries()? {
+            let offset = entry.lba as u64 * SECTOR_SIZE as u64;
+            let path = match extents.get(&offset) {
+                Some((path, size)) if entry.media.floppy_size().is_none_or(|s| s == *size as u64) => path.clone(),
+                _ => {
+                    let path = entry.file_name();
+                    let size = u32::try_from(entry.size).map_err(|_| BuildErr::FileTooLarge(path.clone()))?;
+                    let node = self.imported_file(fs, offset, size, &[]);
+                    let (parent, name) = self.parent_of(&path)?;
+                    if parent.children.contains_key(name) {
+                        return Err(BuildErr::AlreadyExists(path))
+                    }
+                    parent.children.insert(name.to_owned(), node);
+                    self.hidden.insert(path.clone());
+                    path
+                },
+            };
+
+            let mut head = vec![0_u8; entry.size.min(SECTOR_SIZE as u64 * 2) as usize];
+            fs.read_at(offset, &mut head)?;
+            let no_emulation = matches!(entry.media, BootMedia::NoEmulation);
+            let boot_info_table = no_emulation && BootInfoTable::parse(&head)
+                .is_some_and(|t| t.pvd_lba == pvd_lba && t.file_lba == entry.lba && t.file_len as u64 == entry.size);
+            let mut head = vec![0_u8; entry.size.min((GRUB2_BOOT_INFO_OFFSET + GRUB2_BOOT_INFO_SIZE) as u64) as usize];
+            fs.read_at(offset, &mut head)?;
+            let grub2_boot_info = no_emulation && Grub2BootInfo::parse(&head) == Some(Grub2BootInfo::new(entry.lba));
+
+            self.boot_images.push(BootImage {
+                path,
+                platform: entry.platform,
+                media: entry.media,
+                load_segment: entry.load_segment,
+                sys_type: Some(entry.sys_type),
+                sector_count: Some(entry.sector_count),
+                boot_info_table,
+                grub2_boot_info,
+            });
+        }
+        Ok(())
+    }
+
+    /// The file of the root the descriptor names with the identifier `name`,
+    /// by its name in the tree, the reference is dropped when there is no
+    /// such file
+    fn imported_reference<D: BlockDevice>(fs: &mut IsoFs<D>, name: &str, extents: &HashMap<u64, (String, u32)>) -> IdentArg {
+        let path = fs.lookup(name).ok()
+            .and_then(|record| extents.get(&fs.extent_offset(&record)))
+            .map(|(path, _)| path.clone())
+            .filter(|path| !path.contains('/'));
+        match path {
+            Some(path) => IdentArg::File(path),
+            None => IdentArg::Text(String::new()),
+        }
+    }
+
+    /// The node of a file at `offset` in the image being imported: appended
+    /// sessions leave it where it is, new images copy it
+    fn imported_file<D: BlockDevice>(&self, fs: &IsoFs<D>, offset: u64, size: u32, system_use: &[u8]) -> Node {
+        if self.session_start > 0 {
+            Node::Imported {
+                extent: (offset / fs.logical_block_size()) as u32,
+                size,
+                system_use: system_use.to_vec(),
+            }
+        } else {
+            Node::Copied {
+                offset,
+                size,
+            }
+        }
+    }
+
+    /// Adds the entries of `dir`, the directory `path` of `fs`, to the tree
+    fn import_dir<D: BlockDevice>(
+        &mut self,
+        fs: &mut IsoFs<D>,
+        dir: &DirectoryRecord,
+        path: &str,
+        names: Names,
+        extents: &mut HashMap<u64, (String, u32)>,
+        depth: usize,
+    ) -> Result<(), BuildErr> {
+        // relocated directories could loop
+        if depth > MAX_IMPORT_DEPTH {
+            return Err(BuildErr::Image(VDErr::InvalidDirectoryRecord))
+        }
+        for record in fs.read_dir(dir)? {
+            let rock_ridge = match names {
+                Names::RockRidge => fs.rock_ridge(&record)?.unwrap_or_default(),
+                _ => RockRidge::default(),
+            };
+            if record.is_special() {
+                // the attributes of the directory itself
+                if record.file_ident == [0] {
+                    if let Some(attrs) = rock_ridge.attrs {
+                        self.attrs.insert(path.to_owned(), FileAttrs {
+                            mode: attrs.mode & 0o7777,
+                            uid: attrs.uid,
+                            gid: attrs.gid,
+                            mtime: rock_ridge.times.modify,
+                        });
+                    }
+                }
+                continue
+            }
+            if rock_ridge.relocated {
+                continue
+            }
+
+            let name = match (&rock_ridge.name, names) {
+                (Some(name), _) => name.replace('/', "_"),
+                (None, Names::Joliet) => joliet_name(&record.file_ident).into_owned(),
+                (None, _) => record.name().into_owned(),
+            };
+            let child = join(path, &name);
+            let (parent, _) = self.parent_of(&child)?;
+            if parent.children.contains_key(&name) {
+                return Err(BuildErr::AlreadyExists(child))
+            }
+
+            if let Some(target) = rock_ridge.symlink {
+                parent.children.insert(name, Node::Symlink(target));
+            } else if let Some(lba) = rock_ridge.child_link {
+                let mut dot = [0_u8; 256];
+                fs.read_at(lba as u64 * fs.logical_block_size(), &mut dot)?;
+                parent.children.insert(name, Node::Dir(DirNode::default()));
+                self.import_dir(fs, &DirectoryRecord::try_parse(&dot)?, &child, names, extents, depth + 1)?;
+            } else if record.is_dir() {
+                parent.children.insert(name, Node::Dir(DirNode::default()));
+                self.import_dir(fs, &record, &child, names, extents, depth + 1)?;
+            } else {
+                let offset = fs.extent_offset(&record);
+                let node = self.imported_file(fs, offset, record.data_size, &record.system_use);
+                let (parent, _) = self.parent_of(&child)?;
+                parent.children.insert(name, node);
+                if record.data_size > 0 {
+                    extents.entry(offset).or_insert_with(|| (child.clone(), record.data_size));
+                }
+            }
+
+            if let Some(attrs) = rock_ridge.attrs {
+                // directories get the attributes of their `.` record
+                self.attrs.entry(child).or_insert(FileAttrs {
+                    mode: attrs.mode & 0o7777,
+                    uid: attrs.uid,
+                    gid: attrs.gid,
+                    mtime: rock_ridge.times.modify,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Offset in bytes at which the output of `write` goes in the image, 0
+    /// unless appending a session
+    pub fn session_offset(&self) -> u64 {
+        self.session_start as u64 * SECTOR_SIZE as u64
+    }
+
+    pub fn with_sys_ident(mut self, ident: &str) -> Self {
+        self.sys_ident = ident.to_owned();
+        self
+    }
+
+    pub fn with_vol_ident(mut self, ident: &str) -> Self {
+        self.vol_ident = ident.to_owned();
+        self
+    }
+
+    pub fn with_vol_set_ident(mut self, ident: &str) -> Self {
+        self.vol_set_ident = ident.to_owned();
+        self
+    }
+
+    pub fn with_publisher_ident(mut self, ident: &str) -> Self {
+        self.publisher_ident = IdentArg::Text(ident.to_owned());
+        self
+    }
+
+    pub fn with_data_prep_ident(mut self, ident: &str) -> Self {
+        self.data_prep_ident = IdentArg::Text(ident.to_owned());
+        self
+    }
+
+    pub fn with_app_ident(mut self, ident: &str) -> Self {
+        self.app_ident = IdentArg::Text(ident.to_owned());
+        self
+    }
+
+    /// Records the file `name` of the root as the one that holds the
+    /// publisher identifier, instead of the identifier itself, it must be in
+    /// the tree when the image is written
+    pub fn with_publisher_file(mut self, name: &str) -> Self {
+        self.publisher_ident = IdentArg::File(name.to_owned());
+        self
+    }
+
+    /// Like `with_publisher_file` for the data preparer identifier
+    pub fn with_data_prep_file(mut self, name: &str) -> Self {
+        self.data_prep_ident = IdentArg::File(name.to_owned());
+        self
+    }
+
+    /// Like `with_publisher_file` for the application identifier
+    pub fn with_app_file(mut self, name: &str) -> Self {
+        self.app_ident = IdentArg::File(name.to_owned());
+        self
+    }
+
+    /// Records the file `name` of the root as the copyright file of the
+    /// volume, it must be in the tree when the image is written
+    pub fn with_copyright_file(mut self, name: &str) -> Self {
+        self.copyright_file = name.to_owned();
+        self
+    }
+
+    /// Like `with_copyright_file` for the abstract file
+    pub fn with_abstract_file(mut self, name: &str) -> Self {
+        self.abstract_file = name.to_owned();
+        self
+    }
+
+    /// Like `with_copyright_file` for the bibliographic file
+    pub fn with_bibliographic_file(mut self, name: &str) -> Self {
+        self.bibliographic_file = name.to_owned();
+        self
+    }
+
+    /// Records the names, permissions, owners and dates of the files with
+    /// Rock Ridge, like `-r`: files are read only for everyone unless
+    /// `set_attrs` says otherwise
+    pub fn with_rock_ridge(mut self) -> Self {
+        self.rock_ridge = true;
+        self
+    }
+
+    /// Adds a Joliet tree, where Windows finds the names of the files
+    pub fn with_joliet(mut self) -> Self {
+        self.joliet = true;
+        self
+    }
+
+    /// One of `LOGICAL_BLOCK_SIZES`, checked when the image is written
+    pub fn with_logical_block_size(mut self, size: u16) -> Self {
+        self.logical_block_size = size;
+        self
+    }
+
+    /// Date of every record and of the volume, in seconds since the unix
+    /// epoch, for reproducible images
+    pub fn with_creation_time(mut self, secs: i64) -> Self {
+        self.creation_time = secs;
+        self
+    }
+
+    /// Adds an entry to the El Torito boot catalog, the first one is the
+    /// default entry and the next ones are grouped in sections by platform
+    ///
+    /// The file `image.path` must be in the tree when the image is written.
+    pub fn add_boot_image(&mut self, image: BootImage) {
+        self.boot_images.push(image);
+    }
+
+    /// Adds the FAT image built by `fat` as the file `path` and as a no
+    /// emulation UEFI entry of the boot catalog
+    pub fn add_efi_boot_image(&mut self, path: &str, fat: &FatBuilder) -> Result<(), BuildErr> {
+        self.add_file(path, fat.build()?)?;
+        self.add_boot_image(BootImage::no_emulation(path).with_platform(Platform::UEFI));
+        Ok(())
+    }
+
+    /// Fills the system area with `mbr` so that the image also boots from a
+    /// USB stick, the image is padded to a whole number of cylinders
+    ///
+    /// The MBR points at the default boot image, which must be set.
+    pub fn with_hybrid_mbr(mut self, mbr: HybridMbr) -> Self {
+        self.hybrid_mbr = Some(mbr);
+        self
+    }
+
+    /// Writes a protective MBR and a GPT with an EFI System Partition, the
+    /// backup GPT goes after the volume
+    ///
+    /// With a hybrid MBR its boot code is kept but its partition table is the
+    /// protective one. When the ESP is the UEFI boot image, the partition of
+    /// the volume stops right before it and the rest of the volume gets a
+    /// partition of its own, so that no two entries overlap.
+    pub fn with_gpt(mut self, gpt: Gpt) -> Self {
+        self.gpt = Some(gpt);
+        self
+    }
+
+    /// Adds the file `path`, missing parent directories are created
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), BuildErr> {
+        if data.len() > u32::MAX as usize {
+            return Err(BuildErr::FileTooLarge(path.to_owned()))
+        }
+        let (parent, name) = self.parent_of(path)?;
+        if parent.children.contains_key(name) {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), Node::File(data));
+        Ok(())
+    }
+
+    /// Adds the file `path` or replaces it if it already exists
+    pub fn replace_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), BuildErr> {
+        if data.len() > u32::MAX as usize {
+            return Err(BuildErr::FileTooLarge(path.to_owned()))
+        }
+        let (parent, name) = self.parent_of(path)?;
+        if let Some(Node::Dir(_)) = parent.children.get(name) {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), Node::File(data));
+        Ok(())
+    }
+
+    /// Adds the file `path` with the content of the file `host_path`, which
+    /// is only read when the image is written
+    pub fn add_host_file<P: AsRef<Path>>(&mut self, path: &str, host_path: P) -> Result<(), BuildErr> {
+        let host_path = host_path.as_ref();
+        let len = host_path.metadata()
+            .map_err(|e| BuildErr::Host(host_path.to_owned(), e))?
+            .len();
+        let size = u32::try_from(len).map_err(|_| BuildErr::FileTooLarge(path.to_owned()))?;
+        let (parent, name) = self.parent_of(path)?;
+        if parent.children.contains_key(name) {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), Node::Host {
+            path: host_path.to_owned(),
+            size,
+        });
+        Ok(())
+    }
+
+    /// Gives the boot catalog the record `path`, like the `-c` option of
+    /// mkisofs, it has none otherwise
+    ///
+    /// The image must have a boot image.
+    pub fn add_boot_catalog(&mut self, path: &str) -> Result<(), BuildErr> {
+        let (parent, name) = self.parent_of(path)?;
+        if parent.children.contains_key(name) {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), Node::BootCatalog);
+        Ok(())
+    }
+
+    /// Adds the directory `path` and its missing parents, does nothing if it
+    /// already exists
+    pub fn add_dir(&mut self, path: &str) -> Result<(), BuildErr> {
+        let (parent, name) = self.parent_of(path)?;
+        match parent.children.entry(name.to_owned()).or_insert_with(|| Node::Dir(DirNode::default())) {
+            Node::Dir(_) => Ok(()),
+            _ => Err(BuildErr::AlreadyExists(path.to_owned())),
+        }
+    }
+
+    /// Adds the symbolic link `path` to `target`, which is only recorded with
+    /// Rock Ridge
+    pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<(), BuildErr> {
+        let (parent, name) = self.parent_of(path)?;
+        if parent.children.contains_key(name) {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), Node::Symlink(target.to_owned()));
+        Ok(())
+    }
+
+    /// Adds the file `path`, which is written but has no record, for boot
+    /// images that should not show up in the tree
+    pub fn add_hidden_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), BuildErr> {
+        self.add_file(path, data)?;
+        self.hidden.insert(normalize(path));
+        Ok(())
+    }
+
+    /// Sets the Rock Ridge attributes of the file or directory `path`, `""`
+    /// for the root
+    pub fn set_attrs(&mut self, path: &str, attrs: FileAttrs) -> Result<(), BuildErr> {
+        let path = normalize(path);
+        if !path.is_empty() && self.node(&path).is_none() {
+            return Err(BuildErr::NotFound(path))
+        }
+        self.attrs.insert(path, attrs);
+        Ok(())
+    }
+
+    /// Removes the file or directory `path`
+    pub fn remove(&mut self, path: &str) -> Result<(), BuildErr> {
+        let (parent, name) = self.parent_of(path)?;
+        parent.children.remove(name).ok_or_else(|| BuildErr::NotFound(path.to_owned()))?;
+        let path = normalize(path);
+        let prefix = format!("{}/", path);
+        self.attrs.retain(|p, _| *p != path && !p.starts_with(&prefix));
+        self.hidden.retain(|p| *p != path && !p.starts_with(&prefix));
+        Ok(())
+    }
+
+    /// Size of the file `path` of the tree, wherever its data comes from
+    pub fn file_size(&self, path: &str) -> Result<u64, BuildErr> {
+        match self.node(path) {
+            None | Some(Node::Symlink(_)) => Err(BuildErr::NotFound(normalize(path))),
+            Some(Node::Dir(_)) => Err(BuildErr::IsADirectory(normalize(path))),
+            Some(Node::Imported { size, .. }) => Ok(*size as u64),
+            Some(node) => Ok(node.len()),
+        }
+    }
+
+    /// Whether `path` is a directory of the tree
+    pub(crate) fn is_dir(&self, path: &str) -> bool {
+        matches!(self.node(path), Some(Node::Dir(_)))
+    }
+
+    fn node(&self, path: &str) -> Option<&Node> {
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        let mut node = self.root.children.get(components.next()?)?;
+        for component in components {
+            node = match node {
+                Node::Dir(d) => d.children.get(component)?,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    fn parent_of<'a, 'p>(&'a mut self, path: &'p str) -> Result<(&'a mut DirNode, &'p str), BuildErr> {
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let name = components.pop().ok_or_else(|| BuildErr::InvalidPath(path.to_owned()))?;
+        if name == "." || name == ".." || components.iter().any(|c| *c == "." || *c == "..") {
+            return Err(BuildErr::InvalidPath(path.to_owned()))
+        }
+
+        let mut dir = &mut self.root;
+        for component in components {
+            let node = dir.children.entry(component.to_owned())
+                .or_insert_with(|| Node::Dir(DirNode::default()));
+            dir = match node {
+                Node::Dir(d) => d,
+                _ => return Err(BuildErr::NotADirectory(path.to_owned())),
+            };
+        }
+        Ok((dir, name))
+    }
+
+    /// Writes the image in memory
+    pub fn build(&self) -> Result<Vec<u8>, BuildErr> {
+        let mut image = Vec::new();
+        self.write(&mut image)?;
+        Ok(image)
+    }
+
+    /// Writes the image to `out` front to back, returns the number of bytes
+    /// written
+    ///
+    /// Only the new session is written when appending, see `append_to`.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<u64, BuildErr> {
+        let layout = Layout::new(self)?;
+        // offsets are counted from the start of the image
+        let mut out = CountingWriter {
+            inner: out,
+            written: self.session_offset(),
+        };
+
+        let mut system_area = vec![0_u8; DATA_START as usize];
+        if let Some(mbr) = &self.hybrid_mbr {
+            let boot_lba = layout.boot_images.first()
+                .map(|image| layout.files[image.file].sector())
+                .ok_or(BuildErr::MissingBootImage)?;
+            mbr.dump(boot_lba, layout.block_size * layout.nb_blocks as u64, &mut system_area);
+        }
+        if let Some(disk) = &layout.disk {
+            disk.dump_system_area(&mut system_area);
+        }
+        out.write_all(&system_area)?;
+
+        let mut sector = [0_u8; SECTOR_SIZE];
+        layout.pvd(self)?.dump(&mut sector);
+        out.write_all(&sector)?;
+
+        if let Some(catalog) = layout.catalog {
+            sector.fill(0);
+            BootRecord::el_torito(catalog).dump(&mut sector);
+            out.write_all(&sector)?;
+        }
+
+        if layout.joliet.is_some() {
+            dump_joliet_svd(&layout.svd(self)?, &mut sector);
+            out.write_all(&sector)?;
+        }
+
+        sector.fill(0);
+        VD {
+            ty: VDType::VDEnd,
+            version: 1,
+        }.dump(&mut sector);
+        out.write_all(&sector)?;
+
+        let block_size = layout.block_size;
+        let trees = [Some(&layout.tree), layout.joliet.as_ref()];
+        for tree in trees.iter().flatten() {
+            layout.write_path_table(&mut out, &tree.dirs, block_size * tree.path_table_l as u64, false)?;
+            layout.write_path_table(&mut out, &tree.dirs, block_size * tree.path_table_m as u64, true)?;
+        }
+
+        if let Some(catalog) = layout.catalog {
+            pad_to(&mut out, SECTOR_SIZE as u64 * catalog as u64)?;
+            out.write_all(&layout.boot_catalog())?;
+        }
+
+        for tree in trees.iter().flatten() {
+            for (i, dir) in tree.dirs.iter().enumerate() {
+                pad_to(&mut out, block_size * dir.extent as u64)?;
+                out.write_all(&layout.dir_extent(&tree.dirs, i))?;
+                out.write_all(&dir.continuations)?;
+            }
+        }
+
+        let pvd_lba = self.session_start + (DATA_START / SECTOR_SIZE as u64) as u32;
+        for (i, file) in layout.files.iter().enumerate() {
+            if file.node.len() == 0 {
+                continue
+            }
+            pad_to(&mut out, block_size * file.extent as u64)?;
+            let boot = layout.boot_images.iter()
+                .find(|b| b.file == i && (b.image.boot_info_table || b.image.grub2_boot_info));
+            match (file.node, boot) {
+                (_, Some(boot)) => {
+                    let mut data = self.read_node(file.node, file.size as usize)?;
+                    if boot.image.boot_info_table {
+                        BootInfoTable::new(pvd_lba, file.sector(), &data).dump(&mut data);
+                    }
+                    if boot.image.grub2_boot_info {
+                        Grub2BootInfo::new(file.sector()).dump(&mut data);
+                    }
+                    out.write_all(&data)?;
+                },
+                (Node::Copied { offset, size }, None) => self.copy_from_source(*offset, *size as u64, &mut out)?,
+                (Node::Host { path, size }, None) => copy_host_file(path, *size as u64, &mut out)?,
+                (_, None) => out.write_all(file.data)?,
+            }
+        }
+
+        pad_to(&mut out, block_size * layout.nb_blocks as u64)?;
+
+        if let Some(disk) = &layout.disk {
+            if let Some(EfiSystemPartition::Appended(esp)) = self.gpt.as_ref().map(|g| &g.esp) {
+                pad_to(&mut out, disk.esp.first_lba * MBR_SECTOR_SIZE as u64)?;
+                out.write_all(esp)?;
+            }
+            pad_to(&mut out, disk.size() - disk.tables.backup.len() as u64)?;
+            out.write_all(&disk.tables.backup)?;
+        }
+        Ok(out.written - self.session_offset())
+    }
+
+    /// The Rock Ridge entries of `path`, a file of type `file_type`, from
+    /// its attributes or the defaults of `-r`
+    fn rock_ridge_of(&self, path: &str, file_type: u32, nlink: u32) -> RockRidge {
+        let default_mode = match file_type {
+            PosixAttrs::S_IFDIR => 0o555,
+            PosixAttrs::S_IFLNK => 0o777,
+            _ => 0o444,
+        };
+        let attrs = self.attrs.get(path).copied().unwrap_or(FileAttrs::new(default_mode));
+        let mtime = attrs.mtime.unwrap_or(self.creation_time);
+        RockRidge {
+            attrs: Some(PosixAttrs {
+                mode: file_type | attrs.mode & 0o7777,
+                nlink,
+                uid: attrs.uid,
+                gid: attrs.gid,
+                ino: None,
+            }),
+            times: RrTimes {
+                creation: None,
+                modify: Some(mtime),
+                access: Some(mtime),
+                attributes: Some(mtime),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn source(&self) -> &SourceImage {
+        self.source.as_ref().expect("copied files come from a source image")
+    }
+
+    /// The first `len` bytes of the file `node` at most
+    fn read_node(&self, node: &Node, len: usize) -> Result<Vec<u8>, BuildErr> {
+        match node {
+            Node::File(data) => Ok(data[..len.min(data.len())].to_vec()),
+            Node::Copied { offset, size } => {
+                let mut data = vec![0_u8; len.min(*size as usize)];
+                self.source().0.borrow_mut().read_at(*offset, &mut data)?;
+                Ok(data)
+            },
+            Node::Host { path, size } => {
+                let mut data = Vec::new();
+                copy_host_file(path, len.min(*size as usize) as u64, &mut data)?;
+                Ok(data)
+            },
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Writes the `len` bytes at `offset` in the source image to `out`
+    fn copy_from_source<W: Write>(&self, mut offset: u64, mut len: u64, out: &mut W) -> io::Result<()> {
+        let mut fs = self.source().0.borrow_mut();
+        let mut chunk = vec![0_u8; 64 * 1024];
+        while len > 0 {
+            let n = len.min(chunk.len() as u64) as usize;
+            fs.read_at(offset, &mut chunk[..n])?;
+            out.write_all(&chunk[..n])?;
+            offset += n as u64;
+            len -= n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the first `len` bytes of the file `path` of the host to `out`
+fn copy_host_file<W: Write>(path: &Path, len: u64, out: &mut W) -> Result<(), BuildErr> {
+    let host_err = |e| BuildErr::Host(path.to_owned(), e);
+    let file = File::open(path).map_err(host_err)?;
+    // errors of `out` are not the host's
+    let mut reader = file.take(len);
+    let mut chunk = vec![0_u8; 64 * 1024];
+    let mut copied = 0;
+    while copied < len {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => return Err(host_err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file shrank"))),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(host_err(e)),
+        };
+        out.write_all(&chunk[..n])?;
+        copied += n as u64;
+    }
+    Ok(())
+}
+
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_zeroes<W: Write>(out: &mut W, mut len: u64) -> io::Result<()> {
+    const ZEROES: [u8; SECTOR_SIZE] = [0_u8; SECTOR_SIZE];
+    while len > 0 {
+        let chunk = len.min(SECTOR_SIZE as u64) as usize;
+        out.write_all(&ZEROES[..chunk])?;
+        len -= chunk as u64;
+    }
+    Ok(())
+}
+
+fn pad_to<W: Write>(out: &mut CountingWriter<'_, W>, offset: u64) -> io::Result<()> {
+    debug_assert!(out.written <= offset, "regions of the image overlap");
+    let len = offset.saturating_sub(out.written);
+    write_zeroes(out, len)
+}
+
+/// Maps a name to d-characters, `;1` is appended to files
+fn iso_ident(name: &str, is_dir: bool) -> Vec<u8> {
+    let mangle = |s: &str| -> Vec<u8> {
+        s.bytes()
+            .map(|b| match b.to_ascii_uppercase() {
+                b @ (b'A'..=b'Z' | b'0'..=b'9' | b'_') => b,
+                _ => b'_',
+            })
+            .collect()
+    };
+
+    if is_dir {
+        let mut ident = mangle(name);
+        ident.truncate(31);
+        return ident
+    }
+
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (name, ""),
+    };
+    let mut ext = mangle(ext);
+    ext.truncate(8);
+    let mut ident = mangle(stem);
+    ident.truncate(30 - 1 - ext.len());
+    ident.push(b'.');
+    ident.extend_from_slice(&ext);
+    ident.extend_from_slice(b";1");
+    ident
+}
+
+/// How many names of a directory can map to the same identifier before
+/// building fails
+const MAX_CLASHING_NAMES: usize = 100_000;
+
+/// Makes `ident` different from every identifier in `taken` by replacing the
+/// end of its name with a counter, `None` when no counter is left
+fn unique_ident(mut ident: Vec<u8>, is_dir: bool, taken: &HashSet<Vec<u8>>) -> Option<Vec<u8>> {
+    if !taken.contains(&ident) {
+        return Some(ident)
+    }
+    let name_len = if is_dir {
+        ident.len()
+    } else {
+        ident.iter().position(|&b| b == b'.').unwrap_or(ident.len())
+    };
+    let suffix = ident.split_off(name_len);
+    // 31 characters for directories, 30 for the name and extension of files
+    let max_name_len = if is_dir { 31 } else { 30 - (suffix.len() - 3) };
+    // the counter takes more digits once the first thousand are taken
+    for n in 0..MAX_CLASHING_NAMES {
+        let counter = format!("{:03}", n);
+        let Some(room) = max_name_len.checked_sub(counter.len()) else {
+            break
+        };
+        let mut candidate = ident[..name_len.min(room)].to_vec();
+        candidate.extend_from_slice(counter.as_bytes());
+        candidate.extend_from_slice(&suffix);
+        if !taken.contains(&candidate) {
+            return Some(candidate)
+        }
+    }
+    None
+}
+
+/// Like `unique_ident` for the identifiers of the Joliet tree
+fn unique_joliet_ident(name: &str, is_dir: bool, taken: &HashSet<Vec<u8>>) -> Option<Vec<u8>> {
+    let ident = joliet_ident(name, is_dir);
+    if !taken.contains(&ident) {
+        return Some(ident)
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !is_dir && !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    for n in 0..MAX_CLASHING_NAMES {
+        let suffix = format!("{:03}{}", n, ext);
+        let stem: String = stem.chars()
+            .take(JOLIET_MAX_NAME_LEN.saturating_sub(suffix.chars().count()))
+            .collect();
+        let candidate = joliet_ident(&format!("{}{}", stem, suffix), is_dir);
+        if !taken.contains(&candidate) {
+            return Some(candidate)
+        }
+    }
+    None
+}
+
+fn record_len(ident_len: usize, system_use_len: usize) -> usize {
+    ((DIRECTORY_RECORD_HEADER_SIZE + ident_len + 1) & !1) + system_use_len
+}
+
+fn path_table_record_len(ident_len: usize) -> usize {
+    8 + ident_len + (ident_len & 1)
+}
+
+enum EntryKind {
+    Dir(usize),
+    File(usize),
+}
+
+struct Entry<'a> {
+    ident: Vec<u8>,
+    kind: EntryKind,
+    name: &'a str,
+    node: &'a Node,
+    system_use: SystemUse,
+}
+
+/// The system use area of a record, split over continuation areas when it
+/// does not fit in the record
+#[derive(Default)]
+struct SystemUse {
+    /// What goes in the record, without its `CE` entry
+    inline: Vec<u8>,
+    /// What goes in each continuation area, without its `CE` entry
+    areas: Vec<Vec<u8>>,
+    /// Where `areas` went, see `LaidOutDir::place_continuations`
+    placed: Vec<ContinuationArea>,
+}
+
+impl SystemUse {
+    /// Splits the SUSP `entries` of a record that has `room` bytes left
+    fn new(entries: Vec<u8>, room: usize, block_size: usize) -> Self {
+        if entries.len() <= room {
+            return Self {
+                inline: entries,
+                ..Default::default()
+            }
+        }
+        let mut pieces = vec![Vec::new()];
+        let mut capacity = room - CE_ENTRY_SIZE;
+        for entry in susp_entries(&entries) {
+            let len = SUSP_HEADER_SIZE + entry.data.len();
+            if pieces.last().unwrap().len() + len > capacity {
+                pieces.push(Vec::new());
+                capacity = block_size - CE_ENTRY_SIZE;
+            }
+            entry.dump(pieces.last_mut().unwrap());
+        }
+        let inline = pieces.remove(0);
+        Self {
+            inline,
+            areas: pieces,
+            placed: Vec::new(),
+        }
+    }
+
+    /// Size of the area in the record
+    fn len(&self) -> usize {
+        self.inline.len() + if self.areas.is_empty() { 0 } else { CE_ENTRY_SIZE }
+    }
+
+    /// The area of the record, which points at the first continuation area
+    fn encode(&self) -> Vec<u8> {
+        let mut area = self.inline.clone();
+        if let Some(ce) = self.placed.first() {
+            ce.dump(&mut area);
+        }
+        area
+    }
+}
+
+struct LaidOutDir<'a> {
+    /// `[0]` for the root
+    ident: Vec<u8>,
+    /// Normalized, `""` for the root
+    path: String,
+    parent: usize,
+    extent: u32,
+    size: u32,
+    entries: Vec<Entry<'a>>,
+    /// Of the `.` and `..` records
+    dot: SystemUse,
+    dotdot: SystemUse,
+    /// The continuation areas of the system use areas of the records, in the
+    /// blocks that follow the extent
+    continuations: Vec<u8>,
+}
+
+impl LaidOutDir<'_> {
+    fn new(ident: Vec<u8>, path: String, parent: usize) -> Self {
+        Self {
+            ident,
+            path,
+            parent,
+            extent: 0,
+            size: 0,
+            entries: Vec::new(),
+            dot: SystemUse::default(),
+            dotdot: SystemUse::default(),
+            continuations: Vec::new(),
+        }
+    }
+
+    /// Every system use area of the records
+    fn system_uses(&mut self) -> Vec<&mut SystemUse> {
+        [&mut self.dot, &mut self.dotdot].into_iter()
+            .chain(self.entries.iter_mut().map(|e| &mut e.system_use))
+            .collect()
+    }
+
+    /// Places the continuation areas from the block `first_block` on and
+    /// fills `continuations`, areas never cross a block
+    ///
+    /// Readers that stream the image, like libarchive, only find them right
+    /// after the directory, which is where mkisofs puts them.
+    fn place_continuations(&mut self, first_block: u32, block_size: u64) {
+        let mut offset = 0_u64;
+        for system_use in self.system_uses() {
+            let nb_areas = system_use.areas.len();
+            for (i, area) in system_use.areas.iter().enumerate() {
+                let len = area.len() + if i + 1 < nb_areas { CE_ENTRY_SIZE } else { 0 };
+                if offset % block_size + len as u64 > block_size {
+                    offset = offset.next_multiple_of(block_size);
+                }
+                system_use.placed.push(ContinuationArea {
+                    block: first_block + (offset / block_size) as u32,
+                    offset: (offset % block_size) as u32,
+                    len: len as u32,
+                });
+                offset += len as u64;
+            }
+        }
+
+        let mut continuations = vec![0_u8; offset.next_multiple_of(block_size) as usize];
+        for system_use in self.system_uses() {
+            for (i, area) in system_use.areas.iter().enumerate() {
+                let placed = &system_use.placed[i];
+                let mut bytes = area.clone();
+                if let Some(next) = system_use.placed.get(i + 1) {
+                    next.dump(&mut bytes);
+                }
+                let start = (placed.block - first_block) as usize * block_size as usize + placed.offset as usize;
+                continuations[start..start + bytes.len()].copy_from_slice(&bytes);
+            }
+        }
+        self.continuations = continuations;
+    }
+
+    /// Link count of the directory, `.`, its record in its parent and the
+    /// `..` of its subdirectories
+    fn nlink(&self) -> u32 {
+        2 + self.entries.iter().filter(|e| matches!(e.kind, EntryKind::Dir(_))).count() as u32
+    }
+}
+
+/// A hierarchy of directories and its path tables, the ISO 9660 one or the
+/// Joliet one, which share the files
+struct Tree<'a> {
+    /// In path table order, the root is first
+    dirs: Vec<LaidOutDir<'a>>,
+    path_table_size: u32,
+    path_table_l: u32,
+    path_table_m: u32,
+}
+
+impl<'a> Tree<'a> {
+    /// Walks the tree of `builder` breadth first so that directories end up in
+    /// path table order, `lay_out_file` gives the index of the file of a node
+    fn new(builder: &'a IsoBuilder, joliet: bool, lay_out_file: &mut impl FnMut(&'a Node) -> usize) -> Result<Self, BuildErr> {
+        let mut nodes = vec![&builder.root];
+        let mut dirs = vec![LaidOutDir::new(vec![0], String::new(), 0)];
+
+        let mut i = 0;
+        while i < nodes.len() {
+            let dir: &'a DirNode = nodes[i];
+            let mut entries: Vec<(Vec<u8>, &'a str, &'a Node)> = Vec::new();
+            let mut taken = HashSet::new();
+            for (name, node) in &dir.children {
+                // links are only recorded with Rock Ridge
+                let is_link = matches!(node, Node::Symlink(_));
+                if builder.hidden.contains(&join(&dirs[i].path, name)) || is_link && (joliet || !builder.rock_ridge) {
+                    continue
+                }
+                let is_dir = matches!(node, Node::Dir(_));
+                let ident = if joliet {
+                    unique_joliet_ident(name, is_dir, &taken)
+                } else {
+                    unique_ident(iso_ident(name, is_dir), is_dir, &taken)
+                };
+                let ident = ident.ok_or_else(|| BuildErr::IdentifierClash(join(&dirs[i].path, name)))?;
+                taken.insert(ident.clone());
+                entries.push((ident, name, node));
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (ident, name, node) in entries {
+                let kind = match node {
+                    Node::Dir(d) => {
+                        nodes.push(d);
+                        dirs.push(LaidOutDir::new(ident.clone(), join(&dirs[i].path, name), i));
+                        EntryKind::Dir(dirs.len() - 1)
+                    },
+                    _ => EntryKind::File(lay_out_file(node)),
+                };
+                dirs[i].entries.push(Entry {
+                    ident,
+                    kind,
+                    name,
+                    node,
+                    system_use: SystemUse::default(),
+                });
+            }
+            i += 1;
+        }
+
+        let path_table_size: usize = dirs.iter()
+            .map(|d| path_table_record_len(d.ident.len()))
+            .sum();
+        Ok(Self {
+            dirs,
+            path_table_size: path_table_size as u32,
+            path_table_l: 0,
+            path_table_m: 0,
+        })
+    }
+
+    /// Places the path tables at `next_block`
+    fn place_path_tables(&mut self, next_block: &mut u64, block_size: u64) {
+        let blocks = (self.path_table_size as u64).div_ceil(block_size);
+        self.path_table_l = *next_block as u32;
+        self.path_table_m = (*next_block + blocks) as u32;
+        *next_block += 2 * blocks;
+    }
+
+    /// Sizes the directories and places them at `next_block`, each one
+    /// followed by its continuation areas
+    fn place_dirs(&mut self, next_block: &mut u64, block_size: u64) {
+        for dir in self.dirs.iter_mut() {
+            // records never cross a sector boundary
+            let mut size = record_len(1, dir.dot.len()) + record_len(1, dir.dotdot.len());
+            for entry in &dir.entries {
+                let len = record_len(entry.ident.len(), entry.system_use.len());
+                if size % SECTOR_SIZE + len > SECTOR_SIZE {
+                    size = size.next_multiple_of(SECTOR_SIZE);
+                }
+                size += len;
+            }
+            dir.size = size.next_multiple_of(SECTOR_SIZE) as u32;
+            // the sectors counted from the start of the extent are the ones
+            // of the image once it starts a sector, which smaller blocks do not
+            *next_block = next_block.next_multiple_of(SECTOR_SIZE as u64 / block_size);
+            dir.extent = *next_block as u32;
+            *next_block += (dir.size as u64).div_ceil(block_size);
+            dir.place_continuations(*next_block as u32, block_size);
+            *next_block += (dir.continuations.len() as u64).div_ceil(block_size);
+        }
+    }
+}
+
+struct LaidOutFile<'a> {
+    node: &'a Node,
+    /// Empty unless the data is in memory
+    data: &'a [u8],
+    extent: u32,
+    size: u32,
+    system_use: &'a [u8],
+    block_size: u64,
+}
+
+impl<'a> LaidOutFile<'a> {
+    fn new(node: &'a Node, block_size: u64) -> Self {
+        let (data, extent, size, system_use) = match node {
+            Node::File(data) => (&data[..], 0, data.len() as u32, &[][..]),
+            Node::Imported { extent, size, system_use } => (&[][..], *extent, *size, &system_use[..]),
+            Node::Copied { size, .. } | Node::Host { size, .. } => (&[][..], 0, *size, &[][..]),
+            Node::BootCatalog | Node::Symlink(_) | Node::Dir(_) => (&[][..], 0, 0, &[][..]),
+        };
+        Self {
+            node,
+            data,
+            extent,
+            size,
+            system_use,
+            block_size,
+        }
+    }
+}
+
+impl LaidOutFile<'_> {
+    /// Address of the extent in 2K sectors, which is how El Torito counts
+    fn sector(&self) -> u32 {
+        (self.extent as u64 * self.block_size / SECTOR_SIZE as u64) as u32
+    }
+}
+
+struct LaidOutBoot<'a> {
+    image: &'a BootImage,
+    file: usize,
+    sys_type: u8,
+}
+
+/// The partition table written when the image has a GPT
+struct DiskLayout {
+    esp: GptPartition,
+    tables: GptTables,
+    nb_sectors: u64,
+}
+
+impl DiskLayout {
+    fn new(builder: &IsoBuilder, gpt: &Gpt, layout: &Layout<'_>) -> Result<Self, BuildErr> {
+        let sectors = |bytes: u64| bytes.div_ceil(MBR_SECTOR_SIZE as u64);
+        let disk_guid = gpt.disk_guid.unwrap_or_else(|| Guid::from_seed(builder.creation_time as u64));
+        let seed = u64::from_le_bytes(disk_guid.0[..8].try_into().unwrap());
+        let partition = |i: u64, type_guid: Guid, first_lba: u64, last_lba: u64, name: &str| GptPartition {
+            type_guid,
+            guid: Guid::from_seed(seed ^ i),
+            first_lba,
+            last_lba,
+            attributes: 0,
+            name: name.to_owned(),
+        };
+
+        // the volume starts right after the system area
+        let volume_start = sectors(DATA_START);
+        let volume_end = sectors(layout.block_size * layout.nb_blocks as u64);
+        let mut partitions = Vec::new();
+        let esp = match &gpt.esp {
+            EfiSystemPartition::BootImage => {
+                let image = layout.boot_images.iter()
+                    .find(|b| matches!(b.image.platform, Platform::UEFI))
+                    .ok_or(BuildErr::MissingBootImage)?;
+                let file = &layout.files[image.file];
+                let first = file.sector() as u64 * sectors(SECTOR_SIZE as u64);
+                let last = first + sectors(file.size as u64).max(1) - 1;
+                let iso_last = first.checked_sub(1)
+                    .filter(|&lba| lba >= volume_start)
+                    .ok_or(BuildErr::EmptyEfiSystemPartition)?;
+                partitions.push(partition(1, Guid::BASIC_DATA, volume_start, iso_last, "ISO9660"));
+                if last + 1 < volume_end {
+                    partitions.push(partition(3, Guid::BASIC_DATA, last + 1, volume_end - 1, "Gap1"));
+                }
+                partition(2, Guid::EFI_SYSTEM_PARTITION, first, last, "EFI System Partition")
+            },
+            EfiSystemPartition::Appended(data) => {
+                partitions.push(partition(1, Guid::BASIC_DATA, volume_start, volume_end - 1, "ISO9660"));
+                let last = volume_end + sectors(data.len() as u64).max(1) - 1;
+                partition(2, Guid::EFI_SYSTEM_PARTITION, volume_end, last, "Appended2")
+            },
+        };
+        partitions.push(esp.clone());
+        partitions.sort_by_key(|p| p.first_lba);
+
+        // backup partition array and header
+        let tail = GPT_ENTRIES_SECTORS + 1;
+        let data_end = partitions.iter().map(|p| p.last_lba + 1).max().unwrap();
+        let mut nb_sectors = data_end.next_multiple_of(sectors(SECTOR_SIZE as u64)) + tail;
+        if let Some(mbr) = &builder.hybrid_mbr {
+            nb_sectors = nb_sectors.next_multiple_of(sectors(mbr.geometry.cylinder_size()));
+        }
+
+        Ok(Self {
+            esp,
+            tables: gpt_tables(disk_guid, &partitions, nb_sectors),
+            nb_sectors,
+        })
+    }
+
+    /// Size of the whole disk in bytes
+    fn size(&self) -> u64 {
+        self.nb_sectors * MBR_SECTOR_SIZE as u64
+    }
+
+    /// The protective MBR, whose boot code is left as is, and the primary GPT
+    fn dump_system_area(&self, out: &mut [u8]) {
+        let nb_sectors = (self.nb_sectors - 1).min(u32::MAX as u64) as u32;
+        let protective = MbrPartition::new(MBR_TYPE_GPT_PROTECTIVE, 1, nb_sectors, Geometry::default());
+        out[MBR_PARTITION_TABLE..510].fill(0);
+        protective.dump(&mut out[MBR_PARTITION_TABLE..]);
+        out[510..512].copy_from_slice(&MBR_SIGNATURE);
+        out[MBR_SECTOR_SIZE..MBR_SECTOR_SIZE + self.tables.primary.len()].copy_from_slice(&self.tables.primary);
+    }
+}
+
+/// Where everything goes, in logical blocks
+struct Layout<'a> {
+    block_size: u64,
+    date: DirectoryRecordDate,
+    tree: Tree<'a>,
+    joliet: Option<Tree<'a>>,
+    files: Vec<LaidOutFile<'a>>,
+    /// Sector of the boot catalog, if there are boot images
+    catalog: Option<u32>,
+    boot_images: Vec<LaidOutBoot<'a>>,
+    nb_blocks: u32,
+    disk: Option<DiskLayout>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(builder: &'a IsoBuilder) -> Result<Self, BuildErr> {
+        let block_size = check_logical_block_size(builder.logical_block_size)
+            .map_err(|_| BuildErr::InvalidLogicalBlockSize(builder.logical_block_size))?
+            as u64;
+
+        // both trees share the files, in the order of the first one
+        let mut files: Vec<LaidOutFile<'a>> = Vec::new();
+        let mut indices: HashMap<*const Node, usize> = HashMap::new();
+        let mut lay_out_file = |node: &'a Node| -> usize {
+            *indices.entry(node as *const Node).or_insert_with(|| {
+                files.push(LaidOutFile::new(node, block_size));
+                files.len() - 1
+            })
+        };
+        let mut tree = Tree::new(builder, false, &mut lay_out_file)?;
+        let mut joliet = match builder.joliet {
+            true => Some(Tree::new(builder, true, &mut lay_out_file)?),
+            false => None,
+        };
+        for path in &builder.hidden {
+            if let Some(node) = builder.node(path).filter(|n| !matches!(n, Node::Dir(_))) {
+                lay_out_file(node);
+            }
+        }
+
+        let mut boot_images = Vec::new();
+        for image in &builder.boot_images {
+            let node = match builder.node(&image.path) {
+                Some(Node::Dir(_)) => return Err(BuildErr::IsADirectory(image.path.clone())),
+                Some(node) => node,
+                None => return Err(BuildErr::NotFound(image.path.clone())),
+            };
+            let file = files.iter()
+                .position(|f| core::ptr::eq(f.node, node) && !matches!(node, Node::Symlink(_)))
+                .ok_or_else(|| BuildErr::NotFound(image.path.clone()))?;
+            let head = builder.read_node(node, MBR_SECTOR_SIZE)?;
+            boot_images.push(LaidOutBoot {
+                image,
+                file,
+                sys_type: image.check(&files[file], &head)?,
+            });
+        }
+
+        if builder.rock_ridge {
+            Self::add_rock_ridge(builder, &mut tree, block_size as usize);
+        } else {
+            for dir in tree.dirs.iter_mut() {
+                for entry in dir.entries.iter_mut() {
+                    if let EntryKind::File(i) = entry.kind {
+                        entry.system_use.inline = files[i].system_use.to_vec();
+                    }
+                }
+            }
+        }
+
+        let blocks = |bytes: u64| bytes.div_ceil(block_size);
+        // El Torito addresses 2K sectors
+        let blocks_per_sector = SECTOR_SIZE as u64 / block_size;
+        let align_to_sector = |block: u64| block.next_multiple_of(blocks_per_sector);
+
+        let nb_descriptors = 2 + !boot_images.is_empty() as u64 + joliet.is_some() as u64;
+        let session_offset = builder.session_offset();
+        let mut next_block = blocks(session_offset + DATA_START + nb_descriptors * SECTOR_SIZE as u64);
+
+        tree.place_path_tables(&mut next_block, block_size);
+        if let Some(joliet) = &mut joliet {
+            joliet.place_path_tables(&mut next_block, block_size);
+        }
+
+        let catalog = if boot_images.is_empty() {
+            None
+        } else {
+            next_block = align_to_sector(next_block);
+            let catalog = next_block / blocks_per_sector;
+            next_block += blocks(Self::boot_catalog_len(&boot_images) as u64);
+            Some(catalog as u32)
+        };
+        for file in files.iter_mut().filter(|f| matches!(f.node, Node::BootCatalog)) {
+            let catalog = catalog.ok_or(BuildErr::MissingBootImage)?;
+            file.extent = (catalog as u64 * blocks_per_sector) as u32;
+            file.size = Self::boot_catalog_len(&boot_images) as u32;
+        }
+
+        tree.place_dirs(&mut next_block, block_size);
+        if let Some(joliet) = &mut joliet {
+            joliet.place_dirs(&mut next_block, block_size);
+        }
+
+        for (i, file) in files.iter_mut().enumerate() {
+            if file.node.len() > 0 {
+                if boot_images.iter().any(|b| b.file == i) {
+                    next_block = align_to_sector(next_block);
+                }
+                file.extent = next_block as u32;
+                next_block += blocks(file.node.len());
+            }
+        }
+
+        // keep the image a whole number of CD sectors, and of cylinders when
+        // it has a partition table
+        let mut image_size = (next_block * block_size).next_multiple_of(SECTOR_SIZE as u64);
+        if let Some(mbr) = &builder.hybrid_mbr {
+            if mbr.boot_code.len() > HybridMbr::MAX_BOOT_CODE_SIZE {
+                return Err(BuildErr::BootCodeTooLarge(mbr.boot_code.len()))
+            }
+            if !mbr.geometry.is_valid() {
+                return Err(BuildErr::InvalidGeometry(mbr.geometry))
+            }
+            // with a GPT it is the whole disk that is padded
+            if builder.gpt.is_none() {
+                image_size = image_size.next_multiple_of(mbr.geometry.cylinder_size());
+            }
+        }
+        let nb_blocks = image_size / block_size;
+        let nb_blocks = u32::try_from(nb_blocks).map_err(|_| BuildErr::VolumeTooLarge)?;
+
+        let mut layout = Self {
+            block_size,
+            date: DirectoryRecordDate::from_unix_time(builder.creation_time),
+            tree,
+            joliet,
+            files,
+            catalog,
+            boot_images,
+            nb_blocks,
+            disk: None,
+        };
+        if let Some(gpt) = &builder.gpt {
+            let is_empty = match &gpt.esp {
+                EfiSystemPartition::BootImage => layout.boot_images.iter()
+                    .filter(|b| matches!(b.image.platform, Platform::UEFI))
+                    .any(|b| layout.files[b.file].size == 0 || layout.files[b.file].extent == 0),
+                EfiSystemPartition::Appended(data) => data.is_empty(),
+            };
+            if is_empty {
+                return Err(BuildErr::EmptyEfiSystemPartition)
+            }
+            layout.disk = Some(DiskLayout::new(builder, gpt, &layout)?);
+        }
+        Ok(layout)
+    }
+
+    /// Fills the system use areas of `tree` with Rock Ridge entries, the root
+    /// also gets the `SP` and `ER` entries
+    fn add_rock_ridge(builder: &IsoBuilder, tree: &mut Tree<'a>, block_size: usize) {
+        let rock_ridge_of = |dir: &LaidOutDir| builder.rock_ridge_of(&dir.path, PosixAttrs::S_IFDIR, dir.nlink());
+        // with the identifier of a `.` or `..` record
+        let room = 255 - record_len(1, 0);
+        for i in 0..tree.dirs.len() {
+            let dir = &tree.dirs[i];
+            let mut dot = Vec::new();
+            if i == 0 {
+                dump_sp_entry(&mut dot);
+            }
+            rock_ridge_of(dir).dump(&mut dot);
+            if i == 0 {
+                dump_er_entry(&mut dot);
+            }
+            let mut dotdot = Vec::new();
+            rock_ridge_of(&tree.dirs[dir.parent]).dump(&mut dotdot);
+
+            let mut entries = Vec::new();
+            for entry in &dir.entries {
+                let path = join(&dir.path, entry.name);
+                let mut rr = match (entry.node, &entry.kind) {
+                    (_, EntryKind::Dir(d)) => rock_ridge_of(&tree.dirs[*d]),
+                    (Node::Symlink(target), _) => RockRidge {
+                        symlink: Some(target.clone()),
+                        ..builder.rock_ridge_of(&path, PosixAttrs::S_IFLNK, 1)
+                    },
+                    _ => builder.rock_ridge_of(&path, PosixAttrs::S_IFREG, 1),
+                };
+                rr.name = Some(entry.name.to_owned());
+                let mut system_use = Vec::new();
+                rr.dump(&mut system_use);
+                let room = 255 - record_len(entry.ident.len(), 0);
+                entries.push(SystemUse::new(system_use, room, block_size));
+            }
+
+            let dir = &mut tree.dirs[i];
+            dir.dot = SystemUse::new(dot, room, block_size);
+            dir.dotdot = SystemUse::new(dotdot, room, block_size);
+            for (entry, system_use) in dir.entries.iter_mut().zip(entries) {
+                entry.system_use = system_use;
+            }
+        }
+    }
+
+    /// Consecutive images of the same platform share a section, the default
+    /// entry is not part of any
+    fn boot_sections<'b>(boot_images: &'b [LaidOutBoot<'a>]) -> impl Iterator<Item = &'b [LaidOutBoot<'a>]> {
+        boot_images.get(1..)
+            .unwrap_or_default()
+            .chunk_by(|a, b| a.image.platform as u8 == b.image.platform as u8)
+    }
+
+    fn boot_catalog_len(boot_images: &[LaidOutBoot<'a>]) -> usize {
+        let nb_entries = boot_images.len() + 1 + Self::boot_sections(boot_images).count();
+        nb_entries * 32
+    }
+
+    fn sector_count(&self, boot: &LaidOutBoot) -> u16 {
+        boot.image.sector_count
+            .unwrap_or_else(|| boot.image.media.sector_count(self.files[boot.file].size as u64))
+    }
+
+    fn boot_catalog(&self) -> Vec<u8> {
+        let mut catalog = vec![0_u8; Self::boot_catalog_len(&self.boot_images)];
+        let default = &self.boot_images[0];
+        ValidationEntry {
+            header_id: 1,
+            platform_id: default.image.platform,
+            manufacturer_id: None,
+        }.dump(&mut catalog);
+        InitialEntry {
+            boot_indicator: BootIndicator::Bootable,
+            boot_media: default.image.media,
+            load_segment: default.image.load_segment,
+            sys_type: default.sys_type,
+            sector_count: self.sector_count(default),
+            virtual_disk_addr: self.files[default.file].sector(),
+        }.dump(&mut catalog[32..]);
+
+        let mut off = 64;
+        let nb_sections = Self::boot_sections(&self.boot_images).count();
+        for (i, section) in Self::boot_sections(&self.boot_images).enumerate() {
+            SectionHeaderEntry {
+                header_indicator: if i + 1 == nb_sections {
+                    HeaderIndicator::Final
+                } else {
+                    HeaderIndicator::Partial
+                },
+                platform_id: section[0].image.platform,
+                nb_section_entries: section.len() as u16,
+                id_str: None,
+            }.dump(&mut catalog[off..]);
+            off += 32;
+
+            for boot in section {
+                SectionEntry {
+                    boot_indicator: BootIndicator::Bootable,
+                    boot_media: boot.image.media,
+                    has_continuation_entry: false,
+                    image_contains_atapi_driver: false,
+                    image_contains_scsi_driver: false,
+                    load_segment: boot.image.load_segment,
+                    sys_type: boot.sys_type,
+                    sector_count: self.sector_count(boot),
+                    virtual_disk_addr: self.files[boot.file].sector(),
+                    selection_criteria: SelectionCriteria::None,
+                    selection_criteria_bytes: Default::default(),
+                }.dump(&mut catalog[off..]);
+                off += 32;
+            }
+        }
+        catalog
+    }
+
+    fn dir_record(&self, dirs: &[LaidOutDir], ident: Vec<u8>, kind: &EntryKind, system_use: Vec<u8>) -> DirectoryRecord {
+        let (extent_location, data_size, flags) = match *kind {
+            EntryKind::Dir(i) => (dirs[i].extent, dirs[i].size, flags::DIR),
+            EntryKind::File(i) => (self.files[i].extent, self.files[i].size, 0),
+        };
+        DirectoryRecord {
+            size: record_len(ident.len(), system_use.len()) as u8,
+            ext_attr_len: 0,
+            extent_location,
+            data_size,
+            create_date: self.date,
+            flags,
+            interleaved_file_size: None,
+            interleaved_gap_size: None,
+            vol_seq_nul: 1,
+            file_ident: ident,
+            system_use,
+        }
+    }
+
+    fn dir_extent(&self, dirs: &[LaidOutDir], index: usize) -> Vec<u8> {
+        let dir = &dirs[index];
+        let mut extent = vec![0_u8; dir.size as usize];
+
+        let records = [
+            self.dir_record(dirs, vec![0], &EntryKind::Dir(index), dir.dot.encode()),
+            self.dir_record(dirs, vec![1], &EntryKind::Dir(dir.parent), dir.dotdot.encode()),
+        ];
+        let entries = dir.entries.iter()
+            .map(|e| self.dir_record(dirs, e.ident.clone(), &e.kind, e.system_use.encode()));
+
+        let mut off = 0;
+        for record in records.into_iter().chain(entries) {
+            let len = record.encoded_len();
+            if off % SECTOR_SIZE + len > SECTOR_SIZE {
+                off = off.next_multiple_of(SECTOR_SIZE);
+            }
+            record.dump(&mut extent[off..off + len]);
+            off += len;
+        }
+        extent
+    }
+
+    fn write_path_table<W: Write>(
+        &self,
+        out: &mut CountingWriter<'_, W>,
+        dirs: &[LaidOutDir],
+        offset: u64,
+        big_endian: bool,
+    ) -> io::Result<()> {
+        pad_to(out, offset)?;
+        for dir in dirs {
+            let mut record = [0_u8; 8];
+            record[0] = dir.ident.len() as u8;
+            // parents are numbered from 1
+            let parent = dir.parent as u16 + 1;
+            if big_endian {
+                record[2..6].copy_from_slice(&dir.extent.to_be_bytes());
+                record[6..8].copy_from_slice(&parent.to_be_bytes());
+            } else {
+                record[2..6].copy_from_slice(&dir.extent.to_le_bytes());
+                record[6..8].copy_from_slice(&parent.to_le_bytes());
+            }
+            out.write_all(&record)?;
+            out.write_all(&dir.ident)?;
+            if dir.ident.len() & 1 == 1 {
+                out.write_all(&[0])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pvd(&self, builder: &IsoBuilder) -> Result<PVD, BuildErr> {
+        /// `s` padded with spaces to `LEN` bytes
+        fn padded<const LEN: usize>(s: &str) -> Result<[u8; LEN], BuildErr> {
+            if s.len() > LEN {
+                return Err(BuildErr::IdentifierTooLong(s.to_owned(), LEN))
+            }
+            let mut bytes = [b' '; LEN];
+            bytes[..s.len()].copy_from_slice(s.as_bytes());
+            Ok(bytes)
+        }
+
+        let sys_ident = StrA::<32>::from_slice(&padded::<32>(&builder.sys_ident)?)?;
+        let vol_ident = StrD::<32>::from_slice(&padded::<32>(&builder.vol_ident)?)?;
+        let vol_set_ident = StrD::<128>::from_slice(&padded::<128>(&builder.vol_set_ident)?)?;
+        // the identifier the file got in the root, without its version
+        let root_ident = |name: &str| -> Result<String, BuildErr> {
+            let entry = self.tree.dirs[0].entries.iter()
+                .find(|e| e.name == name && matches!(e.kind, EntryKind::File(_)))
+                .ok_or_else(|| BuildErr::NotFound(name.to_owned()))?;
+            let ident = entry.ident.strip_suffix(b";1").unwrap_or(&entry.ident);
+            let ident = ident.strip_suffix(b".").unwrap_or(ident);
+            Ok(String::from_utf8_lossy(ident).into_owned())
+        };
+        let root_file = |name: &str| -> Result<Option<StrD<37>>, BuildErr> {
+            if name.is_empty() {
+                return Ok(None)
+            }
+            Ok(Some(StrD::<37>::from_slice(&padded::<37>(&root_ident(name)?)?)?))
+        };
+        let volume_ident = |ident: &IdentArg| -> Result<Option<VolumeIdent>, BuildErr> {
+            Ok(match ident {
+                IdentArg::Text(s) if s.is_empty() => None,
+                IdentArg::Text(s) => Some(VolumeIdent::Text(StrA::from_slice(&padded::<128>(s)?)?)),
+                IdentArg::File(name) => Some(VolumeIdent::File(StrA::from_slice(&padded::<127>(&root_ident(name)?)?)?)),
+            })
+        };
+
+        let root_record = self.dir_record(&self.tree.dirs, vec![0], &EntryKind::Dir(0), Vec::new());
+        Ok(PVD {
+            sys_ident: Some(sys_ident),
+            vol_ident: Some(vol_ident),
+            vol_space_size: self.nb_blocks,
+            vol_set_size: 1,
+            vol_seq_num: 1,
+            logical_block_size: self.block_size as u16,
+            path_table_size: self.tree.path_table_size,
+            path_table_l_location: self.tree.path_table_l,
+            opt_path_table_l_location: None,
+            path_table_m_location: self.tree.path_table_m,
+            opt_path_table_m_location: None,
+            root_record,
+            vol_set_ident: Some(vol_set_ident),
+            publisher_ident: volume_ident(&builder.publisher_ident)?,
+            data_prep_ident: volume_ident(&builder.data_prep_ident)?,
+            app_ident: volume_ident(&builder.app_ident)?,
+            copyright_file_name: root_file(&builder.copyright_file)?,
+            abstract_file_name: root_file(&builder.abstract_file)?,
+            bibliographic_file_name: root_file(&builder.bibliographic_file)?,
+            vol_create_date_time: Some(DecDateTime::from_unix_time(builder.creation_time)),
+            vol_mod_date_time: Some(DecDateTime::from_unix_time(builder.creation_time)),
+            vol_expiration_date_time: None,
+            vol_effective_date_time: None,
+            application_used: None,
+        })
+    }
+
+    /// The primary volume descriptor with the root and the path tables of the
+    /// Joliet tree, see `dump_joliet_svd`
+    fn svd(&self, builder: &IsoBuilder) -> Result<PVD, BuildErr> {
+        let joliet = self.joliet.as_ref().expect("the image has a Joliet tree");
+        let pvd = self.pvd(builder)?;
+        let text = |ident: Option<VolumeIdent>| ident.filter(|i| matches!(i, VolumeIdent::Text(_)));
+        Ok(PVD {
+            path_table_size: joliet.path_table_size,
+            path_table_l_location: joliet.path_table_l,
+            path_table_m_location: joliet.path_table_m,
+            root_record: self.dir_record(&joliet.dirs, vec![0], &EntryKind::Dir(0), Vec::new()),
+            // the identifiers of the files differ in the Joliet tree
+            publisher_ident: text(pvd.publisher_ident),
+            data_prep_ident: text(pvd.data_prep_ident),
+            app_ident: text(pvd.app_ident),
+            copyright_file_name: None,
+            abstract_file_name: None,
+            bibliographic_file_name: None,
+            ..pvd
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_block_sizes() {
+        let big: Vec<u8> = (0..5000_u32).map(|i| i as u8).collect();
+        for block_size in LOGICAL_BLOCK_SIZES {
+            let mut builder = IsoBuilder::new()
+                .with_vol_ident("TEST")
+                .with_logical_block_size(block_size);
+            builder.add_file("readme.txt", b"hello".to_vec()).unwrap();
+            builder.add_file("a/b/big.bin", big.clone()).unwrap();
+            builder.add_file("a/empty", Vec::new()).unwrap();
+            let image = builder.build().unwrap();
+            assert_eq!(image.len() % SECTOR_SIZE, 0);
+
+            let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+            assert_eq!(fs.logical_block_size(), block_size as u64);
+            let record = fs.lookup("A/B/BIG.BIN").unwrap();
+            assert_eq!(fs.read_file(&record).unwrap(), big);
+            let record = fs.lookup("readme.txt").unwrap();
+            assert_eq!(fs.read_file(&record).unwrap(), b"hello");
+            // records keep to the sectors of the image
+            for dir in ["", "a", "a/b"] {
+                let record = fs.lookup(dir).unwrap();
+                assert_eq!(fs.extent_offset(&record) % SECTOR_SIZE as u64, 0, "{:?}", dir);
+            }
+
+            let iso = IsoImage::new(&image).unwrap();
+            let record = iso.lookup("a/empty").unwrap();
+            assert_eq!(iso.file_contents(&record).unwrap(), b"");
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut builder = IsoBuilder::new()
+            .with_vol_ident("SERDE")
+            .with_publisher_ident("ACME CORP");
+        builder.add_file("boot.bin", vec![0xFA; 4096]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("boot.bin"));
+        let image = builder.build().unwrap();
+        let fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+
+        let json = serde_json::to_string(fs.descriptors()).unwrap();
+        assert!(json.contains("\"vol_ident\":\"SERDE\""));
+        let vds: VolumeDescriptorSet = serde_json::from_str(&json).unwrap();
+        let (mut expected, mut actual) = ([0_u8; SECTOR_SIZE], [0_u8; SECTOR_SIZE]);
+        fs.pvd().dump(&mut expected);
+        vds.pvd.dump(&mut actual);
+        assert_eq!(expected, actual);
+        assert_eq!(vds.boot_record.unwrap().boot_catalog_addr, fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr);
+
+        assert!(serde_json::from_str::<StrD<4>>("\"TOO LONG\"").is_err());
+        assert!(serde_json::from_str::<StrA<8>>("\"caf\u{e9}\"").is_err());
+    }
+
+    #[test]
+    fn test_append_session() {
+        let big: Vec<u8> = (0..50_000_u32).map(|i| i as u8).collect();
+        let mut builder = IsoBuilder::new().with_vol_ident("BACKUP");
+        builder.add_file("data/big.bin", big.clone()).unwrap();
+        builder.add_file("grub.cfg", b"set timeout=5\n".to_vec()).unwrap();
+        builder.add_file("old.txt", b"old".to_vec()).unwrap();
+        let mut image = builder.build().unwrap();
+        let first_len = image.len();
+
+        let mut builder = IsoBuilder::append_to(&mut MemDevice::new(&image)).unwrap();
+        builder.replace_file("GRUB.CFG", b"set timeout=0\n".to_vec()).unwrap();
+        builder.remove("OLD.TXT").unwrap();
+        builder.add_file("new.txt", b"new".to_vec()).unwrap();
+        assert_eq!(builder.session_offset(), first_len as u64);
+        let written = builder.write(&mut image).unwrap();
+        assert_eq!(image.len(), first_len + written as usize);
+        assert!(written < big.len() as u64);
+
+        let mut fs = IsoFs::open_last_session(MemDevice::new(&image)).unwrap();
+        assert_eq!(fs.session_start(), (first_len / SECTOR_SIZE) as u32);
+        assert_eq!(fs.pvd().vol_ident.as_ref().unwrap().as_str().trim_end(), "BACKUP");
+        let record = fs.lookup("data/big.bin").unwrap();
+        assert!((record.extent_location as usize) < first_len / SECTOR_SIZE);
+        assert_eq!(fs.read_file(&record).unwrap(), big);
+        let record = fs.lookup("grub.cfg").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"set timeout=0\n");
+        let record = fs.lookup("new.txt").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"new");
+        assert!(matches!(fs.lookup("old.txt"), Err(VDErr::NotFound)));
+
+        // the first session still sees the first tree
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let record = fs.lookup("old.txt").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_append_keeps_extensions() {
+        let isolinux = vec![0xFA; 5000];
+        let mut builder = IsoBuilder::new().with_rock_ridge().with_joliet();
+        builder.add_file("isolinux/isolinux.bin", isolinux.clone()).unwrap();
+        builder.add_file("Read_Me.txt", b"read me".to_vec()).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_boot_info_table());
+        let mut image = builder.build().unwrap();
+        let first_len = image.len();
+
+        let mut builder = IsoBuilder::append_to(&mut MemDevice::new(&image)).unwrap();
+        builder.add_file("ks.cfg", b"text\n".to_vec()).unwrap();
+        image.truncate(builder.session_offset() as usize);
+        builder.write(&mut image).unwrap();
+
+        let mut fs = IsoFs::open_last_session(MemDevice::new(&image)).unwrap();
+        assert_eq!(fs.session_start(), (first_len / SECTOR_SIZE) as u32);
+        assert!(fs.has_rock_ridge().unwrap());
+        assert!(fs.joliet().unwrap().is_some());
+        for namespace in [Namespace::RockRidge, Namespace::Joliet] {
+            let root = fs.root_entry(namespace).unwrap();
+            let mut names: Vec<String> = fs.entries(&root.record, namespace).unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+            names.sort();
+            assert_eq!(names, ["Read_Me.txt", "isolinux", "ks.cfg"]);
+        }
+
+        // the boot image stays in the first session
+        let entries = fs.boot_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file.as_ref().unwrap().0, "/ISOLINUX/ISOLINUX.BIN");
+        assert!((entries[0].lba as usize) < first_len / SECTOR_SIZE);
+        let data = fs.read_boot_image(&entries[0]).unwrap();
+        assert_eq!(data[..8], isolinux[..8]);
+        assert_eq!(data.len(), isolinux.len());
+    }
+
+    #[test]
+    fn test_hybrid_boot_image() {
+        let mut builder = IsoBuilder::new()
+            .with_vol_ident("LIVE")
+            .with_hybrid_mbr(HybridMbr::new(vec![0xEB; 400]).with_disk_signature(0x1234));
+        builder.add_file("isolinux/isolinux.bin", vec![0xFA; 3000]).unwrap();
+        builder.add_file("efi.img", vec![0; 4096]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin"));
+        builder.add_boot_image(BootImage::no_emulation("efi.img").with_platform(Platform::UEFI));
+        let image = builder.build().unwrap();
+        assert_eq!(image.len() % (1024 * 1024), 0);
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        assert_eq!(fs.pvd().vol_space_size as usize * SECTOR_SIZE, image.len());
+        let isolinux = fs.lookup("isolinux/isolinux.bin").unwrap();
+        let efi = fs.lookup("efi.img").unwrap();
+
+        let catalog = fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr.unwrap();
+        let start = catalog as usize * SECTOR_SIZE;
+        let catalog = BootCatalogRef::new(&image[start..start + SECTOR_SIZE]).unwrap();
+        assert_eq!(catalog.initial_entry().unwrap().virtual_disk_addr, isolinux.extent_location);
+        let sections: Vec<Vec<u32>> = catalog.sections()
+            .map(|s| s.entries().map(|e| e.unwrap().virtual_disk_addr).collect())
+            .collect();
+        assert_eq!(sections, [[efi.extent_location]]);
+        let checksum = image[start..start + 32].chunks_exact(2)
+            .fold(0_u16, |sum, w| sum.wrapping_add(u16::from_le_bytes([w[0], w[1]])));
+        assert_eq!(checksum, 0);
+
+        assert_eq!(&image[..400], &[0xEB; 400]);
+        assert_eq!(image[432..440], (isolinux.extent_location as u64 * 4).to_le_bytes());
+        assert_eq!(image[440..444], 0x1234_u32.to_le_bytes());
+        let partition = MbrPartition::parse(&image[MBR_PARTITION_TABLE..]);
+        assert_eq!(partition.status, 0x80);
+        assert_eq!(partition.ty, 0x17);
+        assert_eq!(partition.start_lba, 0);
+        assert_eq!(partition.nb_sectors as usize * MBR_SECTOR_SIZE, image.len());
+        assert_eq!(image[510..512], MBR_SIGNATURE);
+    }
+
+    #[test]
+    fn test_emulation_boot_images() {
+        let mut disk = vec![0_u8; 64 * 1024];
+        let partition = MbrPartition {
+            status: 0x80,
+            ..MbrPartition::new(0x0C, 1, 127, Geometry::default())
+        };
+        partition.dump(&mut disk[MBR_PARTITION_TABLE + MbrPartition::SIZE..]);
+        disk[510..512].copy_from_slice(&MBR_SIGNATURE);
+
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/floppy.img", vec![0xF6; 1440 * 1024]).unwrap();
+        builder.add_file("boot/disk.img", disk.clone()).unwrap();
+        builder.add_boot_image(BootImage::emulation("boot/floppy.img", BootMedia::Floppy1_44));
+        builder.add_boot_image(BootImage::emulation("boot/disk.img", BootMedia::HardDrive));
+        let image = builder.build().unwrap();
+
+        let fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let catalog = fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr.unwrap();
+        let start = catalog as usize * SECTOR_SIZE;
+        let catalog = BootCatalogRef::new(&image[start..start + SECTOR_SIZE]).unwrap();
+        let initial = catalog.initial_entry().unwrap();
+        assert!(matches!(initial.boot_media, BootMedia::Floppy1_44));
+        assert_eq!((initial.sys_type, initial.sector_count), (0, 1));
+        let entry = catalog.sections().next().unwrap().entries().next().unwrap().unwrap();
+        assert!(matches!(entry.boot_media, BootMedia::HardDrive));
+        assert_eq!((entry.sys_type, entry.sector_count), (0x0C, 1));
+
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/floppy.img", vec![0; 1440 * 1024]).unwrap();
+        builder.add_boot_image(BootImage::emulation("boot/floppy.img", BootMedia::Floppy2_88));
+        assert!(matches!(builder.build(), Err(BuildErr::InvalidFloppySize(_, 1474560))));
+
+        // a second active partition
+        partition.dump(&mut disk[MBR_PARTITION_TABLE..]);
+        let mut builder = IsoBuilder::new();
+        builder.add_file("boot/disk.img", disk).unwrap();
+        builder.add_boot_image(BootImage::emulation("boot/disk.img", BootMedia::HardDrive));
+        assert!(matches!(builder.build(), Err(BuildErr::NoActivePartition(_))));
+        builder.boot_images[0].sys_type = Some(0x83);
+        assert!(builder.build().is_ok());
+
+        // the floppy of `boot/Makefile`, which `examples/bootable.rs` boots
+        let floppy = concat!(env!("CARGO_MANIFEST_DIR"), "/boot/floppy.img");
+        let mut builder = IsoBuilder::new();
+        builder.add_host_file("boot/floppy.img", floppy).unwrap();
+        builder.add_boot_image(BootImage::emulation("boot/floppy.img", BootMedia::Floppy1_44));
+        let image = builder.build().unwrap();
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let entries = fs.boot_entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(entries[0].media, BootMedia::Floppy1_44));
+        assert_eq!(entries[0].file.as_ref().unwrap().0, "/BOOT/FLOPPY.IMG");
+        assert_eq!(fs.read_boot_image(&entries[0]).unwrap(), std::fs::read(floppy).unwrap());
+    }
+
+    fn check_gpt(image: &[u8]) -> Vec<GptPartition> {
+        let protective = MbrPartition::parse(&image[MBR_PARTITION_TABLE..]);
+        assert_eq!(protective.ty, MBR_TYPE_GPT_PROTECTIVE);
+        assert_eq!(protective.start_lba, 1);
+        assert_eq!(protective.nb_sectors as usize, image.len() / MBR_SECTOR_SIZE - 1);
+
+        let primary = GptHeader::parse(&image[MBR_SECTOR_SIZE..]).unwrap();
+        let backup = GptHeader::parse(&image[image.len() - MBR_SECTOR_SIZE..]).unwrap();
+        for header in [&primary, &backup] {
+            assert_eq!(header.header_crc, header.compute_crc());
+            let start = header.entries_lba as usize * MBR_SECTOR_SIZE;
+            let entries = &image[start..start + GPT_NB_ENTRIES * GPT_ENTRY_SIZE];
+            assert_eq!(header.entries_crc, crc32(entries));
+        }
+        assert_eq!(backup.current_lba as usize, image.len() / MBR_SECTOR_SIZE - 1);
+        assert_eq!(primary.backup_lba, backup.current_lba);
+
+        let start = primary.entries_lba as usize * MBR_SECTOR_SIZE;
+        image[start..start + GPT_NB_ENTRIES * GPT_ENTRY_SIZE]
+            .chunks_exact(GPT_ENTRY_SIZE)
+            .filter_map(GptPartition::parse)
+            .collect()
+    }
+
+    #[test]
+    fn test_gpt_esp() {
+        let mut builder = IsoBuilder::new()
+            .with_hybrid_mbr(HybridMbr::new(vec![0xEB; 16]))
+            .with_gpt(Gpt::new(EfiSystemPartition::BootImage));
+        builder.add_file("isolinux.bin", vec![0xFA; 2048]).unwrap();
+        builder.add_file("efi.img", vec![0xF0; 5000]).unwrap();
+        builder.add_file("zzz/after.bin", vec![1; 10_000]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux.bin"));
+        builder.add_boot_image(BootImage::no_emulation("efi.img").with_platform(Platform::UEFI));
+        let image = builder.build().unwrap();
+        assert_eq!(image.len() % (1024 * 1024), 0);
+        assert_eq!(&image[..16], &[0xEB; 16]);
+
+        let efi = IsoFs::open(MemDevice::new(&image)).unwrap().lookup("efi.img").unwrap();
+        let partitions = check_gpt(&image);
+        let names: Vec<&str> = partitions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["ISO9660", "EFI System Partition", "Gap1"]);
+        let esp = &partitions[1];
+        assert_eq!(esp.type_guid, Guid::EFI_SYSTEM_PARTITION);
+        assert_eq!(esp.first_lba, efi.extent_location as u64 * 4);
+        assert_eq!(esp.nb_sectors(), 10);
+        assert_eq!(partitions[0].first_lba, 64);
+        assert_eq!(partitions[0].last_lba + 1, esp.first_lba);
+        assert_eq!(partitions[2].first_lba, esp.last_lba + 1);
+
+        let mut builder = IsoBuilder::new()
+            .with_gpt(Gpt::new(EfiSystemPartition::Appended(vec![0xF0; 3000])));
+        builder.add_file("readme.txt", b"hello".to_vec()).unwrap();
+        let image = builder.build().unwrap();
+        let volume_size = IsoFs::open(MemDevice::new(&image)).unwrap().pvd().vol_space_size as u64 * 4;
+        let partitions = check_gpt(&image);
+        assert_eq!(partitions[0].last_lba + 1, volume_size);
+        let esp = &partitions[1];
+        assert_eq!(esp.first_lba, volume_size);
+        let start = esp.first_lba as usize * MBR_SECTOR_SIZE;
+        assert_eq!(&image[start..start + 3000], &[0xF0; 3000]);
+
+        // a crafted appended ESP larger than the image fails, without
+        // allocating what it claims first
+        let mut image = image;
+        let entry = 2 * MBR_SECTOR_SIZE + GPT_ENTRY_SIZE;
+        image[entry + 40..entry + 48].copy_from_slice(&(u64::MAX / 1024).to_le_bytes());
+        assert!(matches!(IsoBuilder::from_image(MemDevice::new(image)), Err(BuildErr::Io(_))));
+
+        let mut builder = IsoBuilder::new().with_gpt(Gpt::new(EfiSystemPartition::BootImage));
+        builder.add_file("efi.img", Vec::new()).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("efi.img").with_platform(Platform::UEFI));
+        assert!(matches!(builder.build(), Err(BuildErr::EmptyEfiSystemPartition)));
+        let builder = IsoBuilder::new().with_gpt(Gpt::new(EfiSystemPartition::Appended(Vec::new())));
+        assert!(matches!(builder.build(), Err(BuildErr::EmptyEfiSystemPartition)));
+    }
+
+    #[test]
+    fn test_from_image() {
+        let long_name = format!("{}.conf", "very long name ".repeat(14));
+        let isolinux: Vec<u8> = (0..5000_u32).map(|i| (i * 3) as u8).collect();
+        let mut builder = IsoBuilder::new()
+            .with_vol_ident("VENDOR_DVD")
+            .with_publisher_ident("VENDOR")
+            .with_creation_time(1_600_000_000)
+            .with_rock_ridge()
+            .with_joliet()
+            .with_hybrid_mbr(HybridMbr::new(vec![0xEB; 400]).with_disk_signature(0xCAFE))
+            .with_gpt(Gpt::new(EfiSystemPartition::BootImage).with_disk_guid(Guid::from_seed(7)));
+        builder.add_file("isolinux/isolinux.bin", isolinux.clone()).unwrap();
+        builder.add_file("isolinux/isolinux.cfg", b"default linux\n".to_vec()).unwrap();
+        builder.add_file(&format!("etc/{}", long_name), b"long".to_vec()).unwrap();
+        builder.add_symlink("etc/link", "../isolinux/isolinux.cfg").unwrap();
+        builder.add_file("bin/tool", b"#!/bin/sh\n".to_vec()).unwrap();
+        builder.set_attrs("bin/tool", FileAttrs::new(0o755).with_owner(1000, 100).with_mtime(1_500_000_000)).unwrap();
+        builder.add_hidden_file("floppy.img", vec![0xF6; 1440 * 1024]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_sector_count(4).with_boot_info_table());
+        builder.add_boot_image(BootImage::emulation("floppy.img", BootMedia::Floppy1_44));
+        builder.add_efi_boot_image("efi.img", &FatBuilder::new()).unwrap();
+        let image = builder.build().unwrap();
+
+        let mut builder = IsoBuilder::from_image(MemDevice::new(image.clone())).unwrap();
+        builder.add_file("ks.cfg", b"text\n".to_vec()).unwrap();
+        builder.replace_file("isolinux/isolinux.cfg", b"default ks\n".to_vec()).unwrap();
+        let repacked = builder.build().unwrap();
+        assert_ne!(repacked, image);
+
+        let mut fs = IsoFs::open(MemDevice::new(&repacked)).unwrap();
+        assert_eq!(fs.pvd().vol_ident.as_ref().unwrap().as_str().trim_end(), "VENDOR_DVD");
+        assert_eq!(fs.pvd().publisher_ident.as_ref().unwrap().as_str().trim_end(), "VENDOR");
+        assert!(fs.has_rock_ridge().unwrap());
+        assert!(fs.joliet().unwrap().is_some());
+
+        let etc = fs.lookup("etc").unwrap();
+        let mut names = Vec::new();
+        for record in fs.read_dir(&etc).unwrap().iter().filter(|r| !r.is_special()) {
+            let rr = fs.rock_ridge(record).unwrap().unwrap();
+            names.push((rr.name.unwrap(), rr.symlink));
+        }
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&(long_name.clone(), None)));
+        assert!(names.contains(&("link".to_owned(), Some("../isolinux/isolinux.cfg".to_owned()))));
+        let tool = fs.lookup("bin/tool").unwrap();
+        let rr = fs.rock_ridge(&tool).unwrap().unwrap();
+        assert_eq!(rr.attrs.unwrap().mode, PosixAttrs::S_IFREG | 0o755);
+        assert_eq!((rr.attrs.unwrap().uid, rr.times.modify), (1000, Some(1_500_000_000)));
+        let cfg = fs.lookup("isolinux/isolinux.cfg").unwrap();
+        assert_eq!(fs.read_file(&cfg).unwrap(), b"default ks\n");
+        assert!(matches!(fs.lookup("floppy.img"), Err(VDErr::NotFound)));
+
+        let entries = fs.boot_entries().unwrap();
+        let names: Vec<String> = entries.iter().map(BootEntry::file_name).collect();
+        assert_eq!(names, ["boot-00-x86.img", "boot-01-x86.img", "boot-02-uefi.img"]);
+        assert_eq!(entries[0].sector_count, 4);
+        assert!(fs.verify_boot_info_table(&entries[0].file.as_ref().unwrap().1).unwrap());
+        assert!(entries[1].file.is_none());
+        assert_eq!(fs.read_boot_image(&entries[1]).unwrap(), vec![0xF6; 1440 * 1024]);
+
+        assert_eq!(&repacked[..400], &[0xEB; 400]);
+        assert_eq!(repacked[440..444], 0xCAFE_u32.to_le_bytes());
+        let partitions = check_gpt(&repacked);
+        let efi = fs.lookup("efi.img").unwrap();
+        assert_eq!(partitions[1].first_lba, efi.extent_location as u64 * 4);
+        let header = GptHeader::parse(&repacked[MBR_SECTOR_SIZE..]).unwrap();
+        assert_eq!(header.disk_guid, Guid::from_seed(7));
+    }
+
+    #[test]
+    fn test_from_image_idents() {
+        let mut builder = IsoBuilder::new().with_rock_ridge();
+        builder.add_file("ReadMe.txt", b"Installer 2.0".to_vec()).unwrap();
+        let mut image = builder.build().unwrap();
+        // as other tools write them, text and a reference to a file
+        let pvd = &mut image[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
+        pvd[318..446].copy_from_slice(format!("{:128}", "Vendor Inc.").as_bytes());
+        pvd[446..574].copy_from_slice(format!("{:128}", "Release team").as_bytes());
+        pvd[574..702].copy_from_slice(format!("_{:127}", "README.TXT").as_bytes());
+
+        let builder = IsoBuilder::from_image(MemDevice::new(image)).unwrap();
+        let repacked = builder.build().unwrap();
+        let pvd = &repacked[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
+        assert_eq!(&pvd[318..446], format!("{:128}", "Vendor Inc.").as_bytes());
+        assert_eq!(&pvd[446..574], format!("{:128}", "Release team").as_bytes());
+        assert_eq!(&pvd[574..702], format!("_{:127}", "README.TXT").as_bytes());
+        let view = PvdRef::new(pvd).unwrap();
+        assert!(matches!(view.publisher_ident().unwrap(), Some(VolumeIdent::Text(s)) if s.as_str() == "Vendor Inc."));
+    }
+
+    #[test]
+    fn test_volume_idents() {
+        let mut builder = IsoBuilder::new()
+            .with_publisher_ident("ACME")
+            .with_app_file("readme.txt");
+        builder.add_file("readme.txt", b"MKISO 1.0".to_vec()).unwrap();
+        let image = builder.build().unwrap();
+
+        // text is written as is, only file references start with a `_`
+        let pvd = &image[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
+        assert_eq!(&pvd[318..446], format!("{:128}", "ACME").as_bytes());
+        assert_eq!(&pvd[446..574], [b' '; 128]);
+        assert_eq!(&pvd[574..702], format!("_{:127}", "README.TXT").as_bytes());
+
+        let fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        assert!(matches!(&fs.pvd().publisher_ident, Some(VolumeIdent::Text(s)) if s.as_str() == "ACME"));
+        assert!(fs.pvd().data_prep_ident.is_none());
+        assert!(matches!(&fs.pvd().app_ident, Some(VolumeIdent::File(s)) if s.as_str() == "README.TXT"));
+
+        let builder = IsoBuilder::new().with_publisher_file("missing.txt");
+        assert!(matches!(builder.build(), Err(BuildErr::NotFound(_))));
+        let builder = IsoBuilder::new().with_vol_ident(&"A".repeat(33));
+        assert!(matches!(builder.build(), Err(BuildErr::IdentifierTooLong(_, 32))));
+        let builder = IsoBuilder::new().with_app_ident(&"A".repeat(129));
+        assert!(matches!(builder.build(), Err(BuildErr::IdentifierTooLong(_, 128))));
+    }
+
+    #[test]
+    fn test_host_file_and_catalog() {
+        let path = std::env::temp_dir().join(format!("iso9660-host-{}.bin", std::process::id()));
+        let isolinux: Vec<u8> = (0..3000_u32).map(|i| (i * 3) as u8).collect();
+        std::fs::write(&path, &isolinux).unwrap();
+
+        let mut builder = IsoBuilder::new();
+        builder.add_host_file("isolinux/isolinux.bin", &path).unwrap();
+        builder.add_boot_catalog("isolinux/boot.cat").unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_boot_info_table());
+        let image = builder.build();
+        std::fs::remove_file(&path).unwrap();
+        let image = image.unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let record = fs.lookup("isolinux/isolinux.bin").unwrap();
+        assert!(fs.verify_boot_info_table(&record).unwrap());
+        assert_eq!(fs.read_file(&record).unwrap()[64..], isolinux[64..]);
+        let catalog = fs.lookup("isolinux/boot.cat").unwrap();
+        assert_eq!(Some(catalog.extent_location), fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr);
+        assert_eq!(fs.read_file(&catalog).unwrap(), fs.read_boot_catalog().unwrap().unwrap());
+
+        let mut builder = IsoBuilder::new();
+        builder.add_boot_catalog("boot.cat").unwrap();
+        assert!(matches!(builder.build(), Err(BuildErr::MissingBootImage)));
+        builder.add_host_file("missing", &path).unwrap_err();
+    }
+
+    #[test]
+    fn test_unique_ident() {
+        let taken = HashSet::from([iso_ident("Makefile", false)]);
+        assert!(taken.contains(b"MAKEFILE.;1".as_slice()));
+        assert_eq!(unique_ident(iso_ident("makefile", false), false, &taken).unwrap(), b"MAKEFILE000.;1");
+
+        // once the first thousand counters are taken, they get a digit more
+        let name = "a_name_long_enough_to_be_truncated.txt";
+        let mut taken = HashSet::from([iso_ident(name, false)]);
+        for _ in 0..1000 {
+            let ident = unique_ident(iso_ident(name, false), false, &taken).unwrap();
+            taken.insert(ident);
+        }
+        assert!(taken.contains(b"A_NAME_LONG_ENOUGH_TO_BE999.TXT;1".as_slice()));
+        let ident = unique_ident(iso_ident(name, false), false, &taken).unwrap();
+        assert_eq!(ident, b"A_NAME_LONG_ENOUGH_TO_B1000.TXT;1");
+    }
+}
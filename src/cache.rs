@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use crate::{BlockDevice, BlockDeviceMut, FORM2_DATA_SIZE};
+
+/// Number of blocks kept by the cache of an `IsoFs` opened with `IsoFs::open`
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Number of blocks read past a sequential read by default
+pub const DEFAULT_READ_AHEAD: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Blocks served from the cache
+    pub hits: u64,
+    /// Blocks that had to be read from the inner device
+    pub misses: u64,
+    /// Blocks read from the inner device before they were asked for
+    pub read_ahead: u64,
+}
+
+struct CachedBlock {
+    data: Box<[u8]>,
+    last_used: u64,
+}
+
+/// Least recently used block cache over another `BlockDevice`
+///
+/// Reads that continue where the previous one stopped are assumed to be part
+/// of a sequential extent read and fetch a few more blocks from the inner
+/// device in the same request.
+pub struct CachedDevice<D> {
+    inner: D,
+    capacity: usize,
+    read_ahead: usize,
+    blocks: HashMap<u64, CachedBlock>,
+    /// `last_used` -> lba, the first entry is the next one to be evicted
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    next_lba: Option<u64>,
+    stats: CacheStats,
+    scratch: Vec<u8>,
+}
+
+impl<D: BlockDevice> CachedDevice<D> {
+    /// Keeps at most `capacity` blocks, a capacity of 0 disables the cache
+    pub fn new(inner: D, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            read_ahead: if capacity == 0 { 0 } else { DEFAULT_READ_AHEAD },
+            blocks: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            next_lba: None,
+            stats: CacheStats::default(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Number of extra blocks fetched when reads are sequential, it is capped
+    /// to the capacity of the cache
+    pub fn with_read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead = blocks.min(self.capacity);
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Drops every cached block
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.lru.clear();
+        self.next_lba = None;
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Blocks written through the returned reference are not seen by the
+    /// cache, call `clear` afterward
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn touch(&mut self, lba: u64) {
+        self.clock += 1;
+        if let Some(block) = self.blocks.get_mut(&lba) {
+            self.lru.remove(&block.last_used);
+            block.last_used = self.clock;
+            self.lru.insert(self.clock, lba);
+        }
+    }
+
+    fn insert(&mut self, lba: u64, data: &[u8]) {
+        if self.capacity == 0 {
+            return
+        }
+
+        if let Some(block) = self.blocks.get_mut(&lba) {
+            block.data.copy_from_slice(data);
+            self.touch(lba);
+            return
+        }
+
+        let buffer = if self.blocks.len() >= self.capacity {
+            // reuse the buffer of the evicted block
+            let (_, evicted) = self.lru.pop_first().expect("a full cache has a lru entry");
+            let mut block = self.blocks.remove(&evicted).expect("lru entries are cached");
+            block.data.copy_from_slice(data);
+            block.data
+        } else {
+            data.into()
+        };
+
+        self.clock += 1;
+        self.blocks.insert(lba, CachedBlock {
+            data: buffer,
+            last_used: self.clock,
+        });
+        self.lru.insert(self.clock, lba);
+    }
+
+    /// Reads `count` blocks from `lba` that are not in the cache and copies
+    /// them in `out`
+    fn fill(&mut self, lba: u64, count: usize, out: &mut [u8]) -> io::Result<()> {
+        let block_size = self.inner.block_size();
+        self.stats.misses += count as u64;
+
+        let sequential = self.next_lba == Some(lba);
+        if !sequential || self.read_ahead == 0 {
+            self.inner.read_blocks(lba, out)?;
+            for (i, block) in out.chunks_exact(block_size).enumerate() {
+                self.insert(lba + i as u64, block);
+            }
+            return Ok(())
+        }
+
+        let mut scratch = core::mem::take(&mut self.scratch);
+        scratch.resize((count + self.read_ahead) * block_size, 0);
+        let res = match self.inner.read_blocks(lba, &mut scratch) {
+            Ok(()) => {
+                self.stats.read_ahead += self.read_ahead as u64;
+                out.copy_from_slice(&scratch[..out.len()]);
+                // insert the blocks that were asked for last so that they
+                // are not the first ones to be evicted
+                for (i, block) in scratch.chunks_exact(block_size).enumerate().rev() {
+                    self.insert(lba + i as u64, block);
+                }
+                Ok(())
+            },
+            // most likely the read ahead went past the end of the device
+            Err(_) => {
+                self.inner.read_blocks(lba, out).map(|()| {
+                    for (i, block) in out.chunks_exact(block_size).enumerate() {
+                        self.insert(lba + i as u64, block);
+                    }
+                })
+            },
+        };
+        self.scratch = scratch;
+        res
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CachedDevice<D> {
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        let block_size = self.inner.block_size();
+        if !buf.len().is_multiple_of(block_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer length is not a multiple of the block size",
+            ))
+        }
+
+        let nb_blocks = buf.len() / block_size;
+        let mut i = 0;
+        while i < nb_blocks {
+            let block_lba = lba + i as u64;
+            if let Some(block) = self.blocks.get(&block_lba) {
+                buf[i * block_size..(i + 1) * block_size].copy_from_slice(&block.data);
+                self.stats.hits += 1;
+                self.touch(block_lba);
+                i += 1;
+                continue
+            }
+
+            // read every consecutive missing block in one go
+            let missing = (i..nb_blocks)
+                .take_while(|j| !self.blocks.contains_key(&(lba + *j as u64)))
+                .count();
+            let out = &mut buf[i * block_size..(i + missing) * block_size];
+            self.fill(block_lba, missing, out)?;
+            i += missing;
+        }
+
+        self.next_lba = Some(lba + nb_blocks as u64);
+        Ok(())
+    }
+
+    /// Form 2 sectors are never cached, they are only found in streamed
+    /// files that are read once
+    fn read_form2_sector(&mut self, lba: u64, buf: &mut [u8; FORM2_DATA_SIZE]) -> io::Result<()> {
+        self.inner.read_form2_sector(lba, buf)
+    }
+}
+
+/// Writes go through to the inner device, the blocks that are cached are
+/// updated
+impl<D: BlockDeviceMut> BlockDeviceMut for CachedDevice<D> {
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_blocks(lba, buf)?;
+        let block_size = self.inner.block_size();
+        for (i, block) in buf.chunks_exact(block_size).enumerate() {
+            if let Some(cached) = self.blocks.get_mut(&(lba + i as u64)) {
+                cached.data.copy_from_slice(block);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MemDevice, SECTOR_SIZE};
+
+    #[test]
+    fn test_lru_and_read_ahead() {
+        let image: Vec<u8> = (0..SECTOR_SIZE * 8).map(|i| (i / SECTOR_SIZE) as u8).collect();
+        let mut dev = CachedDevice::new(MemDevice::new(&image), 4).with_read_ahead(2);
+        let mut buf = [0_u8; SECTOR_SIZE];
+
+        dev.read_blocks(0, &mut buf).unwrap();
+        dev.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(dev.stats(), CacheStats { hits: 1, misses: 1, read_ahead: 0 });
+
+        // sequential, blocks 2 and 3 are read ahead
+        dev.read_blocks(1, &mut buf).unwrap();
+        assert_eq!(buf[0], 1);
+        dev.read_blocks(2, &mut buf).unwrap();
+        dev.read_blocks(3, &mut buf).unwrap();
+        assert_eq!(buf[0], 3);
+        assert_eq!(dev.stats(), CacheStats { hits: 3, misses: 2, read_ahead: 2 });
+
+        // the cache is full, block 0 is the least recently used one
+        dev.read_blocks(5, &mut buf).unwrap();
+        dev.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(dev.stats().misses, 4);
+
+        // read ahead past the end of the device falls back to a plain read
+        dev.read_blocks(6, &mut buf).unwrap();
+        dev.read_blocks(7, &mut buf).unwrap();
+        assert_eq!(buf[0], 7);
+    }
+}
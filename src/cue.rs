@@ -0,0 +1,405 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::*;
+
+/// Number of frames, or sectors, in a second of audio
+pub const FRAMES_PER_SECOND: u32 = 75;
+
+#[derive(Debug)]
+pub enum CueErr {
+    Io(io::Error),
+    /// A line that could not be parsed, numbered from 1
+    Syntax {
+        line: usize,
+    },
+    /// A `TRACK` or `INDEX` before any `FILE` or `TRACK`
+    Orphan {
+        line: usize,
+    },
+    /// An `INDEX` before the previous one of its file
+    Unordered {
+        line: usize,
+    },
+    /// The file `name` ends before the tracks the sheet puts in it
+    Truncated {
+        name: String,
+    },
+    /// The sheet has no data track
+    NoDataTrack,
+}
+
+impl From<io::Error> for CueErr {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl fmt::Display for CueErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Syntax { line } => write!(f, "syntax error on line {}", line),
+            Self::Orphan { line } => write!(f, "line {} is not in a FILE or TRACK", line),
+            Self::Unordered { line } => write!(f, "the index on line {} comes before the previous one", line),
+            Self::Truncated { name } => write!(f, "{} ends before its last track", name),
+            Self::NoDataTrack => f.write_str("no data track"),
+        }
+    }
+}
+
+impl std::error::Error for CueErr {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackMode {
+    Audio,
+    /// `MODE1/2048`
+    Mode1Cooked,
+    /// `MODE1/2352`
+    Mode1Raw,
+    /// `MODE2/2336`
+    Mode2,
+    /// `MODE2/2352`
+    Mode2Raw,
+    Other(String),
+}
+
+impl TrackMode {
+    fn parse(s: &str) -> Self {
+        match s.to_ascii_uppercase().as_str() {
+            "AUDIO" => Self::Audio,
+            "MODE1/2048" => Self::Mode1Cooked,
+            "MODE1/2352" => Self::Mode1Raw,
+            "MODE2/2336" => Self::Mode2,
+            "MODE2/2352" => Self::Mode2Raw,
+            _ => Self::Other(s.to_owned()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Audio => "AUDIO",
+            Self::Mode1Cooked => "MODE1/2048",
+            Self::Mode1Raw => "MODE1/2352",
+            Self::Mode2 => "MODE2/2336",
+            Self::Mode2Raw => "MODE2/2352",
+            Self::Other(s) => s,
+        }
+    }
+
+    pub fn is_data(&self) -> bool {
+        matches!(self, Self::Mode1Cooked | Self::Mode1Raw | Self::Mode2 | Self::Mode2Raw)
+    }
+
+    /// Size of a sector in the file, `None` for unknown modes
+    pub fn sector_size(&self) -> Option<usize> {
+        match self {
+            Self::Mode1Cooked => Some(SECTOR_SIZE),
+            Self::Mode2 => Some(MODE2_SECTOR_SIZE),
+            Self::Audio | Self::Mode1Raw | Self::Mode2Raw => Some(RAW_SECTOR_SIZE),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CueTrack {
+    pub number: u8,
+    pub mode: TrackMode,
+    /// Index number and position in frames from the start of the file
+    pub indexes: Vec<(u8, u32)>,
+    /// Frames of silence that are not stored in the file
+    pub pregap: u32,
+}
+
+impl CueTrack {
+    /// Frame at which the track starts in its file, its pregap included
+    pub fn file_start(&self) -> u32 {
+        self.indexes.iter().map(|(_, frame)| *frame).min().unwrap_or(0)
+    }
+
+    /// Frame of `INDEX 01` in the file
+    pub fn start(&self) -> u32 {
+        self.indexes.iter()
+            .find(|(number, _)| *number == 1)
+            .map(|(_, frame)| *frame)
+            .unwrap_or_else(|| self.file_start())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CueFile {
+    pub name: String,
+    pub file_type: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueFile {
+    /// Offset in bytes of `frame` in the file, counting each frame with the
+    /// sector size of the track it belongs to
+    pub fn byte_offset(&self, frame: u32) -> u64 {
+        let mut offset = 0;
+        for (i, track) in self.tracks.iter().enumerate() {
+            let start = track.file_start();
+            if start >= frame {
+                break
+            }
+            let end = self.tracks.get(i + 1)
+                .map(|t| t.file_start())
+                .unwrap_or(u32::MAX)
+                .min(frame);
+            let size = track.mode.sector_size().unwrap_or(RAW_SECTOR_SIZE);
+            offset += end.saturating_sub(start) as u64 * size as u64;
+        }
+        offset
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CueSheet {
+    pub files: Vec<CueFile>,
+}
+
+/// Parses `mm:ss:ff` in frames
+fn parse_msf(s: &str) -> Option<u32> {
+    let mut parts = s.split(':').map(|p| p.parse::<u32>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || s >= 60 || f >= FRAMES_PER_SECOND {
+        return None
+    }
+    Some((m * 60 + s) * FRAMES_PER_SECOND + f)
+}
+
+/// Splits `line` on spaces, double quoted words may contain spaces
+fn split_words(line: &str) -> Option<Vec<&str>> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            words.push(&quoted[..end]);
+            rest = quoted[end + 1..].trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            words.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+    }
+    Some(words)
+}
+
+/// Formats `frames` as `mm:ss:ff`
+fn fmt_msf(frames: u32) -> String {
+    let seconds = frames / FRAMES_PER_SECOND;
+    format!("{:02}:{:02}:{:02}", seconds / 60, seconds % 60, frames % FRAMES_PER_SECOND)
+}
+
+impl CueSheet {
+    /// A sheet with a single data track filling the image file `name`
+    pub fn single_track(name: &str, mode: TrackMode) -> Self {
+        Self {
+            files: vec![CueFile {
+                name: name.to_owned(),
+                file_type: "BINARY".to_owned(),
+                tracks: vec![CueTrack {
+                    number: 1,
+                    mode,
+                    indexes: vec![(1, 0)],
+                    pregap: 0,
+                }],
+            }],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CueErr> {
+        let mut sheet = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_nb = i + 1;
+            let syntax = || CueErr::Syntax { line: line_nb };
+            let orphan = || CueErr::Orphan { line: line_nb };
+
+            let words = split_words(line).ok_or_else(syntax)?;
+            let Some(command) = words.first() else { continue };
+
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    let [_, name, file_type] = words[..] else { return Err(syntax()) };
+                    sheet.files.push(CueFile {
+                        name: name.to_owned(),
+                        file_type: file_type.to_owned(),
+                        tracks: Vec::new(),
+                    });
+                },
+                "TRACK" => {
+                    let [_, number, mode] = words[..] else { return Err(syntax()) };
+                    let number = number.parse().map_err(|_| syntax())?;
+                    let file = sheet.files.last_mut().ok_or_else(orphan)?;
+                    file.tracks.push(CueTrack {
+                        number,
+                        mode: TrackMode::parse(mode),
+                        indexes: Vec::new(),
+                        pregap: 0,
+                    });
+                },
+                "INDEX" => {
+                    let [_, number, msf] = words[..] else { return Err(syntax()) };
+                    let number = number.parse().map_err(|_| syntax())?;
+                    let frame = parse_msf(msf).ok_or_else(syntax)?;
+                    let file = sheet.files.last_mut().ok_or_else(orphan)?;
+                    // the length of a track is the distance to the next one
+                    let previous = file.tracks.iter().flat_map(|t| &t.indexes).next_back().map(|(_, f)| *f);
+                    if previous.is_some_and(|previous| frame < previous) {
+                        return Err(CueErr::Unordered { line: line_nb })
+                    }
+                    let track = file.tracks.last_mut().ok_or_else(orphan)?;
+                    track.indexes.push((number, frame));
+                },
+                "PREGAP" => {
+                    let [_, msf] = words[..] else { return Err(syntax()) };
+                    let frames = parse_msf(msf).ok_or_else(syntax)?;
+                    let track = sheet.files.last_mut()
+                        .and_then(|f| f.tracks.last_mut())
+                        .ok_or_else(orphan)?;
+                    track.pregap = frames;
+                },
+                // metadata that has nothing to do with where the data is
+                _ => (),
+            }
+        }
+
+        Ok(sheet)
+    }
+
+    /// The first data track and the file it is in
+    pub fn data_track(&self) -> Option<(&CueFile, &CueTrack)> {
+        self.files.iter()
+            .flat_map(|f| f.tracks.iter().map(move |t| (f, t)))
+            .find(|(_, t)| t.mode.is_data())
+    }
+}
+
+impl fmt::Display for CueSheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            writeln!(f, "FILE \"{}\" {}", file.name, file.file_type)?;
+            for track in &file.tracks {
+                writeln!(f, "  TRACK {:02} {}", track.number, track.mode.as_str())?;
+                if track.pregap != 0 {
+                    writeln!(f, "    PREGAP {}", fmt_msf(track.pregap))?;
+                }
+                for (number, frame) in &track.indexes {
+                    writeln!(f, "    INDEX {:02} {}", number, fmt_msf(*frame))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Opens the data track of the cue sheet at `path`
+///
+/// The image files are looked up next to the sheet. Previous files are
+/// assumed to hold the sectors that come before on the disc, so that the
+/// addresses recorded in the volume still match.
+pub fn open_cue<P: AsRef<Path>>(path: P) -> Result<RawSectorDevice<IoDevice<File>>, CueErr> {
+    let path = path.as_ref();
+    let sheet = CueSheet::parse(&std::fs::read_to_string(path)?)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let (file, track) = sheet.data_track().ok_or(CueErr::NoDataTrack)?;
+    let sector_size = track.mode.sector_size().ok_or(CueErr::NoDataTrack)?;
+
+    // sectors of the files that come before the one of the data track
+    let mut first_lba = 0;
+    for previous in sheet.files.iter().take_while(|f| !core::ptr::eq(*f, file)) {
+        let len = std::fs::metadata(dir.join(&previous.name))?.len();
+        let truncated = || CueErr::Truncated { name: previous.name.clone() };
+        for (i, t) in previous.tracks.iter().enumerate() {
+            let start = previous.byte_offset(t.file_start());
+            let end = match previous.tracks.get(i + 1) {
+                Some(next) => previous.byte_offset(next.file_start()),
+                None => len,
+            };
+            let len = end.checked_sub(start).ok_or_else(truncated)?;
+            first_lba += len / t.mode.sector_size().unwrap_or(RAW_SECTOR_SIZE) as u64;
+        }
+    }
+
+    let bin = File::open(dir.join(&file.name))?;
+    let start = track.file_start();
+    if file.byte_offset(start) > bin.metadata()?.len() {
+        return Err(CueErr::Truncated { name: file.name.clone() })
+    }
+    let dev = IoDevice::with_block_size(bin, sector_size)
+        .with_offset(file.byte_offset(start));
+    Ok(RawSectorDevice::new(dev)?.with_first_lba(first_lba + start as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mixed_mode_sheet() {
+        let sheet = CueSheet::parse(concat!(
+            "REM GENRE Game\n",
+            "FILE \"My Game (Track 1).bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 MODE2/2352\n",
+            "    INDEX 00 00:02:00\n",
+            "    INDEX 01 00:04:00\n",
+        )).unwrap();
+
+        let (file, track) = sheet.data_track().unwrap();
+        assert_eq!(file.name, "My Game (Track 1).bin");
+        assert_eq!(track.number, 2);
+        assert_eq!(track.file_start(), 150);
+        assert_eq!(track.start(), 300);
+        assert_eq!(file.byte_offset(track.file_start()), 150 * RAW_SECTOR_SIZE as u64);
+
+        let text = CueSheet::single_track("out.bin", TrackMode::Mode1Raw).to_string();
+        assert_eq!(text, "FILE \"out.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n");
+
+        assert!(matches!(CueSheet::parse("TRACK 01 AUDIO"), Err(CueErr::Orphan { line: 1 })));
+        assert!(matches!(CueSheet::parse("FILE \"a.bin BINARY"), Err(CueErr::Syntax { line: 1 })));
+        let unordered = "FILE \"a.bin\" BINARY\nTRACK 01 AUDIO\nINDEX 01 00:04:00\nTRACK 02 MODE1/2352\nINDEX 01 00:02:00\n";
+        assert!(matches!(CueSheet::parse(unordered), Err(CueErr::Unordered { line: 5 })));
+    }
+
+    #[test]
+    fn test_open_truncated() {
+        let dir = std::env::temp_dir().join(format!("iso9660-cue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // the audio file is shorter than its second track says
+        std::fs::write(dir.join("audio.bin"), vec![0; 100 * RAW_SECTOR_SIZE]).unwrap();
+        std::fs::write(dir.join("data.bin"), vec![0; 20 * RAW_SECTOR_SIZE]).unwrap();
+        let sheet = concat!(
+            "FILE \"audio.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:04:00\n",
+            "FILE \"data.bin\" BINARY\n",
+            "  TRACK 03 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+        );
+        std::fs::write(dir.join("disc.cue"), sheet).unwrap();
+        assert!(matches!(open_cue(dir.join("disc.cue")), Err(CueErr::Truncated { name }) if name == "audio.bin"));
+
+        // and the data track starts past the end of its file
+        let sheet = concat!(
+            "FILE \"data.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 MODE1/2352\n",
+            "    INDEX 01 00:01:00\n",
+        );
+        std::fs::write(dir.join("disc.cue"), sheet).unwrap();
+        assert!(matches!(open_cue(dir.join("disc.cue")), Err(CueErr::Truncated { name }) if name == "data.bin"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use std::io::{self, Read, Write};
+
+use crate::*;
+
+/// Where the EDC, the P parity and the Q parity of a mode 1 sector are
+const MODE1_EDC: usize = 2064;
+const ECC_P: usize = 2076;
+const ECC_Q: usize = 2248;
+
+/// Where the EDC of a mode 2 sector is, depending on its form
+const FORM1_EDC: usize = 2072;
+const FORM2_EDC: usize = 2348;
+
+const fn edc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut edc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { 0xd801_8001 } else { 0 };
+            bit += 1;
+        }
+        table[i] = edc;
+        i += 1;
+    }
+    table
+}
+
+static EDC_TABLE: [u32; 256] = edc_table();
+
+/// Multiplication by alpha in GF(2^8), with the x^8 + x^4 + x^3 + x^2 + 1
+/// polynomial of the CD-ROM Reed-Solomon code
+const fn ecc_f_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = ((i << 1) ^ if i & 0x80 != 0 { 0x11d } else { 0 }) as u8;
+        i += 1;
+    }
+    table
+}
+
+/// Division by alpha + 1, the inverse of `x -> x ^ f(x)`
+const fn ecc_b_table() -> [u8; 256] {
+    let f = ecc_f_table();
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i ^ f[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+static ECC_F_TABLE: [u8; 256] = ecc_f_table();
+static ECC_B_TABLE: [u8; 256] = ecc_b_table();
+
+/// The CD-ROM error detection code, a CRC-32 that is stored little endian
+pub fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |edc, b| (edc >> 8) ^ EDC_TABLE[((edc ^ *b as u32) & 0xff) as usize])
+}
+
+/// Computes one of the two product codes over `src`, the sector without its
+/// sync pattern
+///
+/// NB: the bytes are seen as a grid of 16 bits words, P codewords are its
+/// columns and Q codewords its diagonals.
+fn ecc_compute(src: &[u8], major_count: usize, minor_count: usize, major_mult: usize, minor_inc: usize, out: &mut [u8]) {
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0_u8;
+        let mut ecc_b = 0_u8;
+        for _ in 0..minor_count {
+            let b = src[index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= b;
+            ecc_b ^= b;
+            ecc_a = ECC_F_TABLE[ecc_a as usize];
+        }
+        ecc_a = ECC_B_TABLE[(ECC_F_TABLE[ecc_a as usize] ^ ecc_b) as usize];
+        out[major] = ecc_a;
+        out[major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+/// P and Q parity of `sector`, `(p, q)`
+fn ecc_parity(sector: &[u8]) -> ([u8; 172], [u8; 104]) {
+    let mut p = [0_u8; 172];
+    let mut q = [0_u8; 104];
+    ecc_compute(&sector[12..ECC_P], 86, 24, 2, 86, &mut p);
+
+    // Q also protects the P parity
+    let mut with_p = [0_u8; ECC_Q - 12];
+    with_p[..ECC_P - 12].copy_from_slice(&sector[12..ECC_P]);
+    with_p[ECC_P - 12..].copy_from_slice(&p);
+    ecc_compute(&with_p, 52, 43, 86, 88, &mut q);
+    (p, q)
+}
+
+/// What is wrong with a raw sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorDefect {
+    /// No sync pattern, the sector is not where it should be or is garbage
+    Sync,
+    /// The mode byte of the header is not 0, 1 or 2
+    Mode(u8),
+    Edc,
+    /// The EDC matches but the parity does not, it is the parity that is
+    /// damaged
+    Ecc,
+}
+
+/// Checks the EDC and ECC of a 2352 bytes `sector`
+///
+/// The ECC of a mode 2 form 1 sector is computed as if its header was zeroed,
+/// so that it survives the header being rewritten. Form 2 sectors have no ECC
+/// and a zero EDC means that there is none.
+pub fn check_raw_sector(sector: &[u8]) -> Result<SectorMode, SectorDefect> {
+    if sector[..12] != SYNC_PATTERN {
+        return Err(SectorDefect::Sync)
+    }
+    let (mode, _) = SectorFormat::Raw.sector_mode(sector)
+        .map_err(|_| SectorDefect::Mode(sector[15]))?;
+
+    let read_edc = |at: usize| u32::from_le_bytes(sector[at..at + 4].try_into().unwrap());
+    let (edc_ok, ecc_ok) = match mode {
+        SectorMode::Mode0 => (true, true),
+        SectorMode::Mode1 => {
+            let (p, q) = ecc_parity(sector);
+            (
+                read_edc(MODE1_EDC) == edc(&sector[..MODE1_EDC]),
+                sector[ECC_P..ECC_Q] == p && sector[ECC_Q..] == q,
+            )
+        },
+        SectorMode::Mode2Form1 => {
+            let mut zeroed = [0_u8; RAW_SECTOR_SIZE];
+            zeroed.copy_from_slice(sector);
+            zeroed[12..16].fill(0);
+            let (p, q) = ecc_parity(&zeroed);
+            (
+                read_edc(FORM1_EDC) == edc(&sector[16..FORM1_EDC]),
+                sector[ECC_P..ECC_Q] == p && sector[ECC_Q..] == q,
+            )
+        },
+        SectorMode::Mode2Form2 => {
+            let stored = read_edc(FORM2_EDC);
+            (stored == 0 || stored == edc(&sector[16..FORM2_EDC]), true)
+        },
+    };
+
+    match (edc_ok, ecc_ok) {
+        (false, _) => Err(SectorDefect::Edc),
+        (true, false) => Err(SectorDefect::Ecc),
+        (true, true) => Ok(mode),
+    }
+}
+
+/// Recomputes the EDC and ECC of a 2352 bytes `sector` after its user data
+/// was modified, its header must already be right
+pub fn regenerate_sector(sector: &mut [u8], mode: SectorMode) {
+    match mode {
+        SectorMode::Mode0 => (),
+        SectorMode::Mode1 => {
+            let edc = edc(&sector[..MODE1_EDC]);
+            sector[MODE1_EDC..MODE1_EDC + 4].copy_from_slice(&edc.to_le_bytes());
+            sector[MODE1_EDC + 4..ECC_P].fill(0);
+            let (p, q) = ecc_parity(sector);
+            sector[ECC_P..ECC_Q].copy_from_slice(&p);
+            sector[ECC_Q..].copy_from_slice(&q);
+        },
+        SectorMode::Mode2Form1 => {
+            let edc = edc(&sector[16..FORM1_EDC]);
+            sector[FORM1_EDC..FORM1_EDC + 4].copy_from_slice(&edc.to_le_bytes());
+            let header: [u8; 4] = sector[12..16].try_into().unwrap();
+            sector[12..16].fill(0);
+            let (p, q) = ecc_parity(sector);
+            sector[12..16].copy_from_slice(&header);
+            sector[ECC_P..ECC_Q].copy_from_slice(&p);
+            sector[ECC_Q..].copy_from_slice(&q);
+        },
+        SectorMode::Mode2Form2 => {
+            let edc = edc(&sector[16..FORM2_EDC]);
+            sector[FORM2_EDC..].copy_from_slice(&edc.to_le_bytes());
+        },
+    }
+}
+
+/// Builds the raw mode 1 sector at `lba` holding the 2048 bytes of `data`
+pub fn encode_mode1_sector(lba: u64, data: &[u8], out: &mut [u8]) {
+    raw_sector_header(lba, 1, out);
+    out[16..16 + SECTOR_SIZE].copy_from_slice(data);
+    regenerate_sector(out, SectorMode::Mode1);
+}
+
+/// A run of consecutive sectors with the same defect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptRange {
+    pub start: u64,
+    pub count: u64,
+    pub defect: SectorDefect,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of sectors that were checked
+    pub sectors: u64,
+    pub corrupt: Vec<CorruptRange>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+
+    /// Total number of corrupt sectors
+    pub fn corrupt_sectors(&self) -> u64 {
+        self.corrupt.iter().map(|r| r.count).sum()
+    }
+
+    fn push(&mut self, lba: u64, defect: SectorDefect) {
+        match self.corrupt.last_mut() {
+            Some(last) if last.defect == defect && last.start + last.count == lba => last.count += 1,
+            _ => self.corrupt.push(CorruptRange {
+                start: lba,
+                count: 1,
+                defect,
+            }),
+        }
+    }
+}
+
+impl<D: BlockDevice> RawSectorDevice<D> {
+    /// Checks every sector of the image, up to the end of `inner`
+    ///
+    /// `MODE2/2336` images have no header to check but their form 1 sectors
+    /// still carry an EDC and an ECC. Cooked images have nothing to verify.
+    pub fn verify(&mut self) -> io::Result<VerifyReport> {
+        let offset = match self.format() {
+            SectorFormat::Raw => 0,
+            SectorFormat::Mode2 => 16,
+            SectorFormat::Cooked => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cooked sectors have no EDC nor ECC",
+            )),
+        };
+
+        let mut report = VerifyReport::default();
+        let mut sector = [0_u8; RAW_SECTOR_SIZE];
+        let mut lba = self.first_lba();
+        loop {
+            match self.read_raw_sector(lba, &mut sector[offset..]) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            if offset != 0 {
+                raw_sector_header(lba, 2, &mut sector);
+            }
+            if let Err(defect) = check_raw_sector(&sector) {
+                report.push(lba, defect);
+            }
+            report.sectors += 1;
+            lba += 1;
+        }
+        Ok(report)
+    }
+}
+
+/// Writes the cooked image `input` as raw mode 1 sectors, the last sector is
+/// padded with zeros
+///
+/// Returns the number of sectors written. The matching cue sheet is
+/// `CueSheet::single_track(name, TrackMode::Mode1Raw)`.
+pub fn write_raw_mode1<R: Read, W: Write>(mut input: R, mut out: W) -> io::Result<u64> {
+    let mut data = [0_u8; SECTOR_SIZE];
+    let mut sector = [0_u8; RAW_SECTOR_SIZE];
+    let mut lba = 0;
+    loop {
+        let mut len = 0;
+        while len < SECTOR_SIZE {
+            match input.read(&mut data[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        if len == 0 {
+            return Ok(lba)
+        }
+        data[len..].fill(0);
+        encode_mode1_sector(lba, &data, &mut sector);
+        out.write_all(&sector)?;
+        lba += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_raw_mode1() {
+        let mut builder = IsoBuilder::new();
+        builder.add_file("README.TXT", b"hello".to_vec()).unwrap();
+        let cooked = builder.build().unwrap();
+
+        let mut raw = Vec::new();
+        let sectors = write_raw_mode1(&cooked[..], &mut raw).unwrap();
+        assert_eq!(sectors as usize * SECTOR_SIZE, cooked.len());
+        assert!(raw.chunks_exact(RAW_SECTOR_SIZE).all(|s| check_raw_sector(s) == Ok(SectorMode::Mode1)));
+
+        // damage the data of 16 and 17, and only the parity of 20
+        raw[16 * RAW_SECTOR_SIZE + 100] ^= 1;
+        raw[17 * RAW_SECTOR_SIZE + 2000] ^= 0x80;
+        raw[20 * RAW_SECTOR_SIZE + 2300] ^= 4;
+
+        let mut dev = RawSectorDevice::new(MemDevice::with_block_size(&raw, RAW_SECTOR_SIZE)).unwrap();
+        let report = dev.verify().unwrap();
+        assert_eq!(report.sectors, sectors);
+        assert_eq!(report.corrupt, [
+            CorruptRange { start: 16, count: 2, defect: SectorDefect::Edc },
+            CorruptRange { start: 20, count: 1, defect: SectorDefect::Ecc },
+        ]);
+
+        let mut sector = raw[16 * RAW_SECTOR_SIZE..17 * RAW_SECTOR_SIZE].to_vec();
+        regenerate_sector(&mut sector, SectorMode::Mode1);
+        assert_eq!(check_raw_sector(&sector), Ok(SectorMode::Mode1));
+    }
+}
@@ -0,0 +1,240 @@
+use std::io;
+
+use crate::*;
+
+/// Catalogs larger than this are considered corrupted
+pub(crate) const MAX_CATALOG_SECTORS: usize = 32;
+
+/// An entry of the boot catalog of an image and the image it points at
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BootEntry {
+    /// 0 for the initial entry, then the section entries in order
+    pub index: usize,
+    pub platform: Platform,
+    pub bootable: bool,
+    pub media: BootMedia,
+    pub load_segment: Option<u16>,
+    pub sys_type: u8,
+    pub sector_count: u16,
+    /// In 2K sectors
+    pub lba: u32,
+    /// The file whose extent starts at `lba`, if any
+    pub file: Option<(String, DirectoryRecord)>,
+    /// Size of the image, see `IsoFs::boot_entries`
+    pub size: u64,
+}
+
+impl BootEntry {
+    /// Name of the image when it is extracted, like `boot-00-x86.img`
+    pub fn file_name(&self) -> String {
+        let platform = match self.platform {
+            Platform::X86 => "x86",
+            Platform::PPC => "ppc",
+            Platform::Mac => "mac",
+            Platform::UEFI => "uefi",
+        };
+        format!("boot-{:02}-{}.img", self.index, platform)
+    }
+}
+
+/// Length of the catalog at the start of `buffer`, `None` if it goes on
+/// past its end
+fn catalog_len(buffer: &[u8]) -> Option<usize> {
+    let mut off = 64;
+    loop {
+        let header = buffer.get(off..off + 32)?;
+        let is_final = match header[0] {
+            0x90 => false,
+            0x91 => true,
+            _ => return Some(off),
+        };
+        let mut nb_entries = u16::from_le_bytes([header[2], header[3]]);
+        off += 32;
+        while nb_entries > 0 || buffer.get(off) == Some(&EXTENSION_ENTRY_INDICATOR) {
+            let entry = buffer.get(off..off + 32)?;
+            if entry[0] != EXTENSION_ENTRY_INDICATOR {
+                nb_entries -= 1;
+            }
+            off += 32;
+        }
+        if is_final {
+            return Some(off)
+        }
+    }
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// The boot catalog pointed at by the El Torito boot record, `None` if
+    /// the image has none
+    pub fn read_boot_catalog(&mut self) -> Result<Option<Vec<u8>>, VDErr> {
+        let Some(lba) = self.descriptors().boot_record.as_ref().and_then(|r| r.boot_catalog_addr) else {
+            return Ok(None)
+        };
+        let mut catalog = Vec::new();
+        for i in 0..MAX_CATALOG_SECTORS {
+            let mut sector = [0_u8; SECTOR_SIZE];
+            self.read_at((lba as u64 + i as u64) * SECTOR_SIZE as u64, &mut sector)?;
+            catalog.extend_from_slice(&sector);
+            if let Some(len) = catalog_len(&catalog) {
+                catalog.truncate(len);
+                return Ok(Some(catalog))
+            }
+        }
+        Err(VDErr::Truncated)
+    }
+
+    /// Every entry of the boot catalog
+    ///
+    /// The size of emulated floppies is the one of their media, and the one
+    /// of hard disks is the end of the last partition of their MBR. Other
+    /// images are as large as the file whose extent they start, or as the
+    /// `sector_count` the BIOS loads when no file matches. None goes past the
+    /// end of the volume.
+    pub fn boot_entries(&mut self) -> Result<Vec<BootEntry>, VDErr> {
+        let Some(buffer) = self.read_boot_catalog()? else {
+            return Ok(Vec::new())
+        };
+        let catalog = BootCatalogRef::new(&buffer)?;
+
+        let initial = catalog.initial_entry()?;
+        let mut entries = vec![BootEntry {
+            index: 0,
+            platform: catalog.validation_entry()?.platform_id,
+            bootable: matches!(initial.boot_indicator, BootIndicator::Bootable),
+            media: initial.boot_media,
+            load_segment: initial.load_segment,
+            sys_type: initial.sys_type,
+            sector_count: initial.sector_count,
+            lba: initial.virtual_disk_addr,
+            file: None,
+            size: 0,
+        }];
+        for section in catalog.sections() {
+            let platform = section.header()?.platform_id;
+            for entry in section.entries() {
+                let entry = entry?;
+                entries.push(BootEntry {
+                    index: entries.len(),
+                    platform,
+                    bootable: matches!(entry.boot_indicator, BootIndicator::Bootable),
+                    media: entry.boot_media,
+                    load_segment: entry.load_segment,
+                    sys_type: entry.sys_type,
+                    sector_count: entry.sector_count,
+                    lba: entry.virtual_disk_addr,
+                    file: None,
+                    size: 0,
+                });
+            }
+        }
+
+        let files = self.files()?;
+        let volume_end = self.volume_end();
+        for entry in &mut entries {
+            let offset = entry.lba as u64 * SECTOR_SIZE as u64;
+            entry.file = files.iter()
+                .find(|(_, record)| record.data_size != 0 && self.extent_offset(record) == offset)
+                .cloned();
+            let disk_size = if matches!(entry.media, BootMedia::HardDrive) {
+                let mut sector = [0_u8; MBR_SECTOR_SIZE];
+                self.read_at(offset, &mut sector)?;
+                Mbr::parse(&sector).and_then(|mbr| mbr.partitions.iter()
+                    .filter(|p| !p.is_empty())
+                    .map(|p| (p.start_lba as u64 + p.nb_sectors as u64) * MBR_SECTOR_SIZE as u64)
+                    .max())
+            } else {
+                entry.media.floppy_size()
+            };
+            entry.size = match (disk_size, &entry.file) {
+                (Some(size), _) => size,
+                (None, Some((_, record))) => record.data_size as u64,
+                (None, None) => entry.sector_count.max(1) as u64 * MBR_SECTOR_SIZE as u64,
+            };
+            // the MBR of a hard disk image can claim anything
+            entry.size = entry.size.min(volume_end.saturating_sub(offset));
+        }
+        Ok(entries)
+    }
+
+    /// The `entry.size` bytes of the image of `entry`, which must not go past
+    /// the end of the volume
+    pub fn read_boot_image(&mut self, entry: &BootEntry) -> io::Result<Vec<u8>> {
+        let start = entry.lba as u64 * SECTOR_SIZE as u64;
+        if start.saturating_add(entry.size) > self.volume_end() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the boot image goes past the end of the volume"))
+        }
+        self.read_vec(start, entry.size)
+    }
+
+    /// Offset in bytes of the end of the volume
+    pub(crate) fn volume_end(&self) -> u64 {
+        self.pvd().vol_space_size as u64 * self.logical_block_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_boot_entries() {
+        let mut disk = vec![0x33_u8; 64 * 1024];
+        disk[..MBR_SECTOR_SIZE].fill(0);
+        MbrPartition {
+            status: 0x80,
+            ..MbrPartition::new(0x0C, 1, 127, Geometry::default())
+        }.dump(&mut disk[MBR_PARTITION_TABLE..]);
+        disk[510..512].copy_from_slice(&MBR_SIGNATURE);
+        let isolinux: Vec<u8> = (0..5000_u32).map(|i| i as u8).collect();
+        let mut fat = FatBuilder::new();
+        fat.add_file("EFI/BOOT/BOOTX64.EFI", vec![1; 100]).unwrap();
+
+        let mut builder = IsoBuilder::new();
+        builder.add_file("isolinux/isolinux.bin", isolinux.clone()).unwrap();
+        builder.add_file("boot/floppy.img", vec![0xF6; 1440 * 1024]).unwrap();
+        builder.add_file("boot/disk.img", disk.clone()).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_sector_count(4));
+        builder.add_boot_image(BootImage::emulation("boot/floppy.img", BootMedia::Floppy1_44));
+        builder.add_boot_image(BootImage::emulation("boot/disk.img", BootMedia::HardDrive));
+        builder.add_efi_boot_image("efi.img", &fat).unwrap();
+        let mut image = builder.build().unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let catalog = fs.read_boot_catalog().unwrap().unwrap();
+        // validation, initial, 2 section headers and 3 section entries
+        assert_eq!(catalog.len(), 7 * 32);
+
+        let entries = fs.boot_entries().unwrap();
+        let names: Vec<String> = entries.iter().map(BootEntry::file_name).collect();
+        assert_eq!(names, ["boot-00-x86.img", "boot-01-x86.img", "boot-02-x86.img", "boot-03-uefi.img"]);
+        assert_eq!(entries[0].file.as_ref().unwrap().0, "/ISOLINUX/ISOLINUX.BIN");
+        assert_eq!(fs.read_boot_image(&entries[0]).unwrap(), isolinux);
+        assert_eq!(fs.read_boot_image(&entries[1]).unwrap(), vec![0xF6; 1440 * 1024]);
+        assert_eq!(entries[2].sys_type, 0x0C);
+        assert_eq!(fs.read_boot_image(&entries[2]).unwrap(), disk);
+        let efi = fs.lookup("efi.img").unwrap();
+        assert_eq!(fs.read_boot_image(&entries[3]).unwrap(), fs.read_file(&efi).unwrap());
+
+        // an image no file starts at is as large as its sector count
+        let lba = entries[0].lba;
+        let start = fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr.unwrap() as usize * SECTOR_SIZE;
+        image[start + 40..start + 44].copy_from_slice(&(lba + 1).to_le_bytes());
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let entry = &fs.boot_entries().unwrap()[0];
+        assert!(entry.file.is_none());
+        assert_eq!(fs.read_boot_image(entry).unwrap(), isolinux[2048..4096]);
+
+        // a hard disk image whose partition claims 2T stops at the volume end
+        let disk_start = entries[2].lba as usize * SECTOR_SIZE;
+        let partition = disk_start + MBR_PARTITION_TABLE + 12;
+        image[partition..partition + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let mut entry = fs.boot_entries().unwrap().swap_remove(2);
+        assert_eq!(entry.size, image.len() as u64 - disk_start as u64);
+        assert_eq!(fs.read_boot_image(&entry).unwrap(), image[disk_start..]);
+        entry.size += 1;
+        let err = fs.read_boot_image(&entry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
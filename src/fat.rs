@@ -0,0 +1,676 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::*;
+
+const FAT_SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+/// Characters of a long name held by one of its directory entries
+const LFN_CHARS: usize = 13;
+
+/// Above this many clusters a volume is FAT16, see the FAT specification
+const FAT12_MAX_CLUSTERS: usize = 4084;
+const FAT16_MAX_CLUSTERS: usize = 65524;
+
+const ROOT_ENTRIES: usize = 512;
+
+mod fat_attr {
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    pub const LONG_NAME: u8 = 0x0F;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FatDir {
+    children: BTreeMap<String, FatNode>,
+}
+
+#[derive(Debug, Clone)]
+enum FatNode {
+    File(Vec<u8>),
+    Dir(FatDir),
+}
+
+/// Builds a FAT12 or FAT16 image, the smallest one that holds its files
+///
+/// This is what a UEFI El Torito entry points at, see
+/// `IsoBuilder::add_efi_boot_image`.
+///
+/// ```no_run
+/// # use iso9660::FatBuilder;
+/// let mut efi = FatBuilder::new();
+/// efi.add_host_file("EFI/BOOT/BOOTX64.EFI", "/usr/lib/grub/x86_64-efi/monolithic/grubx64.efi").unwrap();
+/// let image = efi.build().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FatBuilder {
+    volume_label: String,
+    volume_id: u32,
+    creation_time: i64,
+    root: FatDir,
+}
+
+impl Default for FatBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FatBuilder {
+    /// An empty volume without a label, created now
+    pub fn new() -> Self {
+        let creation_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Self {
+            volume_label: String::new(),
+            volume_id: creation_time as u32,
+            creation_time,
+            root: FatDir::default(),
+        }
+    }
+
+    /// At most 11 characters, they are upper cased
+    pub fn with_volume_label(mut self, label: &str) -> Self {
+        self.volume_label = label.to_ascii_uppercase();
+        self
+    }
+
+    pub fn with_volume_id(mut self, id: u32) -> Self {
+        self.volume_id = id;
+        self
+    }
+
+    /// Date of every entry, in seconds since the unix epoch
+    pub fn with_creation_time(mut self, secs: i64) -> Self {
+        self.creation_time = secs;
+        self
+    }
+
+    /// Adds the file `path`, missing parent directories are created
+    ///
+    /// Names are compared regardless of case as FAT does.
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), BuildErr> {
+        if data.len() > u32::MAX as usize {
+            return Err(BuildErr::FileTooLarge(path.to_owned()))
+        }
+        let (parent, name) = self.parent_of(path)?;
+        if find_ignore_case(parent, name).is_some() {
+            return Err(BuildErr::AlreadyExists(path.to_owned()))
+        }
+        parent.children.insert(name.to_owned(), FatNode::File(data));
+        Ok(())
+    }
+
+    /// Adds the file `path` with the content of the host file `host_path`
+    pub fn add_host_file<P: AsRef<Path>>(&mut self, path: &str, host_path: P) -> Result<(), BuildErr> {
+        let data = std::fs::read(host_path)?;
+        self.add_file(path, data)
+    }
+
+    /// Adds the content of the host directory `host_path` under `path`,
+    /// recursively
+    pub fn add_host_dir<P: AsRef<Path>>(&mut self, path: &str, host_path: P) -> Result<(), BuildErr> {
+        for entry in std::fs::read_dir(host_path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let child = format!("{}/{}", path.trim_end_matches('/'), name);
+            if entry.file_type()?.is_dir() {
+                self.add_host_dir(&child, entry.path())?;
+            } else {
+                self.add_host_file(&child, entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parent_of<'a, 'p>(&'a mut self, path: &'p str) -> Result<(&'a mut FatDir, &'p str), BuildErr> {
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let name = components.pop().ok_or_else(|| BuildErr::InvalidPath(path.to_owned()))?;
+        if name == "." || name == ".." || components.iter().any(|c| *c == "." || *c == "..") {
+            return Err(BuildErr::InvalidPath(path.to_owned()))
+        }
+
+        let mut dir = &mut self.root;
+        for component in components {
+            let key = find_ignore_case(dir, component)
+                .map(|k| k.to_owned())
+                .unwrap_or_else(|| component.to_owned());
+            let node = dir.children.entry(key).or_insert_with(|| FatNode::Dir(FatDir::default()));
+            dir = match node {
+                FatNode::Dir(d) => d,
+                FatNode::File(_) => return Err(BuildErr::NotADirectory(path.to_owned())),
+            };
+        }
+        Ok((dir, name))
+    }
+
+    /// Writes the image in memory
+    pub fn build(&self) -> Result<Vec<u8>, BuildErr> {
+        let mut dirs = Vec::new();
+        lay_out_dir(&self.root, 0, &mut dirs);
+        let geometry = FatGeometry::new(&dirs)?;
+
+        let mut image = vec![0_u8; geometry.total_sectors * FAT_SECTOR_SIZE];
+        geometry.dump_boot_sector(self, &mut image[..FAT_SECTOR_SIZE]);
+
+        // clusters are handed out in directory order, every chain is
+        // contiguous
+        let mut fat = Fat::new(geometry.ty, geometry.nb_clusters);
+        let mut next_cluster = 2;
+        let mut allocate = |len: usize| -> u32 {
+            let count = len.div_ceil(geometry.cluster_size);
+            if count == 0 {
+                return 0
+            }
+            let first = next_cluster;
+            next_cluster += count as u32;
+            fat.chain(first, count);
+            first
+        };
+
+        let dir_clusters: Vec<u32> = dirs.iter()
+            .enumerate()
+            .map(|(i, dir)| if i == 0 { 0 } else { allocate(dir.size().max(1)) })
+            .collect();
+        let file_clusters: Vec<Vec<u32>> = dirs.iter()
+            .map(|dir| dir.entries.iter()
+                .map(|e| match e.kind {
+                    FatEntryKind::File(data) => allocate(data.len()),
+                    FatEntryKind::Dir(_) => 0,
+                })
+                .collect())
+            .collect();
+
+        let fat_bytes = fat.into_bytes(geometry.fat_sectors * FAT_SECTOR_SIZE);
+        for i in 0..2 {
+            let start = (1 + i * geometry.fat_sectors) * FAT_SECTOR_SIZE;
+            image[start..start + fat_bytes.len()].copy_from_slice(&fat_bytes);
+        }
+
+        let (date, time) = dos_date_time(self.creation_time);
+        for (i, dir) in dirs.iter().enumerate() {
+            let mut entries = Vec::new();
+            if i == 0 {
+                if !self.volume_label.is_empty() {
+                    let mut name = [b' '; 11];
+                    let len = self.volume_label.len().min(11);
+                    name[..len].copy_from_slice(&self.volume_label.as_bytes()[..len]);
+                    entries.extend_from_slice(&short_entry(&name, fat_attr::VOLUME_ID, 0, 0, 0, date, time));
+                }
+            } else {
+                let parent = dir_clusters[dir.parent];
+                entries.extend_from_slice(&short_entry(b".          ", fat_attr::DIRECTORY, 0, dir_clusters[i], 0, date, time));
+                entries.extend_from_slice(&short_entry(b"..         ", fat_attr::DIRECTORY, 0, parent, 0, date, time));
+            }
+
+            for (entry, &file_cluster) in dir.entries.iter().zip(&file_clusters[i]) {
+                let (attr, cluster, size) = match entry.kind {
+                    FatEntryKind::Dir(d) => (fat_attr::DIRECTORY, dir_clusters[d], 0),
+                    FatEntryKind::File(data) => (fat_attr::ARCHIVE, file_cluster, data.len() as u32),
+                };
+                if let Some(long_name) = &entry.long_name {
+                    entries.extend_from_slice(&long_name_entries(long_name, &entry.short_name));
+                }
+                entries.extend_from_slice(&short_entry(&entry.short_name, attr, entry.case, cluster, size, date, time));
+            }
+
+            let offset = if i == 0 {
+                geometry.root_offset()
+            } else {
+                geometry.cluster_offset(dir_clusters[i])
+            };
+            image[offset..offset + entries.len()].copy_from_slice(&entries);
+        }
+
+        for (dir, clusters) in dirs.iter().zip(&file_clusters) {
+            for (entry, &cluster) in dir.entries.iter().zip(clusters) {
+                if let FatEntryKind::File(data) = entry.kind {
+                    if !data.is_empty() {
+                        let offset = geometry.cluster_offset(cluster);
+                        image[offset..offset + data.len()].copy_from_slice(data);
+                    }
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+fn find_ignore_case<'a>(dir: &'a FatDir, name: &str) -> Option<&'a str> {
+    dir.children.keys()
+        .find(|k| k.eq_ignore_ascii_case(name))
+        .map(|k| k.as_str())
+}
+
+enum FatEntryKind<'a> {
+    Dir(usize),
+    File(&'a [u8]),
+}
+
+struct FatEntry<'a> {
+    short_name: [u8; 11],
+    /// Lower case flags of the short name, for names that only differ from
+    /// it by their case
+    case: u8,
+    long_name: Option<String>,
+    kind: FatEntryKind<'a>,
+}
+
+struct LaidOutFatDir<'a> {
+    parent: usize,
+    entries: Vec<FatEntry<'a>>,
+}
+
+impl LaidOutFatDir<'_> {
+    /// Size in bytes, `.` and `..` included
+    fn size(&self) -> usize {
+        let entries: usize = self.entries.iter()
+            .map(|e| 1 + e.long_name.as_ref().map_or(0, |n| n.encode_utf16().count().div_ceil(LFN_CHARS)))
+            .sum();
+        (entries + 2) * DIR_ENTRY_SIZE
+    }
+}
+
+/// Directories in breadth first order, the root is first
+fn lay_out_dir<'a>(root: &'a FatDir, parent: usize, dirs: &mut Vec<LaidOutFatDir<'a>>) {
+    let mut nodes = vec![root];
+    dirs.push(LaidOutFatDir {
+        parent,
+        entries: Vec::new(),
+    });
+
+    let mut i = 0;
+    while i < nodes.len() {
+        let mut taken: Vec<[u8; 11]> = Vec::new();
+        for (name, node) in &nodes[i].children {
+            let (short_name, case, long_name) = short_name(name, &taken);
+            taken.push(short_name);
+            let kind = match node {
+                FatNode::Dir(d) => {
+                    nodes.push(d);
+                    dirs.push(LaidOutFatDir {
+                        parent: i,
+                        entries: Vec::new(),
+                    });
+                    FatEntryKind::Dir(dirs.len() - 1)
+                },
+                FatNode::File(data) => FatEntryKind::File(data),
+            };
+            dirs[i].entries.push(FatEntry {
+                short_name,
+                case,
+                long_name,
+                kind,
+            });
+        }
+        i += 1;
+    }
+}
+
+fn is_short_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+/// The 8.3 name of `name` with its case flags, and the long name to store
+/// along with it if it does not fit
+fn short_name(name: &str, taken: &[[u8; 11]]) -> ([u8; 11], u8, Option<String>) {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (name, ""),
+    };
+
+    let fits = stem.len() <= 8 && ext.len() <= 3
+        && stem.bytes().chain(ext.bytes()).all(is_short_char);
+    let single_case = |s: &str| {
+        s.bytes().all(|b| !b.is_ascii_lowercase()) || s.bytes().all(|b| !b.is_ascii_uppercase())
+    };
+    if fits && single_case(stem) && single_case(ext) {
+        let mut short = [b' '; 11];
+        short[..stem.len()].copy_from_slice(stem.to_ascii_uppercase().as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+        if !taken.contains(&short) {
+            let mut case = 0;
+            if stem.bytes().any(|b| b.is_ascii_lowercase()) {
+                case |= 0x08;
+            }
+            if ext.bytes().any(|b| b.is_ascii_lowercase()) {
+                case |= 0x10;
+            }
+            return (short, case, None)
+        }
+    }
+
+    // a numbered basis name, `LONGNA~1.TXT`
+    let mangle = |s: &str, len: usize| -> Vec<u8> {
+        s.bytes()
+            .filter(|&b| b != b' ' && b != b'.')
+            .map(|b| if is_short_char(b) { b.to_ascii_uppercase() } else { b'_' })
+            .take(len)
+            .collect()
+    };
+    let basis = mangle(stem, 8);
+    let ext = mangle(ext, 3);
+    for n in 1..1_000_000_u32 {
+        let suffix = format!("~{}", n);
+        let mut short = [b' '; 11];
+        let len = basis.len().min(8 - suffix.len());
+        short[..len].copy_from_slice(&basis[..len]);
+        short[len..len + suffix.len()].copy_from_slice(suffix.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short) {
+            return (short, 0, Some(name.to_owned()))
+        }
+    }
+    panic!("more than a million names map to the same short name");
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0_u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// The entries that come before the short entry of a long name, last part
+/// first
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Vec<u8> {
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    let checksum = short_name_checksum(short_name);
+    let parts = chars.chunks(LFN_CHARS).collect::<Vec<_>>();
+
+    let mut entries = Vec::new();
+    for (i, part) in parts.iter().enumerate().rev() {
+        let mut entry = [0_u8; DIR_ENTRY_SIZE];
+        entry[0] = (i + 1) as u8 | if i + 1 == parts.len() { 0x40 } else { 0 };
+        entry[11] = fat_attr::LONG_NAME;
+        entry[13] = checksum;
+
+        // the name is terminated by a 0 then padded with 0xFFFF
+        let mut units = [0xFFFF_u16; LFN_CHARS];
+        units[..part.len()].copy_from_slice(part);
+        if part.len() < LFN_CHARS {
+            units[part.len()] = 0;
+        }
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (unit, off) in units.iter().zip(offsets) {
+            entry[off..off + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.extend_from_slice(&entry);
+    }
+    entries
+}
+
+fn short_entry(name: &[u8; 11], attr: u8, case: u8, cluster: u32, size: u32, date: u16, time: u16) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0_u8; DIR_ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[12] = case;
+    entry[14..16].copy_from_slice(&time.to_le_bytes());
+    entry[16..18].copy_from_slice(&date.to_le_bytes());
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+/// DOS dates start in 1980 and have a 2 seconds resolution
+fn dos_date_time(secs: i64) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = civil_from_unix_time(secs);
+    let year = (year - 1980).clamp(0, 127) as u16;
+    let date = (year << 9) | ((month as u16) << 5) | day as u16;
+    let time = ((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2);
+    (date, time)
+}
+
+struct Fat {
+    ty: FatType,
+    entries: Vec<u16>,
+}
+
+impl Fat {
+    fn new(ty: FatType, nb_clusters: usize) -> Self {
+        let eoc = match ty {
+            FatType::Fat12 => 0x0FFF,
+            FatType::Fat16 => 0xFFFF,
+        };
+        let mut entries = vec![0_u16; nb_clusters + 2];
+        // media descriptor and end of chain markers
+        entries[0] = eoc & 0xFFF8;
+        entries[1] = eoc;
+        Self {
+            ty,
+            entries,
+        }
+    }
+
+    fn chain(&mut self, first: u32, count: usize) {
+        let eoc = self.entries[1];
+        for i in 0..count {
+            let cluster = first as usize + i;
+            self.entries[cluster] = if i + 1 == count { eoc } else { cluster as u16 + 1 };
+        }
+    }
+
+    fn into_bytes(self, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0_u8; len];
+        match self.ty {
+            FatType::Fat12 => {
+                // two entries share three bytes
+                for (n, &v) in self.entries.iter().enumerate() {
+                    let off = n * 3 / 2;
+                    if n % 2 == 0 {
+                        bytes[off] = v as u8;
+                        bytes[off + 1] = (bytes[off + 1] & 0xF0) | ((v >> 8) as u8 & 0x0F);
+                    } else {
+                        bytes[off] = (bytes[off] & 0x0F) | ((v as u8 & 0x0F) << 4);
+                        bytes[off + 1] = (v >> 4) as u8;
+                    }
+                }
+            },
+            FatType::Fat16 => {
+                for (n, &v) in self.entries.iter().enumerate() {
+                    bytes[n * 2..n * 2 + 2].copy_from_slice(&v.to_le_bytes());
+                }
+            },
+        }
+        bytes
+    }
+}
+
+struct FatGeometry {
+    ty: FatType,
+    sectors_per_cluster: usize,
+    cluster_size: usize,
+    root_entries: usize,
+    fat_sectors: usize,
+    nb_clusters: usize,
+    total_sectors: usize,
+}
+
+impl FatGeometry {
+    /// The smallest clusters that keep the count of a FAT16
+    fn new(dirs: &[LaidOutFatDir<'_>]) -> Result<Self, BuildErr> {
+        let root_entries = (dirs[0].size() / DIR_ENTRY_SIZE).next_multiple_of(16).max(ROOT_ENTRIES);
+        let root_sectors = root_entries * DIR_ENTRY_SIZE / FAT_SECTOR_SIZE;
+
+        for shift in 0..=7 {
+            let sectors_per_cluster = 1 << shift;
+            let cluster_size = sectors_per_cluster * FAT_SECTOR_SIZE;
+            let clusters = |len: usize| len.div_ceil(cluster_size);
+            let nb_clusters: usize = dirs[1..].iter().map(|d| clusters(d.size()).max(1)).sum::<usize>()
+                + dirs.iter()
+                    .flat_map(|d| &d.entries)
+                    .map(|e| match e.kind {
+                        FatEntryKind::File(data) => clusters(data.len()),
+                        FatEntryKind::Dir(_) => 0,
+                    })
+                    .sum::<usize>();
+            if nb_clusters > FAT16_MAX_CLUSTERS {
+                continue
+            }
+
+            // a FAT12 volume needs at least one cluster
+            let nb_clusters = nb_clusters.max(1);
+            let (ty, fat_len) = if nb_clusters <= FAT12_MAX_CLUSTERS {
+                (FatType::Fat12, ((nb_clusters + 2) * 3).div_ceil(2))
+            } else {
+                (FatType::Fat16, (nb_clusters + 2) * 2)
+            };
+            let fat_sectors = fat_len.div_ceil(FAT_SECTOR_SIZE);
+            let total_sectors = 1 + 2 * fat_sectors + root_sectors + nb_clusters * sectors_per_cluster;
+            return Ok(Self {
+                ty,
+                sectors_per_cluster,
+                cluster_size,
+                root_entries,
+                fat_sectors,
+                nb_clusters,
+                total_sectors,
+            })
+        }
+        Err(BuildErr::VolumeTooLarge)
+    }
+
+    fn root_offset(&self) -> usize {
+        (1 + 2 * self.fat_sectors) * FAT_SECTOR_SIZE
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        self.root_offset() + self.root_entries * DIR_ENTRY_SIZE + (cluster as usize - 2) * self.cluster_size
+    }
+
+    fn dump_boot_sector(&self, builder: &FatBuilder, out: &mut [u8]) {
+        // jmp short and nop, the boot code is an infinite loop
+        out[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        out[3..11].copy_from_slice(b"MSWIN4.1");
+        out[11..13].copy_from_slice(&(FAT_SECTOR_SIZE as u16).to_le_bytes());
+        out[13] = self.sectors_per_cluster as u8;
+        out[14..16].copy_from_slice(&1_u16.to_le_bytes());
+        out[16] = 2;
+        out[17..19].copy_from_slice(&(self.root_entries as u16).to_le_bytes());
+        if self.total_sectors <= u16::MAX as usize {
+            out[19..21].copy_from_slice(&(self.total_sectors as u16).to_le_bytes());
+        } else {
+            out[32..36].copy_from_slice(&(self.total_sectors as u32).to_le_bytes());
+        }
+        out[21] = 0xF8;
+        out[22..24].copy_from_slice(&(self.fat_sectors as u16).to_le_bytes());
+        out[24..26].copy_from_slice(&32_u16.to_le_bytes());
+        out[26..28].copy_from_slice(&64_u16.to_le_bytes());
+        out[36] = 0x80;
+        out[38] = 0x29;
+        out[39..43].copy_from_slice(&builder.volume_id.to_le_bytes());
+        let mut label = [b' '; 11];
+        if builder.volume_label.is_empty() {
+            label.copy_from_slice(b"NO NAME    ");
+        } else {
+            let len = builder.volume_label.len().min(11);
+            label[..len].copy_from_slice(&builder.volume_label.as_bytes()[..len]);
+        }
+        out[43..54].copy_from_slice(&label);
+        out[54..62].copy_from_slice(match self.ty {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+        });
+        out[62..64].copy_from_slice(&[0xEB, 0xFE]);
+        out[510..512].copy_from_slice(&MBR_SIGNATURE);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Follows `path` from the root of `image`, returns the first cluster and
+    /// size of the file
+    fn lookup(image: &[u8], path: &str) -> (usize, usize) {
+        let u16_at = |off: usize| u16::from_le_bytes([image[off], image[off + 1]]) as usize;
+        let spc = image[13] as usize;
+        let fat_sectors = u16_at(22);
+        let root = (1 + 2 * fat_sectors) * FAT_SECTOR_SIZE;
+        let data = root + u16_at(17) * DIR_ENTRY_SIZE;
+
+        let mut dir = root;
+        let mut found = (0, 0);
+        for component in path.split('/') {
+            let mut long_name = String::new();
+            let mut off = dir;
+            loop {
+                let entry = &image[off..off + DIR_ENTRY_SIZE];
+                assert_ne!(entry[0], 0, "{} not found", component);
+                if entry[11] == fat_attr::LONG_NAME {
+                    let units: Vec<u16> = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30].iter()
+                        .map(|&o| u16::from_le_bytes([entry[o], entry[o + 1]]))
+                        .take_while(|&u| u != 0 && u != 0xFFFF)
+                        .collect();
+                    long_name.insert_str(0, &String::from_utf16(&units).unwrap());
+                } else {
+                    let stem = String::from_utf8_lossy(&entry[..8]).trim_end().to_owned();
+                    let ext = String::from_utf8_lossy(&entry[8..11]).trim_end().to_owned();
+                    let short = if ext.is_empty() { stem } else { format!("{}.{}", stem, ext) };
+                    if short.eq_ignore_ascii_case(component) || long_name == component {
+                        let cluster = u16::from_le_bytes([entry[26], entry[27]]) as usize;
+                        let size = u32::from_le_bytes(entry[28..32].try_into().unwrap()) as usize;
+                        found = (cluster, size);
+                        dir = data + (cluster - 2) * spc * FAT_SECTOR_SIZE;
+                        break
+                    }
+                    long_name.clear();
+                }
+                off += DIR_ENTRY_SIZE;
+            }
+        }
+        found
+    }
+
+    fn data_start(image: &[u8], cluster: usize) -> usize {
+        let fat_sectors = u16::from_le_bytes([image[22], image[23]]) as usize;
+        let root_entries = u16::from_le_bytes([image[17], image[18]]) as usize;
+        (1 + 2 * fat_sectors) * FAT_SECTOR_SIZE + root_entries * DIR_ENTRY_SIZE
+            + (cluster - 2) * image[13] as usize * FAT_SECTOR_SIZE
+    }
+
+    #[test]
+    fn test_fat_image() {
+        let loader: Vec<u8> = (0..10_000_u32).map(|i| i as u8).collect();
+        let mut builder = FatBuilder::new().with_volume_label("EFIBOOT");
+        builder.add_file("EFI/BOOT/BOOTX64.EFI", loader.clone()).unwrap();
+        builder.add_file("efi/boot/grub.cfg", b"search --label LIVE\n".to_vec()).unwrap();
+        builder.add_file("EFI/BOOT/a long configuration name.txt", b"long".to_vec()).unwrap();
+        assert!(matches!(builder.add_file("efi/BOOT/bootx64.efi", Vec::new()), Err(BuildErr::AlreadyExists(_))));
+        let image = builder.build().unwrap();
+
+        assert_eq!(&image[54..62], b"FAT12   ");
+        assert_eq!(image.len() % FAT_SECTOR_SIZE, 0);
+
+        let (cluster, size) = lookup(&image, "EFI/BOOT/BOOTX64.EFI");
+        assert_eq!(size, loader.len());
+        assert_eq!(&image[data_start(&image, cluster)..data_start(&image, cluster) + size], loader);
+        let (cluster, size) = lookup(&image, "EFI/BOOT/a long configuration name.txt");
+        assert_eq!(&image[data_start(&image, cluster)..data_start(&image, cluster) + size], b"long");
+        let (_, size) = lookup(&image, "EFI/BOOT/GRUB.CFG");
+        assert_eq!(size, 20);
+
+        // enough clusters to need a FAT16
+        let mut builder = FatBuilder::new();
+        for i in 0..5000 {
+            builder.add_file(&format!("f{}", i), vec![1; 300]).unwrap();
+        }
+        let image = builder.build().unwrap();
+        assert_eq!(&image[54..62], b"FAT16   ");
+        let (cluster, size) = lookup(&image, "f4999");
+        assert_eq!(size, 300);
+        assert_eq!(&image[data_start(&image, cluster)..data_start(&image, cluster) + size], [1; 300]);
+    }
+}
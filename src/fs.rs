@@ -0,0 +1,611 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::*;
+
+/// Lengths that come from the image are read this many bytes at a time
+const READ_CHUNK_SIZE: usize = 64 * SECTOR_SIZE;
+
+/// The descriptors found between `DATA_START` and the set terminator
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VolumeDescriptorSet {
+    pub pvd: PVD,
+    pub boot_record: Option<BootRecord>,
+    /// Every descriptor of the set, terminator included, with its sector
+    pub descriptors: Vec<(u32, VDType)>,
+}
+
+impl VolumeDescriptorSet {
+    pub fn read<D: BlockDevice + ?Sized>(dev: &mut D) -> Result<Self, VDErr> {
+        Self::read_session(dev, 0)
+    }
+
+    /// Reads the descriptors of the session that starts at sector
+    /// `session_start`, see `find_sessions`
+    pub fn read_session<D: BlockDevice + ?Sized>(dev: &mut D, session_start: u32) -> Result<Self, VDErr> {
+        let mut sector = [0_u8; SECTOR_SIZE];
+        let mut lba = session_start + (DATA_START / SECTOR_SIZE as u64) as u32;
+
+        let mut pvd = None;
+        let mut boot_record = None;
+        let mut descriptors = Vec::new();
+
+        loop {
+            read_sector(dev, lba as u64, &mut sector)?;
+            let header = VD::read_header(&sector)?;
+            descriptors.push((lba, header.ty));
+
+            match header.ty {
+                VDType::BootRecord if boot_record.is_none() => {
+                    boot_record = Some(BootRecord::try_parse(&sector)?);
+                },
+                VDType::PrimaryVD if pvd.is_none() => {
+                    pvd = Some(PVD::try_parse(&sector)?);
+                },
+                VDType::VDEnd => break,
+                _ => (),
+            }
+
+            lba += 1;
+        }
+
+        Ok(Self {
+            pvd: pvd.ok_or(VDErr::MissingPrimaryVD)?,
+            boot_record,
+            descriptors,
+        })
+    }
+}
+
+/// Read only access to the files of an image
+///
+/// Every read goes through a `CachedDevice` so that walking a tree does not
+/// hit the device again for the directories that were already listed.
+pub struct IsoFs<D> {
+    dev: CachedDevice<D>,
+    session_start: u32,
+    vds: VolumeDescriptorSet,
+    block_size: u64,
+    sector: [u8; SECTOR_SIZE],
+    /// See `susp_skip`, read the first time it is needed
+    pub(crate) susp_skip: Option<Option<u8>>,
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// Opens `dev` with a cache of `DEFAULT_CACHE_CAPACITY` blocks
+    pub fn open(dev: D) -> Result<Self, VDErr> {
+        Self::open_with_cache(CachedDevice::new(dev, DEFAULT_CACHE_CAPACITY))
+    }
+
+    /// Opens `dev` without caching anything, for devices that are already in
+    /// memory
+    pub fn open_uncached(dev: D) -> Result<Self, VDErr> {
+        Self::open_with_cache(CachedDevice::new(dev, 0))
+    }
+
+    pub fn open_with_cache(dev: CachedDevice<D>) -> Result<Self, VDErr> {
+        Self::open_session_with_cache(dev, 0)
+    }
+
+    /// Opens the session that starts at sector `session_start`
+    ///
+    /// The extents of a later session are still addressed from the start of
+    /// the disc, `dev` must hold every session up to this one.
+    pub fn open_session(dev: D, session_start: u32) -> Result<Self, VDErr> {
+        Self::open_session_with_cache(CachedDevice::new(dev, DEFAULT_CACHE_CAPACITY), session_start)
+    }
+
+    /// Opens the most recent session, see `find_sessions`
+    pub fn open_last_session(dev: D) -> Result<Self, VDErr> {
+        let mut dev = CachedDevice::new(dev, DEFAULT_CACHE_CAPACITY);
+        let last = find_sessions(&mut dev)?.pop().unwrap();
+        Self::open_session_with_cache(dev, last.start)
+    }
+
+    pub fn open_session_with_cache(mut dev: CachedDevice<D>, session_start: u32) -> Result<Self, VDErr> {
+        let vds = VolumeDescriptorSet::read_session(&mut dev, session_start)?;
+        let block_size = vds.pvd.checked_logical_block_size()? as u64;
+        Ok(Self {
+            dev,
+            session_start,
+            vds,
+            block_size,
+            sector: [0_u8; SECTOR_SIZE],
+            susp_skip: None,
+        })
+    }
+
+    /// Sector at which the session that was opened starts, 0 for the first
+    pub fn session_start(&self) -> u32 {
+        self.session_start
+    }
+
+    pub fn descriptors(&self) -> &VolumeDescriptorSet {
+        &self.vds
+    }
+
+    pub fn pvd(&self) -> &PVD {
+        &self.vds.pvd
+    }
+
+    pub fn root(&self) -> &DirectoryRecord {
+        &self.vds.pvd.root_record
+    }
+
+    /// Size of the blocks extents are counted in
+    pub fn logical_block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Offset in bytes of the extent of `record` from the start of the image
+    pub fn extent_offset(&self, record: &DirectoryRecord) -> u64 {
+        record.extent_location as u64 * self.block_size
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.dev.stats()
+    }
+
+    pub fn device(&mut self) -> &mut CachedDevice<D> {
+        &mut self.dev
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dev.into_inner()
+    }
+
+    /// Reads `buf.len()` bytes at `offset` bytes from the start of the image
+    pub fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let lba = offset / SECTOR_SIZE as u64;
+            let start = (offset % SECTOR_SIZE as u64) as usize;
+            let len = buf.len().min(SECTOR_SIZE - start);
+
+            if start == 0 && len == SECTOR_SIZE {
+                let sector: &mut [u8; SECTOR_SIZE] = (&mut buf[..SECTOR_SIZE]).try_into().unwrap();
+                read_sector(&mut self.dev, lba, sector)?;
+            } else {
+                read_sector(&mut self.dev, lba, &mut self.sector)?;
+                buf[..len].copy_from_slice(&self.sector[start..start + len]);
+            }
+
+            offset += len as u64;
+            buf = &mut buf[len..];
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes at `offset` a chunk at a time, so that a corrupted
+    /// length fails at the end of the device before it is all allocated
+    pub(crate) fn read_vec(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut chunk = vec![0_u8; READ_CHUNK_SIZE];
+        while (data.len() as u64) < len {
+            let chunk_len = (len - data.len() as u64).min(chunk.len() as u64) as usize;
+            self.read_at(offset + data.len() as u64, &mut chunk[..chunk_len])?;
+            data.extend_from_slice(&chunk[..chunk_len]);
+        }
+        Ok(data)
+    }
+
+    /// Lists the entries of `dir`, `.` and `..` included
+    pub fn read_dir(&mut self, dir: &DirectoryRecord) -> Result<Vec<DirectoryRecord>, VDErr> {
+        if !dir.is_dir() {
+            return Err(VDErr::NotADirectory)
+        }
+
+        let mut extent = vec![0_u8; dir.data_size as usize];
+        self.read_at(self.extent_offset(dir), &mut extent)?;
+
+        let mut entries = Vec::new();
+        for record in DirectoryRecordRef::iter_with_block_size(&extent, self.block_size as usize) {
+            entries.push(record?.to_record());
+        }
+        Ok(entries)
+    }
+
+    /// Finds the record of `path`, components are separated by `/` and
+    /// compared without their version suffix and regardless of case
+    pub fn lookup(&mut self, path: &str) -> Result<DirectoryRecord, VDErr> {
+        let mut current = self.root().clone();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            current = self.read_dir(&current)?
+                .into_iter()
+                .filter(|r| !r.is_special())
+                .find(|r| r.name().eq_ignore_ascii_case(component))
+                .ok_or(VDErr::NotFound)?;
+        }
+        Ok(current)
+    }
+
+    /// Every file of the tree with its path from the root, like `/DIR/FILE`,
+    /// directories excluded
+    pub fn files(&mut self) -> Result<Vec<(String, DirectoryRecord)>, VDErr> {
+        let mut files = Vec::new();
+        let root = self.root().clone();
+        self.collect_files(&root, String::new(), &mut files)?;
+        Ok(files)
+    }
+
+    fn collect_files(
+        &mut self,
+        dir: &DirectoryRecord,
+        path: String,
+        out: &mut Vec<(String, DirectoryRecord)>,
+    ) -> Result<(), VDErr> {
+        for record in self.read_dir(dir)? {
+            if record.is_special() {
+                continue
+            }
+            let child = format!("{}/{}", path, record.name());
+            if record.is_dir() {
+                self.collect_files(&record, child, out)?;
+            } else {
+                out.push((child, record));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn open_file(&mut self, record: DirectoryRecord) -> IsoFile<'_, D> {
+        IsoFile {
+            fs: self,
+            record,
+            pos: 0,
+        }
+    }
+
+    pub fn read_file(&mut self, record: &DirectoryRecord) -> io::Result<Vec<u8>> {
+        let mut data = vec![0_u8; record.payload_size() as usize];
+        self.open_file(record.clone()).read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl<D: BlockDeviceMut> IsoFs<D> {
+    /// Writes `data` at `offset` bytes from the start of the image
+    pub fn write_at(&mut self, mut offset: u64, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let lba = offset / SECTOR_SIZE as u64;
+            let start = (offset % SECTOR_SIZE as u64) as usize;
+            let len = data.len().min(SECTOR_SIZE - start);
+
+            if start != 0 || len != SECTOR_SIZE {
+                read_sector(&mut self.dev, lba, &mut self.sector)?;
+            }
+            self.sector[start..start + len].copy_from_slice(&data[..len]);
+            write_sector(&mut self.dev, lba, &self.sector)?;
+
+            offset += len as u64;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Overwrites the content of the file `path` with `data` without moving
+    /// its extent, returns its updated record
+    ///
+    /// `data` must fit before the next extent of the image, whatever the
+    /// current size of the file, so a file that was shrunk can grow back.
+    /// Hidden files have no record that tells where they start, the blocks
+    /// past the current content are only taken while they are zeroed. Empty
+    /// files have no extent of their own and stay empty. The size is updated
+    /// in every record of the file, the ones of the Joliet trees included,
+    /// Rock Ridge entries live in the same records and do not repeat it. The
+    /// rest of the old content is zeroed.
+    pub fn replace_in_place(&mut self, path: &str, data: &[u8]) -> Result<DirectoryRecord, VDErr> {
+        let mut record = self.lookup(path)?;
+        if record.is_dir() {
+            return Err(VDErr::IsADirectory)
+        }
+        if record.xa().is_some_and(|xa| xa.is_form2()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "form 2 files can not be written in place",
+            ).into())
+        }
+
+        let needed = data.len() as u64;
+        // builders point empty files at block 0 or at the extent that follows
+        if record.data_size == 0 || record.extent_location == 0 {
+            if needed == 0 {
+                return Ok(record)
+            }
+            return Err(VDErr::ExtentTooSmall { allocated: 0, needed })
+        }
+
+        let roots = self.roots()?;
+        let start = self.extent_offset(&record);
+        let used = (record.data_size as u64).next_multiple_of(self.block_size);
+        let mut allocated = self.next_extent(&roots, start)?.saturating_sub(start).max(used);
+        if needed > used {
+            let len = needed.next_multiple_of(self.block_size).min(allocated) - used;
+            let blocks = self.read_vec(start + used, len)?;
+            if let Some(i) = blocks.iter().position(|&b| b != 0) {
+                allocated = used + i as u64 / self.block_size * self.block_size;
+            }
+        }
+        if needed > allocated {
+            return Err(VDErr::ExtentTooSmall { allocated, needed })
+        }
+
+        let mut extent = data.to_vec();
+        extent.resize(used.max(needed.next_multiple_of(self.block_size)) as usize, 0);
+        self.write_at(start, &extent)?;
+
+        let mut visited = BTreeSet::new();
+        for root in &roots {
+            self.set_data_size(root, &record, data.len() as u32, &mut visited)?;
+        }
+
+        record.data_size = data.len() as u32;
+        Ok(record)
+    }
+
+    /// The roots of the primary tree and of the supplementary ones
+    fn roots(&mut self) -> Result<Vec<DirectoryRecord>, VDErr> {
+        let mut roots = vec![self.root().clone()];
+        for &(lba, ty) in &self.vds.descriptors {
+            if let VDType::EVD = ty {
+                read_sector(&mut self.dev, lba as u64, &mut self.sector)?;
+                roots.push(DirectoryRecord::try_parse(&self.sector[156..190])?);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Offset of the first extent that starts after `offset`, or of the end
+    /// of the volume
+    fn next_extent(&mut self, roots: &[DirectoryRecord], offset: u64) -> Result<u64, VDErr> {
+        let block_size = self.block_size;
+        let pvd = self.pvd();
+        let mut starts: BTreeSet<u64> = [pvd.path_table_l_location, pvd.path_table_m_location]
+            .into_iter()
+            .chain(pvd.opt_path_table_l_location)
+            .chain(pvd.opt_path_table_m_location)
+            .map(|lba| lba as u64 * block_size)
+            .collect();
+        for &(lba, ty) in &self.vds.descriptors {
+            if let VDType::EVD = ty {
+                read_sector(&mut self.dev, lba as u64, &mut self.sector)?;
+                let l = u32::from_le_bytes(self.sector[140..144].try_into().unwrap());
+                let m = u32::from_be_bytes(self.sector[148..152].try_into().unwrap());
+                starts.extend([l, m].map(|lba| lba as u64 * block_size));
+            }
+        }
+        if let Some(lba) = self.descriptors().boot_record.as_ref().and_then(|r| r.boot_catalog_addr) {
+            starts.insert(lba as u64 * SECTOR_SIZE as u64);
+            for entry in self.boot_entries()? {
+                starts.insert(entry.lba as u64 * SECTOR_SIZE as u64);
+            }
+        }
+        let mut visited = BTreeSet::new();
+        for root in roots {
+            self.collect_extents(root, &mut visited, &mut starts)?;
+        }
+        Ok(starts.range(offset + 1..).next().copied().unwrap_or(self.volume_end()))
+    }
+
+    /// Adds the offsets of the extents of the tree `dir` to `starts`
+    fn collect_extents(
+        &mut self,
+        dir: &DirectoryRecord,
+        visited: &mut BTreeSet<u32>,
+        starts: &mut BTreeSet<u64>,
+    ) -> Result<(), VDErr> {
+        if !visited.insert(dir.extent_location) {
+            return Ok(())
+        }
+        starts.insert(self.extent_offset(dir));
+        for record in self.read_dir(dir)? {
+            if record.is_special() {
+                continue
+            }
+            if record.is_dir() {
+                self.collect_extents(&record, visited, starts)?;
+            } else if record.data_size != 0 {
+                starts.insert(self.extent_offset(&record));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the size of the records of the tree `dir` that share the extent
+    /// and the size of `file`
+    fn set_data_size(
+        &mut self,
+        dir: &DirectoryRecord,
+        file: &DirectoryRecord,
+        data_size: u32,
+        visited: &mut BTreeSet<u32>,
+    ) -> Result<(), VDErr> {
+        if !visited.insert(dir.extent_location) {
+            return Ok(())
+        }
+
+        let mut bytes = vec![0_u8; dir.data_size as usize];
+        self.read_at(self.extent_offset(dir), &mut bytes)?;
+
+        let mut subdirs = Vec::new();
+        let mut offsets = Vec::new();
+        for record in DirectoryRecordRef::iter_with_block_size(&bytes, self.block_size as usize) {
+            let record = record?;
+            if record.is_special() {
+                continue
+            }
+            if record.is_dir() {
+                subdirs.push(record.to_record());
+            } else if record.extent_location() == file.extent_location && record.data_size() == file.data_size {
+                offsets.push(record.as_bytes().as_ptr() as usize - bytes.as_ptr() as usize);
+            }
+        }
+
+        if !offsets.is_empty() {
+            for off in offsets {
+                double_endian::put_u32(&mut bytes[off + 10..off + 18], data_size);
+            }
+            self.write_at(self.extent_offset(dir), &bytes)?;
+        }
+
+        for subdir in subdirs {
+            self.set_data_size(&subdir, file, data_size, visited)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file of an `IsoFs`, see `IsoFs::open_file`
+pub struct IsoFile<'a, D> {
+    fs: &'a mut IsoFs<D>,
+    record: DirectoryRecord,
+    pos: u64,
+}
+
+impl<D> IsoFile<'_, D> {
+    pub fn record(&self) -> &DirectoryRecord {
+        &self.record
+    }
+
+    /// Size of the file, see `DirectoryRecord::payload_size`
+    pub fn len(&self) -> u64 {
+        self.record.payload_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `true` if the file is read from the 2324 bytes of data of form 2
+    /// sectors, its XA record says so
+    pub fn is_form2(&self) -> bool {
+        self.record.xa().is_some_and(|xa| xa.is_form2())
+    }
+}
+
+impl<D: BlockDevice> IsoFile<'_, D> {
+    /// Form 2 sectors are read one at a time, and only through a device that
+    /// sees the raw sectors
+    fn read_form2(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let first_sector = self.fs.extent_offset(&self.record) / SECTOR_SIZE as u64;
+        let index = self.pos / FORM2_DATA_SIZE as u64;
+        let start = (self.pos % FORM2_DATA_SIZE as u64) as usize;
+
+        let mut data = [0_u8; FORM2_DATA_SIZE];
+        self.fs.dev.read_form2_sector(first_sector + index, &mut data)?;
+        let len = buf.len().min(FORM2_DATA_SIZE - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+}
+
+impl<D: BlockDevice> Read for IsoFile<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len().saturating_sub(self.pos);
+        let len = buf.len().min(remaining as usize);
+        if len == 0 {
+            return Ok(0)
+        }
+
+        let len = if self.is_form2() {
+            self.read_form2(&mut buf[..len])?
+        } else {
+            let offset = self.fs.extent_offset(&self.record) + self.pos;
+            self.fs.read_at(offset, &mut buf[..len])?;
+            len
+        };
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<D> Seek for IsoFile<'_, D> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::End(v) => self.len().checked_add_signed(v),
+            SeekFrom::Current(v) => self.pos.checked_add_signed(v),
+        };
+        self.pos = new_pos.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        ))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replace_in_place() {
+        let mut builder = IsoBuilder::new().with_vol_ident("INSTALL");
+        builder.add_file("boot/grub.cfg", b"set timeout=5\n".to_vec()).unwrap();
+        builder.add_file("big.bin", vec![7; 3000]).unwrap();
+        builder.add_file("boot/empty", Vec::new()).unwrap();
+        builder.add_file("empty.txt", Vec::new()).unwrap();
+        let mut image = builder.build().unwrap();
+
+        // a Joliet like twin of the tree: a supplementary descriptor whose
+        // root is a copy of the primary one, the path table it replaces is
+        // not read by IsoFs
+        let root = IsoFs::open(MemDevice::new(&image)).unwrap().root().clone();
+        let twin = (image.len() / SECTOR_SIZE) as u32;
+        let start = root.extent_location as usize * SECTOR_SIZE;
+        let copy = image[start..start + root.data_size as usize].to_vec();
+        image.extend_from_slice(&copy);
+        let mut svd = image[16 * SECTOR_SIZE..17 * SECTOR_SIZE].to_vec();
+        svd[0] = VDType::EVD as u8;
+        double_endian::put_u32(&mut svd[156 + 2..156 + 10], twin);
+        let terminator = image[17 * SECTOR_SIZE..18 * SECTOR_SIZE].to_vec();
+        image[17 * SECTOR_SIZE..18 * SECTOR_SIZE].copy_from_slice(&svd);
+        image[18 * SECTOR_SIZE..19 * SECTOR_SIZE].copy_from_slice(&terminator);
+
+        let mut fs = IsoFs::open(MemDevice::new(image)).unwrap();
+        let record = fs.replace_in_place("boot/grub.cfg", b"set timeout=0\nset default=1\n").unwrap();
+        assert_eq!(record.data_size, 28);
+        assert!(matches!(
+            fs.replace_in_place("boot/grub.cfg", &[0; SECTOR_SIZE + 1]),
+            Err(VDErr::ExtentTooSmall { allocated: 2048, needed: 2049 }),
+        ));
+        assert!(matches!(fs.replace_in_place("boot", b""), Err(VDErr::IsADirectory)));
+        fs.replace_in_place("big.bin", &[1; 4096]).unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(fs.into_inner().into_inner())).unwrap();
+        let record = fs.lookup("boot/grub.cfg").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"set timeout=0\nset default=1\n");
+        let record = fs.lookup("big.bin").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), [1; 4096]);
+
+        let twin_root = DirectoryRecord {
+            extent_location: twin,
+            ..fs.root().clone()
+        };
+        let twin_record = fs.read_dir(&twin_root).unwrap()
+            .into_iter()
+            .find(|r| r.name() == "BIG.BIN")
+            .unwrap();
+        assert_eq!(twin_record.data_size, 4096);
+
+        // nothing of the old content is left past the new one
+        fs.replace_in_place("big.bin", b"small").unwrap();
+        let mut extent = vec![0xFF; 4096];
+        fs.read_at(fs.extent_offset(&record), &mut extent).unwrap();
+        assert_eq!(&extent[..5], b"small");
+        assert!(extent[5..].iter().all(|&b| b == 0));
+
+        // the blocks it spanned are still its own
+        fs.replace_in_place("big.bin", &[2; 4096]).unwrap();
+        let record = fs.lookup("big.bin").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), [2; 4096]);
+
+        assert_eq!(fs.replace_in_place("empty.txt", b"").unwrap().data_size, 0);
+        assert!(matches!(
+            fs.replace_in_place("empty.txt", b"x"),
+            Err(VDErr::ExtentTooSmall { allocated: 0, needed: 1 }),
+        ));
+        assert_eq!(fs.lookup("boot/empty").unwrap().data_size, 0);
+        assert_eq!(fs.lookup("empty.txt").unwrap().data_size, 0);
+    }
+}
@@ -0,0 +1,343 @@
+use crate::*;
+
+/// Number of entries of the partition arrays written by `gpt_tables`
+pub const GPT_NB_ENTRIES: usize = 128;
+
+pub const GPT_ENTRY_SIZE: usize = 128;
+
+/// Sectors taken by the partition array
+pub const GPT_ENTRIES_SECTORS: u64 = (GPT_NB_ENTRIES * GPT_ENTRY_SIZE / MBR_SECTOR_SIZE) as u64;
+
+pub const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+
+/// Type of the protective partition of the MBR of a GPT disk
+pub const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+pub const MBR_TYPE_EFI: u8 = 0xEF;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// The CRC-32 of zlib, which is what the GPT checksums use
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0_u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A GUID as it is stored on disk, the first three fields are little endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const EFI_SYSTEM_PARTITION: Self = Self::from_fields(0xC12A7328, 0xF81F, 0x11D2, [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+    pub const BASIC_DATA: Self = Self::from_fields(0xEBD0A0A2, 0xB9E5, 0x4433, [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+
+    /// The GUID written `d1-d2-d3-d4[..2]-d4[2..]`
+    pub const fn from_fields(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Self {
+        let d1 = d1.to_le_bytes();
+        let d2 = d2.to_le_bytes();
+        let d3 = d3.to_le_bytes();
+        Self([
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1],
+            d4[0], d4[1], d4[2], d4[3], d4[4], d4[5], d4[6], d4[7],
+        ])
+    }
+
+    /// A random looking version 4 GUID derived from `seed`, for reproducible
+    /// images
+    pub fn from_seed(seed: u64) -> Self {
+        // splitmix64
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let mut bytes = [0_u8; 16];
+        bytes[..8].copy_from_slice(&next().to_le_bytes());
+        bytes[8..].copy_from_slice(&next().to_le_bytes());
+        bytes[7] = (bytes[7] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+        Self(bytes)
+    }
+}
+
+impl core::fmt::Display for Guid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9],
+        )?;
+        b[10..].iter().try_for_each(|b| write!(f, "{:02X}", b))
+    }
+}
+
+/// The header found in the second sector of the disk and in its last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub nb_entries: u32,
+    pub entry_size: u32,
+    pub entries_crc: u32,
+}
+
+impl GptHeader {
+    pub const SIZE: usize = 92;
+
+    /// `None` if `buffer` does not start with `GPT_SIGNATURE`
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        if buffer.get(..8)? != GPT_SIGNATURE || buffer.len() < Self::SIZE {
+            return None
+        }
+        let u32_at = |off: usize| u32::from_le_bytes(buffer[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(buffer[off..off + 8].try_into().unwrap());
+        Some(Self {
+            revision: u32_at(8),
+            header_size: u32_at(12),
+            header_crc: u32_at(16),
+            current_lba: u64_at(24),
+            backup_lba: u64_at(32),
+            first_usable_lba: u64_at(40),
+            last_usable_lba: u64_at(48),
+            disk_guid: Guid(buffer[56..72].try_into().unwrap()),
+            entries_lba: u64_at(72),
+            nb_entries: u32_at(80),
+            entry_size: u32_at(84),
+            entries_crc: u32_at(88),
+        })
+    }
+
+    /// Writes the header with `header_crc` as it is, see `update_crc`
+    pub fn dump(&self, out: &mut [u8]) {
+        out[..8].copy_from_slice(&GPT_SIGNATURE);
+        out[8..12].copy_from_slice(&self.revision.to_le_bytes());
+        out[12..16].copy_from_slice(&self.header_size.to_le_bytes());
+        out[16..20].copy_from_slice(&self.header_crc.to_le_bytes());
+        out[20..24].fill(0);
+        out[24..32].copy_from_slice(&self.current_lba.to_le_bytes());
+        out[32..40].copy_from_slice(&self.backup_lba.to_le_bytes());
+        out[40..48].copy_from_slice(&self.first_usable_lba.to_le_bytes());
+        out[48..56].copy_from_slice(&self.last_usable_lba.to_le_bytes());
+        out[56..72].copy_from_slice(&self.disk_guid.0);
+        out[72..80].copy_from_slice(&self.entries_lba.to_le_bytes());
+        out[80..84].copy_from_slice(&self.nb_entries.to_le_bytes());
+        out[84..88].copy_from_slice(&self.entry_size.to_le_bytes());
+        out[88..92].copy_from_slice(&self.entries_crc.to_le_bytes());
+    }
+
+    /// Checksum of the header with `header_crc` zeroed
+    pub fn compute_crc(&self) -> u32 {
+        let mut buffer = [0_u8; Self::SIZE];
+        Self {
+            header_crc: 0,
+            ..self.clone()
+        }.dump(&mut buffer);
+        crc32(&buffer)
+    }
+
+    pub fn update_crc(&mut self) {
+        self.header_crc = self.compute_crc();
+    }
+}
+
+/// An entry of the partition array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptPartition {
+    pub type_guid: Guid,
+    pub guid: Guid,
+    pub first_lba: u64,
+    /// Inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    /// At most 36 UTF-16 code units
+    pub name: String,
+}
+
+impl GptPartition {
+    /// Entries whose type is the nil GUID are unused
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        let type_guid = Guid(buffer[..16].try_into().unwrap());
+        if type_guid == Guid::default() {
+            return None
+        }
+        let u64_at = |off: usize| u64::from_le_bytes(buffer[off..off + 8].try_into().unwrap());
+        let name: Vec<u16> = buffer[56..128].chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        Some(Self {
+            type_guid,
+            guid: Guid(buffer[16..32].try_into().unwrap()),
+            first_lba: u64_at(32),
+            last_lba: u64_at(40),
+            attributes: u64_at(48),
+            name: String::from_utf16_lossy(&name),
+        })
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        out[..16].copy_from_slice(&self.type_guid.0);
+        out[16..32].copy_from_slice(&self.guid.0);
+        out[32..40].copy_from_slice(&self.first_lba.to_le_bytes());
+        out[40..48].copy_from_slice(&self.last_lba.to_le_bytes());
+        out[48..56].copy_from_slice(&self.attributes.to_le_bytes());
+        out[56..128].fill(0);
+        for (i, c) in self.name.encode_utf16().take(36).enumerate() {
+            out[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    /// Number of 512 bytes sectors
+    pub fn nb_sectors(&self) -> u64 {
+        match self.last_lba.checked_sub(self.first_lba) {
+            Some(last) => last.saturating_add(1),
+            None => 0,
+        }
+    }
+}
+
+/// The two copies of the partition table of a disk, see `gpt_tables`
+pub struct GptTables {
+    /// Header and partition array, written from the second sector of the disk
+    pub primary: Vec<u8>,
+    /// Partition array and header, they end the disk
+    pub backup: Vec<u8>,
+}
+
+/// Builds the primary and backup tables of a disk of `disk_sectors` 512 bytes
+/// sectors holding `partitions`
+pub fn gpt_tables(disk_guid: Guid, partitions: &[GptPartition], disk_sectors: u64) -> GptTables {
+    let mut entries = vec![0_u8; GPT_NB_ENTRIES * GPT_ENTRY_SIZE];
+    for (partition, out) in partitions.iter().zip(entries.chunks_exact_mut(GPT_ENTRY_SIZE)) {
+        partition.dump(out);
+    }
+
+    let backup_lba = disk_sectors - 1;
+    let mut header = GptHeader {
+        revision: 0x0001_0000,
+        header_size: GptHeader::SIZE as u32,
+        header_crc: 0,
+        current_lba: 1,
+        backup_lba,
+        first_usable_lba: 2 + GPT_ENTRIES_SECTORS,
+        last_usable_lba: backup_lba - GPT_ENTRIES_SECTORS - 1,
+        disk_guid,
+        entries_lba: 2,
+        nb_entries: GPT_NB_ENTRIES as u32,
+        entry_size: GPT_ENTRY_SIZE as u32,
+        entries_crc: crc32(&entries),
+    };
+    header.update_crc();
+
+    let mut primary = vec![0_u8; MBR_SECTOR_SIZE];
+    header.dump(&mut primary);
+    primary.extend_from_slice(&entries);
+
+    header.current_lba = backup_lba;
+    header.backup_lba = 1;
+    header.entries_lba = backup_lba - GPT_ENTRIES_SECTORS;
+    header.update_crc();
+
+    let mut backup = entries;
+    let header_start = backup.len();
+    backup.resize(header_start + MBR_SECTOR_SIZE, 0);
+    header.dump(&mut backup[header_start..]);
+
+    GptTables {
+        primary,
+        backup,
+    }
+}
+
+/// Where the EFI System Partition of an image written with a GPT comes from
+#[derive(Debug, Clone)]
+pub enum EfiSystemPartition {
+    /// The UEFI image of the El Torito catalog, inside the volume, like
+    /// xorriso's `--efi-boot-part --efi-boot-image`
+    BootImage,
+    /// A FAT image appended after the volume, like xorriso's
+    /// `-append_partition 2 0xef`
+    Appended(Vec<u8>),
+}
+
+/// A protective MBR and GPT for the system area, so that UEFI machines boot
+/// the image from a USB stick, see `IsoBuilder::with_gpt`
+#[derive(Debug, Clone)]
+pub struct Gpt {
+    pub esp: EfiSystemPartition,
+    /// Derived from the creation time of the image when not set
+    pub disk_guid: Option<Guid>,
+}
+
+impl Gpt {
+    pub fn new(esp: EfiSystemPartition) -> Self {
+        Self {
+            esp,
+            disk_guid: None,
+        }
+    }
+
+    pub fn with_disk_guid(mut self, guid: Guid) -> Self {
+        self.disk_guid = Some(guid);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32_and_guid() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Guid::EFI_SYSTEM_PARTITION.to_string(), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(Guid::EFI_SYSTEM_PARTITION.0[..4], [0x28, 0x73, 0x2A, 0xC1]);
+    }
+
+    #[test]
+    fn test_nb_sectors() {
+        let partition = |first_lba, last_lba| GptPartition {
+            type_guid: Guid::EFI_SYSTEM_PARTITION,
+            guid: Guid::default(),
+            first_lba,
+            last_lba,
+            attributes: 0,
+            name: String::new(),
+        };
+        assert_eq!(partition(64, 73).nb_sectors(), 10);
+        assert_eq!(partition(64, 63).nb_sectors(), 0);
+        assert_eq!(partition(0, u64::MAX).nb_sectors(), u64::MAX);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::*;
+
+/// Zero copy access to an image that is entirely in memory
+///
+/// Unlike `IsoFs` nothing is read through a `BlockDevice`, records and file
+/// contents are slices of the image itself. Combined with `MmapImage` files
+/// can be hashed straight from the page cache.
+#[derive(Debug, Clone, Copy)]
+pub struct IsoImage<'a> {
+    bytes: &'a [u8],
+    pvd: PvdRef<'a>,
+    block_size: usize,
+}
+
+impl<'a> IsoImage<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, VDErr> {
+        let mut lba = DATA_START as usize / SECTOR_SIZE;
+        loop {
+            let sector = sector_of(bytes, lba as u32, 1)?;
+            let header = VD::read_header(sector)?;
+            match header.ty {
+                VDType::PrimaryVD => {
+                    let pvd = PvdRef::new(sector)?;
+                    let block_size = check_logical_block_size(pvd.logical_block_size())? as usize;
+                    return Ok(Self {
+                        bytes,
+                        pvd,
+                        block_size,
+                    })
+                },
+                VDType::VDEnd => return Err(VDErr::MissingPrimaryVD),
+                _ => lba += 1,
+            }
+        }
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn pvd(&self) -> PvdRef<'a> {
+        self.pvd
+    }
+
+    pub fn root(&self) -> Result<DirectoryRecordRef<'a>, VDErr> {
+        self.pvd.root_record()
+    }
+
+    pub fn logical_block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The `count` 2K sectors starting at `lba`, in sectors not in logical
+    /// blocks
+    pub fn sectors(&self, lba: u32, count: usize) -> Result<&'a [u8], VDErr> {
+        sector_of(self.bytes, lba, count)
+    }
+
+    /// Entries of `dir`, `.` and `..` included
+    pub fn read_dir(&self, dir: &DirectoryRecordRef<'_>) -> Result<DirectoryRecordIter<'a>, VDErr> {
+        if !dir.is_dir() {
+            return Err(VDErr::NotADirectory)
+        }
+        let extent = self.extent(dir.extent_location(), dir.data_size())?;
+        Ok(DirectoryRecordRef::iter_with_block_size(extent, self.block_size))
+    }
+
+    /// See `IsoFs::lookup`
+    pub fn lookup(&self, path: &str) -> Result<DirectoryRecordRef<'a>, VDErr> {
+        let mut current = self.root()?;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let mut found = None;
+            for record in self.read_dir(&current)? {
+                let record = record?;
+                if !record.is_special() && record.name().eq_ignore_ascii_case(component) {
+                    found = Some(record);
+                    break
+                }
+            }
+            current = found.ok_or(VDErr::NotFound)?;
+        }
+        Ok(current)
+    }
+
+    /// Contents of the file described by `record`, borrowed from the image
+    pub fn file_contents(&self, record: &DirectoryRecordRef<'_>) -> Result<&'a [u8], VDErr> {
+        self.extent(record.extent_location(), record.data_size())
+    }
+
+    fn extent(&self, lba: u32, size: u32) -> Result<&'a [u8], VDErr> {
+        range(self.bytes, lba, self.block_size, size as usize)
+    }
+}
+
+fn sector_of(bytes: &[u8], lba: u32, count: usize) -> Result<&[u8], VDErr> {
+    let len = count.checked_mul(SECTOR_SIZE).ok_or(VDErr::Truncated)?;
+    range(bytes, lba, SECTOR_SIZE, len)
+}
+
+/// The `len` bytes at block `lba`, the values come from the image and can
+/// point anywhere
+fn range(bytes: &[u8], lba: u32, block_size: usize, len: usize) -> Result<&[u8], VDErr> {
+    let start = (lba as usize).checked_mul(block_size).ok_or(VDErr::Truncated)?;
+    let end = start.checked_add(len).ok_or(VDErr::Truncated)?;
+    bytes.get(start..end).ok_or(VDErr::Truncated)
+}
+
+#[cfg(feature = "mmap")]
+mod mmap {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+
+    use memmap2::Mmap;
+
+    use super::IsoImage;
+    use crate::VDErr;
+
+    /// An image file mapped in memory, only available with the `mmap` feature
+    ///
+    /// It derefs to the bytes of the image so it can also back a `MemDevice`.
+    #[derive(Debug)]
+    pub struct MmapImage {
+        map: Mmap,
+    }
+
+    impl MmapImage {
+        /// Maps `path` read only
+        ///
+        /// The file must not be truncated nor modified while it is mapped,
+        /// the slices handed out by `IsoImage` would change under our feet.
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = File::open(path)?;
+            Self::from_file(&file)
+        }
+
+        pub fn from_file(file: &File) -> io::Result<Self> {
+            // SAFETY: see `open`, there is no way to enforce it
+            let map = unsafe { Mmap::map(file)? };
+            Ok(Self {
+                map,
+            })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            &self.map
+        }
+
+        pub fn image(&self) -> Result<IsoImage<'_>, VDErr> {
+            IsoImage::new(&self.map)
+        }
+    }
+
+    impl AsRef<[u8]> for MmapImage {
+        fn as_ref(&self) -> &[u8] {
+            &self.map
+        }
+    }
+
+    impl core::ops::Deref for MmapImage {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            &self.map
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+pub use mmap::MmapImage;
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use super::*;
+
+    /// An image whose root holds `DATA.BIN`, laid out by hand
+    fn single_file_image(data: &[u8]) -> Vec<u8> {
+        const ROOT: usize = 18;
+        const DATA: usize = 19;
+        let nb_sectors = DATA + data.len().div_ceil(SECTOR_SIZE);
+        let mut image = vec![0_u8; nb_sectors * SECTOR_SIZE];
+        let record = |out: &mut [u8], lba: usize, size: usize, flags: u8, ident: &[u8]| {
+            let len = (DIRECTORY_RECORD_HEADER_SIZE + ident.len() + 1) & !1;
+            out[0] = len as u8;
+            double_endian::put_u32(&mut out[2..10], lba as u32);
+            double_endian::put_u32(&mut out[10..18], size as u32);
+            out[25] = flags;
+            double_endian::put_u16(&mut out[28..32], 1);
+            out[32] = ident.len() as u8;
+            out[DIRECTORY_RECORD_HEADER_SIZE..][..ident.len()].copy_from_slice(ident);
+            len
+        };
+
+        let pvd = &mut image[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
+        pvd[0] = VDType::PrimaryVD as u8;
+        pvd[1..6].copy_from_slice(VD_IDENT);
+        pvd[6] = 1;
+        pvd[8..72].fill(b' ');
+        double_endian::put_u32(&mut pvd[80..88], nb_sectors as u32);
+        double_endian::put_u16(&mut pvd[120..124], 1);
+        double_endian::put_u16(&mut pvd[124..128], 1);
+        double_endian::put_u16(&mut pvd[128..132], SECTOR_SIZE as u16);
+        record(&mut pvd[156..190], ROOT, SECTOR_SIZE, flags::DIR, &[0]);
+        pvd[190..813].fill(b' ');
+        for date in pvd[813..881].chunks_mut(17) {
+            date[..16].fill(b'0');
+        }
+        pvd[881] = 1;
+
+        let terminator = &mut image[17 * SECTOR_SIZE..];
+        terminator[0] = VDType::VDEnd as u8;
+        terminator[1..6].copy_from_slice(VD_IDENT);
+        terminator[6] = 1;
+
+        let root = &mut image[ROOT * SECTOR_SIZE..(ROOT + 1) * SECTOR_SIZE];
+        let mut off = record(root, ROOT, SECTOR_SIZE, flags::DIR, &[0]);
+        off += record(&mut root[off..], ROOT, SECTOR_SIZE, flags::DIR, &[1]);
+        record(&mut root[off..], DATA, data.len(), 0, b"DATA.BIN;1");
+        image[DATA * SECTOR_SIZE..][..data.len()].copy_from_slice(data);
+        image
+    }
+
+    /// Reads a file of an image through every backend
+    #[test]
+    fn test_backends() {
+        let data: Vec<u8> = (0..5000_u32).map(|i| i as u8).collect();
+        let bytes = single_file_image(&data);
+        let path = std::env::temp_dir().join(format!("iso9660-image-{}.iso", std::process::id()));
+        fs::write(&path, &bytes).unwrap();
+
+        let image = IsoImage::new(&bytes).unwrap();
+        let record = image.lookup("data.bin").unwrap();
+        assert_eq!(image.file_contents(&record).unwrap(), data);
+        assert!(matches!(image.sectors(u32::MAX, usize::MAX), Err(VDErr::Truncated)));
+        assert!(matches!(image.sectors(u32::MAX, 1), Err(VDErr::Truncated)));
+
+        let mut fs = IsoFs::open(IoDevice::new(File::open(&path).unwrap())).unwrap();
+        let record = fs.lookup("data.bin").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), data);
+
+        #[cfg(feature = "mmap")]
+        {
+            let mmap = MmapImage::open(&path).unwrap();
+            assert_eq!(mmap.bytes(), bytes);
+            let image = mmap.image().unwrap();
+            let contents = image.file_contents(&image.lookup("data.bin").unwrap()).unwrap();
+            assert_eq!(contents, data);
+            // borrowed from the mapping, not copied
+            assert!(mmap.bytes().as_ptr_range().contains(&contents.as_ptr()));
+
+            let mut fs = IsoFs::open(MemDevice::new(mmap)).unwrap();
+            let record = fs.lookup("data.bin").unwrap();
+            assert_eq!(fs.read_file(&record).unwrap(), data);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
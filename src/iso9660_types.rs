@@ -34,6 +34,41 @@ pub struct InvalidChar {
     pub alphabet: &'static [u8],
 }
 
+/// Length of `slice` without its padding, fails if one of the remaining bytes
+/// is not part of `alphabet`
+fn unpadded_len(slice: &[u8], alphabet: &[u8;16]) -> Result<usize, InvalidChar> {
+    let len = slice.len() - slice.iter().rev()
+        // NB(louis): the standard specifies that strings should be
+        // padded with spaces but sometimes they are padded with zeroes
+        .take_while(|b| **b == b' ' || **b == 0).count();
+    for &b in &slice[..len] {
+        let byte_index = b / 8;
+        let bit_index = (8 - (b & 7)).saturating_sub(1);
+        let bit_mask = 1 << bit_index;
+        if !b.is_ascii() || bit_mask & alphabet[byte_index as usize] == 0 {
+            return Err(InvalidChar {
+                code_point: b,
+                alphabet: STR_A_CHAR_SET,
+            })
+        }
+    }
+    Ok(len)
+}
+
+/// Borrows `slice` as a string of a-characters without its padding
+pub fn str_a(slice: &[u8]) -> Result<&str, InvalidChar> {
+    let len = unpadded_len(slice, &STR_A_CHAR_SET_BIT_SET)?;
+    // the alphabet is a subset of ascii
+    Ok(unsafe { core::str::from_utf8_unchecked(&slice[..len]) })
+}
+
+/// Borrows `slice` as a string of d-characters without its padding
+pub fn str_d(slice: &[u8]) -> Result<&str, InvalidChar> {
+    let len = unpadded_len(slice, &STR_D_CHAR_SET_BIT_SET)?;
+    // the alphabet is a subset of ascii
+    Ok(unsafe { core::str::from_utf8_unchecked(&slice[..len]) })
+}
+
 pub struct ArrStr<const LEN: usize> {
     bytes: [u8; LEN],
     len: usize,
@@ -82,21 +117,7 @@ impl<const LEN: usize> ArrStr<LEN> {
     /// SAFETY: `slice` must be of size LEN
     pub fn from_slice_with_ascii_subset(slice: &[u8], alphabet: &[u8;16]) -> Result<Self, InvalidChar> {
         assert_eq!(slice.len(), LEN, "`slice` must be of size LEN");
-        let len = LEN - slice.iter().rev()
-            // NB(louis): the standard specifies that strings should be
-            // padded with spaces but sometimes they are padded with zeroes
-            .take_while(|b| **b == b' ' || **b == 0).count();
-        for &b in &slice[..len] {
-            let byte_index = b / 8;
-            let bit_index = (8 - (b & 7)).saturating_sub(1);
-            let bit_mask = 1 << bit_index;
-            if bit_mask & alphabet[byte_index as usize] == 0 {
-                return Err(InvalidChar {
-                    code_point: b,
-                    alphabet: STR_A_CHAR_SET,
-                })
-            }
-        }
+        let len = unpadded_len(slice, alphabet)?;
         unsafe {
             Ok(Self::from_slice_unchecked(slice, len))
         }
@@ -173,7 +194,81 @@ impl<const LEN: usize> StrD<LEN> {
     }
 }
 
+/// `StrA` and `StrD` are strings without their padding, deserializing one
+/// fails if it is too long or has a character outside of its alphabet
+#[cfg(feature = "serde")]
+mod serde_str {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::ser::{Serialize, Serializer};
+
+    use super::*;
+
+    /// `s` padded with spaces to `LEN` bytes
+    fn padded<'de, D: Deserializer<'de>, const LEN: usize>(s: &str) -> Result<[u8; LEN], D::Error> {
+        if s.len() > LEN {
+            return Err(D::Error::custom(format!("{:?} is longer than {} bytes", s, LEN)))
+        }
+        let mut bytes = [b' '; LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(bytes)
+    }
+
+    fn invalid<E: Error>(e: InvalidChar) -> E {
+        E::custom(format!("invalid character 0x{:02x}", e.code_point))
+    }
+
+    impl<const LEN: usize> Serialize for StrA<LEN> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl<'de, const LEN: usize> Deserialize<'de> for StrA<LEN> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let s = String::deserialize(deserializer)?;
+            Self::from_slice(&padded::<D, LEN>(&s)?).map_err(invalid)
+        }
+    }
+
+    impl<const LEN: usize> Serialize for StrD<LEN> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl<'de, const LEN: usize> Deserialize<'de> for StrD<LEN> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let s = String::deserialize(deserializer)?;
+            Self::from_slice(&padded::<D, LEN>(&s)?).map_err(invalid)
+        }
+    }
+
+    /// For `#[serde(with)]` on optional arrays larger than serde handles,
+    /// like `PVD::application_used`
+    pub(crate) mod opt_bytes {
+        use super::*;
+
+        pub fn serialize<S: Serializer, const N: usize>(bytes: &Option<[u8; N]>, serializer: S) -> Result<S::Ok, S::Error> {
+            bytes.as_ref().map(|b| b.as_slice()).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<Option<[u8; N]>, D::Error> {
+            let Some(bytes) = Option::<Vec<u8>>::deserialize(deserializer)? else {
+                return Ok(None)
+            };
+            let len = bytes.len();
+            bytes.try_into()
+                .map(Some)
+                .map_err(|_| D::Error::invalid_length(len, &format!("{} bytes", N).as_str()))
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) use serde_str::opt_bytes;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DecDateTime {
     year: StrD<4>,
     month: StrD<2>,
@@ -211,7 +306,6 @@ pub enum DecDateTimeErr {
     },
 }
 
-#[cfg(not(feature = "no_std"))]
 impl From<io::Error> for DecDateTimeErr {
     fn from(value: io::Error) -> Self {
         DecDateTimeErr::Io(value)
@@ -224,7 +318,111 @@ impl From<InvalidChar> for DecDateTimeErr {
     }
 }
 
+/// Splits `secs` seconds since the unix epoch into
+/// (year, month, day, hour, minute, second), in UTC
+pub(crate) fn civil_from_unix_time(secs: i64) -> (i64, u8, u8, u8, u8, u8) {
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (
+        year,
+        month,
+        day,
+        (rem / 3600) as u8,
+        (rem / 60 % 60) as u8,
+        (rem % 60) as u8,
+    )
+}
+
+/// Seconds since the unix epoch of a date in UTC, the inverse of
+/// `civil_from_unix_time`
+pub(crate) fn unix_time_from_civil(year: i64, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> i64 {
+    // see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    days * 86_400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64
+}
+
 impl DecDateTime {
+    /// Seconds since the unix epoch, `None` if a field is not a number
+    pub fn to_unix_time(&self) -> Option<i64> {
+        let field = |s: &str| s.trim().parse::<u8>().ok();
+        let year = self.year.as_str().trim().parse::<i64>().ok()?;
+        let secs = unix_time_from_civil(
+            year,
+            field(self.month.as_str())?,
+            field(self.day.as_str())?,
+            field(self.hour.as_str())?,
+            field(self.minute.as_str())?,
+            field(self.second.as_str())?,
+        );
+        Some(secs - self.time_zone as i8 as i64 * 15 * 60)
+    }
+
+    /// `secs` seconds since the unix epoch, in UTC
+    pub fn from_unix_time(secs: i64) -> Self {
+        let (year, month, day, hour, minute, second) = civil_from_unix_time(secs);
+        let digits = format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}00",
+            year.clamp(1, 9999), month, day, hour, minute, second,
+        );
+        let digits = digits.as_bytes();
+        Self {
+            year: StrD::from_slice(&digits[..4]).unwrap(),
+            month: StrD::from_slice(&digits[4..6]).unwrap(),
+            day: StrD::from_slice(&digits[6..8]).unwrap(),
+            hour: StrD::from_slice(&digits[8..10]).unwrap(),
+            minute: StrD::from_slice(&digits[10..12]).unwrap(),
+            second: StrD::from_slice(&digits[12..14]).unwrap(),
+            centi_sec: StrD::from_slice(&digits[14..16]).unwrap(),
+            time_zone: 0,
+        }
+    }
+
+    /// Writes the 17 bytes of `date`, an absent date is all `0` digits
+    pub fn dump(date: Option<&Self>, out: &mut [u8]) {
+        let Some(date) = date else {
+            out[..16].fill(b'0');
+            out[16] = 0;
+            return
+        };
+        let fields: [(&[u8], usize); 7] = [
+            (date.year.as_str().as_bytes(), 4),
+            (date.month.as_str().as_bytes(), 2),
+            (date.day.as_str().as_bytes(), 2),
+            (date.hour.as_str().as_bytes(), 2),
+            (date.minute.as_str().as_bytes(), 2),
+            (date.second.as_str().as_bytes(), 2),
+            (date.centi_sec.as_str().as_bytes(), 2),
+        ];
+        let mut off = 0;
+        for (digits, len) in fields {
+            // fields were trimmed of their padding when parsed, right align
+            // what is left
+            let field = &mut out[off..off + len];
+            field.fill(b'0');
+            field[len - digits.len().min(len)..].copy_from_slice(&digits[..digits.len().min(len)]);
+            off += len;
+        }
+        out[16] = date.time_zone;
+    }
+
     pub fn try_parse(buffer: &[u8]) -> Result<Option<Self>, DecDateTimeErr> {
 
         if buffer[16] == 0 && buffer[..16].iter().all(|&b| b == b'0') {
@@ -355,6 +553,18 @@ pub mod double_endian {
 
         u32::from_ne_bytes(buffer)
     }
+
+    /// Writes `value` in both byte orders, little endian first
+    pub fn put_u16(out: &mut [u8], value: u16) {
+        out[..2].copy_from_slice(&value.to_le_bytes());
+        out[2..4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes `value` in both byte orders, little endian first
+    pub fn put_u32(out: &mut [u8], value: u32) {
+        out[..4].copy_from_slice(&value.to_le_bytes());
+        out[4..8].copy_from_slice(&value.to_be_bytes());
+    }
 }
 
 
@@ -0,0 +1,200 @@
+use std::borrow::Cow;
+
+use crate::*;
+
+/// The escape sequences of a supplementary volume descriptor that make it a
+/// Joliet one, for levels 1 to 3
+pub const JOLIET_ESCAPE_SEQUENCES: [[u8; 3]; 3] = [*b"%/@", *b"%/C", *b"%/E"];
+
+/// Longest name Joliet allows, in UCS-2 characters
+pub const JOLIET_MAX_NAME_LEN: usize = 64;
+
+/// The Joliet level of the supplementary volume descriptor `descriptor`,
+/// `None` if it is not a Joliet one
+pub fn joliet_level(descriptor: &[u8]) -> Option<u8> {
+    if descriptor.first() != Some(&(VDType::EVD as u8)) {
+        return None
+    }
+    let escape = descriptor.get(88..91)?;
+    JOLIET_ESCAPE_SEQUENCES.iter()
+        .position(|s| s == escape)
+        .map(|i| i as u8 + 1)
+}
+
+/// Decodes the UCS-2 big endian identifier of a Joliet record without its
+/// `;1` version suffix
+pub fn joliet_name(ident: &[u8]) -> Cow<'_, str> {
+    match ident {
+        [0] => return Cow::Borrowed("."),
+        [1] => return Cow::Borrowed(".."),
+        _ => (),
+    }
+    let units = ident.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]]));
+    let mut name: String = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    if let Some(pos) = name.rfind(';') {
+        if name[pos + 1..].bytes().all(|b| b.is_ascii_digit()) {
+            name.truncate(pos);
+        }
+    }
+    if name.ends_with('.') {
+        name.pop();
+    }
+    Cow::Owned(name)
+}
+
+/// Encodes `name` as the identifier of a Joliet record, `;1` is appended to
+/// files
+///
+/// Characters Joliet forbids and those outside of the BMP become `_`, the name
+/// is cut at `JOLIET_MAX_NAME_LEN` characters, keeping its extension.
+pub fn joliet_ident(name: &str, is_dir: bool) -> Vec<u8> {
+    let mut chars: Vec<u16> = name.chars()
+        .map(|c| match c {
+            '*' | '/' | ':' | ';' | '?' | '\\' => b'_' as u16,
+            c if c.is_control() || c as u32 > 0xFFFF => b'_' as u16,
+            c => c as u16,
+        })
+        .collect();
+    if chars.len() > JOLIET_MAX_NAME_LEN {
+        let ext_start = chars.iter().rposition(|&c| c == b'.' as u16)
+            .filter(|&pos| !is_dir && pos > 0 && chars.len() - pos < JOLIET_MAX_NAME_LEN / 2);
+        match ext_start {
+            Some(pos) => {
+                let ext = chars.split_off(pos);
+                chars.truncate(JOLIET_MAX_NAME_LEN - ext.len());
+                chars.extend_from_slice(&ext);
+            },
+            None => chars.truncate(JOLIET_MAX_NAME_LEN),
+        }
+    }
+    if !is_dir {
+        chars.extend(";1".bytes().map(u16::from));
+    }
+    chars.iter().flat_map(|c| c.to_be_bytes()).collect()
+}
+
+/// `s` in UCS-2 big endian, truncated or padded with spaces to `len` bytes
+pub(crate) fn ucs2_padded(s: &str, len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = s.encode_utf16()
+        .take(len / 2)
+        .flat_map(|c| c.to_be_bytes())
+        .collect();
+    while bytes.len() + 2 <= len {
+        bytes.extend_from_slice(&[0, b' ']);
+    }
+    bytes.resize(len, 0);
+    bytes
+}
+
+/// Turns the dump of a primary volume descriptor into the one of a Joliet
+/// level 3 descriptor with the same fields, its identifiers in UCS-2
+pub(crate) fn dump_joliet_svd(pvd: &PVD, out: &mut [u8]) {
+    let ident = |s: Option<&str>| s.unwrap_or("").trim_end().to_owned();
+    pvd.dump(out);
+    out[0] = VDType::EVD as u8;
+    out[88..91].copy_from_slice(&JOLIET_ESCAPE_SEQUENCES[2]);
+    out[8..40].copy_from_slice(&ucs2_padded(&ident(pvd.sys_ident.as_ref().map(|s| s.as_str())), 32));
+    out[40..72].copy_from_slice(&ucs2_padded(&ident(pvd.vol_ident.as_ref().map(|s| s.as_str())), 32));
+    out[190..318].copy_from_slice(&ucs2_padded(&ident(pvd.vol_set_ident.as_ref().map(|s| s.as_str())), 128));
+    // like in the primary descriptor, text is written as is and only the
+    // references to a file start with a `_`
+    let fields = [
+        (318, &pvd.publisher_ident),
+        (446, &pvd.data_prep_ident),
+        (574, &pvd.app_ident),
+    ];
+    for (off, field) in fields {
+        let field = match field {
+            Some(VolumeIdent::File(name)) => format!("_{}", ident(Some(name.as_str()))),
+            field => ident(field.as_ref().map(VolumeIdent::as_str)),
+        };
+        out[off..off + 128].copy_from_slice(&ucs2_padded(&field, 128));
+    }
+}
+
+/// The Joliet supplementary volume descriptor of an image
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joliet {
+    /// From 1 to 3, see `JOLIET_ESCAPE_SEQUENCES`
+    pub level: u8,
+    /// Sector of the descriptor
+    pub lba: u32,
+    pub vol_ident: String,
+    /// The root of the Joliet tree, read it with `IsoFs::read_dir` and
+    /// `joliet_name`
+    pub root: DirectoryRecord,
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// The first Joliet descriptor of the volume, `None` if there is none
+    pub fn joliet(&mut self) -> Result<Option<Joliet>, VDErr> {
+        let candidates: Vec<u32> = self.descriptors().descriptors.iter()
+            .filter(|(_, ty)| matches!(ty, VDType::EVD))
+            .map(|(lba, _)| *lba)
+            .collect();
+        let mut sector = [0_u8; SECTOR_SIZE];
+        for lba in candidates {
+            self.read_at(lba as u64 * SECTOR_SIZE as u64, &mut sector)?;
+            let Some(level) = joliet_level(&sector) else { continue };
+            let vol_ident = joliet_name(&sector[40..72]).trim_end().to_owned();
+            return Ok(Some(Joliet {
+                level,
+                lba,
+                vol_ident,
+                root: DirectoryRecord::try_parse(&sector[156..190])?,
+            }))
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_joliet_names() {
+        let ident = joliet_ident("Read Me: ünïcode.txt", false);
+        assert_eq!(joliet_name(&ident), "Read Me_ ünïcode.txt");
+
+        let long = format!("{}.tar.gz", "x".repeat(100));
+        let ident = joliet_ident(&long, false);
+        assert_eq!(ident.len(), (JOLIET_MAX_NAME_LEN + 2) * 2);
+        assert!(joliet_name(&ident).ends_with("xxx.gz"));
+        assert_eq!(joliet_ident("dir", true), [0, b'd', 0, b'i', 0, b'r']);
+        assert_eq!(ucs2_padded("AB", 6), [0, b'A', 0, b'B', 0, b' ']);
+    }
+
+    #[test]
+    fn test_joliet_svd() {
+        let builder = IsoBuilder::new()
+            .with_joliet()
+            .with_vol_ident("DISC")
+            .with_publisher_ident("ACME")
+            .with_app_ident("MKISO");
+        let image = builder.build().unwrap();
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let joliet = fs.joliet().unwrap().unwrap();
+        assert_eq!(joliet.vol_ident, "DISC");
+        let svd = &image[joliet.lba as usize * SECTOR_SIZE..][..SECTOR_SIZE];
+        assert_eq!(svd[318..446], ucs2_padded("ACME", 128));
+        assert_eq!(svd[446..574], ucs2_padded("", 128));
+        assert_eq!(svd[574..702], ucs2_padded("MKISO", 128));
+
+        // a reference names a file of the primary tree, it is left out
+        let mut builder = IsoBuilder::new()
+            .with_joliet()
+            .with_publisher_ident("ACME")
+            .with_app_file("readme.txt");
+        builder.add_file("readme.txt", b"MKISO".to_vec()).unwrap();
+        let image = builder.build().unwrap();
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let joliet = fs.joliet().unwrap().unwrap();
+        let svd = &image[joliet.lba as usize * SECTOR_SIZE..][..SECTOR_SIZE];
+        assert_eq!(svd[318..446], ucs2_padded("ACME", 128));
+        assert_eq!(svd[574..702], ucs2_padded("", 128));
+    }
+}
@@ -1,10 +1,80 @@
 #![allow(unused)]
 use std::io::{self, Read, Seek};
+use std::borrow::Cow;
 
 use core::ops::RangeInclusive;
 
 mod iso9660_types;
 use iso9660_types::*;
+pub use iso9660_types::{str_a, str_d, DecDateTime};
+
+mod block_device;
+pub use block_device::*;
+
+mod cache;
+pub use cache::*;
+
+mod fs;
+pub use fs::*;
+
+mod session;
+pub use session::*;
+
+mod views;
+pub use views::*;
+
+mod image;
+pub use image::*;
+
+mod builder;
+pub use builder::*;
+
+mod raw;
+pub use raw::*;
+
+mod ecc;
+pub use ecc::*;
+
+mod xa;
+pub use xa::*;
+
+mod cue;
+pub use cue::*;
+
+mod mbr;
+pub use mbr::*;
+
+mod gpt;
+pub use gpt::*;
+
+mod system_area;
+pub use system_area::*;
+
+mod fat;
+pub use fat::*;
+
+mod boot_info;
+pub use boot_info::*;
+
+mod eltorito;
+pub use eltorito::*;
+
+mod rock_ridge;
+pub use rock_ridge::*;
+
+mod joliet;
+pub use joliet::*;
+
+mod namespace;
+pub use namespace::*;
+
+mod verify;
+pub use verify::*;
+
+#[cfg(feature = "manifest")]
+mod manifest;
+#[cfg(feature = "manifest")]
+pub use manifest::*;
 
 const EL_TORITO_SPECIFICATION_STR: &str = "EL TORITO SPECIFICATION";
 
@@ -12,10 +82,15 @@ pub const SECTOR_SIZE: usize = 2 * 1024; // 2K
 
 pub const DATA_START: u64 = 32_768; // 16 sectors
 
+/// Logical block sizes allowed by ECMA-119, descriptors are still read in
+/// `SECTOR_SIZE` sectors whatever the block size of the volume is
+pub const LOGICAL_BLOCK_SIZES: [u16; 3] = [512, 1024, 2048];
+
 const VD_IDENT: &[u8; 5] = b"CD001";
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VDType {
     BootRecord = 0,
     PrimaryVD = 1,
@@ -41,14 +116,6 @@ impl TryFrom<u8> for VDType {
     }
 }
 
-pub fn read_sector<R: Read>(mut r: R) -> io::Result<Box<[u8]>> {
-    use core::iter::repeat;
-    // try not to allocate 2k in the stack
-    let mut sector: Vec<u8> = repeat(0_u8).take(SECTOR_SIZE).collect();
-    r.read_exact(&mut sector)?;
-    Ok(sector.into_boxed_slice())
-}
-
 
 #[derive(Debug)]
 pub struct VD {
@@ -73,6 +140,20 @@ pub enum VDErr {
     UnknownBootMedia(u8),
     UnknownBootIndicator(u8),
     UnknownHeaderIndicator(u8),
+    InvalidDirectoryRecord,
+    MissingPrimaryVD,
+    NotFound,
+    NotADirectory,
+    Truncated,
+    UnexpectedDescriptor(VDType),
+    InvalidBootCatalog,
+    InvalidLogicalBlockSize(u16),
+    IsADirectory,
+    /// The new content of a file does not fit in the blocks of its extent
+    ExtentTooSmall {
+        allocated: u64,
+        needed: u64,
+    },
 }
 
 impl From<UnknownHeaderIndicator> for VDErr {
@@ -137,6 +218,36 @@ impl From<DecDateTimeErr> for VDErr {
     }
 }
 
+impl core::fmt::Display for VDErr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnknownVersion(v) => write!(f, "unknown descriptor version {}", v),
+            Self::UnknownIdent(ident) => write!(f, "not an ISO 9660 volume, found {:?}", String::from_utf8_lossy(ident)),
+            Self::InvalidAlphabet { code_point, .. } => write!(f, "invalid character 0x{:02x} in an identifier", code_point),
+            Self::InvalidDate { range, actual } => write!(f, "date field {:?} out of {:?}", actual, range),
+            Self::UnknownPlatformId(id) => write!(f, "unknown boot platform 0x{:02x}", id),
+            Self::UnknownBootMedia(media) => write!(f, "unknown boot media 0x{:02x}", media),
+            Self::UnknownBootIndicator(indicator) => write!(f, "unknown boot indicator 0x{:02x}", indicator),
+            Self::UnknownHeaderIndicator(indicator) => write!(f, "unknown section header indicator 0x{:02x}", indicator),
+            Self::InvalidDirectoryRecord => f.write_str("invalid directory record"),
+            Self::MissingPrimaryVD => f.write_str("no primary volume descriptor"),
+            Self::NotFound => f.write_str("no such file or directory"),
+            Self::NotADirectory => f.write_str("not a directory"),
+            Self::Truncated => f.write_str("the image is truncated"),
+            Self::UnexpectedDescriptor(ty) => write!(f, "unexpected {:?} descriptor", ty),
+            Self::InvalidBootCatalog => f.write_str("invalid boot catalog"),
+            Self::InvalidLogicalBlockSize(size) => write!(f, "invalid logical block size {}", size),
+            Self::IsADirectory => f.write_str("is a directory"),
+            Self::ExtentTooSmall { allocated, needed } => {
+                write!(f, "{} bytes do not fit in an extent of {} bytes", needed, allocated)
+            },
+        }
+    }
+}
+
+impl std::error::Error for VDErr {}
+
 impl VD {
     pub fn read_header(buffer: &[u8]) -> Result<Self, VDErr> {
         let ty = VDType::try_from(buffer[0])?;
@@ -165,7 +276,51 @@ impl VD {
     }
 }
 
+pub(crate) fn check_logical_block_size(size: u16) -> Result<u16, VDErr> {
+    if LOGICAL_BLOCK_SIZES.contains(&size) {
+        Ok(size)
+    } else {
+        Err(VDErr::InvalidLogicalBlockSize(size))
+    }
+}
+
+/// The publisher, data preparer or application identifier of a volume,
+/// ECMA-119 8.4.20 to 8.4.22
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VolumeIdent {
+    /// The identifier itself
+    Text(StrA<128>),
+    /// The name of a file of the root directory that holds the identifier,
+    /// recorded after a `_`
+    File(StrA<127>),
+}
+
+impl VolumeIdent {
+    /// The identifier, or the name of the file that holds it
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Text(s) => s.as_str(),
+            Self::File(name) => name.as_str(),
+        }
+    }
+
+    /// Parses the 128 bytes of the field, `None` when it is blank
+    ///
+    /// Text with characters other than a-characters is dropped rather than
+    /// failing, some images have any kind of text there.
+    pub(crate) fn parse(field: &[u8]) -> Result<Option<Self>, InvalidChar> {
+        if field[0] == 0x5f {
+            return Ok(Some(Self::File(StrA::from_slice(&field[1..])?)))
+        }
+        Ok(StrA::from_slice(field).ok()
+            .filter(|text| !text.as_str().is_empty())
+            .map(Self::Text))
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PVD {
     pub sys_ident: Option<StrA<32>>,
     pub vol_ident: Option<StrD<32>>,
@@ -178,10 +333,11 @@ pub struct PVD {
     pub opt_path_table_l_location: Option<u32>,
     pub path_table_m_location: u32,
     pub opt_path_table_m_location: Option<u32>,
+    pub root_record: DirectoryRecord,
     pub vol_set_ident: Option<StrD<128>>,
-    pub publisher_ident: Option<StrA<127>>,
-    pub data_prep_ident: Option<StrA<127>>,
-    pub app_ident: Option<StrA<127>>,
+    pub publisher_ident: Option<VolumeIdent>,
+    pub data_prep_ident: Option<VolumeIdent>,
+    pub app_ident: Option<VolumeIdent>,
     pub copyright_file_name: Option<StrD<37>>,
     pub abstract_file_name: Option<StrD<37>>,
     pub bibliographic_file_name: Option<StrD<37>>,
@@ -189,6 +345,7 @@ pub struct PVD {
     pub vol_mod_date_time: Option<DecDateTime>,
     pub vol_expiration_date_time: Option<DecDateTime>,
     pub vol_effective_date_time: Option<DecDateTime>,
+    #[cfg_attr(feature = "serde", serde(with = "iso9660_types::opt_bytes"))]
     pub application_used: Option<[u8; 512]>,
 }
 
@@ -246,6 +403,8 @@ impl PVD {
             }
         };
 
+        let root_record = DirectoryRecord::try_parse(&buffer[156..190])?;
+
         let vol_set_ident: Option<StrD<128>> = {
             let s = StrD::from_slice(&buffer[190..318])?;
             if s.as_str().is_empty() {
@@ -255,23 +414,9 @@ impl PVD {
             }
         };
 
-        let publisher_ident: Option<StrA<127>> = if buffer[318] == 0x5f {
-            Some(StrA::from_slice(&buffer[319..446])?)
-        } else {
-            None
-        };
-
-        let data_prep_ident: Option<StrA<127>> = if buffer[446] == 0x5f {
-            Some(StrA::from_slice(&buffer[445..574])?)
-        } else {
-            None
-        };
-
-        let app_ident: Option<StrA<127>> = if buffer[574] == 0x5f {
-            Some(StrA::from_slice(&buffer[575..702])?)
-        } else {
-            None
-        };
+        let publisher_ident = VolumeIdent::parse(&buffer[318..446])?;
+        let data_prep_ident = VolumeIdent::parse(&buffer[446..574])?;
+        let app_ident = VolumeIdent::parse(&buffer[574..702])?;
 
         let copyright_file_name: Option<StrD<37>> = {
             let s = StrD::from_slice(&buffer[702..739])?;
@@ -330,6 +475,7 @@ impl PVD {
             opt_path_table_l_location,
             path_table_m_location,
             opt_path_table_m_location,
+            root_record,
             vol_set_ident,
             publisher_ident,
             data_prep_ident,
@@ -345,10 +491,73 @@ impl PVD {
         })
 
     }
+
+    /// `logical_block_size` if it is one of `LOGICAL_BLOCK_SIZES`
+    pub fn checked_logical_block_size(&self) -> Result<u16, VDErr> {
+        check_logical_block_size(self.logical_block_size)
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        fn put_str(out: &mut [u8], s: Option<&str>) {
+            out.fill(b' ');
+            if let Some(s) = s {
+                out[..s.len()].copy_from_slice(s.as_bytes());
+            }
+        }
+
+        fn put_ident(out: &mut [u8], ident: Option<&VolumeIdent>) {
+            match ident {
+                Some(VolumeIdent::File(name)) => {
+                    out[0] = 0x5f;
+                    put_str(&mut out[1..], Some(name.as_str()));
+                },
+                ident => put_str(out, ident.map(VolumeIdent::as_str)),
+            }
+        }
+
+        VD {
+            ty: VDType::PrimaryVD,
+            version: 1,
+        }.dump(out);
+        out[7] = 0;
+        put_str(&mut out[8..40], self.sys_ident.as_ref().map(|s| s.as_str()));
+        put_str(&mut out[40..72], self.vol_ident.as_ref().map(|s| s.as_str()));
+        out[72..80].fill(0);
+        double_endian::put_u32(&mut out[80..88], self.vol_space_size);
+        out[88..120].fill(0);
+        double_endian::put_u16(&mut out[120..124], self.vol_set_size);
+        double_endian::put_u16(&mut out[124..128], self.vol_seq_num);
+        double_endian::put_u16(&mut out[128..132], self.logical_block_size);
+        double_endian::put_u32(&mut out[132..140], self.path_table_size);
+        out[140..144].copy_from_slice(&self.path_table_l_location.to_le_bytes());
+        out[144..148].copy_from_slice(&self.opt_path_table_l_location.unwrap_or(0).to_le_bytes());
+        out[148..152].copy_from_slice(&self.path_table_m_location.to_be_bytes());
+        out[152..156].copy_from_slice(&self.opt_path_table_m_location.unwrap_or(0).to_be_bytes());
+        self.root_record.dump(&mut out[156..190]);
+        put_str(&mut out[190..318], self.vol_set_ident.as_ref().map(|s| s.as_str()));
+        put_ident(&mut out[318..446], self.publisher_ident.as_ref());
+        put_ident(&mut out[446..574], self.data_prep_ident.as_ref());
+        put_ident(&mut out[574..702], self.app_ident.as_ref());
+        put_str(&mut out[702..739], self.copyright_file_name.as_ref().map(|s| s.as_str()));
+        put_str(&mut out[739..776], self.abstract_file_name.as_ref().map(|s| s.as_str()));
+        put_str(&mut out[776..813], self.bibliographic_file_name.as_ref().map(|s| s.as_str()));
+        DecDateTime::dump(self.vol_create_date_time.as_ref(), &mut out[813..830]);
+        DecDateTime::dump(self.vol_mod_date_time.as_ref(), &mut out[830..847]);
+        DecDateTime::dump(self.vol_expiration_date_time.as_ref(), &mut out[847..864]);
+        DecDateTime::dump(self.vol_effective_date_time.as_ref(), &mut out[864..881]);
+        out[881] = 1;
+        out[882] = 0;
+        match self.application_used {
+            Some(ref v) => out[883..1395].copy_from_slice(v),
+            None => out[883..1395].fill(0),
+        }
+        out[1395..SECTOR_SIZE].fill(0);
+    }
 }
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BootRecord {
     pub boot_sys_ident: Option<StrA<32>>,
     pub boot_ident: Option<StrA<32>>,
@@ -428,105 +637,68 @@ impl BootRecord {
 
 }
 
+/// The 7 bytes date of a directory record, unlike `DecDateTime` it is binary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DirectoryRecordDate {
-    pub year: StrD<4>,
-    pub month: StrD<2>,
-    pub day: StrD<2>,
-    pub hour: StrD<2>,
-    pub minute: StrD<2>,
-    pub second: StrD<2>,
-    /// Time zone offset from GMT in 15 minute intervals, starting at interval -48 (0)
-    pub time_zone: u8,
-}
-
-impl Default for DirectoryRecordDate {
-    /// defaults to the first of January at midnight UTC in the year 1
-    fn default() -> Self {
-        Self {
-            year: StrD::from_slice(b" 1").unwrap(),
-            month: StrD::from_slice(b" 1").unwrap(),
-            day: StrD::from_slice(b" 1").unwrap(),
-            hour: StrD::from_slice(b" 0").unwrap(),
-            minute: StrD::from_slice(b" 0").unwrap(),
-            second: StrD::from_slice(b" 0").unwrap(),
-            time_zone: 12,
-        }
-    }
+    pub years_since_1900: u8,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// Time zone offset from GMT in 15 minute intervals, from -48 (West) to +52 (East)
+    pub time_zone: i8,
 }
 
 impl DirectoryRecordDate {
-    pub fn try_parse(buffer: &[u8]) -> Result<Option<Self>, DecDateTimeErr> {
-
-        if buffer[16] == 0 && buffer[..16].iter().all(|&b| b == b'0') {
-            return Ok(None)
-        }
-
-        let year = StrD::<4>::from_slice(&buffer[..4])?;
-        if !("1".."9999").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "1"..="9999",
-                actual: year.as_str().try_into().unwrap(),
-            })
-        }
-
-        let month = StrD::<2>::from_slice(&buffer[4..6])?;
-        if !("1".."9999").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "1"..="12",
-                actual: month.as_str().try_into().unwrap(),
-            })
-        }
-
-        let day = StrD::<2>::from_slice(&buffer[6..8])?;
-        if !("1".."31").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "1"..="31",
-                actual: day.as_str().try_into().unwrap(),
-            })
-        }
-
-        let hour = StrD::<2>::from_slice(&buffer[8..10])?;
-        if !("0".."23").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "1"..="23",
-                actual: hour.as_str().try_into().unwrap(),
-            })
-        }
-
-        let minute = StrD::<2>::from_slice(&buffer[10..12])?;
-        if !("0".."59").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "0"..="59",
-                actual: minute.as_str().try_into().unwrap(),
-            })
-        }
-
-        let second = StrD::<2>::from_slice(&buffer[12..14])?;
-        if !("0".."59").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "0"..="59",
-                actual: second.as_str().try_into().unwrap(),
-            })
-        }
-
-        let centi_sec = StrD::<2>::from_slice(&buffer[14..16])?;
-        if !("0".."99").contains(&year.as_str()) {
-            return Err(DecDateTimeErr::InvalidDate {
-                range: "0"..="99",
-                actual: centi_sec.as_str().try_into().unwrap(),
-            })
-        }
-
-        let time_zone = buffer[16];
-        Ok(Some(Self {
-            year,
+    /// `secs` seconds since the unix epoch, in UTC
+    pub fn from_unix_time(secs: i64) -> Self {
+        let (year, month, day, hour, minute, second) = civil_from_unix_time(secs);
+        Self {
+            years_since_1900: (year - 1900).clamp(0, 255) as u8,
             month,
             day,
             hour,
             minute,
             second,
-            time_zone,
-        }))
+            time_zone: 0,
+        }
+    }
+
+    /// Seconds since the unix epoch
+    pub fn to_unix_time(&self) -> i64 {
+        let secs = unix_time_from_civil(
+            1900 + self.years_since_1900 as i64,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+        );
+        secs - self.time_zone as i64 * 15 * 60
+    }
+
+    pub fn parse(buffer: &[u8]) -> Self {
+        Self {
+            years_since_1900: buffer[0],
+            month: buffer[1],
+            day: buffer[2],
+            hour: buffer[3],
+            minute: buffer[4],
+            second: buffer[5],
+            time_zone: buffer[6] as i8,
+        }
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        out[0] = self.years_since_1900;
+        out[1] = self.month;
+        out[2] = self.day;
+        out[3] = self.hour;
+        out[4] = self.minute;
+        out[5] = self.second;
+        out[6] = self.time_zone as u8;
     }
 }
 
@@ -539,6 +711,11 @@ pub mod flags {
     pub const IS_PARTIAL: u8 = 128;
 }
 
+/// Size of a directory record without its identifier and system use area
+pub const DIRECTORY_RECORD_HEADER_SIZE: usize = 33;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DirectoryRecord {
     pub size: u8,
     pub ext_attr_len: u8,
@@ -549,10 +726,78 @@ pub struct DirectoryRecord {
     pub interleaved_file_size: Option<u8>,
     pub interleaved_gap_size: Option<u8>,
     pub vol_seq_nul: u16,
+    /// `[0]` for the directory itself, `[1]` for its parent
+    pub file_ident: Vec<u8>,
+    pub system_use: Vec<u8>,
+}
+
+impl DirectoryRecord {
+    pub fn try_parse(buffer: &[u8]) -> Result<Self, VDErr> {
+        Ok(DirectoryRecordRef::new(buffer)?.to_record())
+    }
+
+    /// Size of the record once dumped, `size` is ignored
+    pub fn encoded_len(&self) -> usize {
+        let ident_end = DIRECTORY_RECORD_HEADER_SIZE + self.file_ident.len();
+        ((ident_end + 1) & !1) + self.system_use.len()
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        let len = self.encoded_len();
+        out[0] = len as u8;
+        out[1] = self.ext_attr_len;
+        double_endian::put_u32(&mut out[2..10], self.extent_location);
+        double_endian::put_u32(&mut out[10..18], self.data_size);
+        self.create_date.dump(&mut out[18..25]);
+        out[25] = self.flags;
+        out[26] = self.interleaved_file_size.unwrap_or(0);
+        out[27] = self.interleaved_gap_size.unwrap_or(0);
+        double_endian::put_u16(&mut out[28..32], self.vol_seq_nul);
+        out[32] = self.file_ident.len() as u8;
+
+        let ident_end = DIRECTORY_RECORD_HEADER_SIZE + self.file_ident.len();
+        out[DIRECTORY_RECORD_HEADER_SIZE..ident_end].copy_from_slice(&self.file_ident);
+        out[ident_end..(ident_end + 1) & !1].fill(0);
+        out[len - self.system_use.len()..len].copy_from_slice(&self.system_use);
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.flags & flags::DIR != 0
+    }
+
+    /// `true` for the `.` and `..` entries
+    pub fn is_special(&self) -> bool {
+        matches!(self.file_ident.as_slice(), [0] | [1])
+    }
+
+    /// The identifier without its `;1` version suffix nor its trailing `.`
+    pub fn name(&self) -> Cow<'_, str> {
+        record_name(&self.file_ident, self.is_dir())
+    }
+}
+
+fn record_name(file_ident: &[u8], is_dir: bool) -> Cow<'_, str> {
+    match file_ident {
+        [0] => return Cow::Borrowed("."),
+        [1] => return Cow::Borrowed(".."),
+        _ => (),
+    }
+
+    let mut ident = file_ident;
+    if !is_dir {
+        if let Some(pos) = ident.iter().rposition(|&b| b == b';') {
+            ident = &ident[..pos];
+        }
+        if let [rest @ .., b'.'] = ident {
+            ident = rest;
+        }
+    }
+    String::from_utf8_lossy(ident)
 }
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Platform {
     X86 = 0,
     PPC = 1,
@@ -577,6 +822,7 @@ impl TryFrom<u8> for Platform {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidationEntry {
     pub header_id: u8,
     pub platform_id: Platform,
@@ -614,11 +860,20 @@ impl ValidationEntry {
         out[28..30].fill(0);
         out[30] = 0x55;
         out[31] = 0xAA;
+
+        // the 16 bits words of the entry sum up to 0
+        let sum = out[..32].chunks_exact(2)
+            .fold(0_u16, |sum, w| sum.wrapping_add(u16::from_le_bytes([w[0], w[1]])));
+        out[28..30].copy_from_slice(&sum.wrapping_neg().to_le_bytes());
     }
 }
 
+/// First byte of the extension entries that can follow a section entry
+pub const EXTENSION_ENTRY_INDICATOR: u8 = 0x44;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootIndicator {
     NotBootable = 0,
     Bootable = 0x88,
@@ -640,6 +895,7 @@ impl TryFrom<u8> for BootIndicator {
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootMedia {
     NoEmulation = 0,
     Floppy1_2 = 1,
@@ -648,6 +904,28 @@ pub enum BootMedia {
     HardDrive = 4,
 }
 
+impl BootMedia {
+    /// The exact size of the images of the floppy media
+    pub fn floppy_size(self) -> Option<u64> {
+        match self {
+            Self::Floppy1_2 => Some(1200 * 1024),
+            Self::Floppy1_44 => Some(1440 * 1024),
+            Self::Floppy2_88 => Some(2880 * 1024),
+            _ => None,
+        }
+    }
+
+    /// Number of 512 bytes virtual sectors a boot catalog entry loads for an
+    /// image of `image_len` bytes: all of it for no emulation images, and
+    /// the boot sector of the emulated disk otherwise
+    pub fn sector_count(self, image_len: u64) -> u16 {
+        match self {
+            Self::NoEmulation => image_len.div_ceil(512).clamp(1, u16::MAX as u64) as u16,
+            _ => 1,
+        }
+    }
+}
+
 pub struct UnknownBootMedia(pub u8);
 
 impl TryFrom<u8> for BootMedia {
@@ -665,24 +943,37 @@ impl TryFrom<u8> for BootMedia {
     }
 }
 
+/// Segment boot images are loaded at when an entry leaves it to 0
+pub const DEFAULT_LOAD_SEGMENT: u16 = 0x7C0;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InitialEntry {
     pub boot_indicator: BootIndicator,
     pub boot_media: BootMedia,
-    pub load_segment: u16,
+    /// `None` for `DEFAULT_LOAD_SEGMENT`
+    pub load_segment: Option<u16>,
     pub sys_type: u8,
     pub sector_count: u16,
     pub virtual_disk_addr: u32,
 }
 
 impl InitialEntry {
+    /// The segment the image is loaded at, `DEFAULT_LOAD_SEGMENT` if unset
+    pub fn segment(&self) -> u16 {
+        self.load_segment.unwrap_or(DEFAULT_LOAD_SEGMENT)
+    }
+
     pub fn try_parse(buffer: &[u8]) -> Result<Self, VDErr> {
         let boot_indicator = BootIndicator::try_from(buffer[0])?;
         let boot_media = BootMedia::try_from(buffer[1])?;
 
         let mut u16_buffer = [0_u8; 2];
         u16_buffer.copy_from_slice(&buffer[2..4]);
-        let load_segment = u16::from_le_bytes(u16_buffer);
+        let load_segment = match u16::from_le_bytes(u16_buffer) {
+            0 => None,
+            v => Some(v),
+        };
 
         let sys_type = buffer[4];
 
@@ -706,7 +997,7 @@ impl InitialEntry {
     pub fn dump(&self, out: &mut [u8]) {
         out[0] = self.boot_indicator as u8;
         out[1] = self.boot_media as u8;
-        out[2..4].copy_from_slice(&self.load_segment.to_le_bytes());
+        out[2..4].copy_from_slice(&self.load_segment.unwrap_or(0).to_le_bytes());
         out[4] = self.sys_type;
         out[5] = 0;
         out[6..8].copy_from_slice(&self.sector_count.to_le_bytes());
@@ -717,6 +1008,7 @@ impl InitialEntry {
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HeaderIndicator {
     Partial = 0x90,
     Final = 0x91,
@@ -738,6 +1030,7 @@ impl TryFrom<u8> for HeaderIndicator {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SectionHeaderEntry {
     pub header_indicator: HeaderIndicator,
     pub platform_id: Platform,
@@ -785,6 +1078,7 @@ impl SectionHeaderEntry {
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SelectionCriteria {
     None = 0,
     LanguageAndVersion = 1,
@@ -812,13 +1106,15 @@ impl From<u8> for SelectionCriteria {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SectionEntry {
     pub boot_indicator: BootIndicator,
     pub boot_media: BootMedia,
     pub has_continuation_entry: bool,
     pub image_contains_atapi_driver: bool,
     pub image_contains_scsi_driver: bool,
-    pub load_segment: u16,
+    /// `None` for `DEFAULT_LOAD_SEGMENT`
+    pub load_segment: Option<u16>,
     pub sys_type: u8,
     pub sector_count: u16,
     pub virtual_disk_addr: u32,
@@ -827,11 +1123,16 @@ pub struct SectionEntry {
 }
 
 impl SectionEntry {
+    /// The segment the image is loaded at, `DEFAULT_LOAD_SEGMENT` if unset
+    pub fn segment(&self) -> u16 {
+        self.load_segment.unwrap_or(DEFAULT_LOAD_SEGMENT)
+    }
+
     pub fn try_parse(buffer: &[u8]) -> Result<Self, VDErr> {
         let boot_indicator = BootIndicator::try_from(buffer[0])?;
 
-        // the first 3 bits denote the media type
-        let boot_media_bits = buffer[1] & 3;
+        // the first 4 bits denote the media type
+        let boot_media_bits = buffer[1] & 0x0F;
         let boot_media = BootMedia::try_from(boot_media_bits)?;
 
         // the last 3 bits are used as a bitfield
@@ -841,7 +1142,10 @@ impl SectionEntry {
 
         let mut u16_bytes = [0_u8; 2];
         u16_bytes.copy_from_slice(&buffer[2..4]);
-        let load_segment = u16::from_le_bytes(u16_bytes);
+        let load_segment = match u16::from_le_bytes(u16_bytes) {
+            0 => None,
+            v => Some(v),
+        };
 
         let sys_type = buffer[4];
 
@@ -877,12 +1181,12 @@ impl SectionEntry {
         out[0] = self.boot_indicator as u8;
 
         let mut second_bit = self.boot_media as u8;
-        second_bit |= self.has_continuation_entry as u8 >> 5;
-        second_bit |= self.image_contains_atapi_driver as u8 >> 6;
-        second_bit |= self.image_contains_scsi_driver as u8 >> 7;
+        second_bit |= (self.has_continuation_entry as u8) << 5;
+        second_bit |= (self.image_contains_atapi_driver as u8) << 6;
+        second_bit |= (self.image_contains_scsi_driver as u8) << 7;
         out[1] = second_bit;
 
-        out[2..4].copy_from_slice(&self.load_segment.to_le_bytes());
+        out[2..4].copy_from_slice(&self.load_segment.unwrap_or(0).to_le_bytes());
         out[4] = self.sys_type;
         out[5] = 0; // reserved but must be 0
         out[6..8].copy_from_slice(&self.sector_count.to_le_bytes());
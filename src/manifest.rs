@@ -0,0 +1,632 @@
+//! Images described by a TOML manifest, only available with the `manifest`
+//! feature
+//!
+//! ```toml
+//! [volume]
+//! volume_id = "LIVE"
+//! publisher = "ACME"
+//! copyright_file = "COPYING"
+//!
+//! [names]
+//! rock_ridge = true
+//! joliet = true
+//!
+//! [[files]]
+//! path = "/"
+//! source = "rootfs"
+//!
+//! [[files]]
+//! path = "bin/init"
+//! mode = 0o755
+//!
+//! [boot]
+//! catalog = "isolinux/boot.cat"
+//!
+//! [[boot.entries]]
+//! image = "isolinux/isolinux.bin"
+//! load_size = 4
+//! boot_info_table = true
+//!
+//! [[boot.entries]]
+//! platform = "uefi"
+//! image = "efi.img"
+//!
+//! [hybrid]
+//! mbr = "isohdpfx.bin"
+//! gpt = true
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::*;
+
+/// Directories of the host walked before giving up, symbolic links are not
+/// followed but bind mounts can still make loops
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum ManifestErr {
+    /// The manifest could not be read
+    Io(PathBuf, io::Error),
+    /// Not TOML, or a key is unknown or of the wrong type, the message of
+    /// `toml` names the key and its line
+    Parse(toml::de::Error),
+    /// The value of `key` is not valid, `key` is like `files[2].mode`
+    Invalid {
+        key: String,
+        msg: String,
+    },
+}
+
+impl ManifestErr {
+    fn invalid(key: impl Into<String>, msg: impl fmt::Display) -> Self {
+        Self::Invalid {
+            key: key.into(),
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ManifestErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::Invalid { key, msg } => write!(f, "{}: {}", key, msg),
+        }
+    }
+}
+
+impl std::error::Error for ManifestErr {}
+
+/// The fields of the primary volume descriptor, empty ones are left blank
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeSection {
+    pub system_id: String,
+    pub volume_id: String,
+    pub volume_set_id: String,
+    pub publisher: String,
+    pub preparer: String,
+    pub application: String,
+    /// Names of files of the root of the image
+    pub copyright_file: String,
+    pub abstract_file: String,
+    pub bibliographic_file: String,
+    /// One of `LOGICAL_BLOCK_SIZES`, 2048 when not set
+    pub block_size: Option<u16>,
+    /// Seconds since the unix epoch, now when not set
+    pub creation_time: Option<i64>,
+}
+
+/// The extensions that record the names of the files
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamesSection {
+    pub rock_ridge: bool,
+    pub joliet: bool,
+}
+
+/// A file or a directory of the host mapped into the image, or the
+/// attributes of a path mapped by another entry when there is no `source`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileMapping {
+    /// In the image, the content of a directory goes into it, `"/"` for the
+    /// root
+    pub path: String,
+    /// On the host, relative to the directory of the manifest
+    pub source: Option<PathBuf>,
+    /// Permission bits recorded with Rock Ridge
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Written but without a record, see `IsoBuilder::add_hidden_file`
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestPlatform {
+    #[default]
+    Bios,
+    Uefi,
+    Ppc,
+    Mac,
+}
+
+impl From<ManifestPlatform> for Platform {
+    fn from(value: ManifestPlatform) -> Self {
+        match value {
+            ManifestPlatform::Bios => Platform::X86,
+            ManifestPlatform::Uefi => Platform::UEFI,
+            ManifestPlatform::Ppc => Platform::PPC,
+            ManifestPlatform::Mac => Platform::Mac,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestEmulation {
+    #[default]
+    None,
+    /// The media is picked from the size of the image
+    Floppy,
+    HardDisk,
+}
+
+/// An entry of the El Torito boot catalog, see `BootImage`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootEntrySection {
+    pub platform: ManifestPlatform,
+    /// Path of the image in the tree
+    pub image: String,
+    pub emulation: ManifestEmulation,
+    pub load_segment: Option<u16>,
+    /// Number of 512 bytes virtual sectors loaded by the BIOS
+    pub load_size: Option<u16>,
+    pub boot_info_table: bool,
+    pub grub2_boot_info: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootSection {
+    /// Path of the record of the boot catalog, it has none when not set
+    pub catalog: Option<String>,
+    /// The first one is the default entry
+    pub entries: Vec<BootEntrySection>,
+}
+
+/// What goes in the system area so that the image also boots from a disk
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HybridSection {
+    /// The boot code of an isohybrid MBR on the host, like `isohdpfx.bin`
+    pub mbr: Option<PathBuf>,
+    /// Type of the partition of the isohybrid MBR
+    pub partition_type: Option<u8>,
+    /// Writes a GPT with an EFI System Partition
+    pub gpt: bool,
+    /// A FAT image of the host appended as the ESP, the UEFI boot image is
+    /// the ESP when not set
+    pub esp: Option<PathBuf>,
+}
+
+/// An image described in TOML, turned into a builder by
+/// `IsoBuilder::from_manifest`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub volume: VolumeSection,
+    pub names: NamesSection,
+    pub files: Vec<FileMapping>,
+    pub boot: BootSection,
+    pub hybrid: HybridSection,
+    /// Directory the sources are relative to
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+/// Checks that `value` of `key` fits in `len` bytes of `alphabet`
+fn check_ident(key: &str, value: &str, len: usize, alphabet: &[u8]) -> Result<(), ManifestErr> {
+    if value.len() > len {
+        return Err(ManifestErr::invalid(key, format!("longer than {} characters", len)))
+    }
+    match value.bytes().find(|b| !alphabet.contains(b)) {
+        Some(b) => Err(ManifestErr::invalid(key, format!("invalid character {:?}", b as char))),
+        None => Ok(()),
+    }
+}
+
+impl Manifest {
+    /// Parses `text`, the sources are relative to `base_dir`
+    pub fn parse(text: &str, base_dir: impl Into<PathBuf>) -> Result<Self, ManifestErr> {
+        let mut manifest: Self = toml::from_str(text).map_err(ManifestErr::Parse)?;
+        manifest.base_dir = base_dir.into();
+        Ok(manifest)
+    }
+
+    /// Reads the manifest `path`, the sources are relative to its directory
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ManifestErr> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ManifestErr::Io(path.to_owned(), e))?;
+        let base_dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        Self::parse(&text, base_dir)
+    }
+
+    fn source(&self, path: &Path) -> PathBuf {
+        self.base_dir.join(path)
+    }
+
+    /// Checks the values that do not depend on the files of the host,
+    /// `IsoBuilder::from_manifest` checks the rest
+    pub fn validate(&self) -> Result<(), ManifestErr> {
+        let volume = &self.volume;
+        // the publisher, preparer and application are written as text over
+        // the 128 bytes of their field, a leading `_` would have readers take
+        // them for the name of a file. Like the builder and the reader, the
+        // d-character fields take a-characters, as most images out there have
+        // them.
+        let idents: [(&str, &str, usize, &[u8]); 6] = [
+            ("volume.system_id", &volume.system_id, 32, STR_A_CHAR_SET),
+            ("volume.volume_id", &volume.volume_id, 32, STR_A_CHAR_SET),
+            ("volume.volume_set_id", &volume.volume_set_id, 128, STR_A_CHAR_SET),
+            ("volume.publisher", &volume.publisher, 128, STR_A_CHAR_SET),
+            ("volume.preparer", &volume.preparer, 128, STR_A_CHAR_SET),
+            ("volume.application", &volume.application, 128, STR_A_CHAR_SET),
+        ];
+        for (key, value, len, alphabet) in idents {
+            check_ident(key, value, len, alphabet)?;
+        }
+        let texts = [
+            ("volume.publisher", &volume.publisher),
+            ("volume.preparer", &volume.preparer),
+            ("volume.application", &volume.application),
+        ];
+        for (key, text) in texts {
+            if text.starts_with('_') {
+                return Err(ManifestErr::invalid(key, "a leading `_` names a file"))
+            }
+        }
+        let root_files = [
+            ("volume.copyright_file", &volume.copyright_file),
+            ("volume.abstract_file", &volume.abstract_file),
+            ("volume.bibliographic_file", &volume.bibliographic_file),
+        ];
+        for (key, name) in root_files {
+            if name.contains('/') {
+                return Err(ManifestErr::invalid(key, "must be a file of the root"))
+            }
+        }
+        if let Some(size) = volume.block_size {
+            if !LOGICAL_BLOCK_SIZES.contains(&size) {
+                return Err(ManifestErr::invalid("volume.block_size", format!("{} is not 512, 1024 or 2048", size)))
+            }
+        }
+
+        for (i, file) in self.files.iter().enumerate() {
+            let key = |field: &str| format!("files[{}].{}", i, field);
+            if let Some(mode) = file.mode {
+                if mode > 0o7777 {
+                    return Err(ManifestErr::invalid(key("mode"), format!("{:#o} is not a set of permission bits", mode)))
+                }
+            }
+            let attr = [("mode", file.mode.is_some()), ("uid", file.uid.is_some()), ("gid", file.gid.is_some())]
+                .into_iter()
+                .find_map(|(field, is_set)| is_set.then_some(field));
+            let has_attrs = attr.is_some();
+            if let Some(field) = attr.filter(|_| !self.names.rock_ridge) {
+                return Err(ManifestErr::invalid(key(field), "attributes are only recorded with names.rock_ridge"))
+            }
+            if file.source.is_none() && file.hidden {
+                return Err(ManifestErr::invalid(key("hidden"), "only files with a source can be hidden"))
+            }
+            if file.source.is_none() && !has_attrs {
+                return Err(ManifestErr::invalid(key("source"), "missing"))
+            }
+        }
+
+        if self.boot.catalog.is_some() && self.boot.entries.is_empty() {
+            return Err(ManifestErr::invalid("boot.catalog", "the image has no boot entry"))
+        }
+        for (i, entry) in self.boot.entries.iter().enumerate() {
+            let key = |field: &str| format!("boot.entries[{}].{}", i, field);
+            if entry.image.is_empty() {
+                return Err(ManifestErr::invalid(key("image"), "missing"))
+            }
+            if entry.load_size == Some(0) {
+                return Err(ManifestErr::invalid(key("load_size"), "at least one sector is loaded"))
+            }
+            if (entry.boot_info_table || entry.grub2_boot_info) && entry.emulation != ManifestEmulation::None {
+                return Err(ManifestErr::invalid(key("emulation"), "only no emulation images are patched"))
+            }
+        }
+
+        let hybrid = &self.hybrid;
+        if hybrid.mbr.is_some() && self.boot.entries.is_empty() {
+            return Err(ManifestErr::invalid("hybrid.mbr", "the MBR needs a boot entry to load"))
+        }
+        if hybrid.partition_type.is_some() && hybrid.mbr.is_none() {
+            return Err(ManifestErr::invalid("hybrid.partition_type", "needs hybrid.mbr"))
+        }
+        if hybrid.esp.is_some() && !hybrid.gpt {
+            return Err(ManifestErr::invalid("hybrid.esp", "needs hybrid.gpt"))
+        }
+        let has_uefi = self.boot.entries.iter().any(|e| e.platform == ManifestPlatform::Uefi);
+        if hybrid.gpt && hybrid.esp.is_none() && !has_uefi {
+            return Err(ManifestErr::invalid("hybrid.gpt", "needs hybrid.esp or a UEFI boot entry"))
+        }
+        Ok(())
+    }
+}
+
+/// Adds the sources of a manifest to a builder, remembering the size of the
+/// files to pick the media of floppy images
+struct Mapper<'a> {
+    manifest: &'a Manifest,
+    builder: IsoBuilder,
+    sizes: HashMap<String, u64>,
+}
+
+impl Mapper<'_> {
+    fn add(&mut self, key: &str, iso_path: &str, host: &Path, depth: usize) -> Result<(), ManifestErr> {
+        let build_err = |e: BuildErr| ManifestErr::invalid(key, e);
+        let meta = fs::symlink_metadata(host).map_err(|e| ManifestErr::invalid(key, format!("{}: {}", host.display(), e)))?;
+        if meta.file_type().is_symlink() && depth > 0 {
+            // the sources themselves are followed
+            if !self.manifest.names.rock_ridge {
+                return Ok(())
+            }
+            let target = fs::read_link(host).map_err(|e| ManifestErr::invalid(key, format!("{}: {}", host.display(), e)))?;
+            let target = target.to_str()
+                .ok_or_else(|| ManifestErr::invalid(key, format!("{}: not a UTF-8 link target", host.display())))?;
+            return self.builder.add_symlink(iso_path, target).map_err(build_err)
+        }
+
+        let meta = fs::metadata(host).map_err(|e| ManifestErr::invalid(key, format!("{}: {}", host.display(), e)))?;
+        if meta.is_file() {
+            self.builder.add_host_file(iso_path, host).map_err(build_err)?;
+            self.sizes.insert(normalize(iso_path), meta.len());
+            return Ok(())
+        }
+        if !meta.is_dir() {
+            return Err(ManifestErr::invalid(key, format!("{}: not a file nor a directory", host.display())))
+        }
+        if depth >= MAX_DEPTH {
+            return Err(ManifestErr::invalid(key, format!("{}: too deep", host.display())))
+        }
+        if !iso_path.is_empty() {
+            self.builder.add_dir(iso_path).map_err(build_err)?;
+        }
+        let mut entries: Vec<_> = fs::read_dir(host)
+            .and_then(|dir| dir.collect::<io::Result<Vec<_>>>())
+            .map_err(|e| ManifestErr::invalid(key, format!("{}: {}", host.display(), e)))?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_str()
+                .ok_or_else(|| ManifestErr::invalid(key, format!("{}: not a UTF-8 file name", entry.path().display())))?;
+            self.add(key, &join(iso_path, name), &entry.path(), depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn boot_image(&self, i: usize, entry: &BootEntrySection) -> Result<BootImage, ManifestErr> {
+        let key = |field: &str| format!("boot.entries[{}].{}", i, field);
+        let path = normalize(&entry.image);
+        let size = *self.sizes.get(&path)
+            .ok_or_else(|| ManifestErr::invalid(key("image"), format!("{}: not a file of the image", path)))?;
+        let media = match entry.emulation {
+            ManifestEmulation::None => BootMedia::NoEmulation,
+            ManifestEmulation::HardDisk => BootMedia::HardDrive,
+            ManifestEmulation::Floppy => [BootMedia::Floppy1_2, BootMedia::Floppy1_44, BootMedia::Floppy2_88].into_iter()
+                .find(|m| m.floppy_size() == Some(size))
+                .ok_or_else(|| ManifestErr::invalid(key("emulation"), format!("{}: {} bytes is not the size of a floppy", path, size)))?,
+        };
+        let mut image = BootImage::emulation(&path, media).with_platform(entry.platform.into());
+        if let Some(segment) = entry.load_segment {
+            image = image.with_load_segment(segment);
+        }
+        if let Some(count) = entry.load_size {
+            image = image.with_sector_count(count);
+        }
+        if entry.boot_info_table {
+            image = image.with_boot_info_table();
+        }
+        if entry.grub2_boot_info {
+            image = image.with_grub2_boot_info();
+        }
+        Ok(image)
+    }
+}
+
+fn read_host(key: &str, path: &Path) -> Result<Vec<u8>, ManifestErr> {
+    fs::read(path).map_err(|e| ManifestErr::invalid(key, format!("{}: {}", path.display(), e)))
+}
+
+impl IsoBuilder {
+    /// A builder for the image described by `manifest`
+    ///
+    /// The manifest is validated and the files of the host are looked up, so
+    /// that only reading them can fail when the image is written.
+    pub fn from_manifest(manifest: &Manifest) -> Result<Self, ManifestErr> {
+        manifest.validate()?;
+        let volume = &manifest.volume;
+        let mut builder = IsoBuilder::new()
+            .with_sys_ident(&volume.system_id)
+            .with_vol_ident(&volume.volume_id)
+            .with_vol_set_ident(&volume.volume_set_id)
+            .with_publisher_ident(&volume.publisher)
+            .with_data_prep_ident(&volume.preparer)
+            .with_app_ident(&volume.application)
+            .with_copyright_file(&volume.copyright_file)
+            .with_abstract_file(&volume.abstract_file)
+            .with_bibliographic_file(&volume.bibliographic_file);
+        if let Some(size) = volume.block_size {
+            builder = builder.with_logical_block_size(size);
+        }
+        if let Some(secs) = volume.creation_time {
+            builder = builder.with_creation_time(secs);
+        }
+        if manifest.names.rock_ridge {
+            builder = builder.with_rock_ridge();
+        }
+        if manifest.names.joliet {
+            builder = builder.with_joliet();
+        }
+
+        let mut mapper = Mapper {
+            manifest,
+            builder,
+            sizes: HashMap::new(),
+        };
+        for (i, file) in manifest.files.iter().enumerate() {
+            let Some(source) = &file.source else {
+                continue
+            };
+            let key = format!("files[{}].source", i);
+            let host = manifest.source(source);
+            let path = normalize(&file.path);
+            let is_dir = host.is_dir();
+            // a file mapped to a directory goes into it
+            let path = if !is_dir && (path.is_empty() || file.path.ends_with('/')) {
+                let name = host.file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or_else(|| ManifestErr::invalid(&key, format!("{}: not a UTF-8 file name", host.display())))?;
+                join(&path, name)
+            } else {
+                path
+            };
+            if file.hidden && is_dir {
+                return Err(ManifestErr::invalid(format!("files[{}].hidden", i), "directories cannot be hidden"))
+            }
+            if file.hidden {
+                let data = read_host(&key, &host)?;
+                mapper.sizes.insert(path.clone(), data.len() as u64);
+                mapper.builder.add_hidden_file(&path, data).map_err(|e| ManifestErr::invalid(&key, e))?;
+            } else {
+                mapper.add(&key, &path, &host, 0)?;
+            }
+        }
+
+        // the attributes once the whole tree is there, so that they can
+        // override those of files mapped with their directory
+        for (i, file) in manifest.files.iter().enumerate() {
+            if file.mode.is_none() && file.uid.is_none() && file.gid.is_none() {
+                continue
+            }
+            let path = normalize(&file.path);
+            let is_dir = path.is_empty() || mapper.builder.is_dir(&path);
+            let mode = file.mode.unwrap_or(if is_dir { 0o555 } else { 0o444 });
+            let attrs = FileAttrs::new(mode).with_owner(file.uid.unwrap_or(0), file.gid.unwrap_or(0));
+            mapper.builder.set_attrs(&path, attrs)
+                .map_err(|e| ManifestErr::invalid(format!("files[{}].path", i), e))?;
+        }
+
+        let root_files = [
+            ("volume.copyright_file", &volume.copyright_file),
+            ("volume.abstract_file", &volume.abstract_file),
+            ("volume.bibliographic_file", &volume.bibliographic_file),
+        ];
+        for (key, name) in root_files {
+            if !name.is_empty() && !mapper.sizes.contains_key(name.as_str()) {
+                return Err(ManifestErr::invalid(key, format!("{}: not a file of the root", name)))
+            }
+        }
+
+        if let Some(catalog) = &manifest.boot.catalog {
+            mapper.builder.add_boot_catalog(catalog).map_err(|e| ManifestErr::invalid("boot.catalog", e))?;
+        }
+        for (i, entry) in manifest.boot.entries.iter().enumerate() {
+            let image = mapper.boot_image(i, entry)?;
+            mapper.builder.add_boot_image(image);
+        }
+
+        let mut builder = mapper.builder;
+        let hybrid = &manifest.hybrid;
+        if let Some(path) = &hybrid.mbr {
+            let boot_code = read_host("hybrid.mbr", &manifest.source(path))?;
+            if boot_code.len() > HybridMbr::MAX_BOOT_CODE_SIZE {
+                return Err(ManifestErr::invalid("hybrid.mbr", BuildErr::BootCodeTooLarge(boot_code.len())))
+            }
+            let mut mbr = HybridMbr::new(boot_code);
+            if let Some(ty) = hybrid.partition_type {
+                mbr = mbr.with_partition_type(ty);
+            }
+            builder = builder.with_hybrid_mbr(mbr);
+        }
+        if hybrid.gpt {
+            let esp = match &hybrid.esp {
+                Some(path) => EfiSystemPartition::Appended(read_host("hybrid.esp", &manifest.source(path))?),
+                None => EfiSystemPartition::BootImage,
+            };
+            builder = builder.with_gpt(Gpt::new(esp));
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join(format!("iso9660-manifest-{}", std::process::id()));
+        fs::create_dir_all(dir.join("rootfs/bin")).unwrap();
+        fs::write(dir.join("rootfs/bin/init"), b"#!/bin/sh\n").unwrap();
+        fs::write(dir.join("rootfs/COPYING"), b"GPL").unwrap();
+        fs::write(dir.join("boot.bin"), vec![0xFA; 4096]).unwrap();
+
+        let text = r#"
+            [volume]
+            volume_id = "LIVE"
+            publisher = "ACME"
+            copyright_file = "COPYING"
+            creation_time = 1700000000
+
+            [names]
+            rock_ridge = true
+
+            [[files]]
+            path = "/"
+            source = "rootfs"
+
+            [[files]]
+            path = "isolinux/"
+            source = "boot.bin"
+
+            [[files]]
+            path = "bin/init"
+            mode = 0o755
+            uid = 1000
+
+            [boot]
+            catalog = "isolinux/boot.cat"
+
+            [[boot.entries]]
+            image = "isolinux/boot.bin"
+            load_size = 4
+            boot_info_table = true
+        "#;
+        let manifest = Manifest::parse(text, &dir).unwrap();
+        let image = IsoBuilder::from_manifest(&manifest).unwrap().build().unwrap();
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        assert_eq!(fs.pvd().copyright_file_name.as_ref().unwrap().as_str(), "COPYING");
+        let record = fs.lookup("bin/init").unwrap();
+        assert_eq!(fs.read_file(&record).unwrap(), b"#!/bin/sh\n");
+        let entries = fs.boot_entries().unwrap();
+        assert_eq!(entries[0].sector_count, 4);
+
+        let invalid = [
+            ("[volume]\nvolume_id = \"live#disc\"", "volume.volume_id"),
+            ("[[files]]\npath = \"a\"\nmode = 0o755", "files[0].mode"),
+            ("[[files]]\npath = \"a\"\ngid = 100", "files[0].gid"),
+            ("[volume]\ncopyright_file = \"COPYING\"", "volume.copyright_file"),
+            ("[volume]\napplication = \"_README\"", "volume.application"),
+            ("[[boot.entries]]\nimage = \"missing.bin\"", "boot.entries[0].image"),
+            ("[hybrid]\ngpt = true", "hybrid.gpt"),
+        ];
+        for (text, expected) in invalid {
+            let err = Manifest::parse(text, &dir)
+                .and_then(|m| IsoBuilder::from_manifest(&m))
+                .unwrap_err();
+            assert!(matches!(&err, ManifestErr::Invalid { key, .. } if key == expected), "{}", err);
+        }
+        assert!(matches!(Manifest::parse("[volume]\nlabel = \"x\"", &dir), Err(ManifestErr::Parse(_))));
+        // what the builder and `mkiso -V` take
+        let manifest = Manifest::parse("[volume]\nvolume_id = \"Live disc\"", &dir).unwrap();
+        assert!(manifest.validate().is_ok());
+        assert!(IsoBuilder::from_manifest(&manifest).unwrap().build().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
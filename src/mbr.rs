@@ -0,0 +1,194 @@
+use crate::*;
+
+/// Size of the sectors a partition table counts in
+pub const MBR_SECTOR_SIZE: usize = 512;
+
+pub const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Offset of the four partition entries in the MBR
+pub const MBR_PARTITION_TABLE: usize = 446;
+
+/// An entry of the partition table of an MBR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MbrPartition {
+    /// 0x80 for the active partition
+    pub status: u8,
+    pub start_chs: [u8; 3],
+    pub ty: u8,
+    pub end_chs: [u8; 3],
+    /// In 512 bytes sectors
+    pub start_lba: u32,
+    pub nb_sectors: u32,
+}
+
+impl MbrPartition {
+    pub const SIZE: usize = 16;
+
+    /// The partition `start_lba..start_lba + nb_sectors` with its CHS
+    /// addresses computed for `geometry`
+    pub fn new(ty: u8, start_lba: u32, nb_sectors: u32, geometry: Geometry) -> Self {
+        Self {
+            status: 0,
+            start_chs: geometry.chs(start_lba),
+            ty,
+            end_chs: geometry.chs((start_lba + nb_sectors).saturating_sub(1)),
+            start_lba,
+            nb_sectors,
+        }
+    }
+
+    pub fn parse(buffer: &[u8]) -> Self {
+        Self {
+            status: buffer[0],
+            start_chs: [buffer[1], buffer[2], buffer[3]],
+            ty: buffer[4],
+            end_chs: [buffer[5], buffer[6], buffer[7]],
+            start_lba: u32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]),
+            nb_sectors: u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]),
+        }
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        out[0] = self.status;
+        out[1..4].copy_from_slice(&self.start_chs);
+        out[4] = self.ty;
+        out[5..8].copy_from_slice(&self.end_chs);
+        out[8..12].copy_from_slice(&self.start_lba.to_le_bytes());
+        out[12..16].copy_from_slice(&self.nb_sectors.to_le_bytes());
+    }
+
+    /// Entries of type 0 are unused
+    pub fn is_empty(&self) -> bool {
+        self.ty == 0
+    }
+}
+
+/// The disk geometry CHS addresses are computed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    /// From 1 to 255
+    pub heads: u8,
+    /// From 1 to 63
+    pub sectors_per_track: u8,
+}
+
+impl Default for Geometry {
+    /// 64 heads and 32 sectors, the 1M cylinders isohybrid uses
+    fn default() -> Self {
+        Self {
+            heads: 64,
+            sectors_per_track: 32,
+        }
+    }
+}
+
+impl Geometry {
+    pub fn is_valid(&self) -> bool {
+        self.heads >= 1 && (1..=63).contains(&self.sectors_per_track)
+    }
+
+    /// Size of a cylinder in bytes
+    pub fn cylinder_size(&self) -> u64 {
+        self.heads as u64 * self.sectors_per_track as u64 * MBR_SECTOR_SIZE as u64
+    }
+
+    /// The packed CHS address of the sector `lba`, the cylinder saturates at
+    /// 1023 as it does on every disk larger than 8G
+    pub fn chs(&self, lba: u32) -> [u8; 3] {
+        let spt = self.sectors_per_track as u32;
+        let heads = self.heads as u32;
+        let mut cylinder = lba / (spt * heads);
+        let mut head = (lba / spt) % heads;
+        let mut sector = lba % spt + 1;
+        if cylinder > 1023 {
+            cylinder = 1023;
+            head = heads - 1;
+            sector = spt;
+        }
+        [
+            head as u8,
+            sector as u8 | ((cylinder >> 2) & 0xC0) as u8,
+            cylinder as u8,
+        ]
+    }
+}
+
+/// An MBR for the system area that makes the image bootable when it is copied
+/// to a USB stick, in the way of syslinux's isohybrid
+///
+/// The boot code, typically `isohdpfx.bin`, loads the El Torito boot image
+/// whose address in 512 bytes sectors is patched at offset 432.
+#[derive(Debug, Clone)]
+pub struct HybridMbr {
+    /// At most `HybridMbr::MAX_BOOT_CODE_SIZE` bytes
+    pub boot_code: Vec<u8>,
+    pub geometry: Geometry,
+    /// Type of the partition that covers the image
+    pub partition_type: u8,
+    pub disk_signature: u32,
+}
+
+impl HybridMbr {
+    pub const MAX_BOOT_CODE_SIZE: usize = 432;
+
+    /// The defaults of isohybrid: 64 heads, 32 sectors and a partition of
+    /// type 0x17
+    pub fn new(boot_code: Vec<u8>) -> Self {
+        Self {
+            boot_code,
+            geometry: Geometry::default(),
+            partition_type: 0x17,
+            disk_signature: 0,
+        }
+    }
+
+    pub fn with_geometry(mut self, geometry: Geometry) -> Self {
+        self.geometry = geometry;
+        self
+    }
+
+    pub fn with_partition_type(mut self, ty: u8) -> Self {
+        self.partition_type = ty;
+        self
+    }
+
+    pub fn with_disk_signature(mut self, signature: u32) -> Self {
+        self.disk_signature = signature;
+        self
+    }
+
+    /// Writes the 512 bytes of the MBR for an image of `image_size` bytes
+    /// whose boot image is at the 2K sector `boot_lba`
+    pub fn dump(&self, boot_lba: u32, image_size: u64, out: &mut [u8]) {
+        out[..MBR_SECTOR_SIZE].fill(0);
+        out[..self.boot_code.len()].copy_from_slice(&self.boot_code);
+        let boot_lba = boot_lba as u64 * (SECTOR_SIZE / MBR_SECTOR_SIZE) as u64;
+        out[432..440].copy_from_slice(&boot_lba.to_le_bytes());
+        out[440..444].copy_from_slice(&self.disk_signature.to_le_bytes());
+
+        let nb_sectors = (image_size / MBR_SECTOR_SIZE as u64).min(u32::MAX as u64) as u32;
+        let partition = MbrPartition {
+            status: 0x80,
+            ..MbrPartition::new(self.partition_type, 0, nb_sectors, self.geometry)
+        };
+        partition.dump(&mut out[MBR_PARTITION_TABLE..]);
+        out[510..512].copy_from_slice(&MBR_SIGNATURE);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chs() {
+        let geometry = Geometry::default();
+        assert_eq!(geometry.cylinder_size(), 1024 * 1024);
+        assert_eq!(geometry.chs(0), [0, 1, 0]);
+        // last sector of the first cylinder
+        assert_eq!(geometry.chs(2047), [63, 32, 0]);
+        // cylinder 256 sets the high bits of the sector byte
+        assert_eq!(geometry.chs(256 * 2048), [0, 1 | 0x40, 0]);
+        assert_eq!(geometry.chs(u32::MAX), [63, 32 | 0xC0, 0xFF]);
+    }
+}
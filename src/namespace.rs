@@ -0,0 +1,158 @@
+use crate::*;
+
+/// Which names of an image are read, the same files can be seen through
+/// several of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Namespace {
+    /// The identifiers of the records of the primary volume descriptor
+    Iso,
+    /// The tree of the Joliet descriptor
+    Joliet,
+    /// The `NM` entries of the records of the primary volume descriptor
+    RockRidge,
+}
+
+/// An entry of a directory, see `IsoFs::entries`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirEntry {
+    pub name: String,
+    /// For a relocated directory, the `.` record of its extent
+    pub record: DirectoryRecord,
+    /// Only read in the `RockRidge` namespace
+    pub rock_ridge: Option<RockRidge>,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.record.is_dir()
+    }
+
+    /// The target of a Rock Ridge symbolic link
+    pub fn symlink(&self) -> Option<&str> {
+        self.rock_ridge.as_ref().and_then(|rr| rr.symlink.as_deref())
+    }
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// The namespace with the richest names: Rock Ridge, then Joliet, then
+    /// the identifiers of the records
+    pub fn default_namespace(&mut self) -> Result<Namespace, VDErr> {
+        if self.has_rock_ridge()? {
+            Ok(Namespace::RockRidge)
+        } else if self.joliet()?.is_some() {
+            Ok(Namespace::Joliet)
+        } else {
+            Ok(Namespace::Iso)
+        }
+    }
+
+    /// The root of `namespace`, `NotFound` if the image has no Joliet tree
+    pub fn root_entry(&mut self, namespace: Namespace) -> Result<DirEntry, VDErr> {
+        let record = match namespace {
+            Namespace::Joliet => self.joliet()?.ok_or(VDErr::NotFound)?.root,
+            _ => self.root().clone(),
+        };
+        let rock_ridge = match namespace {
+            Namespace::RockRidge => {
+                let dot = self.read_dir(&record)?.swap_remove(0);
+                self.rock_ridge(&dot)?
+            },
+            _ => None,
+        };
+        Ok(DirEntry {
+            name: "/".to_owned(),
+            record,
+            rock_ridge,
+        })
+    }
+
+    /// The entries of `dir` named as in `namespace`, without `.`, `..` and
+    /// the records Rock Ridge marks as relocated
+    ///
+    /// Directories that Rock Ridge relocated to keep the tree 8 levels deep
+    /// are listed where they belong.
+    pub fn entries(&mut self, dir: &DirectoryRecord, namespace: Namespace) -> Result<Vec<DirEntry>, VDErr> {
+        let mut entries = Vec::new();
+        for mut record in self.read_dir(dir)? {
+            if record.is_special() {
+                continue
+            }
+            let rock_ridge = match namespace {
+                Namespace::RockRidge => self.rock_ridge(&record)?,
+                _ => None,
+            };
+            let name = match (namespace, rock_ridge.as_ref().and_then(|rr| rr.name.as_ref())) {
+                (_, Some(name)) => name.clone(),
+                (Namespace::Joliet, None) => joliet_name(&record.file_ident).into_owned(),
+                (_, None) => record.name().into_owned(),
+            };
+            if let Some(rr) = &rock_ridge {
+                if rr.relocated {
+                    continue
+                }
+                if let Some(lba) = rr.child_link {
+                    let mut dot = [0_u8; 255];
+                    self.read_at(lba as u64 * self.logical_block_size(), &mut dot)?;
+                    record = DirectoryRecord::try_parse(&dot)?;
+                }
+            }
+            entries.push(DirEntry {
+                name,
+                record,
+                rock_ridge,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Finds `path` in `namespace`, the identifiers of records are compared
+    /// regardless of case and the other names exactly
+    pub fn lookup_entry(&mut self, path: &str, namespace: Namespace) -> Result<DirEntry, VDErr> {
+        let mut current = self.root_entry(namespace)?;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !current.is_dir() {
+                return Err(VDErr::NotADirectory)
+            }
+            current = self.entries(&current.record, namespace)?
+                .into_iter()
+                .find(|e| match namespace {
+                    Namespace::Iso => e.name.eq_ignore_ascii_case(component),
+                    _ => e.name == component,
+                })
+                .ok_or(VDErr::NotFound)?;
+        }
+        Ok(current)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_namespaces() {
+        let mut builder = IsoBuilder::new().with_rock_ridge().with_joliet();
+        builder.add_file("Docs/Read Me.txt", b"hello".to_vec()).unwrap();
+        builder.add_symlink("readme", "Docs/Read Me.txt").unwrap();
+        let image = builder.build().unwrap();
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        assert_eq!(fs.default_namespace().unwrap(), Namespace::RockRidge);
+        let names = |fs: &mut IsoFs<_>, namespace| -> Vec<String> {
+            let root = fs.root_entry(namespace).unwrap();
+            fs.entries(&root.record, namespace).unwrap().into_iter().map(|e| e.name).collect()
+        };
+        assert_eq!(names(&mut fs, Namespace::RockRidge), ["Docs", "readme"]);
+        assert_eq!(names(&mut fs, Namespace::Joliet), ["Docs"]);
+        assert_eq!(names(&mut fs, Namespace::Iso), ["DOCS", "README"]);
+
+        let entry = fs.lookup_entry("Docs/Read Me.txt", Namespace::Joliet).unwrap();
+        assert_eq!(fs.read_file(&entry.record).unwrap(), b"hello");
+        assert!(matches!(fs.lookup_entry("docs/read me.txt", Namespace::RockRidge), Err(VDErr::NotFound)));
+        assert!(fs.lookup_entry("docs/read_me.txt", Namespace::Iso).is_ok());
+        let link = fs.lookup_entry("readme", Namespace::RockRidge).unwrap();
+        assert_eq!(link.symlink(), Some("Docs/Read Me.txt"));
+    }
+}
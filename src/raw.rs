@@ -0,0 +1,272 @@
+use std::io;
+
+use crate::*;
+
+/// Size of a sector as stored by a raw rip: sync, header, subheader, user
+/// data and error correction
+pub const RAW_SECTOR_SIZE: usize = 2352;
+
+/// Size of a mode 2 sector without its sync pattern and header, the
+/// `MODE2/2336` cue sheet format
+pub const MODE2_SECTOR_SIZE: usize = 2336;
+
+/// User data of a mode 2 form 2 sector, it has no ECC
+pub const FORM2_DATA_SIZE: usize = 2324;
+
+pub const SYNC_PATTERN: [u8; 12] = [
+    0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00,
+];
+
+/// Bit of the subheader submode byte set on form 2 sectors
+pub const SUBMODE_FORM2: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorMode {
+    /// Every user data byte is 0
+    Mode0,
+    /// 2048 bytes of user data protected by EDC and ECC
+    Mode1,
+    /// 2048 bytes of user data protected by EDC and ECC, after an 8 bytes
+    /// subheader
+    Mode2Form1,
+    /// 2324 bytes of user data protected by an optional EDC
+    Mode2Form2,
+}
+
+impl SectorMode {
+    /// Offset of the user data in a 2352 bytes sector
+    pub fn data_offset(self) -> usize {
+        match self {
+            Self::Mode0 | Self::Mode1 => 16,
+            Self::Mode2Form1 | Self::Mode2Form2 => 24,
+        }
+    }
+
+    pub fn data_size(self) -> usize {
+        match self {
+            Self::Mode2Form2 => FORM2_DATA_SIZE,
+            _ => SECTOR_SIZE,
+        }
+    }
+}
+
+/// How the sectors of a track are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorFormat {
+    /// 2048 bytes of user data, what an `.iso` holds
+    Cooked,
+    /// Mode 2 sectors without their sync pattern and header
+    Mode2,
+    /// Complete 2352 bytes sectors
+    Raw,
+}
+
+impl SectorFormat {
+    pub fn from_sector_size(size: usize) -> Option<Self> {
+        match size {
+            SECTOR_SIZE => Some(Self::Cooked),
+            MODE2_SECTOR_SIZE => Some(Self::Mode2),
+            RAW_SECTOR_SIZE => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn sector_size(self) -> usize {
+        match self {
+            Self::Cooked => SECTOR_SIZE,
+            Self::Mode2 => MODE2_SECTOR_SIZE,
+            Self::Raw => RAW_SECTOR_SIZE,
+        }
+    }
+
+    /// Mode of `sector` and where its user data starts in it
+    pub fn sector_mode(self, sector: &[u8]) -> io::Result<(SectorMode, usize)> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        match self {
+            Self::Cooked => Ok((SectorMode::Mode1, 0)),
+            Self::Mode2 => {
+                let mode = if sector[2] & SUBMODE_FORM2 != 0 {
+                    SectorMode::Mode2Form2
+                } else {
+                    SectorMode::Mode2Form1
+                };
+                Ok((mode, 8))
+            },
+            Self::Raw => {
+                if sector[..12] != SYNC_PATTERN {
+                    return Err(invalid("raw sector without a sync pattern"))
+                }
+                let mode = match sector[15] {
+                    0 => SectorMode::Mode0,
+                    1 => SectorMode::Mode1,
+                    2 if sector[18] & SUBMODE_FORM2 != 0 => SectorMode::Mode2Form2,
+                    2 => SectorMode::Mode2Form1,
+                    _ => return Err(invalid("unknown raw sector mode")),
+                };
+                Ok((mode, mode.data_offset()))
+            },
+        }
+    }
+}
+
+/// Exposes the user data of a raw CD image as 2K sectors
+///
+/// `inner` has the sector size of the image as its block size, for instance
+/// `IoDevice::with_block_size(file, RAW_SECTOR_SIZE)`. Reading a form 2
+/// sector through `BlockDevice` only gives its first 2048 bytes, use
+/// `read_user_data` to get the whole 2324 bytes.
+pub struct RawSectorDevice<D> {
+    inner: D,
+    format: SectorFormat,
+    first_lba: u64,
+    sector: Vec<u8>,
+}
+
+impl<D: BlockDevice> RawSectorDevice<D> {
+    pub fn new(inner: D) -> io::Result<Self> {
+        let format = SectorFormat::from_sector_size(inner.block_size())
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not the sector size of a CD image",
+            ))?;
+        Ok(Self {
+            sector: vec![0_u8; format.sector_size()],
+            inner,
+            format,
+            first_lba: 0,
+        })
+    }
+
+    /// Address on the disc of the first sector of `inner`, when the image
+    /// does not start with the first track
+    pub fn with_first_lba(mut self, lba: u64) -> Self {
+        self.first_lba = lba;
+        self
+    }
+
+    pub fn format(&self) -> SectorFormat {
+        self.format
+    }
+
+    pub fn first_lba(&self) -> u64 {
+        self.first_lba
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Reads sector `lba` as stored in the image
+    pub fn read_raw_sector(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        let index = lba.checked_sub(self.first_lba).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "sector before the start of the image",
+        ))?;
+        self.inner.read_blocks(index, buf)
+    }
+
+    /// Copies the user data of sector `lba` in `buf`, 2048 bytes or 2324 for
+    /// form 2 sectors, `buf` must be large enough for either
+    pub fn read_user_data(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<(SectorMode, usize)> {
+        let mut sector = core::mem::take(&mut self.sector);
+        let res = self.read_raw_sector(lba, &mut sector)
+            .and_then(|()| self.format.sector_mode(&sector))
+            .map(|(mode, start)| {
+                let len = mode.data_size().min(sector.len() - start);
+                match mode {
+                    SectorMode::Mode0 => buf[..len].fill(0),
+                    _ => buf[..len].copy_from_slice(&sector[start..start + len]),
+                }
+                (mode, len)
+            });
+        self.sector = sector;
+        res
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for RawSectorDevice<D> {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        if !buf.len().is_multiple_of(SECTOR_SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer length is not a multiple of the block size",
+            ))
+        }
+
+        if self.format == SectorFormat::Cooked {
+            let index = lba.checked_sub(self.first_lba).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sector before the start of the image",
+            ))?;
+            return self.inner.read_blocks(index, buf)
+        }
+
+        let mut user_data = [0_u8; FORM2_DATA_SIZE];
+        for (i, out) in buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.read_user_data(lba + i as u64, &mut user_data)?;
+            out.copy_from_slice(&user_data[..SECTOR_SIZE]);
+        }
+        Ok(())
+    }
+
+    fn read_form2_sector(&mut self, lba: u64, buf: &mut [u8; FORM2_DATA_SIZE]) -> io::Result<()> {
+        let mut sector = core::mem::take(&mut self.sector);
+        let res = self.read_raw_sector(lba, &mut sector)
+            .and_then(|()| match self.format.sector_mode(&sector)? {
+                (SectorMode::Mode2Form1 | SectorMode::Mode2Form2, start) => {
+                    buf.copy_from_slice(&sector[start..start + FORM2_DATA_SIZE]);
+                    Ok(())
+                },
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "not a mode 2 sector")),
+            });
+        self.sector = sector;
+        res
+    }
+}
+
+/// Writes the sync pattern and header of a raw sector at `lba`
+///
+/// Addresses on a disc are offset by the 2 seconds of the first pregap.
+pub fn raw_sector_header(lba: u64, mode: u8, out: &mut [u8]) {
+    let bcd = |v: u64| (((v / 10) << 4) | (v % 10)) as u8;
+    let frames = lba + 150;
+    out[..12].copy_from_slice(&SYNC_PATTERN);
+    out[12] = bcd(frames / 75 / 60);
+    out[13] = bcd(frames / 75 % 60);
+    out[14] = bcd(frames % 75);
+    out[15] = mode;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_raw_mode2_image() {
+        let mut builder = IsoBuilder::new();
+        builder.add_file("SYSTEM.CNF", b"BOOT = cdrom:\\SLUS_000.01;1\n".to_vec()).unwrap();
+        let cooked = builder.build().unwrap();
+
+        // wrap every sector as mode 2 form 1, EDC and ECC are left empty
+        let mut raw = Vec::new();
+        for (lba, data) in cooked.chunks_exact(SECTOR_SIZE).enumerate() {
+            let mut sector = [0_u8; RAW_SECTOR_SIZE];
+            raw_sector_header(lba as u64, 2, &mut sector);
+            sector[24..24 + SECTOR_SIZE].copy_from_slice(data);
+            raw.extend_from_slice(&sector);
+        }
+
+        let dev = RawSectorDevice::new(MemDevice::with_block_size(&raw, RAW_SECTOR_SIZE)).unwrap();
+        let mut fs = IsoFs::open(dev).unwrap();
+        let record = fs.lookup("system.cnf").unwrap();
+        assert_eq!(&fs.read_file(&record).unwrap()[..4], b"BOOT");
+    }
+}
@@ -0,0 +1,511 @@
+use crate::*;
+
+/// Size of the header every SUSP entry starts with
+pub const SUSP_HEADER_SIZE: usize = 4;
+
+/// Size of the `CE` entry that points at a continuation area
+pub const CE_ENTRY_SIZE: usize = 28;
+
+/// Identifier of the extension declared by the `ER` entry of the root
+pub const RRIP_IDENT: &str = "RRIP_1991A";
+const RRIP_DESCRIPTOR: &str = "THE ROCK RIDGE INTERCHANGE PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS";
+const RRIP_SOURCE: &str = "PLEASE CONTACT DISC PUBLISHER FOR SPECIFICATION SOURCE.  \
+    SEE PUBLISHER IDENTIFIER IN PRIMARY VOLUME DESCRIPTOR FOR CONTACT INFORMATION.";
+
+/// Continuation areas followed before a chain is considered a loop
+const MAX_CONTINUATIONS: usize = 64;
+
+/// Longest payload of an entry, its length is a single byte
+const MAX_ENTRY_DATA: usize = 255 - SUSP_HEADER_SIZE;
+
+/// An entry of a system use area, as defined by the System Use Sharing
+/// Protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspEntry<'a> {
+    pub signature: [u8; 2],
+    pub version: u8,
+    /// What follows the header
+    pub data: &'a [u8],
+}
+
+impl SuspEntry<'_> {
+    /// Appends the entry to `out`, `data` is at most 251 bytes
+    pub fn dump(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.signature);
+        out.push((SUSP_HEADER_SIZE + self.data.len()) as u8);
+        out.push(self.version);
+        out.extend_from_slice(self.data);
+    }
+}
+
+/// The entries of `system_use`, up to its `ST` entry or the first one that
+/// is malformed
+pub fn susp_entries(system_use: &[u8]) -> SuspEntries<'_> {
+    SuspEntries {
+        buffer: system_use,
+    }
+}
+
+/// See `susp_entries`
+pub struct SuspEntries<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Iterator for SuspEntries<'a> {
+    type Item = SuspEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.buffer.get(..SUSP_HEADER_SIZE)?;
+        let len = header[2] as usize;
+        if len < SUSP_HEADER_SIZE || len > self.buffer.len() || header[..2] == *b"ST" {
+            self.buffer = &[];
+            return None
+        }
+        let entry = SuspEntry {
+            signature: [header[0], header[1]],
+            version: header[3],
+            data: &self.buffer[SUSP_HEADER_SIZE..len],
+        };
+        self.buffer = &self.buffer[len..];
+        Some(entry)
+    }
+}
+
+/// Where a `CE` entry continues a system use area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContinuationArea {
+    /// In logical blocks
+    pub block: u32,
+    /// In bytes from the start of `block`
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl ContinuationArea {
+    /// `data` is the payload of the `CE` entry
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let data = data.get(..24)?;
+        Some(Self {
+            block: double_endian::u32(&data[0..8]),
+            offset: double_endian::u32(&data[8..16]),
+            len: double_endian::u32(&data[16..24]),
+        })
+    }
+
+    /// Appends the `CE` entry to `out`
+    pub fn dump(&self, out: &mut Vec<u8>) {
+        let mut data = [0_u8; 24];
+        double_endian::put_u32(&mut data[0..8], self.block);
+        double_endian::put_u32(&mut data[8..16], self.offset);
+        double_endian::put_u32(&mut data[16..24], self.len);
+        SuspEntry {
+            signature: *b"CE",
+            version: 1,
+            data: &data,
+        }.dump(out);
+    }
+}
+
+/// Appends the `SP` entry that starts the system use area of the `.` record
+/// of the root, and says that SUSP is in use
+pub fn dump_sp_entry(out: &mut Vec<u8>) {
+    SuspEntry {
+        signature: *b"SP",
+        version: 1,
+        data: &[0xBE, 0xEF, 0],
+    }.dump(out);
+}
+
+/// Appends the `ER` entry that says the image uses Rock Ridge
+pub fn dump_er_entry(out: &mut Vec<u8>) {
+    let mut data = vec![
+        RRIP_IDENT.len() as u8,
+        RRIP_DESCRIPTOR.len() as u8,
+        RRIP_SOURCE.len() as u8,
+        1,
+    ];
+    data.extend_from_slice(RRIP_IDENT.as_bytes());
+    data.extend_from_slice(RRIP_DESCRIPTOR.as_bytes());
+    data.extend_from_slice(RRIP_SOURCE.as_bytes());
+    SuspEntry {
+        signature: *b"ER",
+        version: 1,
+        data: &data,
+    }.dump(out);
+}
+
+/// The `PX` entry, what `stat` says of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PosixAttrs {
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Only recorded by RRIP 1.12
+    pub ino: Option<u32>,
+}
+
+impl PosixAttrs {
+    pub const S_IFMT: u32 = 0o170000;
+    pub const S_IFDIR: u32 = 0o040000;
+    pub const S_IFREG: u32 = 0o100000;
+    pub const S_IFLNK: u32 = 0o120000;
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & Self::S_IFMT == Self::S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & Self::S_IFMT == Self::S_IFLNK
+    }
+}
+
+/// The `TF` entry, in seconds since the unix epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RrTimes {
+    pub creation: Option<i64>,
+    pub modify: Option<i64>,
+    pub access: Option<i64>,
+    /// Last change of the attributes, `ctime`
+    pub attributes: Option<i64>,
+}
+
+/// What the Rock Ridge entries of a record say of its file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RockRidge {
+    pub attrs: Option<PosixAttrs>,
+    /// `NM`, the name of the file in place of the identifier of its record
+    pub name: Option<String>,
+    /// `SL`, the target of a symbolic link
+    pub symlink: Option<String>,
+    pub times: RrTimes,
+    /// `PN`, the major and minor numbers of a device
+    pub device: Option<(u32, u32)>,
+    /// `CL`, the extent of a directory that was relocated from this record
+    pub child_link: Option<u32>,
+    /// `PL`, the extent of the original parent of a relocated directory
+    pub parent_link: Option<u32>,
+    /// `RE`, the record of a relocated directory, which readers skip
+    pub relocated: bool,
+}
+
+impl RockRidge {
+    /// Gathers the Rock Ridge entries among `entries`, others are ignored
+    pub fn parse<'a>(entries: impl IntoIterator<Item = SuspEntry<'a>>) -> Self {
+        let mut rr = Self::default();
+        let mut name = Vec::new();
+        let mut symlink = String::new();
+        // whether the next component of a link is preceded by a `/`
+        let mut separated = false;
+
+        for entry in entries {
+            let data = entry.data;
+            match &entry.signature {
+                b"PX" if data.len() >= 32 => {
+                    rr.attrs = Some(PosixAttrs {
+                        mode: double_endian::u32(&data[0..8]),
+                        nlink: double_endian::u32(&data[8..16]),
+                        uid: double_endian::u32(&data[16..24]),
+                        gid: double_endian::u32(&data[24..32]),
+                        ino: data.get(32..40).map(double_endian::u32),
+                    });
+                },
+                b"NM" if !data.is_empty() => {
+                    // the current and parent flags leave the name empty
+                    name.extend_from_slice(&data[1..]);
+                },
+                b"SL" if !data.is_empty() => {
+                    let mut components = &data[1..];
+                    while let [flags, len, rest @ ..] = components {
+                        let Some(content) = rest.get(..*len as usize) else { break };
+                        let text = match flags & 0x0E {
+                            0x02 => ".".into(),
+                            0x04 => "..".into(),
+                            0x08 => {
+                                symlink.push('/');
+                                separated = false;
+                                components = &rest[*len as usize..];
+                                continue
+                            },
+                            _ => String::from_utf8_lossy(content),
+                        };
+                        if separated {
+                            symlink.push('/');
+                        }
+                        symlink.push_str(&text);
+                        separated = flags & 1 == 0;
+                        components = &rest[*len as usize..];
+                    }
+                },
+                b"TF" if !data.is_empty() => {
+                    let flags = data[0];
+                    let long_form = flags & 0x80 != 0;
+                    let stamp_len = if long_form { 17 } else { 7 };
+                    let mut stamps = &data[1..];
+                    let mut next = |bit: u8| -> Option<i64> {
+                        if flags & (1 << bit) == 0 {
+                            return None
+                        }
+                        let stamp = stamps.get(..stamp_len)?;
+                        stamps = &stamps[stamp_len..];
+                        if long_form {
+                            DecDateTime::try_parse(stamp).ok().flatten()?.to_unix_time()
+                        } else {
+                            Some(DirectoryRecordDate::parse(stamp).to_unix_time())
+                        }
+                    };
+                    rr.times = RrTimes {
+                        creation: next(0),
+                        modify: next(1),
+                        access: next(2),
+                        attributes: next(3),
+                    };
+                },
+                b"PN" if data.len() >= 16 => {
+                    rr.device = Some((double_endian::u32(&data[0..8]), double_endian::u32(&data[8..16])));
+                },
+                b"CL" if data.len() >= 8 => rr.child_link = Some(double_endian::u32(&data[0..8])),
+                b"PL" if data.len() >= 8 => rr.parent_link = Some(double_endian::u32(&data[0..8])),
+                b"RE" => rr.relocated = true,
+                _ => (),
+            }
+        }
+
+        if !name.is_empty() {
+            rr.name = Some(String::from_utf8_lossy(&name).into_owned());
+        }
+        if !symlink.is_empty() {
+            rr.symlink = Some(symlink);
+        }
+        rr
+    }
+
+    /// Appends the entries that record `self` to `out`, long names and links
+    /// are split over several entries
+    pub fn dump(&self, out: &mut Vec<u8>) {
+        let mut entry = |signature: &[u8; 2], data: &[u8]| SuspEntry {
+            signature: *signature,
+            version: 1,
+            data,
+        }.dump(out);
+
+        if let Some(attrs) = &self.attrs {
+            let mut data = vec![0_u8; if attrs.ino.is_some() { 40 } else { 32 }];
+            double_endian::put_u32(&mut data[0..8], attrs.mode);
+            double_endian::put_u32(&mut data[8..16], attrs.nlink);
+            double_endian::put_u32(&mut data[16..24], attrs.uid);
+            double_endian::put_u32(&mut data[24..32], attrs.gid);
+            if let Some(ino) = attrs.ino {
+                double_endian::put_u32(&mut data[32..40], ino);
+            }
+            entry(b"PX", &data);
+        }
+
+        if let Some((major, minor)) = self.device {
+            let mut data = [0_u8; 16];
+            double_endian::put_u32(&mut data[0..8], major);
+            double_endian::put_u32(&mut data[8..16], minor);
+            entry(b"PN", &data);
+        }
+
+        if let Some(target) = &self.symlink {
+            for data in symlink_entries(target) {
+                entry(b"SL", &data);
+            }
+        }
+
+        if let Some(name) = &self.name {
+            let chunks: Vec<&[u8]> = name.as_bytes().chunks(MAX_ENTRY_DATA - 1).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut data = vec![(i + 1 < chunks.len()) as u8];
+                data.extend_from_slice(chunk);
+                entry(b"NM", &data);
+            }
+        }
+
+        for (signature, link) in [(b"CL", self.child_link), (b"PL", self.parent_link)] {
+            if let Some(link) = link {
+                let mut data = [0_u8; 8];
+                double_endian::put_u32(&mut data, link);
+                entry(signature, &data);
+            }
+        }
+        if self.relocated {
+            entry(b"RE", &[]);
+        }
+
+        let times = [self.times.creation, self.times.modify, self.times.access, self.times.attributes];
+        if times.iter().any(Option::is_some) {
+            let mut data = vec![0_u8];
+            for (bit, time) in times.iter().enumerate() {
+                if let Some(time) = time {
+                    data[0] |= 1 << bit;
+                    let mut stamp = [0_u8; 7];
+                    DirectoryRecordDate::from_unix_time(*time).dump(&mut stamp);
+                    data.extend_from_slice(&stamp);
+                }
+            }
+            entry(b"TF", &data);
+        }
+    }
+}
+
+/// The payloads of the `SL` entries of a link to `target`
+fn symlink_entries(target: &str) -> Vec<Vec<u8>> {
+    // component records: flags, length and content
+    let mut components: Vec<Vec<u8>> = Vec::new();
+    if target.starts_with('/') {
+        components.push(vec![0x08, 0]);
+    }
+    for part in target.split('/').filter(|p| !p.is_empty()) {
+        match part {
+            "." => components.push(vec![0x02, 0]),
+            ".." => components.push(vec![0x04, 0]),
+            _ => {
+                let chunks: Vec<&[u8]> = part.as_bytes().chunks(MAX_ENTRY_DATA - 3).collect();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let mut component = vec![(i + 1 < chunks.len()) as u8, chunk.len() as u8];
+                    component.extend_from_slice(chunk);
+                    components.push(component);
+                }
+            },
+        }
+    }
+
+    let mut entries: Vec<Vec<u8>> = vec![vec![0]];
+    for component in components {
+        if entries.last().unwrap().len() + component.len() > MAX_ENTRY_DATA {
+            // the link goes on in the next entry
+            entries.last_mut().unwrap()[0] = 1;
+            entries.push(vec![0]);
+        }
+        entries.last_mut().unwrap().extend_from_slice(&component);
+    }
+    entries
+}
+
+impl<D: BlockDevice> IsoFs<D> {
+    /// The number of bytes to skip at the start of the system use areas, as
+    /// set by the `SP` entry of the root, `None` if the image does not use
+    /// SUSP
+    pub fn susp_skip(&mut self) -> Result<Option<u8>, VDErr> {
+        if let Some(skip) = self.susp_skip {
+            return Ok(skip)
+        }
+        let root = self.root().clone();
+        let dot = self.read_dir(&root)?.swap_remove(0);
+        let skip = susp_entries(&dot.system_use)
+            .next()
+            .filter(|e| e.signature == *b"SP" && e.data.starts_with(&[0xBE, 0xEF]))
+            .and_then(|e| e.data.get(2).copied());
+        self.susp_skip = Some(skip);
+        Ok(skip)
+    }
+
+    /// The SUSP entries of `record`, continuation areas followed, encoded one
+    /// after the other, without the `CE`, `PD` and `ST` entries
+    pub fn system_use_entries(&mut self, record: &DirectoryRecord) -> Result<Vec<u8>, VDErr> {
+        let Some(skip) = self.susp_skip()? else {
+            return Ok(Vec::new())
+        };
+        let mut area = record.system_use.get(skip as usize..).unwrap_or_default().to_vec();
+        let mut entries = Vec::new();
+        for _ in 0..MAX_CONTINUATIONS {
+            let mut continuation = None;
+            for entry in susp_entries(&area) {
+                match &entry.signature {
+                    b"CE" => continuation = ContinuationArea::parse(entry.data),
+                    b"PD" => (),
+                    _ => entry.dump(&mut entries),
+                }
+            }
+            let Some(ce) = continuation else {
+                return Ok(entries)
+            };
+            if ce.len as u64 > self.logical_block_size() {
+                return Err(VDErr::Truncated)
+            }
+            area = vec![0_u8; ce.len as usize];
+            self.read_at(ce.block as u64 * self.logical_block_size() + ce.offset as u64, &mut area)?;
+        }
+        Ok(entries)
+    }
+
+    /// The Rock Ridge entries of `record`, `None` if the image does not use
+    /// SUSP
+    pub fn rock_ridge(&mut self, record: &DirectoryRecord) -> Result<Option<RockRidge>, VDErr> {
+        if self.susp_skip()?.is_none() {
+            return Ok(None)
+        }
+        let entries = self.system_use_entries(record)?;
+        Ok(Some(RockRidge::parse(susp_entries(&entries))))
+    }
+
+    /// `true` if the root declares Rock Ridge with an `ER` entry or records
+    /// its own attributes
+    pub fn has_rock_ridge(&mut self) -> Result<bool, VDErr> {
+        if self.susp_skip()?.is_none() {
+            return Ok(false)
+        }
+        let root = self.root().clone();
+        let dot = self.read_dir(&root)?.swap_remove(0);
+        let entries = self.system_use_entries(&dot)?;
+        Ok(susp_entries(&entries).any(|e| match &e.signature {
+            b"ER" => {
+                let id_len = e.data.first().copied().unwrap_or(0) as usize;
+                let id = e.data.get(4..4 + id_len).unwrap_or_default();
+                id.starts_with(b"RRIP") || id.starts_with(b"IEEE_P1282") || id.starts_with(b"IEEE_1282")
+            },
+            b"PX" => true,
+            _ => false,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rock_ridge_round_trip() {
+        let rr = RockRidge {
+            attrs: Some(PosixAttrs {
+                mode: PosixAttrs::S_IFLNK | 0o777,
+                nlink: 1,
+                uid: 1000,
+                gid: 100,
+                ino: None,
+            }),
+            name: Some("a".repeat(300)),
+            symlink: Some(format!("/usr/../lib/./{}/x", "b".repeat(260))),
+            times: RrTimes {
+                modify: Some(1_700_000_000),
+                access: Some(1_700_000_100),
+                ..Default::default()
+            },
+            device: None,
+            child_link: Some(42),
+            parent_link: None,
+            relocated: true,
+        };
+        let mut entries = Vec::new();
+        rr.dump(&mut entries);
+        let signatures: Vec<[u8; 2]> = susp_entries(&entries).map(|e| e.signature).collect();
+        assert_eq!(signatures, [*b"PX", *b"SL", *b"SL", *b"SL", *b"NM", *b"NM", *b"CL", *b"RE", *b"TF"]);
+        assert_eq!(RockRidge::parse(susp_entries(&entries)), rr);
+
+        let mut ce = Vec::new();
+        let area = ContinuationArea {
+            block: 30,
+            offset: 100,
+            len: 237,
+        };
+        area.dump(&mut ce);
+        assert_eq!(ce.len(), CE_ENTRY_SIZE);
+        assert_eq!(ContinuationArea::parse(susp_entries(&ce).next().unwrap().data), Some(area));
+    }
+}
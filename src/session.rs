@@ -0,0 +1,95 @@
+use std::io;
+
+use crate::*;
+
+/// How far past the end of a session the next one is looked for, in sectors
+///
+/// This is the lead-out and lead-in of the first session of a CD plus a
+/// pregap, the largest gap a burner leaves between two sessions.
+pub const MAX_SESSION_GAP: u32 = 11_400;
+
+/// A descriptor set found by `find_sessions`
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Session {
+    /// Sector at which the session starts, its descriptors are 16 sectors
+    /// later
+    pub start: u32,
+    pub vds: VolumeDescriptorSet,
+}
+
+impl Session {
+    /// Sector right after the last one used by the session, the sectors of the
+    /// previous sessions included
+    pub fn end(&self) -> u32 {
+        let blocks_per_sector = SECTOR_SIZE as u32 / self.vds.pvd.logical_block_size.max(1) as u32;
+        self.vds.pvd.vol_space_size.div_ceil(blocks_per_sector.max(1))
+    }
+}
+
+fn is_pvd(sector: &[u8]) -> bool {
+    matches!(VD::read_header(sector), Ok(VD { ty: VDType::PrimaryVD, .. }))
+}
+
+/// Lists the sessions of a multi-session disc or of an image that was
+/// appended to, the oldest first
+///
+/// Each volume records its own size, which covers the previous sessions, so
+/// the next session is looked for right after it. The last session is the
+/// one that sees the most recent tree.
+pub fn find_sessions<D: BlockDevice + ?Sized>(dev: &mut D) -> Result<Vec<Session>, VDErr> {
+    let mut sessions = vec![Session {
+        start: 0,
+        vds: VolumeDescriptorSet::read(dev)?,
+    }];
+
+    let mut sector = [0_u8; SECTOR_SIZE];
+    'sessions: loop {
+        // a volume that does not cover the previous sessions must not be
+        // found again
+        let last = sessions.last().unwrap();
+        let end = last.end().max(last.start + 1);
+        let first = end as u64 + DATA_START / SECTOR_SIZE as u64;
+        for lba in first..first + MAX_SESSION_GAP as u64 {
+            match read_sector(dev, lba, &mut sector) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break 'sessions,
+                Err(e) => return Err(e.into()),
+            }
+            if is_pvd(&sector) {
+                let start = (lba - DATA_START / SECTOR_SIZE as u64) as u32;
+                sessions.push(Session {
+                    start,
+                    vds: VolumeDescriptorSet::read_session(dev, start)?,
+                });
+                continue 'sessions
+            }
+        }
+        break
+    }
+
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_sessions() {
+        let first = IsoBuilder::new().with_vol_ident("FIRST").build().unwrap();
+        let second = IsoBuilder::new().with_vol_ident("SECOND").build().unwrap();
+
+        // leave a gap as a burner would, the second volume is not usable as
+        // is since its extents do not account for the first session
+        let mut image = first.clone();
+        image.resize(first.len() + 32 * SECTOR_SIZE, 0);
+        image.extend_from_slice(&second);
+
+        let sessions = find_sessions(&mut image.as_slice()).unwrap();
+        let starts: Vec<u32> = sessions.iter().map(|s| s.start).collect();
+        assert_eq!(starts, [0, (first.len() / SECTOR_SIZE) as u32 + 32]);
+        let ident = sessions[1].vds.pvd.vol_ident.as_ref().unwrap();
+        assert_eq!(ident.as_str(), "SECOND");
+    }
+}
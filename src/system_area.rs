@@ -0,0 +1,507 @@
+use crate::*;
+
+/// Offset of the magic number of a SUN disk label
+const SUN_MAGIC_OFFSET: usize = 508;
+const SUN_MAGIC: u16 = 0xDABE;
+const SUN_NB_PARTITIONS: usize = 8;
+/// Partition arrays larger than this are considered corrupted
+const MAX_GPT_ENTRIES_LEN: usize = 1024 * 1024;
+
+/// Signature of the Driver Descriptor Map that starts a disk with an Apple
+/// Partition Map
+pub const APM_DDM_SIGNATURE: [u8; 2] = *b"ER";
+pub const APM_ENTRY_SIGNATURE: [u8; 2] = *b"PM";
+
+/// The first sector of the disk when it ends with `MBR_SIGNATURE`
+#[derive(Debug, Clone)]
+pub struct Mbr {
+    /// `true` if the 440 bytes before the disk signature are not all 0
+    pub has_boot_code: bool,
+    /// The 64 bits that isohybrid's boot code reads the address of the El
+    /// Torito boot image from, in 512 bytes sectors
+    pub isohybrid_boot_lba: u64,
+    pub disk_signature: u32,
+    pub partitions: [MbrPartition; 4],
+}
+
+impl Mbr {
+    /// `None` if the sector does not end with `MBR_SIGNATURE`
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.get(510..512)? != MBR_SIGNATURE {
+            return None
+        }
+        let mut partitions = [MbrPartition::default(); 4];
+        for (i, partition) in partitions.iter_mut().enumerate() {
+            let off = MBR_PARTITION_TABLE + i * MbrPartition::SIZE;
+            *partition = MbrPartition::parse(&sector[off..]);
+        }
+        Some(Self {
+            has_boot_code: sector[..440].iter().any(|&b| b != 0),
+            isohybrid_boot_lba: u64::from_le_bytes(sector[432..440].try_into().unwrap()),
+            disk_signature: u32::from_le_bytes(sector[440..444].try_into().unwrap()),
+            partitions,
+        })
+    }
+
+    /// `true` if the only partition is the protective one of a GPT disk
+    pub fn is_protective(&self) -> bool {
+        let mut used = self.partitions.iter().filter(|p| !p.is_empty());
+        matches!((used.next(), used.next()), (Some(p), None) if p.ty == MBR_TYPE_GPT_PROTECTIVE)
+    }
+
+    /// The partition marked active, `None` if there is none or several
+    pub fn active_partition(&self) -> Option<&MbrPartition> {
+        let mut active = self.partitions.iter().filter(|p| p.status == 0x80 && !p.is_empty());
+        match (active.next(), active.next()) {
+            (Some(p), None) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+/// A GPT as found on an image
+#[derive(Debug, Clone)]
+pub struct GptTable {
+    pub header: GptHeader,
+    pub header_crc_ok: bool,
+    pub entries_crc_ok: bool,
+    /// The used entries, with their index in the array
+    pub partitions: Vec<(usize, GptPartition)>,
+    /// `None` if the backup header is missing or past the end of the image
+    pub backup: Option<GptHeader>,
+}
+
+/// An entry of an Apple Partition Map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApmPartition {
+    /// Number of entries of the map, the same in every entry
+    pub map_entries: u32,
+    /// In blocks of the size the Driver Descriptor Map gives
+    pub start_block: u32,
+    pub nb_blocks: u32,
+    pub name: String,
+    pub ty: String,
+}
+
+impl ApmPartition {
+    pub const SIZE: usize = 512;
+
+    /// `None` if the entry does not start with `APM_ENTRY_SIGNATURE`
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        if buffer.get(..2)? != APM_ENTRY_SIGNATURE || buffer.len() < 80 {
+            return None
+        }
+        let u32_at = |off: usize| u32::from_be_bytes(buffer[off..off + 4].try_into().unwrap());
+        let string = |bytes: &[u8]| {
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..len]).into_owned()
+        };
+        Some(Self {
+            map_entries: u32_at(4),
+            start_block: u32_at(8),
+            nb_blocks: u32_at(12),
+            name: string(&buffer[16..48]),
+            ty: string(&buffer[48..80]),
+        })
+    }
+}
+
+/// An Apple Partition Map, found on images that also boot on Macs
+#[derive(Debug, Clone)]
+pub struct Apm {
+    /// Size of the blocks of the map, 2048 for most hybrid images
+    pub block_size: u16,
+    pub partitions: Vec<ApmPartition>,
+}
+
+/// A SUN disk label, found on images that boot SPARC machines
+#[derive(Debug, Clone)]
+pub struct SunLabel {
+    pub label: String,
+    pub nb_heads: u16,
+    pub sectors_per_track: u16,
+    /// Start cylinder and number of 512 bytes sectors of the 8 partitions
+    pub partitions: [(u32, u32); SUN_NB_PARTITIONS],
+    /// `true` if the 16 bits words of the label xor to 0
+    pub checksum_ok: bool,
+}
+
+impl SunLabel {
+    /// `None` if the sector does not hold the magic number of a SUN label
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        let u16_at = |off: usize| u16::from_be_bytes([sector[off], sector[off + 1]]);
+        if sector.len() < MBR_SECTOR_SIZE || u16_at(SUN_MAGIC_OFFSET) != SUN_MAGIC {
+            return None
+        }
+        let u32_at = |off: usize| u32::from_be_bytes(sector[off..off + 4].try_into().unwrap());
+
+        let label_len = sector[..128].iter().position(|&b| b == 0).unwrap_or(128);
+        let mut partitions = [(0, 0); SUN_NB_PARTITIONS];
+        for (i, partition) in partitions.iter_mut().enumerate() {
+            *partition = (u32_at(444 + i * 8), u32_at(448 + i * 8));
+        }
+        let checksum = sector[..MBR_SECTOR_SIZE].chunks_exact(2)
+            .fold(0_u16, |sum, w| sum ^ u16::from_be_bytes([w[0], w[1]]));
+        Some(Self {
+            label: String::from_utf8_lossy(&sector[..label_len]).into_owned(),
+            nb_heads: u16_at(436),
+            sectors_per_track: u16_at(438),
+            partitions,
+            checksum_ok: checksum == 0,
+        })
+    }
+
+    /// Size in bytes of the cylinders partitions start on
+    pub fn cylinder_size(&self) -> u64 {
+        self.nb_heads as u64 * self.sectors_per_track as u64 * MBR_SECTOR_SIZE as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PartitionScheme {
+    Mbr,
+    Gpt,
+    Apm,
+    Sun,
+}
+
+/// A partition of any of the tables of the system area, see
+/// `SystemArea::partitions`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Partition {
+    pub scheme: PartitionScheme,
+    /// Counted from 1 as partitioning tools do
+    pub number: usize,
+    pub name: String,
+    /// The MBR type in hex, the GPT type GUID or the APM type
+    pub ty: String,
+    /// In bytes from the start of the image
+    pub start: u64,
+    pub size: u64,
+}
+
+/// What the content of a partition is from the point of view of the volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PartitionAlias {
+    /// The partition starts before the first volume descriptor, mounting it
+    /// shows the volume
+    Volume,
+    /// The partition starts at the extent of this file
+    File(String),
+    /// The partition is inside the volume but does not start at a file
+    InsideVolume,
+    /// The partition is past the end of the volume, like the ones appended
+    /// by xorriso
+    OutsideVolume,
+}
+
+/// The partition tables found in the 32K before the first volume descriptor
+///
+/// Hybrid images can carry several at once, an MBR for BIOS machines along
+/// with a GPT for UEFI ones or an APM for Macs.
+#[derive(Debug, Clone, Default)]
+pub struct SystemArea {
+    pub mbr: Option<Mbr>,
+    pub gpt: Option<GptTable>,
+    pub apm: Option<Apm>,
+    pub sun: Option<SunLabel>,
+}
+
+/// Reads `len` bytes at `offset`, `None` if they are past the end of `dev`
+fn read_bytes<D: BlockDevice + ?Sized>(dev: &mut D, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let Some(end) = offset.checked_add(len as u64) else {
+        return Ok(None)
+    };
+    let mut bytes = Vec::new();
+    let mut sector = [0_u8; SECTOR_SIZE];
+    let first = offset / SECTOR_SIZE as u64;
+    let last = end.div_ceil(SECTOR_SIZE as u64);
+    for lba in first..last {
+        match read_sector(dev, lba, &mut sector) {
+            Ok(()) => bytes.extend_from_slice(&sector),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+    let start = (offset % SECTOR_SIZE as u64) as usize;
+    Ok(Some(bytes[start..start + len].to_vec()))
+}
+
+impl SystemArea {
+    pub fn read<D: BlockDevice + ?Sized>(dev: &mut D) -> Result<Self, VDErr> {
+        let area = read_bytes(dev, 0, DATA_START as usize)?.ok_or(VDErr::Truncated)?;
+        let mbr = Mbr::parse(&area);
+        let sun = SunLabel::parse(&area);
+        let apm = Self::read_apm(dev, &area)?;
+        let gpt = Self::read_gpt(dev, &area)?;
+        Ok(Self {
+            mbr,
+            gpt,
+            apm,
+            sun,
+        })
+    }
+
+    fn read_gpt<D: BlockDevice + ?Sized>(dev: &mut D, area: &[u8]) -> Result<Option<GptTable>, VDErr> {
+        let Some(header) = GptHeader::parse(&area[MBR_SECTOR_SIZE..]) else {
+            return Ok(None)
+        };
+        let header_crc_ok = header.header_size as usize >= GptHeader::SIZE
+            && header.compute_crc() == header.header_crc;
+
+        // don't trust a broken header with a huge allocation, or an offset
+        // past any disk
+        let entry_size = header.entry_size as usize;
+        let len = (header.nb_entries as usize).checked_mul(entry_size)
+            .filter(|&len| entry_size >= GPT_ENTRY_SIZE && len <= MAX_GPT_ENTRIES_LEN);
+        let offset = header.entries_lba.checked_mul(MBR_SECTOR_SIZE as u64);
+        let entries = match (offset, len) {
+            (Some(offset), Some(len)) => read_bytes(dev, offset, len)?,
+            _ => None,
+        };
+
+        let (entries_crc_ok, partitions) = match entries {
+            Some(entries) => {
+                let partitions = entries.chunks_exact(entry_size)
+                    .enumerate()
+                    .filter_map(|(i, entry)| Some((i, GptPartition::parse(entry)?)))
+                    .collect();
+                (crc32(&entries) == header.entries_crc, partitions)
+            },
+            None => (false, Vec::new()),
+        };
+
+        let backup = match header.backup_lba.checked_mul(MBR_SECTOR_SIZE as u64) {
+            Some(offset) => read_bytes(dev, offset, MBR_SECTOR_SIZE)?.and_then(|sector| GptHeader::parse(&sector)),
+            None => None,
+        };
+
+        Ok(Some(GptTable {
+            header,
+            header_crc_ok,
+            entries_crc_ok,
+            partitions,
+            backup,
+        }))
+    }
+
+    fn read_apm<D: BlockDevice + ?Sized>(dev: &mut D, area: &[u8]) -> Result<Option<Apm>, VDErr> {
+        if area[..2] != APM_DDM_SIGNATURE {
+            return Ok(None)
+        }
+        let block_size = match u16::from_be_bytes([area[2], area[3]]) {
+            0 => MBR_SECTOR_SIZE as u16,
+            size => size,
+        };
+
+        let mut partitions = Vec::new();
+        let mut i = 1;
+        loop {
+            let offset = i as u64 * block_size as u64;
+            let Some(entry) = read_bytes(dev, offset, ApmPartition::SIZE)? else {
+                break
+            };
+            let Some(partition) = ApmPartition::parse(&entry) else {
+                break
+            };
+            let map_entries = partition.map_entries;
+            partitions.push(partition);
+            if i >= map_entries as usize {
+                break
+            }
+            i += 1;
+        }
+
+        Ok(Some(Apm {
+            block_size,
+            partitions,
+        }))
+    }
+
+    /// Every partition of every table, in bytes
+    pub fn partitions(&self) -> Vec<Partition> {
+        let mut partitions = Vec::new();
+        let sector = MBR_SECTOR_SIZE as u64;
+
+        if let Some(mbr) = &self.mbr {
+            for (i, p) in mbr.partitions.iter().enumerate().filter(|(_, p)| !p.is_empty()) {
+                partitions.push(Partition {
+                    scheme: PartitionScheme::Mbr,
+                    number: i + 1,
+                    name: String::new(),
+                    ty: format!("0x{:02x}", p.ty),
+                    start: p.start_lba as u64 * sector,
+                    size: p.nb_sectors as u64 * sector,
+                });
+            }
+        }
+
+        if let Some(gpt) = &self.gpt {
+            for (i, p) in &gpt.partitions {
+                partitions.push(Partition {
+                    scheme: PartitionScheme::Gpt,
+                    number: i + 1,
+                    name: p.name.clone(),
+                    ty: p.type_guid.to_string(),
+                    start: p.first_lba * sector,
+                    size: p.nb_sectors() * sector,
+                });
+            }
+        }
+
+        if let Some(apm) = &self.apm {
+            let block_size = apm.block_size as u64;
+            for (i, p) in apm.partitions.iter().enumerate() {
+                partitions.push(Partition {
+                    scheme: PartitionScheme::Apm,
+                    number: i + 1,
+                    name: p.name.clone(),
+                    ty: p.ty.clone(),
+                    start: p.start_block as u64 * block_size,
+                    size: p.nb_blocks as u64 * block_size,
+                });
+            }
+        }
+
+        if let Some(sun) = &self.sun {
+            for (i, &(cylinder, nb_sectors)) in sun.partitions.iter().enumerate() {
+                if nb_sectors == 0 {
+                    continue
+                }
+                partitions.push(Partition {
+                    scheme: PartitionScheme::Sun,
+                    number: i + 1,
+                    name: String::new(),
+                    ty: String::new(),
+                    start: cylinder as u64 * sun.cylinder_size(),
+                    size: nb_sectors as u64 * sector,
+                });
+            }
+        }
+
+        partitions
+    }
+
+    /// Says for every partition what it holds of the volume of `fs`
+    pub fn aliases<D: BlockDevice>(&self, fs: &mut IsoFs<D>) -> Result<Vec<(Partition, PartitionAlias)>, VDErr> {
+        let volume_end = fs.pvd().vol_space_size as u64 * fs.logical_block_size();
+        let extents: Vec<(String, u64)> = fs.files()?
+            .into_iter()
+            .filter(|(_, record)| record.data_size != 0)
+            .map(|(path, record)| (path, fs.extent_offset(&record)))
+            .collect();
+
+        let aliases = self.partitions()
+            .into_iter()
+            .map(|partition| {
+                let alias = if partition.start <= DATA_START {
+                    PartitionAlias::Volume
+                } else if partition.start >= volume_end {
+                    PartitionAlias::OutsideVolume
+                } else {
+                    extents.iter()
+                        .find(|(_, offset)| *offset == partition.start)
+                        .map(|(path, _)| PartitionAlias::File(path.clone()))
+                        .unwrap_or(PartitionAlias::InsideVolume)
+                };
+                (partition, alias)
+            })
+            .collect();
+        Ok(aliases)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_layout() {
+        let mut builder = IsoBuilder::new()
+            .with_hybrid_mbr(HybridMbr::new(vec![0xEB; 16]))
+            .with_gpt(Gpt::new(EfiSystemPartition::BootImage));
+        builder.add_file("isolinux.bin", vec![0xFA; 2048]).unwrap();
+        builder.add_file("efi/efiboot.img", vec![0xF0; 5000]).unwrap();
+        builder.add_file("zzz/after.bin", vec![1; 10_000]).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux.bin"));
+        builder.add_boot_image(BootImage::no_emulation("efi/efiboot.img").with_platform(Platform::UEFI));
+        let image = builder.build().unwrap();
+
+        let area = SystemArea::read(&mut image.as_slice()).unwrap();
+        let mbr = area.mbr.as_ref().unwrap();
+        assert!(mbr.has_boot_code);
+        assert!(mbr.is_protective());
+        let gpt = area.gpt.as_ref().unwrap();
+        assert!(gpt.header_crc_ok && gpt.entries_crc_ok);
+        assert_eq!(gpt.backup.as_ref().unwrap().current_lba, gpt.header.backup_lba);
+        assert!(area.apm.is_none() && area.sun.is_none());
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let aliases: Vec<(PartitionScheme, PartitionAlias)> = area.aliases(&mut fs).unwrap()
+            .into_iter()
+            .map(|(p, alias)| (p.scheme, alias))
+            .collect();
+        assert_eq!(aliases, [
+            (PartitionScheme::Mbr, PartitionAlias::Volume),
+            (PartitionScheme::Gpt, PartitionAlias::Volume),
+            (PartitionScheme::Gpt, PartitionAlias::File("/EFI/EFIBOOT.IMG".to_owned())),
+            (PartitionScheme::Gpt, PartitionAlias::InsideVolume),
+        ]);
+
+        // a header whose tables would be past any disk has no partitions
+        let mut image = image;
+        let header = &mut image[MBR_SECTOR_SIZE..2 * MBR_SECTOR_SIZE];
+        header[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        header[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+        let gpt = SystemArea::read(&mut image.as_slice()).unwrap().gpt.unwrap();
+        assert!(!gpt.header_crc_ok && !gpt.entries_crc_ok);
+        assert!(gpt.partitions.is_empty() && gpt.backup.is_none());
+    }
+
+    #[test]
+    fn test_apm_and_sun_label() {
+        let mut image = IsoBuilder::new().build().unwrap();
+
+        // SUN label in the first sector, the Driver Descriptor Map of the APM
+        // is the start of its text
+        let sector = &mut image[..MBR_SECTOR_SIZE];
+        sector[..2].copy_from_slice(&APM_DDM_SIGNATURE);
+        sector[2..4].copy_from_slice(&2048_u16.to_be_bytes());
+        sector[436..438].copy_from_slice(&1_u16.to_be_bytes());
+        sector[438..440].copy_from_slice(&640_u16.to_be_bytes());
+        // second partition on the third cylinder
+        sector[452..456].copy_from_slice(&2_u32.to_be_bytes());
+        sector[456..460].copy_from_slice(&640_u32.to_be_bytes());
+        sector[508..510].copy_from_slice(&SUN_MAGIC.to_be_bytes());
+        let checksum = sector.chunks_exact(2)
+            .fold(0_u16, |sum, w| sum ^ u16::from_be_bytes([w[0], w[1]]));
+        sector[510..512].copy_from_slice(&checksum.to_be_bytes());
+
+        let entry = &mut image[2048..2560];
+        entry[..2].copy_from_slice(&APM_ENTRY_SIGNATURE);
+        entry[4..8].copy_from_slice(&1_u32.to_be_bytes());
+        entry[8..12].copy_from_slice(&16_u32.to_be_bytes());
+        entry[12..16].copy_from_slice(&64_u32.to_be_bytes());
+        entry[16..23].copy_from_slice(b"ISO9660");
+        entry[48..57].copy_from_slice(b"Apple_HFS");
+
+        let area = SystemArea::read(&mut image.as_slice()).unwrap();
+        assert!(area.mbr.is_none() && area.gpt.is_none());
+        assert!(area.sun.as_ref().unwrap().checksum_ok);
+        assert_eq!(area.apm.as_ref().unwrap().partitions[0].ty, "Apple_HFS");
+
+        let mut fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let aliases = area.aliases(&mut fs).unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases[0].0.name, "ISO9660");
+        assert_eq!(aliases[0].0.start, DATA_START);
+        assert_eq!(aliases[0].1, PartitionAlias::Volume);
+        assert_eq!(aliases[1].0.scheme, PartitionScheme::Sun);
+        assert_eq!(aliases[1].0.number, 2);
+        assert_eq!(aliases[1].0.start, 2 * 640 * 512);
+        assert_eq!(aliases[1].1, PartitionAlias::OutsideVolume);
+    }
+}
@@ -0,0 +1,1109 @@
+//! Checks an image against ECMA-119, El Torito and SUSP, see `verify`
+//!
+//! Unlike `IsoFs`, which reads what it can and stops at the first error, the
+//! checks go on past every problem so that a single run lists all of them.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+
+use crate::*;
+
+/// The a-characters of ECMA-119 7.4.1, without the lowercase letters the
+/// parser accepts
+const A_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_ !\"%&'()*+,-./:;<=>?";
+
+/// The d-characters of ECMA-119 7.4.1
+const D_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+
+/// Characters Joliet does not allow in names
+const JOLIET_FORBIDDEN: &[u16] = &[b'*' as u16, b'/' as u16, b':' as u16, b';' as u16, b'?' as u16, b'\\' as u16];
+
+/// ECMA-119 6.8.2.1 limits the hierarchy of the primary volume to 8 levels
+const MAX_ISO_LEVELS: usize = 8;
+
+/// Continuation areas a record can chain before the chain is considered a
+/// loop, like `IsoFs::system_use_entries` does
+const MAX_CONTINUATIONS: usize = 64;
+
+/// Descriptors read before giving up on finding the terminator
+const MAX_DESCRIPTORS: u32 = 256;
+
+/// How bad a finding is
+///
+/// Readers are expected to choke on errors, warnings are departures from the
+/// standards most readers cope with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Where in the image a finding is
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Location {
+    /// The image as a whole
+    Image,
+    /// The volume descriptor in this 2K sector
+    Descriptor(u32),
+    /// The path table at logical block `block`, `record` is numbered from 1
+    /// like the directories are
+    PathTable { block: u32, record: Option<usize> },
+    /// The directory record of `path`, `offset` bytes into logical block
+    /// `block`, Joliet paths start with `joliet:`
+    Record { path: String, block: u32, offset: u32 },
+    /// The continuation area a `CE` entry of the record of `path` points at
+    Continuation { path: String, block: u32, offset: u32 },
+    /// The 32 bytes entry `entry` of the boot catalog at 2K sector `sector`,
+    /// 0 being the validation entry
+    BootCatalog { sector: u32, entry: usize },
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image => write!(f, "image"),
+            Self::Descriptor(lba) => write!(f, "descriptor at sector {}", lba),
+            Self::PathTable { block, record: None } => write!(f, "path table at block {}", block),
+            Self::PathTable { block, record: Some(record) } => {
+                write!(f, "path table at block {}, record {}", block, record)
+            },
+            Self::Record { path, block, offset } => write!(f, "{} (block {} + {})", path, block, offset),
+            Self::Continuation { path, block, offset } => {
+                write!(f, "continuation area of {} (block {} + {})", path, block, offset)
+            },
+            Self::BootCatalog { sector, entry } => write!(f, "boot catalog at sector {}, entry {}", sector, entry),
+        }
+    }
+}
+
+/// A departure from the standards
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Finding {
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.location, self.message)
+    }
+}
+
+/// What `verify` found, in the order it was found
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verification {
+    pub findings: Vec<Finding>,
+}
+
+impl Verification {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// `true` if nothing is worse than a warning
+    pub fn is_ok(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+}
+
+/// Checks the last session of `dev`, the one a mount sees
+///
+/// `image_len` is the size of the image when it is known, to check it
+/// against the size of the volume. Only failing to read the device is an
+/// error, everything wrong with the image is a finding.
+pub fn verify<D: BlockDevice>(mut dev: D, image_len: Option<u64>) -> io::Result<Verification> {
+    // a broken first session is reported by the checks of session 0
+    let start = match find_sessions(&mut dev) {
+        Ok(mut sessions) => sessions.pop().map_or(0, |s| s.start),
+        Err(VDErr::Io(e)) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+        Err(_) => 0,
+    };
+    verify_session(dev, start, image_len)
+}
+
+/// Same as `verify` for the session whose descriptors start 16 sectors after
+/// `session_start`
+pub fn verify_session<D: BlockDevice>(dev: D, session_start: u32, image_len: Option<u64>) -> io::Result<Verification> {
+    let mut verifier = Verifier {
+        dev: CachedDevice::new(dev, DEFAULT_CACHE_CAPACITY),
+        session_start,
+        block_size: SECTOR_SIZE as u64,
+        volume_end: 0,
+        susp_skip: None,
+        extents: Vec::new(),
+        findings: Vec::new(),
+    };
+    verifier.run(image_len)?;
+    Ok(Verification {
+        findings: verifier.findings,
+    })
+}
+
+/// The fields of a primary or Joliet descriptor the checks of its hierarchy
+/// need
+struct Volume {
+    joliet: bool,
+    path_table_size: u32,
+    /// L, optional L, M and optional M, 0 when absent
+    path_tables: [u32; 4],
+    root: DirectoryRecord,
+}
+
+/// A directory of the hierarchy, in the order of the path table
+struct Dir {
+    path: String,
+    ident: Vec<u8>,
+    extent: u32,
+    data_size: u32,
+    /// Index in the hierarchy, the root is its own parent
+    parent: usize,
+    depth: usize,
+}
+
+/// A record of a path table
+#[derive(PartialEq, Eq)]
+struct PathTableRecord {
+    ident: Vec<u8>,
+    extent: u32,
+    /// Numbered from 1
+    parent: u16,
+}
+
+/// Bytes of the image something claims, for the overlap check
+struct Extent {
+    start: u64,
+    end: u64,
+    location: Location,
+}
+
+struct Verifier<D> {
+    dev: CachedDevice<D>,
+    session_start: u32,
+    block_size: u64,
+    /// In bytes from the start of the image
+    volume_end: u64,
+    /// Set by the `SP` entry of the root of the primary volume
+    susp_skip: Option<u8>,
+    extents: Vec<Extent>,
+    findings: Vec<Finding>,
+}
+
+fn both_endian_u16(field: &[u8]) -> Option<u16> {
+    let le = u16::from_le_bytes([field[0], field[1]]);
+    (le == u16::from_be_bytes([field[2], field[3]])).then_some(le)
+}
+
+fn both_endian_u32(field: &[u8]) -> Option<u32> {
+    let le = u32::from_le_bytes(field[..4].try_into().unwrap());
+    (le == u32::from_be_bytes(field[4..8].try_into().unwrap())).then_some(le)
+}
+
+/// The severity of `c` showing up where `charset` is expected, lowercase
+/// letters are common enough for readers to accept them
+fn char_severity(c: u8) -> Severity {
+    if c.is_ascii_lowercase() {
+        Severity::Warning
+    } else {
+        Severity::Error
+    }
+}
+
+/// Describes `c` for a message
+fn show_char(c: u8) -> String {
+    if c.is_ascii_graphic() || c == b' ' {
+        format!("'{}'", c as char)
+    } else {
+        format!("0x{:02X}", c)
+    }
+}
+
+fn parse_path_table(data: &[u8], big_endian: bool) -> Result<Vec<PathTableRecord>, usize> {
+    let mut records = Vec::new();
+    let mut off = 0;
+    while off < data.len() {
+        let len = data[off] as usize;
+        if len == 0 || off + 8 + len > data.len() {
+            return Err(records.len() + 1)
+        }
+        let extent: [u8; 4] = data[off + 2..off + 6].try_into().unwrap();
+        let parent = [data[off + 6], data[off + 7]];
+        records.push(PathTableRecord {
+            ident: data[off + 8..off + 8 + len].to_vec(),
+            extent: if big_endian { u32::from_be_bytes(extent) } else { u32::from_le_bytes(extent) },
+            parent: if big_endian { u16::from_be_bytes(parent) } else { u16::from_le_bytes(parent) },
+        });
+        off += 8 + len + (len & 1);
+    }
+    Ok(records)
+}
+
+impl<D: BlockDevice> Verifier<D> {
+    fn report(&mut self, severity: Severity, location: Location, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            location,
+            message: message.into(),
+        });
+    }
+
+    fn error(&mut self, location: Location, message: impl Into<String>) {
+        self.report(Severity::Error, location, message)
+    }
+
+    fn warning(&mut self, location: Location, message: impl Into<String>) {
+        self.report(Severity::Warning, location, message)
+    }
+
+    /// Reads `buf.len()` bytes at `offset`, `false` if they are past the end
+    /// of the device
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<bool> {
+        let mut sector = [0_u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            match read_sector(&mut self.dev, pos / SECTOR_SIZE as u64, &mut sector) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let start = (pos % SECTOR_SIZE as u64) as usize;
+            let len = (SECTOR_SIZE - start).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&sector[start..start + len]);
+            done += len;
+        }
+        Ok(true)
+    }
+
+    /// Reads `len` bytes at `offset` of something at `location`, reported
+    /// and `None` when the image ends before them
+    fn read_extent(&mut self, offset: u64, len: usize, location: &Location) -> io::Result<Option<Vec<u8>>> {
+        let mut data = vec![0_u8; len];
+        if self.read(offset, &mut data)? {
+            Ok(Some(data))
+        } else {
+            self.error(location.clone(), "the image ends before the data");
+            Ok(None)
+        }
+    }
+
+    /// Notes that `location` claims `len` bytes from logical block `block`,
+    /// `false` if they run past the end of the volume
+    fn claim(&mut self, block: u32, len: u64, location: &Location, what: &str) -> bool {
+        if len == 0 {
+            return true
+        }
+        let start = block as u64 * self.block_size;
+        let end = start + len.div_ceil(self.block_size) * self.block_size;
+        if end > self.volume_end {
+            self.error(location.clone(), format!(
+                "{} runs past the end of the volume, blocks {}..{} of {}",
+                what, block, end / self.block_size, self.volume_end / self.block_size,
+            ));
+            return false
+        }
+        self.extents.push(Extent {
+            start,
+            end,
+            location: location.clone(),
+        });
+        true
+    }
+
+    fn run(&mut self, image_len: Option<u64>) -> io::Result<()> {
+        let Some(volumes) = self.check_descriptors()? else {
+            return Ok(())
+        };
+        self.check_volume_size(image_len)?;
+        for volume in &volumes {
+            let dirs = self.check_hierarchy(volume)?;
+            self.check_path_tables(volume, &dirs)?;
+        }
+        self.check_overlaps();
+        Ok(())
+    }
+
+    /// Checks the descriptor set and the fields of the primary descriptor,
+    /// returns the volumes whose hierarchy can be walked
+    fn check_descriptors(&mut self) -> io::Result<Option<Vec<Volume>>> {
+        let first = self.session_start + (DATA_START / SECTOR_SIZE as u64) as u32;
+        let mut types = Vec::new();
+        let mut sectors = Vec::new();
+        let mut terminated = false;
+        let mut sector = [0_u8; SECTOR_SIZE];
+        for lba in first..first + MAX_DESCRIPTORS {
+            let location = Location::Descriptor(lba);
+            if !self.read(lba as u64 * SECTOR_SIZE as u64, &mut sector)? {
+                self.error(location, "the image ends before the set terminator");
+                break
+            }
+            if sector[1..6] != *b"CD001" {
+                self.error(location, "no CD001 identifier, the set has no terminator");
+                break
+            }
+            if sector[6] != 1 {
+                self.error(location.clone(), format!("version {}, not 1", sector[6]));
+            }
+            match sector[0] {
+                0..=3 => (),
+                255 => {
+                    types.push((lba, sector[0]));
+                    terminated = true;
+                    break
+                },
+                ty => self.error(location, format!("unknown descriptor type {}", ty)),
+            }
+            types.push((lba, sector[0]));
+            sectors.push((lba, sector));
+        }
+        if !terminated && types.len() as u32 == MAX_DESCRIPTORS {
+            self.error(Location::Descriptor(first), format!("no set terminator in {} descriptors", MAX_DESCRIPTORS));
+        }
+        if let Some(&(last, _)) = types.last() {
+            self.extents.push(Extent {
+                start: first as u64 * SECTOR_SIZE as u64,
+                end: (last as u64 + 1) * SECTOR_SIZE as u64,
+                location: Location::Descriptor(first),
+            });
+        }
+
+        let Some(pvd_index) = sectors.iter().position(|(_, s)| s[0] == VDType::PrimaryVD as u8) else {
+            self.error(Location::Image, "no primary volume descriptor");
+            return Ok(None)
+        };
+        if pvd_index != 0 {
+            self.warning(Location::Descriptor(sectors[0].0), "the set does not start with the primary volume descriptor");
+        }
+        for &(lba, _) in sectors[pvd_index + 1..].iter().filter(|(_, s)| s[0] == VDType::PrimaryVD as u8) {
+            self.warning(Location::Descriptor(lba), "another primary volume descriptor, readers only use the first");
+        }
+
+        let (pvd_lba, pvd) = sectors[pvd_index];
+        let Some(primary) = self.check_pvd(pvd_lba, &pvd) else {
+            return Ok(None)
+        };
+        let mut volumes = vec![primary];
+        let mut has_boot_record = false;
+        for (lba, sector) in &sectors {
+            match sector[0] {
+                0 if sector[7..30] == *b"EL TORITO SPECIFICATION" && !has_boot_record => {
+                    has_boot_record = true;
+                    self.check_boot_record(*lba, sector)?;
+                },
+                2 if joliet_level(sector).is_some() => {
+                    if let Some(volume) = self.check_svd(*lba, sector) {
+                        volumes.push(volume);
+                    }
+                },
+                _ => (),
+            }
+        }
+        Ok(Some(volumes))
+    }
+
+    /// Checks the characters of the identifier `field` of a descriptor,
+    /// `files` allows the separators of a file identifier
+    fn check_ident_field(&mut self, lba: u32, name: &str, field: &[u8], charset: &[u8], files: bool) {
+        let len = field.len() - field.iter().rev().take_while(|&&c| c == b' ' || c == 0).count();
+        let invalid = field[..len].iter()
+            .find(|&&c| !charset.contains(&c) && !(files && (c == b'.' || c == b';')));
+        if let Some(&c) = invalid {
+            let set = if charset == D_CHARS { "a d-character" } else { "an a-character" };
+            self.report(char_severity(c), Location::Descriptor(lba), format!(
+                "{} in the {} is not {}", show_char(c), name, set,
+            ));
+        }
+    }
+
+    fn check_pvd(&mut self, lba: u32, sector: &[u8; SECTOR_SIZE]) -> Option<Volume> {
+        let location = Location::Descriptor(lba);
+        let fields: [(&str, std::ops::Range<usize>, bool); 5] = [
+            ("volume space size", 80..88, false),
+            ("volume set size", 120..124, true),
+            ("volume sequence number", 124..128, true),
+            ("logical block size", 128..132, true),
+            ("path table size", 132..140, false),
+        ];
+        for (name, range, is_u16) in fields {
+            let agrees = if is_u16 {
+                both_endian_u16(&sector[range]).is_some()
+            } else {
+                both_endian_u32(&sector[range]).is_some()
+            };
+            if !agrees {
+                self.error(location.clone(), format!("the two byte orders of the {} differ", name));
+            }
+        }
+
+        let block_size = u16::from_le_bytes([sector[128], sector[129]]);
+        if !LOGICAL_BLOCK_SIZES.contains(&block_size) {
+            self.error(location, format!("logical block size {}, the hierarchy cannot be checked", block_size));
+            return None
+        }
+        self.block_size = block_size as u64;
+        self.volume_end = u32::from_le_bytes(sector[80..84].try_into().unwrap()) as u64 * self.block_size;
+        // the descriptors were claimed before the volume size was known
+        let descriptors_end = self.extents.first().map_or(0, |e| e.end);
+        if descriptors_end > self.volume_end {
+            self.error(location.clone(), "the volume ends before its descriptors");
+        }
+
+        let set_size = u16::from_le_bytes([sector[120], sector[121]]);
+        let seq = u16::from_le_bytes([sector[124], sector[125]]);
+        if seq == 0 || seq > set_size {
+            self.warning(location.clone(), format!("volume {} of a set of {}", seq, set_size));
+        }
+        if sector[881] != 1 {
+            self.error(location.clone(), format!("file structure version {}, not 1", sector[881]));
+        }
+
+        self.check_ident_field(lba, "system identifier", &sector[8..40], A_CHARS, false);
+        self.check_ident_field(lba, "volume identifier", &sector[40..72], D_CHARS, false);
+        self.check_ident_field(lba, "volume set identifier", &sector[190..318], D_CHARS, false);
+        self.check_ident_field(lba, "publisher identifier", &sector[318..446], A_CHARS, false);
+        self.check_ident_field(lba, "data preparer identifier", &sector[446..574], A_CHARS, false);
+        self.check_ident_field(lba, "application identifier", &sector[574..702], A_CHARS, false);
+        self.check_ident_field(lba, "copyright file identifier", &sector[702..739], D_CHARS, true);
+        self.check_ident_field(lba, "abstract file identifier", &sector[739..776], D_CHARS, true);
+        self.check_ident_field(lba, "bibliographic file identifier", &sector[776..813], D_CHARS, true);
+
+        self.volume(lba, sector, false)
+    }
+
+    fn check_svd(&mut self, lba: u32, sector: &[u8; SECTOR_SIZE]) -> Option<Volume> {
+        let block_size = both_endian_u16(&sector[128..132]);
+        if block_size != Some(self.block_size as u16) {
+            self.error(Location::Descriptor(lba), "the logical block size differs from the one of the primary volume");
+            return None
+        }
+        let space_size = both_endian_u32(&sector[80..88]).map(|size| size as u64 * self.block_size);
+        if space_size != Some(self.volume_end) {
+            self.warning(Location::Descriptor(lba), "the volume space size differs from the one of the primary volume");
+        }
+        self.volume(lba, sector, true)
+    }
+
+    fn volume(&mut self, lba: u32, sector: &[u8; SECTOR_SIZE], joliet: bool) -> Option<Volume> {
+        let location = Location::Descriptor(lba);
+        if sector[156] != 34 || sector[156 + 32] != 1 || sector[156 + 33] != 0 {
+            self.error(location, "the root record is not a 34 bytes `.` record");
+            return None
+        }
+        let root = DirectoryRecord::try_parse(&sector[156..190]).ok()?;
+        if !root.is_dir() {
+            self.error(location.clone(), "the root record is not a directory");
+        }
+        Some(Volume {
+            joliet,
+            path_table_size: u32::from_le_bytes(sector[132..136].try_into().unwrap()),
+            path_tables: [
+                u32::from_le_bytes(sector[140..144].try_into().unwrap()),
+                u32::from_le_bytes(sector[144..148].try_into().unwrap()),
+                u32::from_be_bytes(sector[148..152].try_into().unwrap()),
+                u32::from_be_bytes(sector[152..156].try_into().unwrap()),
+            ],
+            root,
+        })
+    }
+
+    fn check_volume_size(&mut self, image_len: Option<u64>) -> io::Result<()> {
+        let end = self.volume_end;
+        let Some(len) = image_len else {
+            let mut byte = [0_u8];
+            if end > 0 && !self.read(end - 1, &mut byte)? {
+                self.error(Location::Image, format!("the volume is {} bytes but the image is shorter", end));
+            }
+            return Ok(())
+        };
+        if len < end {
+            self.error(Location::Image, format!("the volume is {} bytes but the image only {}", end, len));
+        } else if len > end {
+            // hybrid images put a backup GPT or partitions after the volume
+            let explained = SystemArea::read(&mut self.dev).is_ok_and(|area| {
+                area.gpt.is_some() || area.partitions().iter().any(|p| p.start + p.size > end)
+            });
+            if !explained {
+                self.warning(Location::Image, format!("{} bytes after the end of the volume", len - end));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_boot_record(&mut self, lba: u32, sector: &[u8; SECTOR_SIZE]) -> io::Result<()> {
+        if lba != self.session_start + 17 {
+            self.warning(Location::Descriptor(lba), "the El Torito boot record is not at sector 17");
+        }
+        let catalog = u32::from_le_bytes(sector[71..75].try_into().unwrap());
+        let offset = catalog as u64 * SECTOR_SIZE as u64;
+        if offset + SECTOR_SIZE as u64 > self.volume_end {
+            self.error(Location::Descriptor(lba), format!("the boot catalog at sector {} is past the end of the volume", catalog));
+            return Ok(())
+        }
+        let mut data = Vec::new();
+        let mut len = None;
+        let mut sector = [0_u8; SECTOR_SIZE];
+        for i in 0..MAX_CATALOG_SECTORS as u64 {
+            if offset + (i + 1) * SECTOR_SIZE as u64 > self.volume_end || !self.read(offset + i * SECTOR_SIZE as u64, &mut sector)? {
+                break
+            }
+            data.extend_from_slice(&sector);
+            len = self.check_boot_catalog(catalog, &data, false);
+            if len.is_some() {
+                break
+            }
+        }
+        if len.is_none() {
+            self.check_boot_catalog(catalog, &data, true);
+        }
+        let len = len.unwrap_or(data.len()) as u64;
+        let location = Location::BootCatalog { sector: catalog, entry: 0 };
+        let blocks_per_sector = SECTOR_SIZE as u64 / self.block_size;
+        self.claim((catalog as u64 * blocks_per_sector) as u32, len, &location, "the boot catalog");
+        Ok(())
+    }
+
+    /// Checks the entries of the catalog in `data`, returns its length,
+    /// `None` if it goes on past the end of `data`
+    ///
+    /// Findings are only reported once the whole catalog is read, or when
+    /// `last` says there is no more of it.
+    fn check_boot_catalog(&mut self, lba: u32, data: &[u8], last: bool) -> Option<usize> {
+        let mut findings = Vec::new();
+        let location = |entry: usize| Location::BootCatalog {
+            sector: lba + (entry * 32 / SECTOR_SIZE) as u32,
+            entry,
+        };
+        let mut report = |entry: usize, severity, message: String| findings.push(Finding {
+            severity,
+            location: location(entry),
+            message,
+        });
+
+        let validation = &data[..32];
+        if validation[0] != 1 {
+            report(0, Severity::Error, format!("header id 0x{:02X}, not 1", validation[0]));
+        }
+        if Platform::try_from(validation[1]).is_err() {
+            report(0, Severity::Warning, format!("unknown platform 0x{:02X}", validation[1]));
+        }
+        if validation[30..32] != [0x55, 0xAA] {
+            report(0, Severity::Error, format!("key bytes {:02X} {:02X}, not 55 AA", validation[30], validation[31]));
+        }
+        let sum = validation.chunks(2).fold(0_u16, |sum, w| sum.wrapping_add(u16::from_le_bytes([w[0], w[1]])));
+        if sum != 0 {
+            report(0, Severity::Error, format!("the checksum does not add up, the words sum to 0x{:04X}", sum));
+        }
+
+        let volume_sectors = self.volume_end / SECTOR_SIZE as u64;
+        let check_entry = |entry: usize, bytes: &[u8], report: &mut dyn FnMut(usize, Severity, String)| {
+            if bytes[0] != 0x88 && bytes[0] != 0 {
+                report(entry, Severity::Error, format!("boot indicator 0x{:02X}, not 88 or 00", bytes[0]));
+            }
+            if bytes[1] & 0x0F > 4 {
+                report(entry, Severity::Error, format!("unknown boot media {}", bytes[1] & 0x0F));
+            }
+            let rba = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as u64;
+            if bytes[0] == 0x88 && (rba == 0 || rba >= volume_sectors) {
+                report(entry, Severity::Error, format!("the image at sector {} is outside the volume", rba));
+            }
+        };
+
+        let initial = data.get(32..64)?;
+        if initial[1] & 0xF0 != 0 {
+            report(1, Severity::Error, format!("unknown boot media {}", initial[1]));
+        }
+        check_entry(1, initial, &mut report);
+
+        let mut entry = 2;
+        let mut open_section = false;
+        let len = loop {
+            let Some(header) = data.get(entry * 32..entry * 32 + 32) else {
+                break None
+            };
+            let is_final = match header[0] {
+                0x90 => false,
+                0x91 => true,
+                0 => {
+                    if open_section {
+                        report(entry - 1, Severity::Warning, "the last section header is not marked final with 0x91".to_owned());
+                    }
+                    break Some(entry * 32)
+                },
+                indicator => {
+                    report(entry, Severity::Error, format!("unknown header indicator 0x{:02X}", indicator));
+                    break Some(entry * 32)
+                },
+            };
+            open_section = !is_final;
+            if Platform::try_from(header[1]).is_err() {
+                report(entry, Severity::Warning, format!("unknown platform 0x{:02X}", header[1]));
+            }
+            let mut nb_entries = u16::from_le_bytes([header[2], header[3]]);
+            entry += 1;
+            let mut truncated = false;
+            while nb_entries > 0 || data.get(entry * 32) == Some(&EXTENSION_ENTRY_INDICATOR) {
+                let Some(bytes) = data.get(entry * 32..entry * 32 + 32) else {
+                    truncated = true;
+                    break
+                };
+                if bytes[0] != EXTENSION_ENTRY_INDICATOR {
+                    check_entry(entry, bytes, &mut report);
+                    nb_entries -= 1;
+                }
+                entry += 1;
+            }
+            if truncated {
+                break None
+            }
+            if is_final {
+                break Some(entry * 32)
+            }
+        };
+
+        match len {
+            Some(_) => self.findings.append(&mut findings),
+            None if last => {
+                self.findings.append(&mut findings);
+                self.error(Location::BootCatalog { sector: lba, entry: 0 }, "the catalog does not end");
+            },
+            None => (),
+        }
+        len
+    }
+
+    /// Walks the directories of `volume` breadth first, in the order of the
+    /// path table
+    fn check_hierarchy(&mut self, volume: &Volume) -> io::Result<Vec<Dir>> {
+        let prefix = if volume.joliet { "joliet:" } else { "" };
+        let root_location = Location::Record {
+            path: format!("{}/", prefix),
+            block: volume.root.extent_location,
+            offset: 0,
+        };
+        let mut dirs = vec![Dir {
+            path: String::new(),
+            ident: vec![0],
+            extent: volume.root.extent_location,
+            data_size: volume.root.data_size,
+            parent: 0,
+            depth: 1,
+        }];
+        let mut seen = HashSet::from([volume.root.extent_location]);
+        if !self.claim(volume.root.extent_location, volume.root.data_size as u64, &root_location, "the root directory") {
+            return Ok(dirs)
+        }
+
+        let mut i = 0;
+        while i < dirs.len() {
+            let dir_path = format!("{}{}/", prefix, dirs[i].path);
+            let extent = dirs[i].extent;
+            let location = Location::Record {
+                path: dir_path.clone(),
+                block: extent,
+                offset: 0,
+            };
+            let Some(data) = self.read_extent(extent as u64 * self.block_size, dirs[i].data_size as usize, &location)? else {
+                i += 1;
+                continue
+            };
+
+            let mut off = 0;
+            let mut index = 0;
+            while off < data.len() {
+                let size = data[off] as usize;
+                if size == 0 {
+                    off = (off / self.block_size as usize + 1) * self.block_size as usize;
+                    continue
+                }
+                let location = Location::Record {
+                    path: dir_path.clone(),
+                    block: extent + (off as u64 / self.block_size) as u32,
+                    offset: (off as u64 % self.block_size) as u32,
+                };
+                // the sectors are the ones of the image, not of the extent
+                let start = extent as u64 * self.block_size + off as u64;
+                if start % SECTOR_SIZE as u64 + size as u64 > SECTOR_SIZE as u64 {
+                    self.error(location.clone(), format!("a record of {} bytes crosses a sector boundary", size));
+                }
+                let Some(record) = data.get(off..off + size).and_then(|bytes| DirectoryRecord::try_parse(bytes).ok()) else {
+                    self.error(location, format!("malformed record of {} bytes, the rest of the directory is skipped", size));
+                    break
+                };
+                off += size;
+                index += 1;
+
+                let name = match record.file_ident.as_slice() {
+                    [0] => ".".to_owned(),
+                    [1] => "..".to_owned(),
+                    ident if volume.joliet => joliet_name(ident).into_owned(),
+                    ident => String::from_utf8_lossy(ident).into_owned(),
+                };
+                let path = match index {
+                    1 | 2 => format!("{}{}", dir_path, name),
+                    _ => format!("{}{}/{}", prefix, dirs[i].path, name),
+                };
+                let location = match location {
+                    Location::Record { block, offset, .. } => Location::Record { path: path.clone(), block, offset },
+                    other => other,
+                };
+
+                if !volume.joliet {
+                    self.check_system_use(&record, &path, &location, i == 0 && index == 1)?;
+                }
+                match (index, record.file_ident.as_slice()) {
+                    (1, [0]) => {
+                        if record.extent_location != extent || record.data_size != dirs[i].data_size {
+                            self.error(location, "`.` does not point at its own directory");
+                        }
+                        continue
+                    },
+                    (1, _) => self.error(location.clone(), "the first record is not `.`"),
+                    (2, [1]) => {
+                        let parent = &dirs[dirs[i].parent];
+                        if record.extent_location != parent.extent {
+                            self.error(location, "`..` does not point at the parent directory");
+                        }
+                        continue
+                    },
+                    (2, _) => self.error(location.clone(), "the second record is not `..`"),
+                    (_, [0] | [1]) => {
+                        self.error(location, "another `.` or `..` record");
+                        continue
+                    },
+                    _ => (),
+                }
+
+                if volume.joliet {
+                    self.check_joliet_ident(&record.file_ident, &location);
+                } else {
+                    self.check_iso_ident(&record.file_ident, record.is_dir(), &location);
+                }
+                let len = record.ext_attr_len as u64 * self.block_size + record.data_size as u64;
+                if !record.is_dir() {
+                    self.claim(record.extent_location, len, &location, "the file");
+                    continue
+                }
+                if !seen.insert(record.extent_location) {
+                    self.error(location, "the directory is already linked elsewhere in the hierarchy");
+                    continue
+                }
+                if !self.claim(record.extent_location, len, &location, "the directory") {
+                    continue
+                }
+                let depth = dirs[i].depth + 1;
+                if !volume.joliet && depth == MAX_ISO_LEVELS + 1 {
+                    self.warning(location, format!("deeper than the {} levels of ECMA-119", MAX_ISO_LEVELS));
+                }
+                dirs.push(Dir {
+                    path: format!("{}/{}", dirs[i].path, name),
+                    ident: record.file_ident,
+                    extent: record.extent_location,
+                    data_size: record.data_size,
+                    parent: i,
+                    depth,
+                });
+            }
+            if index < 2 {
+                self.error(Location::Record {
+                    path: dir_path,
+                    block: extent,
+                    offset: 0,
+                }, "the directory has no `.` and `..` records");
+            }
+            i += 1;
+        }
+        Ok(dirs)
+    }
+
+    /// Checks the identifier of a record of the primary hierarchy
+    fn check_iso_ident(&mut self, ident: &[u8], is_dir: bool, location: &Location) {
+        let invalid = |part: &[u8], allowed: &[u8]| part.iter().copied().find(|c| !D_CHARS.contains(c) && !allowed.contains(c));
+        if is_dir {
+            if let Some(c) = invalid(ident, &[]) {
+                self.report(char_severity(c), location.clone(), format!("{} in the identifier is not a d-character", show_char(c)));
+            }
+            if ident.len() > 31 {
+                self.warning(location.clone(), format!("directory identifier of {} characters, more than 31", ident.len()));
+            }
+            return
+        }
+
+        let (name, version) = match ident.iter().rposition(|&c| c == b';') {
+            Some(pos) => (&ident[..pos], Some(&ident[pos + 1..])),
+            None => (ident, None),
+        };
+        match version {
+            None => self.warning(location.clone(), "no `;` version number"),
+            Some(version) => {
+                let number = std::str::from_utf8(version).ok().and_then(|v| v.parse::<u16>().ok());
+                if !matches!(number, Some(1..=32767)) || !version.iter().all(u8::is_ascii_digit) {
+                    self.error(location.clone(), format!("invalid version number `{}`", String::from_utf8_lossy(version)));
+                }
+            },
+        }
+        if name.iter().filter(|&&c| c == b'.').count() > 1 {
+            self.error(location.clone(), "more than one `.` in the identifier");
+        } else if let Some(c) = invalid(name, b".") {
+            self.report(char_severity(c), location.clone(), format!("{} in the identifier is not a d-character", show_char(c)));
+        }
+        if matches!(name, [] | [b'.']) {
+            self.error(location.clone(), "empty file identifier");
+        }
+        if name.len() > 31 {
+            self.warning(location.clone(), format!("file identifier of {} characters, more than 30", name.len() - 1));
+        }
+    }
+
+    fn check_joliet_ident(&mut self, ident: &[u8], location: &Location) {
+        if !ident.len().is_multiple_of(2) {
+            self.error(location.clone(), "odd length UCS-2 identifier");
+            return
+        }
+        let chars: Vec<u16> = ident.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        let name = match chars.iter().rposition(|&c| c == b';' as u16) {
+            Some(pos) if chars[pos + 1..].iter().all(|&c| (b'0' as u16..=b'9' as u16).contains(&c)) => &chars[..pos],
+            _ => &chars[..],
+        };
+        if let Some(&c) = name.iter().find(|&&c| c < 0x20 || JOLIET_FORBIDDEN.contains(&c)) {
+            self.error(location.clone(), format!("U+{:04X} is not allowed in Joliet names", c));
+        }
+        if name.len() > JOLIET_MAX_NAME_LEN {
+            self.warning(location.clone(), format!("name of {} characters, more than {}", name.len(), JOLIET_MAX_NAME_LEN));
+        }
+    }
+
+    /// Checks the SUSP entries of `record` and the chain of continuation
+    /// areas they point at, `root` is the `.` record of the root which sets
+    /// where the entries start
+    fn check_system_use(&mut self, record: &DirectoryRecord, path: &str, location: &Location, root: bool) -> io::Result<()> {
+        if root {
+            self.susp_skip = susp_entries(&record.system_use)
+                .next()
+                .filter(|e| e.signature == *b"SP" && e.data.starts_with(&[0xBE, 0xEF]))
+                .and_then(|e| e.data.get(2).copied());
+        }
+        let Some(skip) = self.susp_skip else {
+            return Ok(())
+        };
+
+        let mut area = record.system_use.get(skip as usize..).unwrap_or_default().to_vec();
+        let mut location = location.clone();
+        let mut visited = HashSet::new();
+        let mut has_er = false;
+        for _ in 0..=MAX_CONTINUATIONS {
+            let mut continuations = Vec::new();
+            let mut off = 0;
+            while off + SUSP_HEADER_SIZE <= area.len() {
+                let entry = &area[off..];
+                if entry.iter().all(|&b| b == 0) {
+                    break
+                }
+                let len = entry[2] as usize;
+                if len < SUSP_HEADER_SIZE || len > entry.len() {
+                    self.error(location.clone(), format!("malformed SUSP entry at byte {}", off));
+                    break
+                }
+                match &entry[..2] {
+                    b"CE" if len != CE_ENTRY_SIZE => {
+                        self.error(location.clone(), format!("CE entry of {} bytes, not {}", len, CE_ENTRY_SIZE));
+                    },
+                    b"CE" => continuations.extend(ContinuationArea::parse(&entry[SUSP_HEADER_SIZE..len])),
+                    b"ER" => has_er = true,
+                    b"ST" => break,
+                    _ => (),
+                }
+                off += len;
+            }
+            if continuations.len() > 1 {
+                self.error(location.clone(), "more than one CE entry in the same area");
+            }
+
+            let Some(ce) = continuations.first().copied() else {
+                break
+            };
+            let next = Location::Continuation {
+                path: path.to_owned(),
+                block: ce.block,
+                offset: ce.offset,
+            };
+            if !visited.insert((ce.block, ce.offset)) {
+                self.error(next, "the continuation areas loop");
+                break
+            }
+            if visited.len() > MAX_CONTINUATIONS {
+                self.error(next, format!("more than {} continuation areas", MAX_CONTINUATIONS));
+                break
+            }
+            if ce.offset as u64 + ce.len as u64 > self.block_size {
+                self.error(next, "the area runs past the end of its block");
+                break
+            }
+            let start = ce.block as u64 * self.block_size + ce.offset as u64;
+            if start + ce.len as u64 > self.volume_end {
+                self.error(next, "the area is past the end of the volume");
+                break
+            }
+            let Some(data) = self.read_extent(start, ce.len as usize, &next)? else {
+                break
+            };
+            area = data;
+            location = next;
+        }
+
+        if root && !has_er {
+            self.warning(location, "the root uses SUSP without an ER entry");
+        }
+        Ok(())
+    }
+
+    /// Checks the 4 path tables of `volume` against `dirs`
+    fn check_path_tables(&mut self, volume: &Volume, dirs: &[Dir]) -> io::Result<()> {
+        let expected: Vec<PathTableRecord> = dirs.iter()
+            .map(|dir| PathTableRecord {
+                ident: dir.ident.clone(),
+                extent: dir.extent,
+                parent: dir.parent as u16 + 1,
+            })
+            .collect();
+        let names = ["type L path table", "optional type L path table", "type M path table", "optional type M path table"];
+        for (i, &block) in volume.path_tables.iter().enumerate() {
+            let optional = i % 2 == 1;
+            if optional && block == 0 {
+                continue
+            }
+            let location = Location::PathTable { block, record: None };
+            if !self.claim(block, volume.path_table_size as u64, &location, names[i]) {
+                continue
+            }
+            let Some(data) = self.read_extent(block as u64 * self.block_size, volume.path_table_size as usize, &location)? else {
+                continue
+            };
+            let records = match parse_path_table(&data, i >= 2) {
+                Ok(records) => records,
+                Err(record) => {
+                    self.error(Location::PathTable { block, record: Some(record) }, "malformed record");
+                    continue
+                },
+            };
+            if records.len() != expected.len() {
+                self.error(location, format!(
+                    "{} directories in the table but {} in the hierarchy", records.len(), expected.len(),
+                ));
+            }
+            let mismatch = records.iter().zip(&expected).position(|(a, b)| a != b);
+            if let Some(index) = mismatch {
+                let (found, dir) = (&records[index], &dirs[index]);
+                self.error(Location::PathTable { block, record: Some(index + 1) }, format!(
+                    "block {} with parent {}, the hierarchy has {} at block {} with parent {}",
+                    found.extent, found.parent, if dir.path.is_empty() { "/" } else { &dir.path },
+                    dir.extent, dir.parent + 1,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports the extents that share bytes without being the same extent,
+    /// files linked from both hierarchies or twice in one are fine
+    fn check_overlaps(&mut self) {
+        let mut extents = std::mem::take(&mut self.extents);
+        extents.sort_by_key(|e| (e.start, e.end));
+        extents.dedup_by_key(|e| (e.start, e.end));
+        let mut furthest: Option<&Extent> = None;
+        let mut overlaps = Vec::new();
+        for extent in &extents {
+            match furthest {
+                Some(prev) if extent.start < prev.end => {
+                    overlaps.push((extent.location.clone(), prev.location.to_string()));
+                    if extent.end > prev.end {
+                        furthest = Some(extent);
+                    }
+                },
+                _ => furthest = Some(extent),
+            }
+        }
+        for (location, other) in overlaps {
+            self.error(location, format!("the extent overlaps the one of {}", other));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let mut builder = IsoBuilder::new();
+        builder.add_file("isolinux/isolinux.bin", vec![1; 5000]).unwrap();
+        builder.add_file("docs/readme.txt", b"hello".to_vec()).unwrap();
+        builder.add_boot_image(BootImage::no_emulation("isolinux/isolinux.bin").with_sector_count(4));
+        let image = builder.build().unwrap();
+        let len = Some(image.len() as u64);
+
+        let verification = verify(MemDevice::new(&image), len).unwrap();
+        assert_eq!(verification.findings, []);
+
+        // a truncated image and a broken catalog checksum
+        let mut broken = image.clone();
+        let fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let catalog = fs.descriptors().boot_record.as_ref().unwrap().boot_catalog_addr.unwrap();
+        broken[catalog as usize * SECTOR_SIZE + 28] ^= 1;
+        broken.truncate(broken.len() - SECTOR_SIZE);
+        let verification = verify(MemDevice::new(&broken), Some(broken.len() as u64)).unwrap();
+        assert!(!verification.is_ok());
+        let locations: Vec<&Location> = verification.findings.iter().map(|f| &f.location).collect();
+        assert!(locations.contains(&&Location::Image));
+        assert!(locations.contains(&&Location::BootCatalog { sector: catalog, entry: 0 }));
+
+        // the type M table no longer agrees with the hierarchy
+        let mut broken = image.clone();
+        let m = fs.pvd().path_table_m_location as usize * SECTOR_SIZE;
+        broken[m + 12..m + 16].copy_from_slice(&1000_u32.to_be_bytes());
+        let verification = verify(MemDevice::new(&broken), len).unwrap();
+        let m = m as u32 / SECTOR_SIZE as u32;
+        assert_eq!(verification.findings.len(), 1, "{:?}", verification.findings);
+        assert_eq!(verification.findings[0].location, Location::PathTable { block: m, record: Some(2) });
+    }
+
+    #[test]
+    fn test_verify_small_blocks() {
+        let mut builder = IsoBuilder::new().with_logical_block_size(512);
+        builder.add_file("docs/readme.txt", b"hello".to_vec()).unwrap();
+        for i in 0..60 {
+            builder.add_file(&format!("data/file{:02}.bin", i), vec![i; 100]).unwrap();
+        }
+        let mut image = builder.build().unwrap();
+        let verification = verify(MemDevice::new(&image), Some(image.len() as u64)).unwrap();
+        assert_eq!(verification.findings, []);
+
+        // the directory now starts a block earlier, on the zeroes that end the
+        // root, which moves its records away from the start of the extent
+        let fs = IsoFs::open(MemDevice::new(&image)).unwrap();
+        let root = fs.extent_offset(fs.root()) as usize;
+        let ident = b"\x04DATA";
+        let pos = root + image[root..].windows(ident.len()).position(|w| w == ident).unwrap() - 32;
+        let extent = double_endian::u32(&image[pos + 2..pos + 10]) - 1;
+        let data_size = double_endian::u32(&image[pos + 10..pos + 18]) + 512;
+        assert_eq!(extent % 4, 3);
+        double_endian::put_u32(&mut image[pos + 2..pos + 10], extent);
+        double_endian::put_u32(&mut image[pos + 10..pos + 18], data_size);
+        let verification = verify(MemDevice::new(&image), Some(image.len() as u64)).unwrap();
+        assert!(verification.findings.iter().all(|f| !f.message.contains("crosses")), "{:?}", verification.findings);
+    }
+}
@@ -0,0 +1,429 @@
+use std::borrow::Cow;
+
+use crate::*;
+
+fn non_empty(s: &str) -> Option<&str> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+fn le_u16(buffer: &[u8]) -> u16 {
+    let mut u16_buffer = [0_u8; 2];
+    u16_buffer.copy_from_slice(&buffer[..2]);
+    u16::from_le_bytes(u16_buffer)
+}
+
+fn le_u32(buffer: &[u8]) -> u32 {
+    let mut u32_buffer = [0_u8; 4];
+    u32_buffer.copy_from_slice(&buffer[..4]);
+    u32::from_le_bytes(u32_buffer)
+}
+
+/// Borrowed view over a primary volume descriptor sector
+///
+/// Fields are decoded when they are asked for, use `to_pvd` to get a `PVD`.
+#[derive(Debug, Clone, Copy)]
+pub struct PvdRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> PvdRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, VDErr> {
+        if buffer.len() < SECTOR_SIZE {
+            return Err(VDErr::Truncated)
+        }
+        let header = VD::read_header(buffer)?;
+        if !matches!(header.ty, VDType::PrimaryVD) {
+            return Err(VDErr::UnexpectedDescriptor(header.ty))
+        }
+        Ok(Self {
+            buffer: &buffer[..SECTOR_SIZE],
+        })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn sys_ident(&self) -> Result<Option<&'a str>, VDErr> {
+        Ok(non_empty(str_a(&self.buffer[8..40])?))
+    }
+
+    pub fn vol_ident(&self) -> Result<Option<&'a str>, VDErr> {
+        Ok(non_empty(str_d(&self.buffer[40..72])?))
+    }
+
+    pub fn vol_space_size(&self) -> u32 {
+        double_endian::u32(&self.buffer[80..88])
+    }
+
+    pub fn vol_set_size(&self) -> u16 {
+        double_endian::u16(&self.buffer[120..124])
+    }
+
+    pub fn vol_seq_num(&self) -> u16 {
+        double_endian::u16(&self.buffer[124..128])
+    }
+
+    pub fn logical_block_size(&self) -> u16 {
+        double_endian::u16(&self.buffer[128..132])
+    }
+
+    pub fn path_table_size(&self) -> u32 {
+        double_endian::u32(&self.buffer[132..140])
+    }
+
+    pub fn path_table_l_location(&self) -> u32 {
+        le_u32(&self.buffer[140..144])
+    }
+
+    pub fn path_table_m_location(&self) -> u32 {
+        u32::from_be_bytes(self.buffer[148..152].try_into().unwrap())
+    }
+
+    pub fn root_record(&self) -> Result<DirectoryRecordRef<'a>, VDErr> {
+        DirectoryRecordRef::new(&self.buffer[156..190])
+    }
+
+    pub fn vol_set_ident(&self) -> Result<Option<&'a str>, VDErr> {
+        Ok(non_empty(str_d(&self.buffer[190..318])?))
+    }
+
+    pub fn publisher_ident(&self) -> Result<Option<VolumeIdent>, VDErr> {
+        Ok(VolumeIdent::parse(&self.buffer[318..446])?)
+    }
+
+    pub fn data_prep_ident(&self) -> Result<Option<VolumeIdent>, VDErr> {
+        Ok(VolumeIdent::parse(&self.buffer[446..574])?)
+    }
+
+    pub fn app_ident(&self) -> Result<Option<VolumeIdent>, VDErr> {
+        Ok(VolumeIdent::parse(&self.buffer[574..702])?)
+    }
+
+    pub fn vol_create_date_time(&self) -> Result<Option<DecDateTime>, VDErr> {
+        Ok(DecDateTime::try_parse(&self.buffer[813..830])?)
+    }
+
+    pub fn vol_mod_date_time(&self) -> Result<Option<DecDateTime>, VDErr> {
+        Ok(DecDateTime::try_parse(&self.buffer[830..847])?)
+    }
+
+    pub fn application_used(&self) -> &'a [u8; 512] {
+        self.buffer[883..1395].try_into().unwrap()
+    }
+
+    pub fn to_pvd(&self) -> Result<PVD, VDErr> {
+        PVD::try_parse(self.buffer)
+    }
+}
+
+/// Borrowed view over a single directory record
+#[derive(Debug, Clone, Copy)]
+pub struct DirectoryRecordRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> DirectoryRecordRef<'a> {
+    /// `buffer` starts with the record, it may continue past its end
+    pub fn new(buffer: &'a [u8]) -> Result<Self, VDErr> {
+        let size = *buffer.first().ok_or(VDErr::InvalidDirectoryRecord)? as usize;
+        if size < DIRECTORY_RECORD_HEADER_SIZE || size > buffer.len() {
+            return Err(VDErr::InvalidDirectoryRecord)
+        }
+        if DIRECTORY_RECORD_HEADER_SIZE + buffer[32] as usize > size {
+            return Err(VDErr::InvalidDirectoryRecord)
+        }
+        Ok(Self {
+            buffer: &buffer[..size],
+        })
+    }
+
+    /// Iterates over the records of a directory extent, skipping the padding
+    /// at the end of each sector
+    pub fn iter(extent: &'a [u8]) -> DirectoryRecordIter<'a> {
+        Self::iter_with_block_size(extent, SECTOR_SIZE)
+    }
+
+    /// Same as `iter` for a volume whose logical blocks are `block_size`
+    /// bytes, padding is skipped up to the next block
+    pub fn iter_with_block_size(extent: &'a [u8], block_size: usize) -> DirectoryRecordIter<'a> {
+        DirectoryRecordIter {
+            extent,
+            off: 0,
+            block_size,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn size(&self) -> u8 {
+        self.buffer[0]
+    }
+
+    pub fn ext_attr_len(&self) -> u8 {
+        self.buffer[1]
+    }
+
+    pub fn extent_location(&self) -> u32 {
+        double_endian::u32(&self.buffer[2..10])
+    }
+
+    pub fn data_size(&self) -> u32 {
+        double_endian::u32(&self.buffer[10..18])
+    }
+
+    pub fn create_date(&self) -> DirectoryRecordDate {
+        DirectoryRecordDate::parse(&self.buffer[18..25])
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.buffer[25]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.flags() & flags::DIR != 0
+    }
+
+    pub fn vol_seq_num(&self) -> u16 {
+        double_endian::u16(&self.buffer[28..32])
+    }
+
+    pub fn file_ident(&self) -> &'a [u8] {
+        let ident_len = self.buffer[32] as usize;
+        &self.buffer[DIRECTORY_RECORD_HEADER_SIZE..DIRECTORY_RECORD_HEADER_SIZE + ident_len]
+    }
+
+    pub fn is_special(&self) -> bool {
+        matches!(self.file_ident(), [0] | [1])
+    }
+
+    /// See `DirectoryRecord::name`
+    pub fn name(&self) -> Cow<'a, str> {
+        record_name(self.file_ident(), self.is_dir())
+    }
+
+    pub fn system_use(&self) -> &'a [u8] {
+        let ident_end = DIRECTORY_RECORD_HEADER_SIZE + self.buffer[32] as usize;
+        self.buffer.get((ident_end + 1) & !1..).unwrap_or_default()
+    }
+
+    pub fn to_record(&self) -> DirectoryRecord {
+        DirectoryRecord {
+            size: self.size(),
+            ext_attr_len: self.ext_attr_len(),
+            extent_location: self.extent_location(),
+            data_size: self.data_size(),
+            create_date: self.create_date(),
+            flags: self.flags(),
+            interleaved_file_size: match self.buffer[26] {
+                0 => None,
+                v => Some(v),
+            },
+            interleaved_gap_size: match self.buffer[27] {
+                0 => None,
+                v => Some(v),
+            },
+            vol_seq_nul: self.vol_seq_num(),
+            file_ident: self.file_ident().to_vec(),
+            system_use: self.system_use().to_vec(),
+        }
+    }
+}
+
+/// See `DirectoryRecordRef::iter`
+pub struct DirectoryRecordIter<'a> {
+    extent: &'a [u8],
+    off: usize,
+    block_size: usize,
+}
+
+impl<'a> Iterator for DirectoryRecordIter<'a> {
+    type Item = Result<DirectoryRecordRef<'a>, VDErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let &size = self.extent.get(self.off)?;
+            if size == 0 {
+                // records never cross a sector boundary, the rest of the
+                // block is padding
+                self.off = (self.off / self.block_size + 1) * self.block_size;
+                continue
+            }
+
+            let sector_end = ((self.off / SECTOR_SIZE + 1) * SECTOR_SIZE).min(self.extent.len());
+            let res = DirectoryRecordRef::new(&self.extent[self.off..sector_end]);
+            match res {
+                Ok(_) => self.off += size as usize,
+                // don't loop on the same broken record
+                Err(_) => self.off = self.extent.len(),
+            }
+            return Some(res)
+        }
+    }
+}
+
+/// Borrowed view over the sector holding a boot catalog
+#[derive(Debug, Clone, Copy)]
+pub struct BootCatalogRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> BootCatalogRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, VDErr> {
+        if buffer.len() < 64 {
+            return Err(VDErr::Truncated)
+        }
+        if buffer[0] != 1 || buffer[30] != 0x55 || buffer[31] != 0xAA {
+            return Err(VDErr::InvalidBootCatalog)
+        }
+        Ok(Self {
+            buffer,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn validation_entry(&self) -> Result<ValidationEntry, VDErr> {
+        ValidationEntry::try_parse(&self.buffer[..32])
+    }
+
+    pub fn initial_entry(&self) -> Result<InitialEntry, VDErr> {
+        InitialEntry::try_parse(&self.buffer[32..64])
+    }
+
+    /// Sections following the initial entry, up to the final section header
+    pub fn sections(&self) -> BootSectionIter<'a> {
+        BootSectionIter {
+            buffer: self.buffer,
+            off: 64,
+        }
+    }
+}
+
+/// A section header and the entries that follow it
+#[derive(Debug, Clone, Copy)]
+pub struct BootSectionRef<'a> {
+    header: &'a [u8],
+    entries: &'a [u8],
+}
+
+impl<'a> BootSectionRef<'a> {
+    pub fn header(&self) -> Result<SectionHeaderEntry, VDErr> {
+        SectionHeaderEntry::try_parse(self.header)
+    }
+
+    /// Section entries, extension entries are skipped
+    pub fn entries(&self) -> impl Iterator<Item = Result<SectionEntry, VDErr>> + 'a {
+        self.entries.chunks_exact(32)
+            .filter(|e| e[0] != EXTENSION_ENTRY_INDICATOR)
+            .map(SectionEntry::try_parse)
+    }
+}
+
+/// See `BootCatalogRef::sections`
+pub struct BootSectionIter<'a> {
+    buffer: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Iterator for BootSectionIter<'a> {
+    type Item = BootSectionRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.buffer.get(self.off..self.off + 32)?;
+        let is_final = match header[0] {
+            0x90 => false,
+            0x91 => true,
+            _ => return None,
+        };
+        let nb_entries = le_u16(&header[2..4]) as usize;
+
+        let start = self.off + 32;
+        let mut end = start;
+        let mut seen = 0;
+        while seen < nb_entries {
+            let Some(entry) = self.buffer.get(end..end + 32) else { break };
+            if entry[0] != EXTENSION_ENTRY_INDICATOR {
+                seen += 1;
+            }
+            end += 32;
+        }
+        // extension entries of the last section entry
+        while self.buffer.get(end) == Some(&EXTENSION_ENTRY_INDICATOR) {
+            end += 32;
+        }
+        let end = end.min(self.buffer.len());
+
+        self.off = if is_final { self.buffer.len() } else { end };
+        Some(BootSectionRef {
+            header,
+            entries: &self.buffer[start..end],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_boot_catalog_sections() {
+        let mut catalog = [0_u8; SECTOR_SIZE];
+        ValidationEntry {
+            header_id: 1,
+            platform_id: Platform::X86,
+            manufacturer_id: None,
+        }.dump(&mut catalog);
+        InitialEntry {
+            boot_indicator: BootIndicator::Bootable,
+            boot_media: BootMedia::NoEmulation,
+            load_segment: None,
+            sys_type: 0,
+            sector_count: 4,
+            virtual_disk_addr: 20,
+        }.dump(&mut catalog[32..]);
+
+        let sections = [(HeaderIndicator::Partial, 2), (HeaderIndicator::Final, 1)];
+        let mut off = 64;
+        for (header_indicator, nb_section_entries) in sections {
+            SectionHeaderEntry {
+                header_indicator,
+                platform_id: Platform::UEFI,
+                nb_section_entries,
+                id_str: None,
+            }.dump(&mut catalog[off..]);
+            off += 32;
+            for i in 0..nb_section_entries {
+                SectionEntry {
+                    boot_indicator: BootIndicator::Bootable,
+                    boot_media: BootMedia::NoEmulation,
+                    has_continuation_entry: false,
+                    image_contains_atapi_driver: false,
+                    image_contains_scsi_driver: false,
+                    load_segment: None,
+                    sys_type: 0,
+                    sector_count: 1,
+                    virtual_disk_addr: 30 + off as u32 + i as u32,
+                    selection_criteria: SelectionCriteria::None,
+                    selection_criteria_bytes: Default::default(),
+                }.dump(&mut catalog[off..]);
+                off += 32;
+            }
+        }
+
+        let catalog = BootCatalogRef::new(&catalog).unwrap();
+        assert_eq!(catalog.initial_entry().unwrap().virtual_disk_addr, 20);
+        let counts: Vec<usize> = catalog.sections()
+            .map(|s| s.entries().filter(|e| e.is_ok()).count())
+            .collect();
+        assert_eq!(counts, [2, 1]);
+    }
+}
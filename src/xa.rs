@@ -0,0 +1,155 @@
+use crate::*;
+
+/// Attribute bits of an `XaRecord`
+pub mod xa_attr {
+    pub const OWNER_READ: u16 = 0x0001;
+    pub const OWNER_EXEC: u16 = 0x0004;
+    pub const GROUP_READ: u16 = 0x0010;
+    pub const GROUP_EXEC: u16 = 0x0040;
+    pub const WORLD_READ: u16 = 0x0100;
+    pub const WORLD_EXEC: u16 = 0x0400;
+    /// The file is made of mode 2 form 1 sectors
+    pub const MODE2: u16 = 0x0800;
+    /// The file is made of mode 2 form 2 sectors
+    pub const MODE2_FORM2: u16 = 0x1000;
+    /// Sectors of several files or channels are interleaved
+    pub const INTERLEAVED: u16 = 0x2000;
+    /// The file is an audio track
+    pub const CDDA: u16 = 0x4000;
+    pub const DIRECTORY: u16 = 0x8000;
+}
+
+/// The CD-ROM XA extension found at the start of the system use area of the
+/// directory records of mode 2 discs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XaRecord {
+    pub group_id: u16,
+    pub user_id: u16,
+    /// See `xa_attr`
+    pub attributes: u16,
+    pub file_number: u8,
+}
+
+impl XaRecord {
+    pub const SIZE: usize = 14;
+    pub const SIGNATURE: [u8; 2] = *b"XA";
+
+    /// `None` if `system_use` does not start with an XA record
+    pub fn parse(system_use: &[u8]) -> Option<Self> {
+        let buffer = system_use.get(..Self::SIZE)?;
+        if buffer[6..8] != Self::SIGNATURE {
+            return None
+        }
+
+        // NB: unlike everything else in the volume these are big endian only
+        Some(Self {
+            group_id: u16::from_be_bytes([buffer[0], buffer[1]]),
+            user_id: u16::from_be_bytes([buffer[2], buffer[3]]),
+            attributes: u16::from_be_bytes([buffer[4], buffer[5]]),
+            file_number: buffer[8],
+        })
+    }
+
+    pub fn dump(&self, out: &mut [u8]) {
+        out[0..2].copy_from_slice(&self.group_id.to_be_bytes());
+        out[2..4].copy_from_slice(&self.user_id.to_be_bytes());
+        out[4..6].copy_from_slice(&self.attributes.to_be_bytes());
+        out[6..8].copy_from_slice(&Self::SIGNATURE);
+        out[8] = self.file_number;
+        out[9..14].fill(0);
+    }
+
+    pub fn is_form2(&self) -> bool {
+        self.attributes & xa_attr::MODE2_FORM2 != 0
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        self.attributes & xa_attr::INTERLEAVED != 0
+    }
+
+    pub fn is_cdda(&self) -> bool {
+        self.attributes & xa_attr::CDDA != 0
+    }
+}
+
+impl DirectoryRecord {
+    pub fn xa(&self) -> Option<XaRecord> {
+        XaRecord::parse(&self.system_use)
+    }
+
+    /// Writes `xa` at the start of the system use area, replacing the XA
+    /// record that was there if any
+    pub fn set_xa(&mut self, xa: &XaRecord) {
+        if self.xa().is_none() {
+            self.system_use.splice(0..0, [0; XaRecord::SIZE]);
+        }
+        xa.dump(&mut self.system_use[..XaRecord::SIZE]);
+    }
+
+    /// Size of the file as read through `IsoFile`, form 2 sectors carry 2324
+    /// bytes of data instead of 2048
+    pub fn payload_size(&self) -> u64 {
+        match self.xa() {
+            Some(xa) if xa.is_form2() => {
+                (self.data_size as u64).div_ceil(SECTOR_SIZE as u64) * FORM2_DATA_SIZE as u64
+            },
+            _ => self.data_size as u64,
+        }
+    }
+}
+
+impl DirectoryRecordRef<'_> {
+    pub fn xa(&self) -> Option<XaRecord> {
+        XaRecord::parse(self.system_use())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_form2_file() {
+        let mut builder = IsoBuilder::new();
+        builder.add_file("MOVIE.STR", vec![0; SECTOR_SIZE * 2]).unwrap();
+        let cooked = builder.build().unwrap();
+
+        let mut record = IsoFs::open(cooked.as_slice()).unwrap().lookup("MOVIE.STR").unwrap();
+        let extent = record.extent_location as usize;
+
+        // make a mode 2 disc where the file is 2 form 2 sectors
+        let mut raw = Vec::new();
+        for (lba, data) in cooked.chunks_exact(SECTOR_SIZE).enumerate() {
+            let mut sector = [0_u8; RAW_SECTOR_SIZE];
+            raw_sector_header(lba as u64, 2, &mut sector);
+            if (extent..extent + 2).contains(&lba) {
+                sector[18] = SUBMODE_FORM2;
+                sector[24..24 + FORM2_DATA_SIZE].fill(lba as u8);
+            } else {
+                sector[24..24 + SECTOR_SIZE].copy_from_slice(data);
+            }
+            raw.extend_from_slice(&sector);
+        }
+
+        let xa = XaRecord {
+            attributes: xa_attr::MODE2_FORM2 | xa_attr::OWNER_READ,
+            file_number: 1,
+            ..Default::default()
+        };
+        record.set_xa(&xa);
+        assert_eq!(record.xa(), Some(xa));
+        assert_eq!(record.payload_size(), 2 * FORM2_DATA_SIZE as u64);
+
+        let mut buf = vec![0; record.encoded_len()];
+        record.dump(&mut buf);
+        assert_eq!(DirectoryRecordRef::new(&buf).unwrap().xa(), Some(xa));
+
+        let dev = RawSectorDevice::new(MemDevice::with_block_size(&raw, RAW_SECTOR_SIZE)).unwrap();
+        let mut fs = IsoFs::open(dev).unwrap();
+        let data = fs.read_file(&record).unwrap();
+        assert_eq!(data.len(), 2 * FORM2_DATA_SIZE);
+        assert!(data[..FORM2_DATA_SIZE].iter().all(|b| *b == extent as u8));
+        assert!(data[FORM2_DATA_SIZE..].iter().all(|b| *b == extent as u8 + 1));
+    }
+}